@@ -0,0 +1,25 @@
+//! Choose the defmt log level at compile time. Log calls below the chosen level are compiled
+//! out entirely. Trace logs run on every clock tick and can upset timing, so release builds only
+//! keep errors. Set `DEFMT_LOG` to override, e.g. `DEFMT_LOG=trace cargo embed`.
+//!
+//! The level chosen here only applies to this crate. Dependencies which log with defmt, e.g.
+//! `rp2040-hal`, read `DEFMT_LOG` themselves, and only log errors while it's unset. It can't be
+//! set for them from `.cargo/config.toml`, because that would apply to release builds too.
+
+use std::env;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=DEFMT_LOG");
+    println!("cargo:rustc-check-cfg=cfg(log_trace)");
+    let log_level = env::var("DEFMT_LOG").unwrap_or_else(|_| {
+        let level = match env::var("PROFILE").as_deref() {
+            Ok("release") => "error",
+            _ => "debug",
+        };
+        println!("cargo:rustc-env=DEFMT_LOG={}", level);
+        level.into()
+    });
+    if log_level.contains("trace") {
+        println!("cargo:rustc-cfg=log_trace");
+    }
+}
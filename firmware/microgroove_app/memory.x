@@ -1,6 +1,7 @@
+/* the last 32K of flash holds the pattern slots, see src/flash.rs */
 MEMORY {
     BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100 - 32K
     RAM   : ORIGIN = 0x20000000, LENGTH = 256K
 }
 
@@ -1,6 +1,8 @@
 /// Rendering UI graphics to the display.
-use crate::{input::InputMode, peripherals::Display};
-use microgroove_sequencer::{map_to_range, part::Part, Sequence};
+use crate::peripherals::Display;
+use microgroove_sequencer::{
+    input_mode::InputMode, map_to_range, part::Part, render_snapshot::RenderSnapshot, TRACK_COUNT,
+};
 
 use core::{fmt::Write, iter::zip, str::FromStr};
 use display_interface::DisplayError;
@@ -30,6 +32,10 @@ const WARNING_BORDER: u32 = 1;
 
 const HEADER_HEIGHT: u32 = 6;
 const HEADER_PLAYING_ICON_X_POS: i32 = 24;
+const HEADER_OWN_HARMONY_ICON_X_POS: i32 = 30;
+const HEADER_BPM_X_POS: i32 = 35; // 3 chars, ends before the widest page title
+const HEADER_TRACK_ICON_SIZE: u32 = 3;
+const HEADER_TRACK_ICON_SPACING: i32 = 4;
 
 const SEQUENCE_X_POS: i32 = 0;
 const SEQUENCE_Y_POS: i32 = HEADER_HEIGHT as i32 + 1;
@@ -60,30 +66,55 @@ pub fn render_splash_screen_view(display: &mut Display) -> DisplayResult {
     Ok(())
 }
 
-type ParamData = Vec<(String<6>, String<6>), 6>;
+pub type ParamData = Vec<(String<6>, String<6>), 6>;
 
 #[derive(Debug)]
 pub struct PerformView {
     pub input_mode: InputMode,
     pub playing: bool,
     pub track_num: u8,
-    pub sequence: Option<Sequence>,
+
+    /// Tempo of the clock, or `None` while stopped or once clock ticks stop arriving, shown as
+    /// "---".
+    pub bpm: Option<u16>,
+
+    pub snapshot: Option<RenderSnapshot>,
     pub part: Part,
-    pub active_step_num: Option<u8>,
+
+    /// Whether each track will play, or `None` for disabled tracks. Tracks which are muted, or
+    /// silenced by another track being soloed, are shown in the header.
+    pub audible_tracks: [Option<bool>; TRACK_COUNT],
+
     pub machine_name: Option<String<10>>,
     pub param_data: Option<ParamData>,
+
+    /// True if the track uses its own scale and key, rather than following the global harmony.
+    pub own_harmony: bool,
+
+    /// Chord implied by the notes of all tracks at the current step, shown on the Harmony page.
+    pub chord_name: Option<String<8>>,
+
+    /// What the machine selected in the browser does, shown in place of params.
+    pub description: Option<&'static str>,
+
+    /// Worst case microseconds taken to advance the sequencer and to render, shown over the
+    /// sequence for debugging.
+    pub max_elapsed_us: Option<(u64, u64)>,
 }
 
 impl PerformView {
     pub fn render(&self, display: &mut Display) -> DisplayResult {
         display.clear();
         self.draw_header(display)?;
-        if self.sequence.is_some() {
+        if self.snapshot.is_some() {
             self.draw_sequence(display)?;
             self.draw_params(display)?;
         } else {
             draw_disabled_track_warning(display)?;
         }
+        if let Some((advance_us, render_us)) = self.max_elapsed_us {
+            draw_timing_overlay(display, advance_us, render_us)?;
+        }
         display.flush()?;
         Ok(())
     }
@@ -109,13 +140,38 @@ impl PerformView {
             )
             .draw(display)?;
         }
+        if self.own_harmony {
+            Text::with_baseline(
+                "#",
+                Point::new(HEADER_OWN_HARMONY_ICON_X_POS, 0),
+                default_character_style(),
+                Baseline::Top,
+            )
+            .draw(display)?;
+        }
+        let mut bpm_str: String<3> = String::new();
+        match self.bpm {
+            Some(bpm) => write!(bpm_str, "{}", bpm.min(999)),
+            None => write!(bpm_str, "---"),
+        }
+        .expect("write! bpm_str should succeed");
+        Text::with_baseline(
+            bpm_str.as_str(),
+            Point::new(HEADER_BPM_X_POS, 0),
+            default_character_style(),
+            Baseline::Top,
+        )
+        .draw(display)?;
         let title = match self.input_mode {
             InputMode::Track => "TRACK",
             InputMode::Sequence => "SEQUENCE",
+            InputMode::Clock => "CLOCK",
             InputMode::Rhythm => "RHYTHM",
             InputMode::Groove => "GROOVE",
+            InputMode::Feel => "FEEL",
             InputMode::Melody => "MELODY",
             InputMode::Harmony => "HARMONY",
+            InputMode::Browse => "BROWSE",
         };
         Text::with_text_style(
             title,
@@ -125,7 +181,7 @@ impl PerformView {
         )
         .draw(display)?;
         match self.input_mode {
-            InputMode::Rhythm | InputMode::Melody => {
+            InputMode::Rhythm | InputMode::Feel | InputMode::Melody | InputMode::Browse => {
                 Text::with_text_style(
                     self.machine_name.as_ref().map(|s| s.as_str()).unwrap_or(""),
                     Point::new(DISPLAY_WIDTH, 0),
@@ -134,57 +190,113 @@ impl PerformView {
                 )
                 .draw(display)?;
             }
-            _ => { /* don't do nuffink */ }
+            InputMode::Harmony => {
+                Text::with_text_style(
+                    self.chord_name.as_ref().map(|s| s.as_str()).unwrap_or(""),
+                    Point::new(DISPLAY_WIDTH, 0),
+                    default_character_style(),
+                    right_align(),
+                )
+                .draw(display)?;
+            }
+            _ => self.draw_track_icons(display)?,
+        }
+        Ok(())
+    }
+
+    /// Draw a row of icons in the top right, one per enabled track: a box for tracks which will
+    /// play, and a dash, like a muted step, for tracks which are muted or silenced by a solo.
+    fn draw_track_icons(&self, display: &mut Display) -> DisplayResult {
+        let icons_x = DISPLAY_WIDTH - TRACK_COUNT as i32 * HEADER_TRACK_ICON_SPACING;
+        for (track_num, audible) in self.audible_tracks.iter().enumerate() {
+            let x = icons_x + track_num as i32 * HEADER_TRACK_ICON_SPACING;
+            match audible {
+                Some(true) => {
+                    Rectangle::new(
+                        Point::new(x, 1),
+                        Size::new(HEADER_TRACK_ICON_SIZE, HEADER_TRACK_ICON_SIZE),
+                    )
+                    .into_styled(filled_style())
+                    .draw(display)?;
+                }
+                Some(false) => {
+                    Line::new(
+                        Point::new(x, 2),
+                        Point::new(x + HEADER_TRACK_ICON_SIZE as i32 - 1, 2),
+                    )
+                    .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+                    .draw(display)?;
+                }
+                None => {}
+            }
         }
         Ok(())
     }
 
     fn draw_sequence(&self, display: &mut Display) -> DisplayResult {
-        let sequence = self
-            .sequence
-            .as_ref()
-            .expect("get sequence as_ref should succeed");
-        let length = sequence.len();
+        let snapshot = self.snapshot.as_ref().expect("should get render snapshot");
+        let length = snapshot.len();
         let part_mask = Part::new_mask(self.part, length);
         let step_width: u32 = if length <= 16 { 6 } else { 3 };
         let step_height: u32 = step_width;
         let display_sequence_margin_left =
             (DISPLAY_WIDTH - ((length as i32) * ((step_width as i32) + 1))) / 2;
-        let (note_min, note_max) = note_min_max_as_u8s(&sequence);
+        let (note_min, note_max) = snapshot.note_min_max;
         let note_y_pos_min: u32 = 35;
         let note_y_pos_max: u32 = 9 + step_height as u32;
         let step_size = Size::new(step_width, step_height);
         let mut step_num: u8 = 0;
         let stroke = PrimitiveStyle::with_stroke(BinaryColor::On, 1);
 
-        for (step, &masked) in sequence.steps.iter().zip(part_mask.iter()) {
+        for (step, &masked) in snapshot.steps().iter().zip(part_mask.iter()) {
             let x = display_sequence_margin_left + (step_num as i32 * (step_width as i32 + 1));
             let x2 = x + step_width as i32;
 
             // draw step
             if let Some(step) = step {
+                // delayed steps are nudged right, so the groove can be seen
+                let offset = step.delay_offset(step_width as i32 + 1);
+                let (x, x2) = (x + offset, x2 + offset);
+
                 // draw step indicator
-                let note_num: u8 = step.note.into();
                 let y = map_to_range(
-                    note_num as i32,
+                    step.note as i32,
                     note_min as i32,
                     note_max as i32,
                     note_y_pos_min as i32,
                     note_y_pos_max as i32,
                 );
-                let step_style =
-                    if step_num == self.active_step_num.expect("should get active step num") {
+                if step.muted {
+                    // muted steps are drawn as a dash, so they can be seen but stand apart from
+                    // steps which will play
+                    Line::new(Point::new(x, y), Point::new(x2 - 1, y))
+                        .into_styled(stroke)
+                        .draw(display)?;
+                } else {
+                    let step_style = if step_num == snapshot.active_step_num {
                         outline_style()
                     } else {
                         filled_style()
                     };
-                Rectangle::new(Point::new(x as i32, y as i32), step_size)
-                    .into_styled(step_style)
-                    .draw(display)?;
+                    Rectangle::new(Point::new(x as i32, y as i32), step_size)
+                        .into_styled(step_style)
+                        .draw(display)?;
+
+                    // gates longer than a step are drawn on over the steps they sustain across
+                    let sustain_length = step.sustain_line_length(step_width as i32 + 1);
+                    if sustain_length > 0 {
+                        let sustain_y = y + step_height as i32 / 2;
+                        Line::new(
+                            Point::new(x2, sustain_y),
+                            Point::new(x2 + sustain_length - 1, sustain_y),
+                        )
+                        .into_styled(stroke)
+                        .draw(display)?;
+                    }
+                }
 
                 // draw velocity tick
-                let velocity: u8 = step.velocity.into();
-                let velocity_tick_height = velocity >> 5;
+                let velocity_tick_height = step.velocity >> 5;
                 Line::new(
                     Point::new(x, SEQUENCE_UNDERLINE_Y_POS),
                     Point::new(x, SEQUENCE_UNDERLINE_Y_POS - velocity_tick_height as i32),
@@ -210,6 +322,17 @@ impl PerformView {
     }
 
     fn draw_params(&self, display: &mut Display) -> DisplayResult {
+        if let Some(description) = self.description {
+            Text::with_baseline(
+                description,
+                Point::new(0, PARAM_Y_POS as i32),
+                default_character_style(),
+                Baseline::Top,
+            )
+            .draw(display)?;
+            return Ok(());
+        }
+
         let is_track = match self.input_mode {
             InputMode::Track => true,
             _ => false,
@@ -254,9 +377,17 @@ impl PerformView {
             Point::new(value1_x, row1_y),
             Point::new(value2_x, row1_y),
         ];
+        // on the Sequence page [ENCODER2] sets the track length in bars, which isn't a param, so
+        // the params after SWING line up with the encoders after it
+        let param_slots: &[usize] = match self.input_mode {
+            InputMode::Sequence => &[0, 2, 3, 4, 5],
+            _ => &[0, 1, 2, 3, 4, 5],
+        };
         let params = zip(
             self.param_data.as_ref().expect("should get param data"),
-            zip(param_name_points, param_value_points),
+            param_slots
+                .iter()
+                .map(|&slot| (param_name_points[slot], param_value_points[slot])),
         );
 
         for ((param_name, param_value), (name_point, value_point)) in params {
@@ -311,17 +442,20 @@ fn draw_disabled_track_warning(display: &mut Display) -> DisplayResult {
     warning(display, "TRACK DISABLED")
 }
 
-fn note_min_max_as_u8s(sequence: &Sequence) -> (u8, u8) {
-    let mut min = 127;
-    let mut max = 0;
-    for step in &sequence.steps {
-        if let Some(step) = step {
-            let note: u8 = step.note.into();
-            min = note.min(min);
-            max = note.max(max);
-        }
-    }
-    (min, max)
+fn draw_timing_overlay(display: &mut Display, advance_us: u64, render_us: u64) -> DisplayResult {
+    let mut timings: String<24> = String::new();
+    write!(timings, "A{} R{}", advance_us, render_us).expect("write! timings should succeed");
+    let text = Text::with_text_style(
+        timings.as_str(),
+        Point::new(DISPLAY_WIDTH, SEQUENCE_Y_POS),
+        default_character_style(),
+        right_align(),
+    );
+    text.bounding_box()
+        .into_styled(background_style())
+        .draw(display)?;
+    text.draw(display)?;
+    Ok(())
 }
 
 fn default_character_style<'a>() -> MonoTextStyle<'a, BinaryColor> {
@@ -4,9 +4,28 @@ pub mod positional_encoder {
     use rotary_encoder_hal::{Direction, Rotary};
     use rp_pico::hal::gpio::DynPin;
 
+    /// Clicks closer together than this, in microseconds, are accelerated. Slower turns move by
+    /// exactly 1 per click, so precise edits are still possible.
+    const ACCELERATION_WINDOW_US: u64 = 60_000;
+
+    /// Most a single click can move, when clicks come as fast as the encoder can be read.
+    const ACCELERATION_MAX_CLICK_SIZE: u8 = 8;
+
+    /// Shape of the acceleration curve, the power turning speed is raised to. 1 accelerates in
+    /// proportion to speed, higher values keep moderate turns slower and save the biggest moves
+    /// for the fastest spins.
+    const ACCELERATION_CURVE: u32 = 2;
+
+    // resolution of turning speed used to calculate the acceleration curve
+    const SPEED_SCALE: u64 = 256;
+
     pub struct PositionalEncoder {
         encoder: Rotary<DynPin, DynPin>,
         value: i8,
+
+        /// Time and direction, 1 for clockwise or -1 for counter-clockwise, of the last click, to
+        /// accelerate fast turns.
+        last_click: Option<(u64, i8)>,
     }
 
     impl PositionalEncoder {
@@ -16,22 +35,27 @@ pub mod positional_encoder {
             PositionalEncoder {
                 encoder: Rotary::new(pin_a.into(), pin_b.into()),
                 value: 0,
+                last_click: None,
             }
         }
 
         /// Check the encoder state for changes. This should be called frequently, e.g.
-        /// every 1ms. Returns a `Some` containing the encoder value if there have been
-        /// changes, `None` otherwise.
-        pub fn update(&mut self) -> Option<i8> {
+        /// every 1ms, with the time in microseconds. Returns a `Some` containing the encoder
+        /// value if there have been changes, `None` otherwise. Clicks which follow quickly on
+        /// from the last one in the same direction move the value further, see
+        /// `ACCELERATION_WINDOW_US`.
+        pub fn update(&mut self, now_us: u64) -> Option<i8> {
             match self.encoder.update() {
                 Ok(Direction::Clockwise) => {
                     trace!("[PositionalEncoder::update] Direction::Clockwise");
-                    self.value += 1;
+                    let click_size = self.click_size(1, now_us);
+                    self.value = self.value.saturating_add(click_size);
                     Some(self.value)
                 }
                 Ok(Direction::CounterClockwise) => {
                     trace!("[PositionalEncoder::update] Direction::CounterClockwise");
-                    self.value -= 1;
+                    let click_size = self.click_size(-1, now_us);
+                    self.value = self.value.saturating_sub(click_size);
                     Some(self.value)
                 }
                 Ok(Direction::None) => None,
@@ -42,6 +66,26 @@ pub mod positional_encoder {
             }
         }
 
+        /// How far a click in `direction` at `now_us` moves the value. Changing direction starts
+        /// again from 1.
+        fn click_size(&mut self, direction: i8, now_us: u64) -> i8 {
+            let last_click = self.last_click.replace((now_us, direction));
+            let interval_us = match last_click {
+                Some((last_click_us, last_direction)) if last_direction == direction => {
+                    now_us.saturating_sub(last_click_us)
+                }
+                _ => return 1,
+            };
+            if interval_us >= ACCELERATION_WINDOW_US {
+                return 1;
+            }
+            let speed =
+                (ACCELERATION_WINDOW_US - interval_us) * SPEED_SCALE / ACCELERATION_WINDOW_US;
+            let extra = (ACCELERATION_MAX_CLICK_SIZE as u64 - 1) * speed.pow(ACCELERATION_CURVE)
+                / SPEED_SCALE.pow(ACCELERATION_CURVE);
+            1 + extra as i8
+        }
+
         /// Get the value of the encoder, and then reset that to zero. This has the
         /// semantics of "I would like to know your value, which I will use to update my
         /// state, so you can then discard it."
@@ -67,7 +111,7 @@ pub mod encoder_array {
     use super::positional_encoder::PositionalEncoder;
     use heapless::Vec;
 
-    pub const ENCODER_COUNT: usize = 6;
+    pub use microgroove_sequencer::input_mode::ENCODER_COUNT;
 
     /// An array of multiple `PositionalEncoders`.
     pub struct EncoderArray {
@@ -79,11 +123,12 @@ pub mod encoder_array {
             EncoderArray { encoders }
         }
 
-        pub fn update(&mut self) -> Option<()> {
+        /// Check every encoder for changes, see `PositionalEncoder::update`.
+        pub fn update(&mut self, now_us: u64) -> Option<()> {
             let any_changes = self
                 .encoders
                 .iter_mut()
-                .map(|enc| enc.update())
+                .map(|enc| enc.update(now_us))
                 .any(|opt| opt.is_some());
             if any_changes {
                 Some(())
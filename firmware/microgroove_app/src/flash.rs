@@ -0,0 +1,100 @@
+/// Pattern slots in the RP2040's flash, see `microgroove_sequencer::storage`. The slots live in
+/// the last sectors of the Pico's 2MB flash, which memory.x keeps free of firmware.
+///
+/// Flash can't be read while it's being written, and the firmware runs from flash, so writes are
+/// made by a function copied to RAM, with interrupts disabled. The ROM flash functions leave the
+/// flash in a slow read mode, so the boot loader is run again afterwards to restore fast reads.
+use core::ptr;
+use cortex_m::interrupt;
+use microgroove_sequencer::storage::{Flash, SLOT_COUNT, SLOT_SIZE};
+use rp2040_hal::rom_data;
+
+const XIP_BASE: usize = 0x1000_0000;
+const FLASH_SIZE_BYTES: usize = 2048 * 1024;
+const STORAGE_SIZE_BYTES: usize = SLOT_COUNT * SLOT_SIZE;
+const STORAGE_OFFSET: usize = FLASH_SIZE_BYTES - STORAGE_SIZE_BYTES;
+
+// 64KB block erase, used by the ROM where it can be
+const FLASH_BLOCK_SIZE: u32 = 1 << 16;
+const FLASH_BLOCK_ERASE_CMD: u8 = 0xd8;
+
+// flash is programmed in 256 byte pages
+const FLASH_PAGE_SIZE: usize = 256;
+
+const BOOT2_SIZE_WORDS: usize = 64;
+
+/// ROM functions used to write to flash, looked up before the write because the lookup runs from
+/// flash.
+struct RomFlashFunctions {
+    connect_internal_flash: unsafe extern "C" fn(),
+    flash_exit_xip: unsafe extern "C" fn(),
+    flash_range_erase: unsafe extern "C" fn(u32, usize, u32, u8),
+    flash_range_program: unsafe extern "C" fn(u32, *const u8, usize),
+    flash_flush_cache: unsafe extern "C" fn(),
+}
+
+pub struct PicoFlash;
+
+impl Flash for PicoFlash {
+    fn erase_and_write(&mut self, offset: usize, bytes: &[u8]) {
+        assert!(offset.is_multiple_of(SLOT_SIZE) && offset + SLOT_SIZE <= STORAGE_SIZE_BYTES);
+        assert!(bytes.len() <= SLOT_SIZE);
+
+        // whole pages must be programmed, pad with the value of erased flash
+        let mut pages = [0xff; SLOT_SIZE];
+        pages[..bytes.len()].copy_from_slice(bytes);
+        let page_bytes_len = bytes.len().div_ceil(FLASH_PAGE_SIZE) * FLASH_PAGE_SIZE;
+
+        let rom = RomFlashFunctions {
+            connect_internal_flash: rom_data::connect_internal_flash::ptr(),
+            flash_exit_xip: rom_data::flash_exit_xip::ptr(),
+            flash_range_erase: rom_data::flash_range_erase::ptr(),
+            flash_range_program: rom_data::flash_range_program::ptr(),
+            flash_flush_cache: rom_data::flash_flush_cache::ptr(),
+        };
+        let mut boot2 = [0u32; BOOT2_SIZE_WORDS];
+        unsafe {
+            ptr::copy_nonoverlapping(XIP_BASE as *const u32, boot2.as_mut_ptr(), BOOT2_SIZE_WORDS);
+        }
+        interrupt::free(|_| unsafe {
+            write_flash(
+                &rom,
+                (STORAGE_OFFSET + offset) as u32,
+                &pages[..page_bytes_len],
+                &boot2,
+            );
+        });
+    }
+
+    fn read(&mut self, offset: usize, bytes: &mut [u8]) {
+        assert!(offset + bytes.len() <= STORAGE_SIZE_BYTES);
+        unsafe {
+            ptr::copy_nonoverlapping(
+                (XIP_BASE + STORAGE_OFFSET + offset) as *const u8,
+                bytes.as_mut_ptr(),
+                bytes.len(),
+            );
+        }
+    }
+}
+
+/// Erase the slot at `address`, counted from the start of flash, and program `pages` to it. Runs
+/// from RAM, and must not call any code in flash until fast reads are restored by `boot2`.
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe fn write_flash(
+    rom: &RomFlashFunctions,
+    address: u32,
+    pages: &[u8],
+    boot2: &[u32; BOOT2_SIZE_WORDS],
+) {
+    (rom.connect_internal_flash)();
+    (rom.flash_exit_xip)();
+    (rom.flash_range_erase)(address, SLOT_SIZE, FLASH_BLOCK_SIZE, FLASH_BLOCK_ERASE_CMD);
+    (rom.flash_range_program)(address, pages.as_ptr(), pages.len());
+    (rom.flash_flush_cache)();
+
+    // boot2 is thumb code, so set the low bit of its address
+    let boot2: unsafe extern "C" fn() = core::mem::transmute(boot2.as_ptr() as usize + 1);
+    boot2();
+}
@@ -1,53 +1,65 @@
-use crate::encoder::encoder_array::ENCODER_COUNT;
 use microgroove_sequencer::{
-    machine::{MelodyMachineId, RhythmMachineId},
+    input_mode::{
+        encoder_map, InputMode, BPM_ENCODER_INDEX, BROWSE_ENCODER_INDEX,
+        CLOCK_MASTER_ENCODER_INDEX, CLOCK_MOD_ENCODER_INDEX, CLOCK_SOURCE_ENCODER_INDEX,
+        DIRECTION_ENCODER_INDEX, ENCODER_COUNT, LENGTH_IN_BARS_ENCODER_INDEX, LINK_ENCODER_INDEX,
+        OCTAVE_SHIFT_ENCODER_INDEX, PLAY_ENCODER_INDEX, PROGRAM_ENCODER_INDEX,
+    },
+    machine::{GrooveMachineId, MelodyMachineId, RhythmMachineId},
+    machine_browser::{BrowserEntry, MachineBrowser},
+    midi::Note,
     param::{wrapping_add, ParamError, ParamList, ParamValue},
     sequence_generator::SequenceGenerator,
     sequencer::Sequencer,
-    Track, TRACK_COUNT,
+    sysex::{self, SysExError},
+    TimeDivision, Track, TRACK_COUNT,
 };
 
 use core::iter::zip;
-use defmt::{debug, error, Format};
+use defmt::{debug, error};
 use heapless::Vec;
 
-type EncoderValues = Vec<Option<i8>, ENCODER_COUNT>;
+pub type EncoderValues = Vec<Option<i8>, ENCODER_COUNT>;
 
 const TRACK_NUM_PARAM_INDEX: usize = 2;
 
-#[derive(Clone, Copy, Debug, Default, Format)]
-pub enum InputMode {
-    #[default]
-    Track,
-    Sequence,
-    Rhythm,
-    Groove,
-    Melody,
-    Harmony,
-}
+// LEN moves by whole beats, unless fine adjust is on
+const LENGTH_PARAM_INDEX: usize = 1;
+
+// indexes of the virtual machine params of `Track`
+const RHYTHM_MACHINE_PARAM_INDEX: usize = 0;
+const MELODY_MACHINE_PARAM_INDEX: usize = 3;
+
+// index of the groove param which selects the generator's groove machine
+const GROOVE_MACHINE_PARAM_INDEX: usize = 3;
 
 /// Iterate over `encoder_values` and pass to a destination set of `Param`s
 /// determined by `InputMode`. This may have side-effects, including that sequence data may need to be
-/// regenerated.
+/// regenerated. If `fine_adjust` is set, each encoder moves its param by the smallest possible
+/// amount, however far it was turned. Otherwise the track's LEN moves by whole beats. If a note
+/// param was changed, the new note is returned so that it can be previewed.
 pub fn apply_encoder_values(
-    encoder_values: EncoderValues,
+    mut encoder_values: EncoderValues,
     input_mode: InputMode,
+    fine_adjust: bool,
     current_track: &mut u8,
     sequencer: &mut Sequencer,
     sequence_generators: &mut Vec<SequenceGenerator, TRACK_COUNT>,
-) -> Result<(), ParamError> {
+    machine_browser: &mut MachineBrowser,
+) -> Result<Option<Note>, ParamError> {
     if track_num_has_changed(input_mode, &encoder_values) {
         update_current_track(&encoder_values, current_track);
-        return Ok(());
+        return Ok(None);
     }
     if track_disabled(sequencer, current_track) {
-        enable_track(sequencer, current_track);
-        return Ok(());
+        enable_track(sequencer, sequence_generators, current_track);
+        return Ok(None);
     }
     let generator = sequence_generators
         .get_mut(*current_track as usize)
         .expect("should get mut ref to sequence generator for current track");
-    match input_mode {
+    let mut regenerate = true;
+    let preview_note = match input_mode {
         InputMode::Track => {
             let track = sequencer
                 .tracks
@@ -55,33 +67,204 @@ pub fn apply_encoder_values(
                 .expect("should get current track")
                 .as_mut()
                 .expect("should get current track as mut ref");
+            // tracks which keep their steps are resized by `Track::apply_params` instead
+            regenerate = !(track.keep_steps_on_resize && only_length_changed(&encoder_values));
+            let beats_increment = if fine_adjust {
+                None
+            } else {
+                encoder_values[LENGTH_PARAM_INDEX].take()
+            };
             let params = track.params_mut();
-            update_params(&encoder_values, params)?;
+            let preview_note = update_params(&encoder_values, input_mode, params, fine_adjust)?;
             if rhythm_machine_changed(input_mode, &encoder_values) {
-                update_rhythm_machine(generator, params[0].value())
+                update_rhythm_machine(generator, params[RHYTHM_MACHINE_PARAM_INDEX].value())
             }
             if melody_machine_changed(input_mode, &encoder_values) {
-                update_melody_machine(generator, params[3].value())
+                update_melody_machine(generator, params[MELODY_MACHINE_PARAM_INDEX].value())
             }
             track.apply_params()?;
+            if let Some(beats_increment) = beats_increment {
+                track.increment_length_in_beats(beats_increment);
+            }
+            generator.set_time_division(track.time_division);
+            preview_note
         }
         InputMode::Sequence => {
-            update_params(&encoder_values, sequencer.params_mut())?;
+            let preview_note = update_params(
+                &encoder_values,
+                input_mode,
+                sequencer.params_mut(),
+                fine_adjust,
+            )?;
+            if let Some(bars_increment) = encoder_values[LENGTH_IN_BARS_ENCODER_INDEX] {
+                update_length_in_bars(sequencer, current_track, bars_increment);
+            }
+            if let Some(program_increment) = encoder_values[PROGRAM_ENCODER_INDEX] {
+                let program_increment = if fine_adjust {
+                    program_increment.signum()
+                } else {
+                    program_increment
+                };
+                update_program(sequencer, current_track, program_increment);
+            }
+            if let Some(clock_mod_increment) = encoder_values[CLOCK_MOD_ENCODER_INDEX] {
+                update_clock_mod(sequencer, current_track, clock_mod_increment.signum());
+            }
+            if let Some(direction_increment) = encoder_values[DIRECTION_ENCODER_INDEX] {
+                update_direction(sequencer, current_track, direction_increment.signum());
+            }
+            preview_note
         }
-        InputMode::Rhythm => {
-            update_params(&encoder_values, generator.rhythm_machine.params_mut())?;
+        InputMode::Clock => {
+            regenerate = false;
+            if let Some(increment) = encoder_values[CLOCK_SOURCE_ENCODER_INDEX] {
+                let clock_source = sequencer.clock_source().increment(increment.signum());
+                sequencer.set_clock_source(clock_source);
+            }
+            if let Some(increment) = encoder_values[BPM_ENCODER_INDEX] {
+                let increment = if fine_adjust {
+                    increment.signum()
+                } else {
+                    increment
+                };
+                let bpm = sequencer.internal_clock_bpm() as i16 + increment as i16;
+                sequencer.set_internal_clock_bpm(bpm.max(0) as u16);
+            }
+            // turn right to switch on, left to switch off
+            if let Some(increment) = encoder_values[CLOCK_MASTER_ENCODER_INDEX] {
+                sequencer.set_clock_master(increment > 0);
+            }
+            if let Some(increment) = encoder_values[PLAY_ENCODER_INDEX] {
+                update_playing(sequencer, increment > 0);
+            }
+            if let Some(increment) = encoder_values[LINK_ENCODER_INDEX] {
+                update_link(sequencer, current_track, increment.signum());
+            }
+            None
         }
+        InputMode::Rhythm => update_params(
+            &encoder_values,
+            input_mode,
+            generator.rhythm_machine.params_mut(),
+            fine_adjust,
+        )?,
         InputMode::Groove => {
-            update_params(&encoder_values, generator.groove_params_mut())?;
-        }
-        InputMode::Melody => {
-            update_params(&encoder_values, generator.melody_machine.params_mut())?;
+            let params = generator.groove_params_mut();
+            let preview_note = update_params(&encoder_values, input_mode, params, fine_adjust)?;
+            if encoder_values[GROOVE_MACHINE_PARAM_INDEX].is_some() {
+                let param_value = params[GROOVE_MACHINE_PARAM_INDEX].value();
+                update_groove_machine(generator, param_value);
+            }
+            preview_note
         }
+        InputMode::Feel => update_params(
+            &encoder_values,
+            input_mode,
+            generator.groove_machine.params_mut(),
+            fine_adjust,
+        )?,
+        InputMode::Melody => update_params(
+            &encoder_values,
+            input_mode,
+            generator.melody_machine.params_mut(),
+            fine_adjust,
+        )?,
         InputMode::Harmony => {
-            update_params(&encoder_values, generator.harmony_params_mut())?;
+            let preview_note = update_params(
+                &encoder_values,
+                input_mode,
+                generator.harmony_params_mut(),
+                fine_adjust,
+            )?;
+            if let Some(octaves) = encoder_values[OCTAVE_SHIFT_ENCODER_INDEX] {
+                generator.shift_octave(octaves.signum());
+            }
+            preview_note
+        }
+        InputMode::Browse => {
+            if let Some(increment) = encoder_values[BROWSE_ENCODER_INDEX] {
+                machine_browser.scroll(increment.signum().into());
+                browse_machine(
+                    sequencer,
+                    current_track,
+                    generator,
+                    machine_browser.selected(),
+                );
+            }
+            None
         }
+    };
+    if regenerate {
+        update_sequence(sequencer, current_track, generator);
     }
-    update_sequence(sequencer, current_track, generator);
+    if let InputMode::Track | InputMode::Sequence | InputMode::Clock = input_mode {
+        if let Some(linked_track_num) = sequencer.sync_linked_track(*current_track) {
+            let linked_generator = sequence_generators
+                .get_mut(linked_track_num as usize)
+                .expect("should get sequence generator for linked track");
+            if let Some(Some(linked_track)) = sequencer.tracks.get(linked_track_num as usize) {
+                linked_generator.set_time_division(linked_track.time_division);
+            }
+            update_sequence(sequencer, &linked_track_num, linked_generator);
+        }
+    }
+    Ok(preview_note)
+}
+
+/// Drop input which would allocate on the heap: switching machines, or enabling the current track,
+/// which any input does if the track is disabled. Used when the heap is running low, so that
+/// other edits still work.
+pub fn drop_allocating_input(
+    encoder_values: &mut EncoderValues,
+    input_mode: InputMode,
+    sequencer: &Sequencer,
+    current_track: &u8,
+) {
+    if track_num_has_changed(input_mode, encoder_values) {
+        return;
+    }
+    if track_disabled(sequencer, current_track) {
+        encoder_values.iter_mut().for_each(|value| *value = None);
+        return;
+    }
+    let machine_encoder_indexes: &[usize] = match input_mode {
+        InputMode::Track => &[RHYTHM_MACHINE_PARAM_INDEX, MELODY_MACHINE_PARAM_INDEX],
+        InputMode::Groove => &[GROOVE_MACHINE_PARAM_INDEX],
+        InputMode::Browse => &[BROWSE_ENCODER_INDEX],
+        _ => &[],
+    };
+    for &index in machine_encoder_indexes {
+        encoder_values[index] = None;
+    }
+}
+
+/// Load a track received as SysEx from another Microgroove into the current track, enabling it
+/// if it's disabled. The track's generator switches to the received machines, but the received
+/// sequence is kept rather than regenerated.
+pub fn receive_track(
+    sequencer: &mut Sequencer,
+    sequence_generators: &mut Vec<SequenceGenerator, TRACK_COUNT>,
+    current_track: &u8,
+    bytes: &[u8],
+) -> Result<(), SysExError> {
+    if track_disabled(sequencer, current_track) {
+        enable_track(sequencer, sequence_generators, current_track);
+    }
+    let track = sequencer
+        .tracks
+        .get_mut(*current_track as usize)
+        .expect("should get current track")
+        .as_mut()
+        .expect("should get current track as mut ref");
+    sysex::load_track_sysex(track, bytes)?;
+    let generator = sequence_generators
+        .get_mut(*current_track as usize)
+        .expect("should get sequence generator for current track");
+    let params = track.params();
+    update_rhythm_machine(generator, params[RHYTHM_MACHINE_PARAM_INDEX].value());
+    update_melody_machine(generator, params[MELODY_MACHINE_PARAM_INDEX].value());
+    generator.set_time_division(track.time_division);
+    debug!("[receive_track] track_num={}", current_track);
     Ok(())
 }
 
@@ -107,6 +290,13 @@ fn track_num_has_changed(input_mode: InputMode, encoder_values: &EncoderValues)
     }
 }
 
+fn only_length_changed(encoder_values: &EncoderValues) -> bool {
+    matches!(
+        encoder_values.as_slice(),
+        [None, Some(_), None, None, None, None]
+    )
+}
+
 fn rhythm_machine_changed(input_mode: InputMode, encoder_values: &EncoderValues) -> bool {
     match input_mode {
         InputMode::Track => match encoder_values.as_slice() {
@@ -135,52 +325,198 @@ fn track_disabled(sequencer: &Sequencer, track_num: &u8) -> bool {
         .is_none()
 }
 
-fn enable_track(sequencer: &mut Sequencer, track_num: &u8) {
+fn enable_track(
+    sequencer: &mut Sequencer,
+    sequence_generators: &mut Vec<SequenceGenerator, TRACK_COUNT>,
+    track_num: &u8,
+) {
+    let generator = sequence_generators
+        .get_mut(*track_num as usize)
+        .expect("should get mut ref to sequence generator for new track");
+    *generator = sequencer.new_sequence_generator();
     let mut new_track = Track::default();
     new_track.midi_channel = (*track_num).into();
     new_track.sequence = SequenceGenerator::initial_sequence(new_track.length);
     let _ = sequencer.enable_track(*track_num, new_track);
 }
 
-fn update_params(encoder_values: &EncoderValues, params: &mut ParamList) -> Result<(), ParamError> {
-    let params_and_values = zip(params.iter_mut(), encoder_values);
-    for (param, &value) in params_and_values {
-        if let Some(value) = value {
+fn update_params(
+    encoder_values: &EncoderValues,
+    input_mode: InputMode,
+    params: &mut ParamList,
+    fine_adjust: bool,
+) -> Result<Option<Note>, ParamError> {
+    let mut changed_note = None;
+    for (param_index, &value) in zip(encoder_map(input_mode), encoder_values) {
+        let param = param_index.and_then(|param_index| params.get_mut(param_index));
+        if let (Some(param), Some(value)) = (param, value) {
             debug!(
-                "[map_encoder_input] increment param={}, value={}",
+                "[map_encoder_input] increment param={}, value={}, fine_adjust={}",
                 param.name(),
-                value
+                value,
+                fine_adjust
             );
-            param.increment(value.into())?;
+            if fine_adjust {
+                param.increment_fine(value.into())?;
+            } else {
+                param.increment(value.into())?;
+            }
+            if let ParamValue::Note(note) = param.value() {
+                changed_note = Some(note);
+            }
         }
     }
-    Ok(())
+    Ok(changed_note)
+}
+
+/// Move the length of a track up or down to the next whole bar. A partial bar at the end of the
+/// track is dropped when moving down.
+fn update_length_in_bars(sequencer: &mut Sequencer, track_num: &u8, bars_increment: i8) {
+    let track = sequencer
+        .tracks
+        .get_mut(*track_num as usize)
+        .expect("should get current track")
+        .as_mut()
+        .expect("should get current track as mut ref");
+    let bars = track.length_in_bars();
+    let whole_bars = TimeDivision::bars_to_steps(track.time_division, bars) == track.length as u16;
+    let new_bars = match bars_increment.signum() {
+        1 => bars.saturating_add(1),
+        -1 if whole_bars => bars.saturating_sub(1).max(1),
+        _ => bars.max(1),
+    };
+    debug!(
+        "[update_length_in_bars] track_num={}, bars={}",
+        track_num, new_bars
+    );
+    track.set_length_in_bars(new_bars);
+}
+
+fn update_program(sequencer: &mut Sequencer, track_num: &u8, program_increment: i8) {
+    let track = sequencer
+        .tracks
+        .get_mut(*track_num as usize)
+        .expect("should get current track")
+        .as_mut()
+        .expect("should get current track as mut ref");
+    track.increment_program(program_increment);
+    debug!(
+        "[update_program] track_num={}, program={}",
+        track_num, track.program
+    );
+}
+
+/// Start or stop playback from the panel, e.g. when running on the internal clock. Starting while
+/// already playing does nothing, rather than restarting from the top.
+fn update_playing(sequencer: &mut Sequencer, playing: bool) {
+    debug!("[update_playing] playing={}", playing);
+    match (playing, sequencer.playing()) {
+        (true, false) => sequencer.start_playing(),
+        (false, true) => sequencer.stop_playing(),
+        _ => (),
+    }
+}
+
+/// Step the track linked to the current track through OFF and every other track. The linked track
+/// takes the current track's length and time division straight away.
+fn update_link(sequencer: &mut Sequencer, track_num: &u8, link_increment: i8) {
+    // 0 is OFF, otherwise the linked track's number plus 1
+    let mut link = sequencer
+        .linked_track(*track_num)
+        .map_or(0, |linked_track_num| linked_track_num as i32 + 1);
+    loop {
+        link = wrapping_add(link, link_increment as i32, TRACK_COUNT as i32);
+        // a track can't link to itself
+        if link != *track_num as i32 + 1 {
+            break;
+        }
+    }
+    debug!("[update_link] track_num={} link={}", track_num, link);
+    match link {
+        0 => sequencer.unlink_track(*track_num),
+        _ => sequencer.link_tracks(*track_num, link as u8 - 1),
+    }
+}
+
+fn update_clock_mod(sequencer: &mut Sequencer, track_num: &u8, clock_mod_increment: i8) {
+    let track = sequencer
+        .tracks
+        .get_mut(*track_num as usize)
+        .expect("should get current track")
+        .as_mut()
+        .expect("should get current track as mut ref");
+    track.clock_mod = track.clock_mod.increment(clock_mod_increment);
+    debug!("[update_clock_mod] track_num={}", track_num);
+}
+
+fn update_direction(sequencer: &mut Sequencer, track_num: &u8, direction_increment: i8) {
+    let direction = sequencer
+        .tracks
+        .get(*track_num as usize)
+        .expect("should get current track")
+        .as_ref()
+        .expect("should get current track as ref")
+        .direction
+        .increment(direction_increment);
+    sequencer
+        .set_direction(*track_num, direction)
+        .expect("should set direction of current track");
+    debug!("[update_direction] track_num={}", track_num);
 }
 
 fn update_rhythm_machine(generator: &mut SequenceGenerator, param_value: ParamValue) {
     let id: RhythmMachineId = param_value
         .try_into()
         .expect("unexpected rhythm machine param");
-    generator.rhythm_machine = id.into();
+    generator.set_rhythm_machine(id);
 }
 
 fn update_melody_machine(generator: &mut SequenceGenerator, param_value: ParamValue) {
     let id: MelodyMachineId = param_value
         .try_into()
         .expect("unexpected melody machine param");
-    generator.melody_machine = id.into();
+    generator.set_melody_machine(id);
 }
 
-fn update_sequence(
+fn update_groove_machine(generator: &mut SequenceGenerator, param_value: ParamValue) {
+    let id: GrooveMachineId = param_value
+        .try_into()
+        .expect("unexpected groove machine param");
+    generator.set_groove_machine(id);
+}
+
+/// Switch the current track to the machine selected in the browser, so it can be heard. The
+/// track's machine params are updated to match, so the Track page shows the new machine. Groove
+/// machines are selected by the generator's own GROOVE param, which the generator updates.
+fn browse_machine(
     sequencer: &mut Sequencer,
     track_num: &u8,
-    generator: &SequenceGenerator,
+    generator: &mut SequenceGenerator,
+    entry: BrowserEntry,
 ) {
+    debug!("[browse_machine] track_num={}", track_num);
+    entry.select(generator);
+    if let Some(Some(track)) = sequencer.tracks.get_mut(*track_num as usize) {
+        let params = track.params_mut();
+        match entry {
+            BrowserEntry::Rhythm(id) => {
+                params[RHYTHM_MACHINE_PARAM_INDEX].set(ParamValue::RhythmMachineId(id))
+            }
+            BrowserEntry::Groove(_) => Ok(()),
+            BrowserEntry::Melody(id) => {
+                params[MELODY_MACHINE_PARAM_INDEX].set(ParamValue::MelodyMachineId(id))
+            }
+        }
+        .expect("should set machine param to browsed machine");
+    }
+}
+
+fn update_sequence(sequencer: &mut Sequencer, track_num: &u8, generator: &SequenceGenerator) {
     debug!("[update_sequence] track_num={}", track_num);
     match sequencer.tracks.get_mut(*track_num as usize) {
         Some(mut_track) => match mut_track.as_mut() {
             Some(track) => {
-                track.sequence = generator.apply(track.length);
+                track.sequence = generator.apply(track.length).carry_locks(&track.sequence);
             }
             None => {
                 error!("[update_sequence] tried to update sequence for disabled track");
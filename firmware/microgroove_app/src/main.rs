@@ -3,12 +3,18 @@
 
 mod display;
 mod encoder;
+mod flash;
 mod input;
 mod midi;
 mod peripherals;
 
 use panic_probe as _;
 
+// trace logs are emitted on every clock tick, which affects timing, so keep them out of release
+// builds (see build.rs)
+#[cfg(all(log_trace, not(debug_assertions)))]
+compile_error!("trace logging should not be compiled into release builds, unset DEFMT_LOG");
+
 // RTIC app module runs the app as a set of concurrent tasks modifying shared state
 // this module is responsible for interfacing with the hardware
 #[rtic::app(
@@ -22,33 +28,54 @@ mod app {
     use debouncr::{debounce_8, Debouncer, Edge, Repeat8};
     use defmt::{self, debug, error, info, trace};
     use defmt_rtt as _;
-    use embedded_hal::digital::v2::InputPin;
+    use embedded_hal::{digital::v2::InputPin, serial::Read};
+    use embedded_midi::MidiParser;
     use fugit::MicrosDurationU64;
     use heapless::{String, Vec};
     use midi_types::MidiMessage;
-    use nb::block;
     use rp_pico::hal::timer::{monotonic::Monotonic, Alarm0};
 
     use crate::{
         display::{self, PerformView},
         encoder::encoder_array::EncoderArray,
-        input::{self, InputMode},
-        midi,
+        flash::PicoFlash,
+        input, midi,
         peripherals::{
             setup, ButtonMelodyPin, ButtonRhythmPin, ButtonTrackPin, Display, MidiIn, MidiOut,
         },
     };
     use microgroove_sequencer::{
+        elapsed::MaxElapsed,
+        heap::{HeapUsage, OomPolicy},
+        input_mode::{next_input_mode, InputMode, PageButton},
+        machine_browser::MachineBrowser,
         machine_resources::MachineResources,
+        param::ParamList,
         sequence_generator::SequenceGenerator,
-        sequencer::{ScheduledMidiMessage, Sequencer},
-        Track, TRACK_COUNT,
+        sequencer::{self, ClockSource, ScheduledMidiMessage, Sequencer},
+        storage,
+        sysex::{self, SysExReceiver, TrackSysEx},
+        NotePriority, Track, TRACK_COUNT,
     };
 
     #[global_allocator]
     static ALLOCATOR: CortexMHeap = CortexMHeap::empty();
     const HEAP_SIZE_BYTES: usize = 8 * 1024;
 
+    // when the heap is low, refuse work which allocates, or with the `panic_on_oom` feature, go
+    // ahead and panic if the heap runs out
+    const OOM_POLICY: OomPolicy = if cfg!(feature = "panic_on_oom") {
+        OomPolicy::Panic
+    } else {
+        OomPolicy::Recover
+    };
+
+    // capacity of the `midi_send` task, which must be written out as a literal in its attribute.
+    // It has room for every message of the busiest tick, plus note offs still scheduled from
+    // earlier ticks
+    const MIDI_SEND_CAPACITY: usize = 256;
+    const _: () = assert!(sequencer::MAX_MESSAGES_PER_TICK <= MIDI_SEND_CAPACITY);
+
     // how often to read button state
     const BUTTON_READ_INTERVAL: MicrosDurationU64 = MicrosDurationU64::millis(5);
 
@@ -77,8 +104,27 @@ mod app {
         /// Current page of the UI.
         input_mode: InputMode,
 
+        /// True while the [TRACK] button is held, encoders make fine adjustments.
+        fine_adjust: bool,
+
+        /// True if an encoder was turned while [TRACK] was held, so releasing [TRACK] shouldn't
+        /// change page.
+        fine_adjust_used: bool,
+
         // set of SequenceGenerators, one for each `Track` in `Sequencer`
         sequence_generators: Vec<SequenceGenerator, TRACK_COUNT>,
+
+        /// Machine selected on the Browse page.
+        machine_browser: MachineBrowser,
+
+        /// Worst case time taken to advance the sequencer and render the display, shown on screen
+        /// with the `timing_overlay` feature.
+        advance_elapsed: MaxElapsed,
+        render_elapsed: MaxElapsed,
+
+        /// MIDI output port (1 half of the split UART). Taken for a moment while a track is sent
+        /// as SysEx.
+        midi_out: Option<MidiOut>,
     }
 
     /// RTIC local resources.
@@ -87,8 +133,11 @@ mod app {
         /// MIDI input port (1 half of the split UART).
         midi_in: MidiIn,
 
-        /// MIDI output port (1 half of the split UART).
-        midi_out: MidiOut,
+        /// Parses bytes from MIDI in into messages.
+        midi_parser: MidiParser,
+
+        /// Collects SysEx bytes from MIDI in into a track.
+        sysex_receiver: SysExReceiver,
 
         /// Interface to the display.
         display: Display,
@@ -111,6 +160,10 @@ mod app {
         /// Debounce state for [MELODY] button
         button_melody_state: Debouncer<u8, Repeat8>,
 
+        /// [MELODY] was pressed on the Sequence page, and changes page when it's released unless
+        /// it was used to start a note repeat.
+        melody_page_pending: bool,
+
         // encoders
         encoders: EncoderArray,
 
@@ -166,27 +219,51 @@ mod app {
         new_track.sequence = generator.apply(new_track.length);
         sequencer.enable_track(0, new_track);
 
+        // pick up where we left off, if a pattern has been saved to the first slot
+        match storage::load_slot(&mut PicoFlash, 0)
+            .and_then(|snapshot| snapshot.restore(&mut sequencer, &mut sequence_generators))
+        {
+            Ok(()) => info!("[init] loaded slot 0"),
+            Err(storage::StorageError::EmptySlot) => {}
+            Err(_) => error!("[init] couldn't load slot 0"),
+        }
+
         // show a splash screen for a bit
         display::render_splash_screen_view(&mut display)
             .expect("render_splash_screen_view should succeed");
 
-        // start scheduled tasks to read buttons, read encoders and update display
+        // play a short arpeggio on channel 1 to check MIDI out is wired up
+        #[cfg(feature = "midi_out_test")]
+        for message in sequencer::midi_test_messages() {
+            schedule_midi_send(message);
+        }
+
+        // start scheduled tasks to read buttons, read encoders, update display and tick the
+        // internal clock
         read_buttons::spawn().expect("read_buttons::spawn should succeed");
         read_encoders::spawn().expect("read_encoders::spawn should succeed");
         update_display::spawn().expect("update_display::spawn should succeed");
+        internal_clock::spawn().expect("internal_clock::spawn should succeed");
 
         info!("[init] complete 🤘");
 
         (
             Shared {
                 input_mode: Default::default(),
+                fine_adjust: false,
+                fine_adjust_used: false,
                 current_track: 0,
                 sequencer,
                 sequence_generators,
+                machine_browser: Default::default(),
+                advance_elapsed: Default::default(),
+                render_elapsed: Default::default(),
+                midi_out: Some(midi_out),
             },
             Local {
                 midi_in,
-                midi_out,
+                midi_parser: MidiParser::new(),
+                sysex_receiver: SysExReceiver::new(),
                 display,
                 button_track_pin,
                 button_rhythm_pin,
@@ -194,6 +271,7 @@ mod app {
                 button_track_state,
                 button_rhythm_state,
                 button_melody_state,
+                melody_page_pending: false,
                 encoders,
                 machine_resources,
             },
@@ -201,59 +279,82 @@ mod app {
         )
     }
 
-    /// Handle MIDI input. Triggered by a byte being received on UART0.
+    /// Handle MIDI input. Triggered by a byte being received on UART0. A track sent as SysEx
+    /// from another Microgroove is loaded into the current track.
     #[task(
         binds = UART0_IRQ,
         priority = 4,
-        shared = [sequencer],
-        local = [midi_in]
+        shared = [sequencer, advance_elapsed],
+        local = [midi_in, midi_parser, sysex_receiver]
     )]
     fn uart0_irq(mut ctx: uart0_irq::Context) {
         let start = monotonics::now();
         trace!("[uart0_irq] start");
 
         // read those sweet sweet midi bytes!
-        // TODO do we need the block! here?
-        if let Ok(message) = block!(ctx.local.midi_in.read()) {
-            ctx.shared.sequencer.lock(|sequencer| match message {
-                MidiMessage::TimingClock => {
-                    trace!("[midi] clock");
-                    let now_us = monotonics::now().duration_since_epoch().to_micros();
-                    let messages = sequencer.advance(now_us);
-                    for message in messages {
-                        match message {
-                            ScheduledMidiMessage::Immediate(message) => {
-                                if let Err(_err) = midi_send::spawn(message) {
-                                    error!("could not spawn midi_send for immediate message")
-                                }
-                            }
-                            ScheduledMidiMessage::Delayed(message, delay) => {
-                                if let Err(_err) = midi_send::spawn_after(delay, message) {
-                                    error!("could not spawn midi_send for delayed message")
+        while let Ok(byte) = ctx.local.midi_in.read() {
+            if let Some(sysex) = ctx.local.sysex_receiver.receive(byte) {
+                if receive_track::spawn(sysex).is_err() {
+                    error!("[uart0_irq] couldn't spawn receive_track, already receiving");
+                }
+            }
+            if let Some(message) = ctx.local.midi_parser.parse_byte(byte) {
+                let advance_us = ctx.shared.sequencer.lock(|sequencer| match message {
+                    MidiMessage::TimingClock => {
+                        trace!("[midi] clock");
+                        let advance_start = monotonics::now();
+                        let now_us = advance_start.duration_since_epoch().to_micros();
+                        match sequencer.external_tick(now_us) {
+                            Ok(messages) => {
+                                for message in messages {
+                                    schedule_midi_send(message);
                                 }
                             }
+                            Err(_) => error!("[midi] couldn't advance sequencer"),
+                        }
+                        if sequencer.has_looped_tracks() {
+                            // fails if the task is already queued, which is fine
+                            let _ = evolve_sequences::spawn();
                         }
+                        Some((monotonics::now() - advance_start).to_micros())
                     }
+                    MidiMessage::Start => {
+                        info!("[midi] start");
+                        sequencer.start_playing();
+                        None
+                    }
+                    MidiMessage::Stop => {
+                        info!("[midi] stop");
+                        sequencer.stop_playing();
+                        None
+                    }
+                    MidiMessage::Continue => {
+                        info!("[midi] continue");
+                        sequencer.continue_playing();
+                        None
+                    }
+                    _ => {
+                        trace!("[midi] UNKNOWN");
+                        None
+                    }
+                });
+                if let Some(advance_us) = advance_us {
+                    ctx.shared.advance_elapsed.lock(|advance_elapsed| {
+                        advance_elapsed.record(advance_us);
+                    });
                 }
-                MidiMessage::Start => {
-                    info!("[midi] start");
-                    sequencer.start_playing();
-                }
-                MidiMessage::Stop => {
-                    info!("[midi] stop");
-                    sequencer.stop_playing();
-                }
-                MidiMessage::Continue => {
-                    info!("[midi] continue");
-                    sequencer.continue_playing();
-                }
-                _ => trace!("[midi] UNKNOWN"),
-            });
 
-            // pass received message to midi out ("soft thru")
-            match midi_send::spawn(message) {
-                Ok(_) => (),
-                Err(_) => error!("could not spawn midi_send to pass through message"),
+                // pass received message to midi out ("soft thru"), unless it's filtered out
+                if ctx
+                    .shared
+                    .sequencer
+                    .lock(|sequencer| sequencer.passes_thru(&message))
+                {
+                    match midi_send::spawn(message) {
+                        Ok(_) => (),
+                        Err(_) => error!("could not spawn midi_send to pass through message"),
+                    }
+                }
             }
         }
 
@@ -263,27 +364,164 @@ mod app {
         );
     }
 
+    /// Tick the internal clock. The sequencer ignores these ticks while it follows MIDI clock from
+    /// MIDI in, so it's safe to run this all the time.
+    #[task(
+        priority = 4,
+        shared = [sequencer, advance_elapsed]
+    )]
+    fn internal_clock(mut ctx: internal_clock::Context) {
+        let start = monotonics::now();
+        let tick_duration = ctx.shared.sequencer.lock(|sequencer| {
+            let now_us = start.duration_since_epoch().to_micros();
+            match sequencer.internal_tick(now_us) {
+                Ok(messages) => {
+                    for message in messages {
+                        schedule_midi_send(message);
+                    }
+                }
+                Err(_) => error!("[internal_clock] couldn't advance sequencer"),
+            }
+            sequencer.internal_tick_duration()
+        });
+        let advance_us = (monotonics::now() - start).to_micros();
+        ctx.shared.advance_elapsed.lock(|advance_elapsed| {
+            advance_elapsed.record(advance_us);
+        });
+
+        internal_clock::spawn_after(tick_duration).expect("should spawn internal_clock task");
+    }
+
+    fn stop_note_repeat(sequencer: &mut Sequencer, track_num: u8) {
+        if sequencer.note_repeat().is_some() {
+            sequencer
+                .set_note_repeat(track_num, None)
+                .expect("should stop note repeat");
+            debug!("[read_buttons] note repeat stopped");
+        }
+    }
+
+    /// Bytes of heap in use and free. The heap is small, so this is worth keeping an eye on when
+    /// adding machines or anything else which allocates.
+    fn heap_usage() -> HeapUsage {
+        HeapUsage {
+            used: ALLOCATOR.used(),
+            free: ALLOCATOR.free(),
+        }
+    }
+
+    /// Spawn `midi_send` for a message, either immediately or after its delay.
+    fn schedule_midi_send(message: ScheduledMidiMessage) {
+        match message {
+            ScheduledMidiMessage::Immediate(message) => {
+                if let Err(_err) = midi_send::spawn(message) {
+                    error!("could not spawn midi_send for immediate message")
+                }
+            }
+            ScheduledMidiMessage::Delayed(message, delay) => {
+                if let Err(_err) = midi_send::spawn_after(delay, message) {
+                    error!("could not spawn midi_send for delayed message")
+                }
+            }
+        }
+    }
+
     /// Send a MIDI message. Implemented as a task to allow cooperative multitasking with
-    /// higher-pri tasks.
+    /// higher-pri tasks. Capacity is `MIDI_SEND_CAPACITY`, enough for every message of a tick,
+    /// including ratcheted steps, which schedule a note on and note off for every retrigger. The
+    /// sequencer may drop a message which is no longer wanted by the time it's due.
     #[task(
         priority = 3,
-        capacity = 64,
-        local = [midi_out]
+        capacity = 256,
+        shared = [sequencer, midi_out]
     )]
-    fn midi_send(ctx: midi_send::Context, message: MidiMessage) {
+    fn midi_send(mut ctx: midi_send::Context, message: MidiMessage) {
         trace!("midi_send");
+        let now_us = monotonics::now().duration_since_epoch().to_micros();
+        if !ctx
+            .shared
+            .sequencer
+            .lock(|sequencer| sequencer.should_send(&message, now_us))
+        {
+            trace!("[midi_send] dropped note off of stolen voice");
+            return;
+        }
         midi::log_message(&message);
-        ctx.local
-            .midi_out
-            .write(&message)
-            .expect("midi_out.write(message) should succeed");
+        ctx.shared.midi_out.lock(|midi_out| {
+            midi_out
+                .as_mut()
+                .expect("midi_out should be available")
+                .write(&message)
+                .expect("midi_out.write(message) should succeed");
+        });
+    }
+
+    /// Send the current track to MIDI out as SysEx, for another Microgroove to load. The message
+    /// is written straight to the UART, which holds up other MIDI out, including clock, for the
+    /// tenth of a second or so it takes. Runs at the same priority as `midi_send`, so the two
+    /// never interleave.
+    #[task(priority = 3, shared = [current_track, sequencer, midi_out])]
+    fn send_track(mut ctx: send_track::Context) {
+        let (track_num, sysex) =
+            (ctx.shared.current_track, ctx.shared.sequencer).lock(|current_track, sequencer| {
+                let track = sequencer
+                    .tracks
+                    .get(*current_track as usize)
+                    .and_then(|track| track.as_ref());
+                (*current_track, track.map(sysex::track_to_sysex))
+            });
+        let sysex = match sysex {
+            Some(sysex) => sysex,
+            None => {
+                error!("[send_track] couldn't send track, track disabled");
+                return;
+            }
+        };
+        ctx.shared.midi_out.lock(|midi_out| {
+            let writer = midi_out
+                .take()
+                .expect("midi_out should be available")
+                .release();
+            writer.write_full_blocking(&sysex);
+            // SysEx cancels running status, a new `MidiOut` starts without it
+            *midi_out = Some(MidiOut::new(writer));
+        });
+        info!("[send_track] sent track_num={}", track_num);
+    }
+
+    /// Load a track received as SysEx into the current track. Switching machines allocates, so
+    /// this is refused while the heap is low.
+    #[task(priority = 1, shared = [current_track, sequencer, sequence_generators])]
+    fn receive_track(ctx: receive_track::Context, sysex: TrackSysEx) {
+        if !heap_usage().can_allocate(OOM_POLICY) {
+            error!("[receive_track] heap low, dropped received track");
+            return;
+        }
+        (
+            ctx.shared.current_track,
+            ctx.shared.sequencer,
+            ctx.shared.sequence_generators,
+        )
+            .lock(|current_track, sequencer, sequence_generators| {
+                match input::receive_track(sequencer, sequence_generators, current_track, &sysex) {
+                    Ok(()) => info!("[receive_track] loaded track_num={}", current_track),
+                    Err(_) => error!("[receive_track] couldn't load track, invalid sysex"),
+                }
+            });
     }
 
     /// Check state of buttons, debouncing inputs, and update the `input_mode` shared resource.
+    /// [TRACK] doubles as a modifier: holding it while turning an encoder makes fine adjustments,
+    /// so it changes page on release rather than on press. Pressing [RHYTHM] and [MELODY]
+    /// together returns to the Track page. On the Rhythm page, holding [TRACK] and tapping
+    /// [RHYTHM] sets the step nearest to each tap, and on the Clock page it saves the pattern.
+    /// Holding [TRACK] and pressing [MELODY] on the Clock page sends the current track as SysEx. On
+    /// the Sequence page, holding [MELODY] and pressing [TRACK] repeats the current track's step
+    /// until either is released, so [MELODY] changes page on release there.
     #[task(
         priority = 4,
-        shared = [input_mode],
-        local = [button_track_pin, button_rhythm_pin, button_melody_pin, button_track_state, button_rhythm_state, button_melody_state]
+        shared = [current_track, sequencer, input_mode, fine_adjust, fine_adjust_used],
+        local = [button_track_pin, button_rhythm_pin, button_melody_pin, button_track_state, button_rhythm_state, button_melody_state, melody_page_pending]
     )]
     fn read_buttons(mut ctx: read_buttons::Context) {
         let start = monotonics::now();
@@ -296,14 +534,51 @@ mod app {
             .is_low()
             .expect("should get track button state");
         let track_edge = ctx.local.button_track_state.update(track_pressed);
-        if track_edge == Some(Edge::Rising) {
-            info!("[TRACK] pressed");
-            ctx.shared.input_mode.lock(|input_mode| {
-                *input_mode = match *input_mode {
-                    InputMode::Track => InputMode::Sequence,
-                    _ => InputMode::Track,
+        match track_edge {
+            Some(Edge::Rising) => {
+                info!("[TRACK] pressed");
+                (ctx.shared.fine_adjust, ctx.shared.fine_adjust_used).lock(
+                    |fine_adjust, fine_adjust_used| {
+                        *fine_adjust = true;
+                        *fine_adjust_used = false;
+                    },
+                );
+                if *ctx.local.melody_page_pending {
+                    // hold [MELODY] on the sequence page and press [TRACK] to repeat the current
+                    // step
+                    *ctx.local.melody_page_pending = false;
+                    ctx.shared.fine_adjust_used.lock(|fine_adjust_used| {
+                        *fine_adjust_used = true;
+                    });
+                    (ctx.shared.sequencer, ctx.shared.current_track).lock(
+                        |sequencer, current_track| {
+                            let rate = sequencer.note_repeat_rate();
+                            match sequencer.set_note_repeat(*current_track, Some(rate)) {
+                                Ok(()) => debug!("[TRACK] note repeat started"),
+                                Err(_) => error!("[TRACK] couldn't repeat note, track disabled"),
+                            }
+                        },
+                    );
                 }
-            });
+            }
+            Some(Edge::Falling) => {
+                info!("[TRACK] released");
+                let fine_adjust_used = (ctx.shared.fine_adjust, ctx.shared.fine_adjust_used).lock(
+                    |fine_adjust, fine_adjust_used| {
+                        *fine_adjust = false;
+                        *fine_adjust_used
+                    },
+                );
+                if !fine_adjust_used {
+                    ctx.shared.input_mode.lock(|input_mode| {
+                        *input_mode = next_input_mode(*input_mode, PageButton::Track, false)
+                    });
+                }
+                // releasing either button stops a note repeat
+                (ctx.shared.sequencer, ctx.shared.current_track)
+                    .lock(|sequencer, current_track| stop_note_repeat(sequencer, *current_track));
+            }
+            None => (),
         }
 
         let rhythm_pressed = ctx
@@ -314,12 +589,50 @@ mod app {
         let rhythm_edge = ctx.local.button_rhythm_state.update(rhythm_pressed);
         if rhythm_edge == Some(Edge::Rising) {
             info!("[RHYTHM] pressed");
-            ctx.shared.input_mode.lock(|input_mode| {
-                *input_mode = match *input_mode {
-                    InputMode::Rhythm => InputMode::Groove,
-                    _ => InputMode::Rhythm,
+            let track_held = ctx.local.button_track_state.is_high();
+            let input_mode = ctx.shared.input_mode.lock(|input_mode| *input_mode);
+            if track_held && matches!(input_mode, InputMode::Rhythm) {
+                // tap record: hold [TRACK] on the rhythm page and tap [RHYTHM] in time to set steps
+                ctx.shared.fine_adjust_used.lock(|fine_adjust_used| {
+                    *fine_adjust_used = true;
+                });
+                (ctx.shared.sequencer, ctx.shared.current_track).lock(
+                    |sequencer, current_track| match sequencer.tap_step(*current_track) {
+                        Ok(step_num) => debug!("[RHYTHM] tapped step={}", step_num),
+                        Err(_) => error!("[RHYTHM] couldn't tap step, track disabled"),
+                    },
+                );
+            } else if track_held && matches!(input_mode, InputMode::Clock) {
+                // hold [TRACK] on the clock page and press [RHYTHM] to save the pattern
+                ctx.shared.fine_adjust_used.lock(|fine_adjust_used| {
+                    *fine_adjust_used = true;
+                });
+                if save_pattern::spawn().is_err() {
+                    error!("[RHYTHM] couldn't save pattern, already saving");
                 }
-            });
+            } else if track_held && matches!(input_mode, InputMode::Sequence) {
+                // hold [TRACK] on the sequence page and press [RHYTHM] to mute the current track
+                ctx.shared.fine_adjust_used.lock(|fine_adjust_used| {
+                    *fine_adjust_used = true;
+                });
+                (ctx.shared.sequencer, ctx.shared.current_track).lock(
+                    |sequencer, current_track| match sequencer.toggle_mute(*current_track) {
+                        Ok(muted) => debug!("[RHYTHM] track muted={}", muted),
+                        Err(_) => error!("[RHYTHM] couldn't mute track, track disabled"),
+                    },
+                );
+            } else {
+                // [MELODY] pressed first on the sequence page hasn't changed page yet, pressing
+                // [RHYTHM] with it still escapes to the track page
+                *ctx.local.melody_page_pending = false;
+                let melody_held = ctx.local.button_melody_state.is_high();
+                ctx.shared.input_mode.lock(|input_mode| {
+                    *input_mode = next_input_mode(*input_mode, PageButton::Rhythm, melody_held)
+                });
+            }
+        }
+        if rhythm_edge == Some(Edge::Falling) {
+            info!("[RHYTHM] released");
         }
 
         let melody_pressed = ctx
@@ -330,12 +643,57 @@ mod app {
         let melody_edge = ctx.local.button_melody_state.update(melody_pressed);
         if melody_edge == Some(Edge::Rising) {
             info!("[MELODY] pressed");
-            ctx.shared.input_mode.lock(|input_mode| {
-                *input_mode = match *input_mode {
-                    InputMode::Melody => InputMode::Harmony,
-                    _ => InputMode::Melody,
+            let input_mode = ctx.shared.input_mode.lock(|input_mode| *input_mode);
+            if ctx.local.button_track_state.is_high() {
+                ctx.shared.fine_adjust_used.lock(|fine_adjust_used| {
+                    *fine_adjust_used = true;
+                });
+                if matches!(input_mode, InputMode::Sequence) {
+                    // hold [TRACK] on the sequence page and press [MELODY] to solo the current
+                    // track
+                    (ctx.shared.sequencer, ctx.shared.current_track).lock(
+                        |sequencer, current_track| match sequencer.toggle_solo(*current_track) {
+                            Ok(soloed) => debug!("[MELODY] track soloed={}", soloed),
+                            Err(_) => error!("[MELODY] couldn't solo track, track disabled"),
+                        },
+                    );
+                } else if matches!(input_mode, InputMode::Clock) {
+                    // hold [TRACK] on the clock page and press [MELODY] to send the current track
+                    if send_track::spawn().is_err() {
+                        error!("[MELODY] couldn't send track, already sending");
+                    }
+                } else {
+                    // hold [TRACK] and press [MELODY] to browse machines
+                    ctx.shared.input_mode.lock(|input_mode| {
+                        *input_mode = InputMode::Browse;
+                    });
                 }
-            });
+            } else if matches!(input_mode, InputMode::Sequence)
+                && !ctx.local.button_rhythm_state.is_high()
+            {
+                // wait for release, [MELODY] may be held to start a note repeat
+                *ctx.local.melody_page_pending = true;
+            } else {
+                let rhythm_held = ctx.local.button_rhythm_state.is_high();
+                ctx.shared.input_mode.lock(|input_mode| {
+                    *input_mode = next_input_mode(*input_mode, PageButton::Melody, rhythm_held)
+                });
+            }
+        }
+
+        if melody_edge == Some(Edge::Falling) {
+            info!("[MELODY] released");
+            if *ctx.local.melody_page_pending {
+                *ctx.local.melody_page_pending = false;
+                ctx.shared.input_mode.lock(|input_mode| {
+                    if matches!(input_mode, InputMode::Sequence) {
+                        *input_mode = next_input_mode(*input_mode, PageButton::Melody, false)
+                    }
+                });
+            }
+            // releasing either button stops a note repeat
+            (ctx.shared.sequencer, ctx.shared.current_track)
+                .lock(|sequencer, current_track| stop_note_repeat(sequencer, *current_track));
         }
 
         read_buttons::spawn_after(BUTTON_READ_INTERVAL).expect("should spawn read_buttons task");
@@ -350,30 +708,77 @@ mod app {
     /// Reading every 1ms removes some of the noise vs reading on each interrupt.
     #[task(
         priority = 4,
-        shared = [input_mode, current_track, sequencer, sequence_generators],
-        local = [encoders, machine_resources],
+        shared = [input_mode, fine_adjust, fine_adjust_used, current_track, sequencer, sequence_generators, machine_browser],
+        local = [encoders],
     )]
     fn read_encoders(ctx: read_encoders::Context) {
         let start = monotonics::now();
         trace!("[read_encoders] start");
 
-        if let Some(_changes) = ctx.local.encoders.update() {
+        let now_us = start.duration_since_epoch().to_micros();
+        if let Some(_changes) = ctx.local.encoders.update(now_us) {
             (
                 ctx.shared.input_mode,
+                ctx.shared.fine_adjust,
+                ctx.shared.fine_adjust_used,
                 ctx.shared.current_track,
                 ctx.shared.sequencer,
                 ctx.shared.sequence_generators,
+                ctx.shared.machine_browser,
             )
                 .lock(
-                    |input_mode, current_track, sequencer, sequence_generators| {
-                        input::apply_encoder_values(
-                            ctx.local.encoders.take_values(),
+                    |input_mode,
+                     fine_adjust,
+                     fine_adjust_used,
+                     current_track,
+                     sequencer,
+                     sequence_generators,
+                     machine_browser| {
+                        let mut encoder_values = ctx.local.encoders.take_values();
+                        let heap = heap_usage();
+                        if !heap.can_allocate(OOM_POLICY) {
+                            error!(
+                                "[read_encoders] heap low, ignoring input which allocates heap_used={} heap_free={}",
+                                heap.used, heap.free
+                            );
+                            input::drop_allocating_input(
+                                &mut encoder_values,
+                                *input_mode,
+                                sequencer,
+                                current_track,
+                            );
+                            if encoder_values.iter().all(Option::is_none) {
+                                return;
+                            }
+                        }
+                        if *fine_adjust {
+                            *fine_adjust_used = true;
+                        }
+                        let preview_note = input::apply_encoder_values(
+                            encoder_values,
                             *input_mode,
+                            *fine_adjust,
                             current_track,
                             sequencer,
                             sequence_generators,
+                            machine_browser,
                         )
                         .expect("should be able to apply encoder values");
+                        let heap = heap_usage();
+                        debug!(
+                            "[read_encoders] heap_used={} heap_free={}",
+                            heap.used, heap.free
+                        );
+
+                        // play the note being edited so it can be heard
+                        let current_track_channel = sequencer.tracks[*current_track as usize]
+                            .as_ref()
+                            .map(|track| track.midi_channel);
+                        if let (Some(note), Some(channel)) = (preview_note, current_track_channel) {
+                            for message in sequencer::preview_note_messages(channel, note.into()) {
+                                schedule_midi_send(message);
+                            }
+                        }
                     },
                 )
         }
@@ -388,6 +793,78 @@ mod app {
         );
     }
 
+    /// Regenerate some steps of tracks which have just looped, so that patterns evolve over
+    /// time. Runs at low priority because generating sequences is relatively slow.
+    #[task(
+        priority = 1,
+        shared = [sequencer, sequence_generators],
+        local = [machine_resources],
+    )]
+    fn evolve_sequences(ctx: evolve_sequences::Context) {
+        let start = monotonics::now();
+        trace!("[evolve_sequences] start");
+
+        let looped_tracks = ctx
+            .shared
+            .sequencer
+            .lock(|sequencer| sequencer.take_looped_tracks());
+        for track_num in looped_tracks {
+            // evolving clones the track's machines, skip it until there's room, the track carries
+            // on playing its current sequence
+            let heap = heap_usage();
+            if !heap.can_allocate(OOM_POLICY) {
+                error!(
+                    "[evolve_sequences] heap low, not evolving track_num={} heap_used={} heap_free={}",
+                    track_num, heap.used, heap.free
+                );
+                continue;
+            }
+            let Some(sequence) = ctx.shared.sequencer.lock(|sequencer| {
+                sequencer.tracks[track_num as usize]
+                    .as_ref()
+                    .map(|track| track.sequence.clone())
+            }) else {
+                continue;
+            };
+
+            // generating is slow, so build the new sequence without holding the sequencer, which
+            // would hold up the next tick
+            let evolved_sequence = ctx.shared.sequence_generators.lock(|sequence_generators| {
+                sequence_generators[track_num as usize]
+                    .evolve(sequence.clone(), ctx.local.machine_resources)
+            });
+
+            ctx.shared.sequencer.lock(|sequencer| {
+                sequencer.swap_evolved_sequence(
+                    track_num,
+                    &sequence,
+                    evolved_sequence,
+                    ctx.local.machine_resources,
+                )
+            });
+        }
+
+        trace!(
+            "[evolve_sequences] elapsed_time={}",
+            (monotonics::now() - start).to_micros()
+        );
+    }
+
+    /// Save the pattern to the first slot, so that it's loaded again at boot. Writing to flash
+    /// stops everything else for a moment, so this only happens when asked for.
+    #[task(priority = 1, shared = [sequencer, sequence_generators])]
+    fn save_pattern(ctx: save_pattern::Context) {
+        let snapshot = (ctx.shared.sequencer, ctx.shared.sequence_generators).lock(
+            |sequencer, sequence_generators| {
+                storage::SequencerSnapshot::capture(sequencer, sequence_generators)
+            },
+        );
+        match storage::save_slot(&mut PicoFlash, 0, &snapshot) {
+            Ok(()) => info!("[save_pattern] saved slot 0"),
+            Err(_) => error!("[save_pattern] couldn't save slot 0"),
+        }
+    }
+
     /// Update the display by rendering a view object. This method creates an instance of a view,
     /// passing in relevant data (which is copied). The view then takes care of rendering to the
     /// display. Rendering is time-consuming, because writing data across I2C is slow. Hence the
@@ -395,82 +872,199 @@ mod app {
     /// tasks to interrupt the rendering.
     #[task(
         priority = 1,
-        shared = [input_mode, current_track, sequencer, sequence_generators],
+        shared = [input_mode, current_track, sequencer, sequence_generators, machine_browser, advance_elapsed, render_elapsed],
     )]
-    fn update_display(ctx: update_display::Context) {
+    fn update_display(mut ctx: update_display::Context) {
         let start = monotonics::now();
         trace!("[update_display] start");
+        let now_us = start.duration_since_epoch().to_micros();
+
+        let max_elapsed_us = if cfg!(feature = "timing_overlay") {
+            let advance_us = ctx
+                .shared
+                .advance_elapsed
+                .lock(|advance_elapsed| advance_elapsed.max_us());
+            let render_us = ctx
+                .shared
+                .render_elapsed
+                .lock(|render_elapsed| render_elapsed.max_us());
+            Some((advance_us, render_us))
+        } else {
+            None
+        };
 
         (
             ctx.shared.input_mode,
             ctx.shared.current_track,
             ctx.shared.sequencer,
             ctx.shared.sequence_generators,
+            ctx.shared.machine_browser,
         )
             .lock(
-                |input_mode, current_track, sequencer, sequence_generators| {
-                    let tick = sequencer.tick();
+                |input_mode, current_track, sequencer, sequence_generators, machine_browser| {
+                    let chord_name = match input_mode {
+                        InputMode::Harmony => sequencer.current_chord_name(),
+                        _ => None,
+                    };
+                    let snapshot = sequencer
+                        .render_snapshot(*current_track, NotePriority::default())
+                        .ok();
+                    let audible_tracks = core::array::from_fn(|track_num| {
+                        sequencer.tracks[track_num]
+                            .as_ref()
+                            .map(|_| sequencer.track_audible(track_num as u8))
+                    });
+                    let generator = sequence_generators
+                        .get(*current_track as usize)
+                        .expect("should get current sequence generator");
+                    let part = generator.part();
+                    // the internal clock keeps ticking while stopped, so only show its tempo
+                    // while playing
+                    let bpm = if sequencer.playing() {
+                        sequencer.bpm(now_us)
+                    } else {
+                        None
+                    };
+                    let own_harmony = sequencer.has_own_harmony(generator);
                     let maybe_track = sequencer
                         .tracks
                         .get_mut(*current_track as usize)
                         .expect("should get current track")
                         .as_mut();
-                    let generator = sequence_generators
-                        .get(*current_track as usize)
-                        .expect("should get current sequence generator");
-                    let part = generator.part();
                     let view = match maybe_track {
                         Some(track) => {
-                            let sequence = Some(track.sequence.clone());
-                            let active_step_num = Some(track.step_num(tick));
                             let machine_name = match input_mode {
                                 InputMode::Rhythm => {
                                     Some(String::<10>::from(generator.rhythm_machine.name()))
                                 }
+                                InputMode::Feel => {
+                                    Some(String::<10>::from(generator.groove_machine.name()))
+                                }
                                 InputMode::Melody => {
                                     Some(String::<10>::from(generator.melody_machine.name()))
                                 }
+                                InputMode::Browse => {
+                                    let mut name = String::<10>::new();
+                                    write!(name, "{}", machine_browser.selected())
+                                        .expect("should write machine name to string buf");
+                                    Some(name)
+                                }
+                                _ => None,
+                            };
+                            let description = match input_mode {
+                                InputMode::Browse => Some(machine_browser.selected().description()),
                                 _ => None,
                             };
+                            let empty_params = ParamList::new();
                             let params = match input_mode {
                                 InputMode::Track => track.params(),
                                 InputMode::Sequence => sequencer.params(),
+                                InputMode::Clock => &empty_params,
                                 InputMode::Rhythm => generator.rhythm_machine.params(),
                                 InputMode::Groove => generator.groove_params(),
+                                InputMode::Feel => generator.groove_machine.params(),
                                 InputMode::Melody => generator.melody_machine.params(),
                                 InputMode::Harmony => generator.harmony_params(),
+                                InputMode::Browse => &empty_params,
                             };
-                            let param_data = Some(
-                                params
-                                    .iter()
-                                    .map(|param| {
-                                        let mut value_string = String::new();
-                                        write!(value_string, "{}", param.value())
-                                            .expect("should write param value to string buf");
-                                        (String::<6>::from(param.name()), value_string)
-                                    })
-                                    .collect(),
-                            );
+                            let mut param_data: display::ParamData = params
+                                .iter()
+                                .map(|param| {
+                                    let mut value_string = String::new();
+                                    param
+                                        .value()
+                                        .write_in_scale(&mut value_string, generator.scale())
+                                        .expect("should write param value to string buf");
+                                    (String::<6>::from(param.name()), value_string)
+                                })
+                                .collect();
+                            if let InputMode::Sequence = input_mode {
+                                // the track's program, clock mod and direction aren't params,
+                                // show them after the sequencer's params
+                                let mut value_string = String::new();
+                                match track.program {
+                                    Some(program) => write!(value_string, "{}", program),
+                                    None => write!(value_string, "OFF"),
+                                }
+                                .expect("should write program to string buf");
+                                let _ = param_data.push((String::from("PROG"), value_string));
+                                let mut value_string = String::new();
+                                write!(value_string, "{}", track.clock_mod)
+                                    .expect("should write clock mod to string buf");
+                                let _ = param_data.push((String::from("CLKMOD"), value_string));
+                                let mut value_string = String::new();
+                                write!(value_string, "{}", track.direction)
+                                    .expect("should write direction to string buf");
+                                let _ = param_data.push((String::from("DIR"), value_string));
+                            }
+                            if let InputMode::Clock = input_mode {
+                                // the clock page's controls aren't params, show their settings
+                                let on_off = |on: bool| if on { "ON" } else { "OFF" };
+                                // on AUTO, show which clock is driving the sequencer right now
+                                let now_us = start.duration_since_epoch().to_micros();
+                                let mut value_string = String::new();
+                                match sequencer.clock_source() {
+                                    ClockSource::Auto if sequencer.using_external_clock(now_us) => {
+                                        write!(value_string, "A-EXT")
+                                    }
+                                    ClockSource::Auto => write!(value_string, "A-INT"),
+                                    clock_source => write!(value_string, "{}", clock_source),
+                                }
+                                .expect("should write clock source to string buf");
+                                let _ = param_data.push((String::from("CLOCK"), value_string));
+                                let mut value_string = String::new();
+                                write!(value_string, "{}", sequencer.internal_clock_bpm())
+                                    .expect("should write bpm to string buf");
+                                let _ = param_data.push((String::from("BPM"), value_string));
+                                let _ = param_data.push((
+                                    String::from("MASTER"),
+                                    String::from(on_off(sequencer.clock_master())),
+                                ));
+                                let _ = param_data.push((
+                                    String::from("PLAY"),
+                                    String::from(on_off(sequencer.playing())),
+                                ));
+                                let mut value_string = String::new();
+                                match sequencer.linked_track(*current_track) {
+                                    Some(linked_track_num) => {
+                                        write!(value_string, "{}", linked_track_num)
+                                    }
+                                    None => write!(value_string, "OFF"),
+                                }
+                                .expect("should write linked track to string buf");
+                                let _ = param_data.push((String::from("LINK"), value_string));
+                            }
+                            let param_data = Some(param_data);
                             PerformView {
                                 input_mode: *input_mode,
                                 playing: sequencer.playing(),
                                 track_num: *current_track,
-                                sequence,
+                                bpm,
+                                snapshot,
                                 part,
-                                active_step_num,
+                                audible_tracks,
                                 machine_name,
                                 param_data,
+                                own_harmony,
+                                chord_name,
+                                description,
+                                max_elapsed_us,
                             }
                         }
                         None => PerformView {
                             input_mode: *input_mode,
                             playing: sequencer.playing(),
                             track_num: *current_track,
-                            sequence: None,
+                            bpm,
+                            snapshot,
                             part,
-                            active_step_num: None,
+                            audible_tracks,
                             machine_name: None,
                             param_data: None,
+                            own_harmony: false,
+                            chord_name: None,
+                            description: None,
+                            max_elapsed_us,
                         },
                     };
 
@@ -489,9 +1083,10 @@ mod app {
 
     #[task(
         priority = 1,
+        shared = [render_elapsed],
         local = [display]
     )]
-    fn render_view(ctx: render_view::Context, view: PerformView) {
+    fn render_view(mut ctx: render_view::Context, view: PerformView) {
         let start = monotonics::now();
         trace!("[render_view] start");
 
@@ -499,10 +1094,11 @@ mod app {
             error!("PerformView::render error");
         }
 
-        trace!(
-            "[render_view] elapsed_time={}",
-            (monotonics::now() - start).to_micros()
-        );
+        let elapsed_us = (monotonics::now() - start).to_micros();
+        ctx.shared.render_elapsed.lock(|render_elapsed| {
+            render_elapsed.record(elapsed_us);
+        });
+        trace!("[render_view] elapsed_time={}", elapsed_us);
     }
 
     // idle task needed because default RTIC idle task calls wfi(), which breaks rtt
@@ -27,8 +27,9 @@ type MidiOutUartPin = Pin<Gpio16, FunctionUart>;
 type MidiInUartPin = Pin<Gpio17, FunctionUart>;
 type MidiUartPins = (MidiOutUartPin, MidiInUartPin);
 
-// microgroove-specific midi in/out channel types
-pub type MidiIn = embedded_midi::MidiIn<Reader<UART0, MidiUartPins>>;
+// microgroove-specific midi in/out channel types. MIDI in is read a byte at a time, so that SysEx
+// can be collected as well as parsed into messages.
+pub type MidiIn = Reader<UART0, MidiUartPins>;
 pub type MidiOut = embedded_midi::MidiOut<Writer<UART0, MidiUartPins>>;
 
 // type alias for display pins
@@ -173,10 +174,7 @@ fn new_midi_uart(
         .expect("enabling uart for midi should succeed");
     midi_uart.enable_rx_interrupt();
     let (midi_reader, midi_writer) = midi_uart.split();
-    (
-        embedded_midi::MidiIn::new(midi_reader),
-        embedded_midi::MidiOut::new(midi_writer),
-    )
+    (midi_reader, embedded_midi::MidiOut::new(midi_writer))
 }
 
 fn new_display(
@@ -0,0 +1,50 @@
+/// Track the worst case time taken by time-critical tasks, to see how close they come to their
+/// budget on the device, e.g. advancing the sequencer must finish well within one MIDI tick.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MaxElapsed {
+    max_us: u64,
+}
+
+impl MaxElapsed {
+    /// Record the time taken by one run of a task. Returns true if it's the longest yet.
+    pub fn record(&mut self, elapsed_us: u64) -> bool {
+        if elapsed_us > self.max_us {
+            self.max_us = elapsed_us;
+            return true;
+        }
+        false
+    }
+
+    pub fn max_us(&self) -> u64 {
+        self.max_us
+    }
+
+    pub fn reset(&mut self) {
+        self.max_us = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_elapsed_should_keep_longest_time() {
+        let mut elapsed = MaxElapsed::default();
+        assert_eq!(0, elapsed.max_us());
+        assert!(elapsed.record(120));
+        assert!(!elapsed.record(80));
+        assert!(elapsed.record(450));
+        assert!(!elapsed.record(450));
+        assert_eq!(450, elapsed.max_us());
+    }
+
+    #[test]
+    fn max_elapsed_reset_should_forget_longest_time() {
+        let mut elapsed = MaxElapsed::default();
+        elapsed.record(300);
+        elapsed.reset();
+        assert_eq!(0, elapsed.max_us());
+        assert!(elapsed.record(10));
+    }
+}
@@ -0,0 +1,76 @@
+//! Heap usage, and what to do when the heap runs low. The device only has a small heap, used by
+//! machines and params, so work which allocates, e.g. switching machines, enabling a track or
+//! evolving a sequence, can run it out. Running out panics, so by default that work is refused
+//! while the heap is low, and playback carries on.
+
+/// Bytes of heap kept free as a safety margin. Work which allocates is refused if less than this
+/// is free, unless the policy is to panic.
+pub const HEAP_RESERVE_BYTES: usize = 1024;
+
+/// What to do when work which allocates is asked for while the heap is low.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OomPolicy {
+    /// Go ahead anyway, and panic if the heap runs out. Useful during development, to find out
+    /// what's using the heap.
+    Panic,
+
+    /// Refuse the work until enough heap is free again.
+    #[default]
+    Recover,
+}
+
+/// Bytes of heap in use and free.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HeapUsage {
+    pub used: usize,
+    pub free: usize,
+}
+
+impl HeapUsage {
+    pub fn is_low(&self) -> bool {
+        self.free < HEAP_RESERVE_BYTES
+    }
+
+    /// True if work which allocates should go ahead under `policy`.
+    pub fn can_allocate(&self, policy: OomPolicy) -> bool {
+        match policy {
+            OomPolicy::Panic => true,
+            OomPolicy::Recover => !self.is_low(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heap_usage_should_be_low_below_reserve() {
+        assert!(!HeapUsage {
+            used: 0,
+            free: HEAP_RESERVE_BYTES
+        }
+        .is_low());
+        assert!(HeapUsage {
+            used: 0,
+            free: HEAP_RESERVE_BYTES - 1
+        }
+        .is_low());
+    }
+
+    #[test]
+    fn heap_usage_should_refuse_allocation_when_low_unless_policy_is_panic() {
+        let plenty = HeapUsage {
+            used: 1024,
+            free: 7 * 1024,
+        };
+        let low = HeapUsage {
+            used: 7 * 1024 + 512,
+            free: 512,
+        };
+        assert!(plenty.can_allocate(OomPolicy::Recover));
+        assert!(plenty.can_allocate(OomPolicy::Panic));
+        assert!(!low.can_allocate(OomPolicy::Recover));
+        assert!(low.can_allocate(OomPolicy::Panic));
+    }
+}
@@ -0,0 +1,255 @@
+//! Pages of the UI, and which param each encoder edits on each page.
+
+/// Number of encoders on the device, one for each param shown on a page.
+pub const ENCODER_COUNT: usize = 6;
+
+/// Index of the param edited by each physical encoder on a page, or `None` if the encoder doesn't
+/// edit a param on that page.
+pub type EncoderMap = [Option<usize>; ENCODER_COUNT];
+
+// encoders map directly to params, for pages where every encoder edits a param
+const ALL_PARAMS: EncoderMap = [Some(0), Some(1), Some(2), Some(3), Some(4), Some(5)];
+
+// the sequence page has 2 params, the 2nd encoder sets the current track's length in whole bars,
+// the 4th its MIDI program, the 5th its clock mod and the 6th its direction
+pub const LENGTH_IN_BARS_ENCODER_INDEX: usize = 1;
+pub const PROGRAM_ENCODER_INDEX: usize = 3;
+pub const CLOCK_MOD_ENCODER_INDEX: usize = 4;
+pub const DIRECTION_ENCODER_INDEX: usize = 5;
+
+// the harmony page has 3 params, the 4th encoder shifts the track up and down by octaves
+pub const OCTAVE_SHIFT_ENCODER_INDEX: usize = 3;
+
+// the clock page has no params, its encoders set the clock source, the tempo of the internal
+// clock, whether to send transport messages as clock master, start and stop playback, and which
+// track the current track is linked to
+pub const CLOCK_SOURCE_ENCODER_INDEX: usize = 0;
+pub const BPM_ENCODER_INDEX: usize = 1;
+pub const CLOCK_MASTER_ENCODER_INDEX: usize = 2;
+pub const PLAY_ENCODER_INDEX: usize = 3;
+pub const LINK_ENCODER_INDEX: usize = 4;
+
+// the machine browser has no params, the 1st encoder scrolls through machines
+pub const BROWSE_ENCODER_INDEX: usize = 0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum InputMode {
+    #[default]
+    Track,
+    Sequence,
+    Clock,
+    Rhythm,
+    Groove,
+    Feel,
+    Melody,
+    Harmony,
+    Browse,
+}
+
+/// Buttons which switch between pages of the UI.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PageButton {
+    Track,
+    Rhythm,
+    Melody,
+}
+
+/// Work out which page to show when a page button is pressed. Each button cycles through its
+/// pages. Pressing [RHYTHM] and [MELODY] together is an escape gesture which always
+/// returns to the Track page.
+pub fn next_input_mode(
+    input_mode: InputMode,
+    button: PageButton,
+    other_button_held: bool,
+) -> InputMode {
+    if other_button_held {
+        return InputMode::Track;
+    }
+    match (button, input_mode) {
+        (PageButton::Track, InputMode::Track) => InputMode::Sequence,
+        (PageButton::Track, InputMode::Sequence) => InputMode::Clock,
+        (PageButton::Track, _) => InputMode::Track,
+        (PageButton::Rhythm, InputMode::Rhythm) => InputMode::Groove,
+        (PageButton::Rhythm, InputMode::Groove) => InputMode::Feel,
+        (PageButton::Rhythm, _) => InputMode::Rhythm,
+        (PageButton::Melody, InputMode::Melody) => InputMode::Harmony,
+        (PageButton::Melody, _) => InputMode::Melody,
+    }
+}
+
+/// Which param each encoder edits on each page. Encoders without a param are either unused or
+/// trigger an action, e.g. shifting the track by an octave on the Harmony page. Machine pages map
+/// every encoder, machines with fewer params just ignore the extra encoders.
+pub fn encoder_map(input_mode: InputMode) -> EncoderMap {
+    match input_mode {
+        InputMode::Track => ALL_PARAMS,
+        // SWING, length in bars, REPEAT, then program, clock mod and direction
+        InputMode::Sequence => [Some(0), None, Some(1), None, None, None],
+        // clock source, BPM, clock master, play, then link
+        InputMode::Clock => [None, None, None, None, None, None],
+        InputMode::Rhythm => ALL_PARAMS,
+        InputMode::Groove => ALL_PARAMS,
+        InputMode::Feel => ALL_PARAMS,
+        InputMode::Melody => ALL_PARAMS,
+        // SCALE, KEY, CHORD, then octave shift
+        InputMode::Harmony => [Some(0), Some(1), Some(2), None, None, None],
+        // scroll through machines
+        InputMode::Browse => [None, None, None, None, None, None],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        param::ParamList, sequence_generator::SequenceGenerator, sequencer::Sequencer, Track,
+    };
+
+    /// Names of the params edited by each encoder, with `None` for encoders without a param.
+    fn encoder_param_names(
+        input_mode: InputMode,
+        params: &ParamList,
+    ) -> [Option<&str>; ENCODER_COUNT] {
+        encoder_map(input_mode).map(|maybe_index| maybe_index.map(|index| params[index].name()))
+    }
+
+    #[test]
+    fn encoder_map_should_target_track_params() {
+        let track = Track::default();
+        assert_eq!(
+            [
+                Some("RHYTHM"),
+                Some("LEN"),
+                Some("TRACK"),
+                Some("MELODY"),
+                Some("SPD"),
+                Some("CHAN")
+            ],
+            encoder_param_names(InputMode::Track, track.params())
+        );
+    }
+
+    #[test]
+    fn encoder_map_should_target_sequencer_params() {
+        let sequencer = Sequencer::default();
+        assert_eq!(
+            [Some("SWING"), None, Some("REPEAT"), None, None, None],
+            encoder_param_names(InputMode::Sequence, sequencer.params())
+        );
+    }
+
+    #[test]
+    fn encoder_map_should_target_groove_params() {
+        let generator = SequenceGenerator::default();
+        assert_eq!(
+            [
+                Some("PART"),
+                Some("VEL"),
+                Some("EVOLVE"),
+                Some("GROOVE"),
+                Some("PROB"),
+                Some("RATCH")
+            ],
+            encoder_param_names(InputMode::Groove, generator.groove_params())
+        );
+    }
+
+    #[test]
+    fn encoder_map_should_target_harmony_params() {
+        let generator = SequenceGenerator::default();
+        assert_eq!(
+            [Some("SCALE"), Some("KEY"), Some("CHORD"), None, None, None],
+            encoder_param_names(InputMode::Harmony, generator.harmony_params())
+        );
+    }
+
+    #[test]
+    fn encoder_map_should_leave_encoders_free_for_actions() {
+        assert_eq!(
+            None,
+            encoder_map(InputMode::Sequence)[LENGTH_IN_BARS_ENCODER_INDEX]
+        );
+        assert_eq!(
+            None,
+            encoder_map(InputMode::Sequence)[PROGRAM_ENCODER_INDEX]
+        );
+        assert_eq!(
+            None,
+            encoder_map(InputMode::Sequence)[CLOCK_MOD_ENCODER_INDEX]
+        );
+        assert_eq!(
+            None,
+            encoder_map(InputMode::Sequence)[DIRECTION_ENCODER_INDEX]
+        );
+        assert_eq!(
+            None,
+            encoder_map(InputMode::Harmony)[OCTAVE_SHIFT_ENCODER_INDEX]
+        );
+        for encoder_index in [
+            CLOCK_SOURCE_ENCODER_INDEX,
+            BPM_ENCODER_INDEX,
+            CLOCK_MASTER_ENCODER_INDEX,
+            PLAY_ENCODER_INDEX,
+            LINK_ENCODER_INDEX,
+        ] {
+            assert_eq!(None, encoder_map(InputMode::Clock)[encoder_index]);
+        }
+        assert_eq!(None, encoder_map(InputMode::Browse)[BROWSE_ENCODER_INDEX]);
+    }
+
+    #[test]
+    fn next_input_mode_should_cycle_through_pages_of_each_button() {
+        let pages: [(PageButton, &[InputMode]); 3] = [
+            (
+                PageButton::Track,
+                &[InputMode::Track, InputMode::Sequence, InputMode::Clock],
+            ),
+            (
+                PageButton::Rhythm,
+                &[InputMode::Rhythm, InputMode::Groove, InputMode::Feel],
+            ),
+            (PageButton::Melody, &[InputMode::Melody, InputMode::Harmony]),
+        ];
+        for (button, button_pages) in pages {
+            for (i, &page) in button_pages.iter().enumerate() {
+                let next_page = button_pages[(i + 1) % button_pages.len()];
+                assert_eq!(next_page, next_input_mode(page, button, false));
+            }
+            assert_eq!(
+                button_pages[0],
+                next_input_mode(InputMode::Browse, button, false)
+            );
+        }
+    }
+
+    #[test]
+    fn next_input_mode_should_escape_from_sequence_page_in_either_order() {
+        // note repeat mustn't take over the escape gesture on the sequence page
+        for button in [PageButton::Rhythm, PageButton::Melody] {
+            assert_eq!(
+                InputMode::Track,
+                next_input_mode(InputMode::Sequence, button, true)
+            );
+        }
+    }
+
+    #[test]
+    fn next_input_mode_should_escape_to_track_page_when_both_page_buttons_pressed() {
+        let all_input_modes = [
+            InputMode::Track,
+            InputMode::Sequence,
+            InputMode::Clock,
+            InputMode::Rhythm,
+            InputMode::Groove,
+            InputMode::Feel,
+            InputMode::Melody,
+            InputMode::Harmony,
+            InputMode::Browse,
+        ];
+        for input_mode in all_input_modes {
+            for button in [PageButton::Rhythm, PageButton::Melody] {
+                assert_eq!(InputMode::Track, next_input_mode(input_mode, button, true));
+            }
+        }
+    }
+}
@@ -1,28 +1,40 @@
 #![cfg_attr(not(test), no_std)]
 
+pub mod elapsed;
+pub mod heap;
+pub mod input_mode;
 pub mod machine;
+pub mod machine_browser;
 pub mod machine_resources;
 pub mod midi;
 pub mod param;
 pub mod part;
+pub mod prelude;
 pub mod quantizer;
+pub mod render_snapshot;
 pub mod sequence_generator;
 pub mod sequencer;
+pub mod song;
+pub mod storage;
+pub mod sysex;
 
 extern crate alloc;
 
-use midi::{Note, NoteError};
-use param::{Param, ParamError, ParamList};
+use machine_resources::{MachineResources, Rng};
+use midi::{Note, NoteBounds, NoteError};
+use param::{Param, ParamError, ParamList, ParamValue};
 use sequence_generator::SequenceGenerator;
 
 use alloc::boxed::Box;
 use core::{
     cmp::Ordering,
-    fmt::{Display, Formatter, Result as FmtResult},
+    fmt::{Display, Formatter, Result as FmtResult, Write},
+    ops::{Bound, RangeBounds},
     slice::{Iter, IterMut},
 };
+use fugit::MicrosDurationU64;
 use heapless::Vec;
-use midi_types::{Channel, Value14, Value7};
+use midi_types::{Channel, Control, Value14, Value7};
 
 pub const TRACK_COUNT: usize = 8;
 
@@ -32,10 +44,45 @@ const TRACK_DEFAULT_LENGTH: u8 = 8; // because techno
 
 const SEQUENCE_MAX_STEPS: usize = TRACK_MAX_LENGTH as usize;
 
+const TICKS_PER_BAR_24PPQN: u8 = 96; // 4/4 time
+const TICKS_PER_BEAT_24PPQN: u8 = 24;
+
 const TRACK_MIN_NUM: u8 = 1;
 
+// 32-bit FNV-1a, used for sequence checksums
+const FNV_OFFSET_BASIS: u32 = 0x811c9dc5;
+const FNV_PRIME: u32 = 0x01000193;
+
 const MIDI_MIN_CHANNEL: u8 = 1;
 const MIDI_MAX_CHANNEL: u8 = 16;
+const MIDI_MAX_PROGRAM: u8 = 127;
+
+pub const MORPH_MAX: u8 = 100;
+pub const MORPH_PATTERN_COUNT: usize = 2;
+
+// multiplier coprime with `MORPH_MAX`, so steps switch patterns at distinct, scattered amounts
+const MORPH_STEP_SPREAD: usize = 61;
+
+pub const VIBRATO_MAX_DEPTH: u8 = 100;
+
+// the sequencer only updates pitch bend every other tick, so faster vibrato can't be followed
+const VIBRATO_MIN_CYCLE_TICKS: u32 = 6;
+
+const PITCH_BEND_MAX: i32 = 8191;
+
+pub const LFO_MAX_DEPTH: u8 = 100;
+
+// CC value at the centre of an LFO's sweep, and the furthest it moves either side
+const LFO_CENTRE: i32 = 64;
+const LFO_PEAK: i32 = 63;
+
+/// Number of random values a sample and hold LFO steps through, one per cycle, before repeating.
+const LFO_SAMPLE_COUNT: usize = 16;
+
+pub const STEP_PROBABILITY_MAX: u8 = 100;
+pub const STEP_RATCHET_MAX: u8 = 8;
+
+pub const CLOCK_MOD_MAX: u8 = 8;
 
 pub fn map_to_range(x: i32, in_min: i32, in_max: i32, out_min: i32, out_max: i32) -> i32 {
     (x - in_min) * (out_max - out_min + 1) / (in_max - in_min + 1) + out_min
@@ -53,9 +100,69 @@ pub struct Step {
     pub length_step_cents: u8,
 
     /// Delay playing this step for % of track time division. Used for swing. Can be abused
-    /// for general timing madness. Note that its not possible to play a step early. This
-    /// is because Microgroove depends on an external clock.
-    pub delay: u8,
+    /// for general timing madness. Negative values play the step early, but only by up to one
+    /// clock tick, and only when the sequencer looks ahead. This is because Microgroove depends
+    /// on an external clock.
+    pub delay: i8,
+
+    /// Muted steps keep their note data but aren't played. Unlike a rest, this doesn't change the
+    /// rhythm of the sequence.
+    pub muted: bool,
+
+    /// Chance of the step playing each time round the sequence, as %. Steps with less than
+    /// `STEP_PROBABILITY_MAX` are left out at random, so patterns vary as they loop.
+    pub probability: u8,
+
+    /// Number of times the note is retriggered, evenly spread across the step, for rolls. 1 plays
+    /// the step normally, up to `STEP_RATCHET_MAX`.
+    pub ratchet: u8,
+
+    /// Values locked on this step, which are played in place of the generated ones.
+    pub lock: StepLock,
+}
+
+/// A field of a step which can be locked to its own value, like an Elektron parameter lock.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StepLockField {
+    Velocity,
+    Length,
+    PitchBend,
+    Delay,
+}
+
+/// Values locked on a step. Fields which are `None` aren't locked, and play the value generated
+/// for the step. Locks are kept when the sequence is regenerated, see `Sequence::carry_locks`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StepLock {
+    pub velocity: Option<Value7>,
+    pub length_step_cents: Option<u8>,
+    pub pitch_bend: Option<Value14>,
+    pub delay: Option<i8>,
+}
+
+impl StepLock {
+    pub fn is_empty(&self) -> bool {
+        *self == StepLock::default()
+    }
+
+    /// Lock `field` to `value`, or unlock it if `value` is `None`. Values are clamped to the
+    /// range of the field.
+    pub fn set(&mut self, field: StepLockField, value: Option<i16>) {
+        match field {
+            StepLockField::Velocity => {
+                self.velocity = value.map(|value| (value.clamp(0, 127) as u8).into())
+            }
+            StepLockField::Length => {
+                self.length_step_cents = value.map(|value| value.clamp(0, u8::MAX as i16) as u8)
+            }
+            StepLockField::PitchBend => {
+                self.pitch_bend = value.map(|value| (value.clamp(0, 0x3fff) as u16).into())
+            }
+            StepLockField::Delay => {
+                self.delay = value.map(|value| value.clamp(i8::MIN as i16, i8::MAX as i16) as i8)
+            }
+        }
+    }
 }
 
 impl Step {
@@ -66,8 +173,76 @@ impl Step {
             pitch_bend: 0u16.into(),
             length_step_cents: 80,
             delay: 0,
+            muted: false,
+            probability: STEP_PROBABILITY_MAX,
+            ratchet: 1,
+            lock: StepLock::default(),
         })
     }
+
+    /// This step as it's played, with its locked values in place of the generated ones.
+    pub fn with_locks(&self) -> Step {
+        Step {
+            velocity: self.lock.velocity.unwrap_or(self.velocity),
+            length_step_cents: self
+                .lock
+                .length_step_cents
+                .unwrap_or(self.length_step_cents),
+            pitch_bend: self.lock.pitch_bend.unwrap_or(self.pitch_bend),
+            delay: self.lock.delay.unwrap_or(self.delay),
+            ..self.clone()
+        }
+    }
+
+    /// All notes sounded by this step. Steps are currently monophonic, so this is always a
+    /// single note.
+    pub fn notes(&self) -> &[Note] {
+        core::slice::from_ref(&self.note)
+    }
+
+    /// The note used to represent this step when it sounds more than one note, e.g. on the
+    /// display.
+    pub fn representative_note(&self, priority: NotePriority) -> Note {
+        priority
+            .select(self.notes())
+            .expect("step should have at least one note")
+    }
+
+    /// How far to shift this step to the right when drawing a sequence whose steps are `width`
+    /// pixels apart, in proportion to its delay. Early steps shift left. Never reaches the next
+    /// or previous step.
+    pub fn delay_offset(&self, width: i32) -> i32 {
+        render_snapshot::delay_offset(self.delay, width)
+    }
+
+    /// Length of the line drawn on from the end of this step to show that its gate sustains over
+    /// the following steps, when drawing a sequence whose steps are `width` pixels apart. 0 for
+    /// gates of one step or less.
+    pub fn sustain_line_length(&self, width: i32) -> i32 {
+        render_snapshot::sustain_line_length(self.length_step_cents, width)
+    }
+}
+
+/// Policy for choosing a single note from a group of notes which sound together.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum NotePriority {
+    /// The first note of the group.
+    #[default]
+    Root,
+    Lowest,
+    Highest,
+}
+
+impl NotePriority {
+    pub fn select(&self, notes: &[Note]) -> Option<Note> {
+        let note_num = |note: &&Note| -> u8 { (**note).into() };
+        match self {
+            NotePriority::Root => notes.first(),
+            NotePriority::Lowest => notes.iter().min_by_key(note_num),
+            NotePriority::Highest => notes.iter().max_by_key(note_num),
+        }
+        .copied()
+    }
 }
 
 impl PartialEq for Step {
@@ -102,6 +277,10 @@ pub enum TimeDivision {
     Eigth,
     Quarter,
     Whole,
+
+    /// Not a rhythmic division: the track holds a single note for as long as it's unchanged, for
+    /// pads and drones. Counts as whole notes wherever a step length is needed.
+    Free,
 }
 
 impl TimeDivision {
@@ -113,6 +292,7 @@ impl TimeDivision {
             "1/8" => TimeDivision::Eigth,
             "1/4" => TimeDivision::Quarter,
             "1" => TimeDivision::Whole,
+            "FREE" => TimeDivision::Free,
             _ => TimeDivision::Sixteenth,
         }
     }
@@ -124,8 +304,29 @@ impl TimeDivision {
             TimeDivision::Eigth => 12,
             TimeDivision::Quarter => 24,
             TimeDivision::Whole => 96,
+            TimeDivision::Free => 96,
         }
     }
+
+    /// Number of steps in one bar of 4/4 at this time division.
+    pub fn steps_per_bar(time_div: TimeDivision) -> u8 {
+        TICKS_PER_BAR_24PPQN / TimeDivision::division_length_24ppqn(time_div)
+    }
+
+    /// Number of steps in one beat at this time division. Divisions longer than a beat count as
+    /// one step per beat.
+    pub fn steps_per_beat(time_div: TimeDivision) -> u8 {
+        (TICKS_PER_BEAT_24PPQN / TimeDivision::division_length_24ppqn(time_div)).max(1)
+    }
+
+    pub fn bars_to_steps(time_div: TimeDivision, bars: u8) -> u16 {
+        bars as u16 * TimeDivision::steps_per_bar(time_div) as u16
+    }
+
+    /// Number of whole bars covered by `steps`. Any partial bar is ignored.
+    pub fn steps_to_bars(time_div: TimeDivision, steps: u8) -> u8 {
+        steps / TimeDivision::steps_per_bar(time_div)
+    }
 }
 
 impl Display for TimeDivision {
@@ -140,6 +341,7 @@ impl Display for TimeDivision {
                 TimeDivision::Eigth => "1/8",
                 TimeDivision::Quarter => "1/4",
                 TimeDivision::Whole => "1",
+                TimeDivision::Free => "FREE",
             }
         )
     }
@@ -155,6 +357,7 @@ impl TryFrom<u8> for TimeDivision {
             2 => Ok(TimeDivision::Eigth),
             3 => Ok(TimeDivision::Quarter),
             4 => Ok(TimeDivision::Whole),
+            5 => Ok(TimeDivision::Free),
             _ => Err(()),
         }
     }
@@ -175,6 +378,9 @@ impl Sequence {
     pub fn len(&self) -> usize {
         self.steps.len()
     }
+
+    /// Iterate over the steps of the sequence. Steps are always yielded in playback order, from
+    /// the first step to the last, including rests.
     pub fn iter(&self) -> Iter<Option<Step>> {
         self.steps.iter()
     }
@@ -185,11 +391,75 @@ impl Sequence {
         self.steps.as_slice()
     }
 
+    /// Iterate over the active steps of the sequence, skipping rests. Yields the index of each
+    /// step in the sequence alongside the step.
+    pub fn iter_active(&self) -> impl Iterator<Item = (usize, &Step)> {
+        self.steps
+            .iter()
+            .enumerate()
+            .filter_map(|(i, step)| step.as_ref().map(|step| (i, step)))
+    }
+
+    /// Number of active (non-rest) steps in the sequence.
+    pub fn active_count(&self) -> usize {
+        self.steps.iter().filter(|step| step.is_some()).count()
+    }
+
+    /// Compare every field of every step with `steps`, in order. `PartialEq` for `Sequence` only
+    /// compares notes, this is stricter and makes machine tests much less verbose.
+    #[cfg(test)]
+    pub fn steps_eq(&self, steps: &[Option<Step>]) -> bool {
+        self.len() == steps.len()
+            && self.iter().zip(steps).all(|(a, b)| match (a, b) {
+                (Some(a), Some(b)) => {
+                    a.note == b.note
+                        && a.velocity == b.velocity
+                        && a.pitch_bend == b.pitch_bend
+                        && a.length_step_cents == b.length_step_cents
+                        && a.delay == b.delay
+                        && a.muted == b.muted
+                        && a.probability == b.probability
+                        && a.ratchet == b.ratchet
+                        && a.lock == b.lock
+                }
+                (None, None) => true,
+                _ => false,
+            })
+    }
+
     pub fn set_steps(mut self, steps: Vec<Option<Step>, SEQUENCE_MAX_STEPS>) -> Self {
         self.steps = steps;
         self
     }
 
+    pub fn reverse(self) -> Sequence {
+        let len = self.len();
+        self.reverse_range(0..len)
+    }
+
+    /// Reverse the order of a region of steps, leaving steps outside the region untouched. Rests
+    /// move along with notes. Accepts inclusive or exclusive ranges, and any part of the range
+    /// beyond the end of the sequence is ignored.
+    pub fn reverse_range(mut self, range: impl RangeBounds<usize>) -> Sequence {
+        let len = self.len();
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start + 1,
+            Bound::Unbounded => 0,
+        }
+        .min(len);
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end + 1,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => len,
+        }
+        .min(len);
+        if start < end {
+            self.steps[start..end].reverse();
+        }
+        self
+    }
+
     pub fn rotate_left(mut self, amount: usize) -> Sequence {
         self.steps.rotate_left(amount);
         self
@@ -200,6 +470,16 @@ impl Sequence {
         self
     }
 
+    /// Change the number of steps in the sequence. Shortening drops steps from the end, while
+    /// lengthening repeats the sequence from the start to fill the new steps.
+    pub fn resize(self, length: usize) -> Sequence {
+        let length = length.min(SEQUENCE_MAX_STEPS);
+        if self.steps.is_empty() {
+            return (0..length).map(|_| None).collect();
+        }
+        self.steps.iter().cycle().take(length).cloned().collect()
+    }
+
     pub fn map_notes(mut self, mut f: impl FnMut(Note) -> Note) -> Self {
         for step in self.steps.iter_mut() {
             if let Some(step) = step {
@@ -209,6 +489,27 @@ impl Sequence {
         self
     }
 
+    /// Transpose every note by a number of semitones. Notes which would fall outside the MIDI
+    /// note range are brought back into range according to `bounds`.
+    pub fn transpose(self, semitones: i32, bounds: NoteBounds) -> Self {
+        self.map_notes(|note| {
+            let note_num: u8 = note.into();
+            bounds.apply(note_num as i32 + semitones)
+        })
+    }
+
+    /// Shift every note up or down by a number of octaves.
+    pub fn shift_octaves(self, octaves: i8, bounds: NoteBounds) -> Self {
+        self.transpose(octaves as i32 * 12, bounds)
+    }
+
+    pub fn map_velocities(mut self, mut f: impl FnMut(Value7) -> Value7) -> Self {
+        for step in self.steps.iter_mut().flatten() {
+            step.velocity = f(step.velocity);
+        }
+        self
+    }
+
     pub fn set_notes<I>(mut self, notes: I) -> Self
     where
         I: IntoIterator<Item = Note>,
@@ -223,6 +524,164 @@ impl Sequence {
         self
     }
 
+    /// Set the velocity of each step from `velocities` in order. Rests consume a velocity too, so
+    /// a repeating pattern stays aligned to the step grid.
+    pub fn set_velocities<I>(mut self, velocities: I) -> Self
+    where
+        I: IntoIterator<Item = Value7>,
+    {
+        for (step, velocity) in self.steps.iter_mut().zip(velocities) {
+            if let Some(step) = step {
+                step.velocity = velocity;
+            }
+        }
+        self
+    }
+
+    /// Mute or unmute a step. Rests can't be muted, so this has no effect on them.
+    pub fn set_step_muted(&mut self, step_num: usize, muted: bool) {
+        if let Some(Some(step)) = self.steps.get_mut(step_num) {
+            step.muted = muted;
+        }
+    }
+
+    pub fn toggle_step_muted(&mut self, step_num: usize) {
+        if let Some(Some(step)) = self.steps.get_mut(step_num) {
+            step.muted = !step.muted;
+        }
+    }
+
+    /// Lock `field` of a step to `value`, or unlock it if `value` is `None`. Rests can't be
+    /// locked, so this has no effect on them.
+    pub fn set_step_lock(&mut self, step_num: usize, field: StepLockField, value: Option<i16>) {
+        if let Some(Some(step)) = self.steps.get_mut(step_num) {
+            step.lock.set(field, value);
+        }
+    }
+
+    /// Copy the locks of the steps of `from` onto the same steps of this sequence, e.g. after
+    /// regenerating it, so that locks aren't lost when the sequence changes. Locks on steps which
+    /// are now rests are dropped.
+    pub fn carry_locks(mut self, from: &Sequence) -> Sequence {
+        for (step, from_step) in self.steps.iter_mut().zip(from.iter()) {
+            if let (Some(step), Some(from_step)) = (step, from_step) {
+                step.lock = from_step.lock;
+            }
+        }
+        self
+    }
+
+    /// Make a step play. Muted steps are unmuted. Rests are filled with a step playing the same
+    /// note as the first active step, or middle C if there isn't one.
+    pub fn activate_step(&mut self, step_num: usize) {
+        let template = self.iter_active().map(|(_, step)| step.clone()).next();
+        match self.steps.get_mut(step_num) {
+            Some(Some(step)) => step.muted = false,
+            Some(rest) => {
+                *rest = template.or_else(|| Step::new(60).ok()).map(|mut step| {
+                    step.muted = false;
+                    step
+                })
+            }
+            None => (),
+        }
+    }
+
+    /// Blend between two sequences, from `a` when `amount` is 0 to `b` when it's `MORPH_MAX`.
+    /// Each step switches from `a` to `b` at its own amount, so the rhythm crossfades a step at a
+    /// time. Steps active in both sequences have their note and velocity interpolated. The result
+    /// has the length of `a`.
+    pub fn morph(a: &Sequence, b: &Sequence, amount: u8) -> Sequence {
+        let amount = amount.min(MORPH_MAX);
+        let lerp = |from: u8, to: u8| -> u8 {
+            (from as i32 + (to as i32 - from as i32) * amount as i32 / MORPH_MAX as i32) as u8
+        };
+        let b = b.clone().resize(a.len());
+        a.iter()
+            .zip(b.iter())
+            .enumerate()
+            .map(|(i, (step_a, step_b))| {
+                let switch_amount = (i * MORPH_STEP_SPREAD % MORPH_MAX as usize) as u8;
+                let mut step = if amount > switch_amount {
+                    step_b.clone()
+                } else {
+                    step_a.clone()
+                };
+                if let (Some(step), Some(step_a), Some(step_b)) = (&mut step, step_a, step_b) {
+                    step.note = lerp(step_a.note.into(), step_b.note.into())
+                        .try_into()
+                        .expect("note between two notes should be valid");
+                    step.velocity = lerp(step_a.velocity.into(), step_b.velocity.into()).into();
+                }
+                step
+            })
+            .collect()
+    }
+
+    /// Write the notes of the sequence as comma separated MIDI note numbers, to export a pattern
+    /// to other tools. Rests have no note, so they're written as `rest`, e.g. "--" or "-1".
+    pub fn write_notes(&self, out: &mut impl Write, rest: &str) -> FmtResult {
+        for (i, step) in self.steps.iter().enumerate() {
+            if i > 0 {
+                out.write_char(',')?;
+            }
+            match step {
+                Some(step) => {
+                    let note_num: u8 = step.note.into();
+                    write!(out, "{}", note_num)?;
+                }
+                None => out.write_str(rest)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Fast hash of every field of every step, so that changes to a sequence can be detected
+    /// cheaply, e.g. to decide whether to redraw the display. Not suitable for cryptographic use.
+    pub fn checksum(&self) -> u32 {
+        let mut hash = FNV_OFFSET_BASIS;
+        let mut add_byte = |byte: u8| {
+            hash ^= byte as u32;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        };
+        for step in self.steps.iter() {
+            match step {
+                Some(step) => {
+                    let pitch_bend: u16 = step.pitch_bend.into();
+                    add_byte(1);
+                    add_byte(step.note.into());
+                    add_byte(step.velocity.into());
+                    add_byte((pitch_bend >> 8) as u8);
+                    add_byte(pitch_bend as u8);
+                    add_byte(step.length_step_cents);
+                    add_byte(step.delay as u8);
+                    add_byte(step.muted as u8);
+                    add_byte(step.probability);
+                    add_byte(step.ratchet);
+                    let lock = step.lock;
+                    add_byte(lock.velocity.map_or(0xff, u8::from));
+                    add_byte(
+                        lock.length_step_cents
+                            .map_or(0, |length| length.wrapping_add(1)),
+                    );
+                    let pitch_bend = lock.pitch_bend.map_or(0xffff, u16::from);
+                    add_byte((pitch_bend >> 8) as u8);
+                    add_byte(pitch_bend as u8);
+                    add_byte(lock.delay.is_some() as u8);
+                    add_byte(lock.delay.unwrap_or(0) as u8);
+                }
+                None => add_byte(0),
+            }
+        }
+        hash
+    }
+
+    /// The rhythm of the sequence, as a mask which is true for active steps and false for rests.
+    /// This is the inverse of `mask_steps`.
+    pub fn to_mask(&self) -> Vec<bool, SEQUENCE_MAX_STEPS> {
+        self.steps.iter().map(|step| step.is_some()).collect()
+    }
+
     pub fn mask_steps<I>(mut self, step_mask: I) -> Self
     where
         I: IntoIterator<Item = bool>,
@@ -252,6 +711,237 @@ impl FromIterator<Option<Step>> for Sequence {
     }
 }
 
+/// Pitch bend LFO applied to the notes of a track while they sound, synced to the clock.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vibrato {
+    /// How far the pitch is bent either side of centre, as a percentage of the full bend range.
+    /// Vibrato is off at 0.
+    pub depth: u8,
+
+    /// Length of one cycle. Rates faster than 1/16 are played at 1/16.
+    pub rate: TimeDivision,
+}
+
+impl Vibrato {
+    pub fn enabled(&self) -> bool {
+        self.depth > 0
+    }
+
+    pub fn cycle_ticks(&self) -> u32 {
+        (TimeDivision::division_length_24ppqn(self.rate) as u32).max(VIBRATO_MIN_CYCLE_TICKS)
+    }
+
+    /// Pitch bend at `tick`, from -8191 to 8191. A triangle wave which starts at centre, peaks
+    /// a quarter of the way through each cycle and bottoms out three quarters of the way through.
+    pub fn pitch_bend(&self, tick: u32) -> i16 {
+        let peak =
+            PITCH_BEND_MAX * self.depth.min(VIBRATO_MAX_DEPTH) as i32 / VIBRATO_MAX_DEPTH as i32;
+        triangle_wave(tick, self.cycle_ticks(), peak) as i16
+    }
+}
+
+/// Triangle wave at `tick`, from `-peak` to `peak`, which starts at 0, peaks a quarter of the way
+/// through each cycle and bottoms out three quarters of the way through.
+fn triangle_wave(tick: u32, cycle_ticks: u32, peak: i32) -> i32 {
+    let cycle_ticks = cycle_ticks as i32;
+    let phase = (tick % cycle_ticks as u32) as i32 * 4;
+    if phase < cycle_ticks {
+        peak * phase / cycle_ticks
+    } else if phase < cycle_ticks * 3 {
+        peak * (cycle_ticks * 2 - phase) / cycle_ticks
+    } else {
+        peak * (phase - cycle_ticks * 4) / cycle_ticks
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LfoShape {
+    #[default]
+    Triangle,
+
+    /// Rises from the bottom of the sweep to the top over each cycle.
+    Ramp,
+
+    /// Top of the sweep for the first half of each cycle, bottom for the second.
+    Square,
+
+    /// Jumps to a new random level at the start of each cycle.
+    SampleAndHold,
+}
+
+/// MIDI CC LFO sent by a track, synced to the clock. Sweeps the CC either side of the centre of
+/// its range.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lfo {
+    pub shape: LfoShape,
+
+    /// Length of one cycle.
+    pub rate: TimeDivision,
+
+    /// How far the CC sweeps either side of centre, as a percentage of half its range. The LFO
+    /// is off at 0.
+    pub depth: u8,
+
+    pub control: Control,
+    pub midi_channel: Channel,
+
+    /// Random levels for `LfoShape::SampleAndHold`, from 0 to 255, see `Lfo::reroll_samples`.
+    samples: Vec<u8, LFO_SAMPLE_COUNT>,
+}
+
+impl Default for Lfo {
+    fn default() -> Lfo {
+        Lfo {
+            shape: Default::default(),
+            rate: TimeDivision::Whole,
+            depth: 0,
+            // mod wheel
+            control: 1.into(),
+            midi_channel: 0.into(),
+            samples: Vec::new(),
+        }
+    }
+}
+
+impl Lfo {
+    pub fn enabled(&self) -> bool {
+        self.depth > 0
+    }
+
+    pub fn cycle_ticks(&self) -> u32 {
+        TimeDivision::division_length_24ppqn(self.rate) as u32
+    }
+
+    /// CC value at `tick`, from 0 to 127. Sample and hold stays at centre until it has been given
+    /// random levels by `Lfo::reroll_samples`.
+    pub fn value(&self, tick: u32) -> u8 {
+        let cycle_ticks = self.cycle_ticks();
+        let phase = (tick % cycle_ticks) as i32;
+        let peak = LFO_PEAK * self.depth.min(LFO_MAX_DEPTH) as i32 / LFO_MAX_DEPTH as i32;
+        let level = match self.shape {
+            LfoShape::Triangle => triangle_wave(tick, cycle_ticks, peak),
+            LfoShape::Ramp => peak * (phase * 2 - cycle_ticks as i32) / cycle_ticks as i32,
+            LfoShape::Square if phase * 2 < cycle_ticks as i32 => peak,
+            LfoShape::Square => -peak,
+            LfoShape::SampleAndHold => {
+                let cycle_num = (tick / cycle_ticks) as usize;
+                self.samples
+                    .get(cycle_num % LFO_SAMPLE_COUNT)
+                    .map_or(0, |&sample| peak * (sample as i32 * 2 - 255) / 255)
+            }
+        };
+        (LFO_CENTRE + level) as u8
+    }
+
+    /// Choose new random levels for `LfoShape::SampleAndHold`.
+    pub fn reroll_samples(&mut self, machine_resources: &mut MachineResources) {
+        self.samples = (0..LFO_SAMPLE_COUNT)
+            .map(|_| machine_resources.random_u64() as u8)
+            .collect();
+    }
+}
+
+/// Run a track slower or faster than the sequencer clock, for polymeter. Applied on top of the
+/// track's time division, e.g. `Mult(4)` with `Div(3)` on another track plays 4 steps for every 3.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClockMod {
+    /// Advance one step for every `n` steps of the time division.
+    Div(u8),
+
+    /// Advance `n` steps for every step of the time division.
+    Mult(u8),
+}
+
+impl Default for ClockMod {
+    fn default() -> ClockMod {
+        ClockMod::Mult(1)
+    }
+}
+
+impl ClockMod {
+    /// Steps advanced per step of the time division, as multiplier and divisor. Factors are
+    /// clamped to 1..=`CLOCK_MOD_MAX`.
+    fn ratio(&self) -> (u32, u32) {
+        match *self {
+            ClockMod::Div(n) => (1, n.clamp(1, CLOCK_MOD_MAX) as u32),
+            ClockMod::Mult(n) => (n.clamp(1, CLOCK_MOD_MAX) as u32, 1),
+        }
+    }
+
+    /// Scale the length of a step of the time division to the length of a step of the track.
+    pub fn scale_step_duration(&self, step_duration: MicrosDurationU64) -> MicrosDurationU64 {
+        let (mult, div) = self.ratio();
+        step_duration * div / mult
+    }
+
+    /// Move `n` places along the range of clock mods, from `Div(CLOCK_MOD_MAX)` through
+    /// `Mult(1)` to `Mult(CLOCK_MOD_MAX)`, stopping at either end.
+    pub fn increment(&self, n: i8) -> ClockMod {
+        let max = CLOCK_MOD_MAX as i16 - 1;
+        let position = match *self {
+            ClockMod::Div(n) => 1 - n.clamp(1, CLOCK_MOD_MAX) as i16,
+            ClockMod::Mult(n) => n.clamp(1, CLOCK_MOD_MAX) as i16 - 1,
+        };
+        match (position + n as i16).clamp(-max, max) {
+            position if position < 0 => ClockMod::Div((1 - position) as u8),
+            position => ClockMod::Mult((position + 1) as u8),
+        }
+    }
+}
+
+impl Display for ClockMod {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            ClockMod::Div(n) => write!(f, "/{}", n),
+            ClockMod::Mult(n) => write!(f, "x{}", n),
+        }
+    }
+}
+
+/// Order in which a track plays its steps.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Direction {
+    #[default]
+    Forward,
+    Reverse,
+
+    /// Forward then back again, without repeating the first and last steps.
+    PingPong,
+
+    /// Each step of a loop is chosen at random. A new order is chosen every loop, see
+    /// `Track::reroll_random_steps`.
+    Random,
+}
+
+const DIRECTIONS: [Direction; 4] = [
+    Direction::Forward,
+    Direction::Reverse,
+    Direction::PingPong,
+    Direction::Random,
+];
+
+impl Direction {
+    /// Move `n` places through the directions, wrapping around at either end.
+    pub fn increment(&self, n: i8) -> Direction {
+        let index = param::wrapping_add(*self as i32, n as i32, DIRECTIONS.len() as i32 - 1);
+        DIRECTIONS[index as usize]
+    }
+}
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        Display::fmt(
+            match self {
+                Direction::Forward => "FWD",
+                Direction::Reverse => "REV",
+                Direction::PingPong => "PING",
+                Direction::Random => "RAND",
+            },
+            f,
+        )
+    }
+}
+
 #[derive(Debug)]
 pub struct Track {
     pub time_division: TimeDivision,
@@ -259,6 +949,37 @@ pub struct Track {
     pub midi_channel: Channel,
     pub sequence: Sequence,
     pub params: ParamList,
+
+    /// Tracks in the same mute group choke each other: a new note releases any notes still
+    /// sounding from the group, like open and closed hi-hats on a drum machine.
+    pub mute_group: Option<u8>,
+
+    /// Sequences stored to morph between, see `Track::morph`.
+    pub patterns: [Option<Sequence>; MORPH_PATTERN_COUNT],
+
+    pub vibrato: Vibrato,
+
+    pub lfo: Lfo,
+
+    /// MIDI program the track's synth is switched to, sent whenever it changes and when playback
+    /// starts. No program change is sent with `None`.
+    pub program: Option<u8>,
+
+    /// When the length changes, keep the existing steps, truncating or repeating them with
+    /// `Sequence::resize`, rather than leaving the sequence to be regenerated. Protects edits
+    /// made to the sequence by hand.
+    pub keep_steps_on_resize: bool,
+
+    pub clock_mod: ClockMod,
+
+    /// Muted tracks keep time with the rest of the sequencer but don't play, so they come back
+    /// in sync when unmuted.
+    pub muted: bool,
+
+    pub direction: Direction,
+
+    /// Steps played at each position of the loop with `Direction::Random`.
+    random_steps: Vec<u8, SEQUENCE_MAX_STEPS>,
 }
 
 impl Default for Track {
@@ -272,6 +993,16 @@ impl Default for Track {
             midi_channel: 0.into(),
             sequence,
             params,
+            mute_group: None,
+            patterns: Default::default(),
+            vibrato: Default::default(),
+            lfo: Default::default(),
+            program: None,
+            keep_steps_on_resize: false,
+            clock_mod: Default::default(),
+            muted: false,
+            direction: Default::default(),
+            random_steps: Vec::new(),
         }
     }
 }
@@ -316,20 +1047,194 @@ impl Track {
         // params 0 (rhythm machine), 2 (track number) and 3 (melody machine) are intentionally ignored
         // they are "virtual parameters" which don't actually relate to a `Track` at all. They're
         // handled by microgroove_app::input::map_encoder_values directly.
-        self.length = self.params[1].value().try_into()?;
+        self.set_length(self.params[1].value().try_into()?);
         self.time_division = self.params[4].value().try_into()?;
         let channel_num: u8 = self.params[5].value().try_into()?;
         self.midi_channel = channel_num.into();
         Ok(())
     }
 
+    /// Set the length of the track in steps, clamped to the range of valid track lengths.
+    pub fn set_length(&mut self, length: u8) {
+        let length = length.clamp(TRACK_MIN_LENGTH, TRACK_MAX_LENGTH);
+        if self.keep_steps_on_resize && length != self.length {
+            self.sequence = self.sequence.clone().resize(length as usize);
+        }
+        self.length = length;
+        self.params[1]
+            .set(ParamValue::Number(length))
+            .expect("should set track length param");
+    }
+
+    /// Length of the track in whole bars, ignoring any partial bar.
+    pub fn length_in_bars(&self) -> u8 {
+        TimeDivision::steps_to_bars(self.time_division, self.length)
+    }
+
+    /// Set the length of the track to a number of bars at the current time division. The
+    /// resulting length is clamped to the range of valid track lengths.
+    pub fn set_length_in_bars(&mut self, bars: u8) {
+        let steps = TimeDivision::bars_to_steps(self.time_division, bars)
+            .clamp(TRACK_MIN_LENGTH as u16, TRACK_MAX_LENGTH as u16);
+        self.set_length(steps as u8);
+    }
+
+    /// Move the length of the track up or down to the next whole beat at the current time
+    /// division, for quickly setting long patterns. Moving down stops at one beat long.
+    pub fn increment_length_in_beats(&mut self, n: i8) {
+        let steps_per_beat = TimeDivision::steps_per_beat(self.time_division);
+        let whole_beats = self.length / steps_per_beat;
+        let new_length = match n.signum() {
+            1 => (whole_beats + 1) * steps_per_beat,
+            -1 if whole_beats * steps_per_beat == self.length => {
+                whole_beats.saturating_sub(1) * steps_per_beat
+            }
+            -1 => whole_beats * steps_per_beat,
+            _ => self.length,
+        };
+        let min_length = steps_per_beat.min(self.length);
+        self.set_length(new_length.clamp(min_length, TRACK_MAX_LENGTH));
+    }
+
+    /// Move the program up or down by `n`, from off through 0 to 127.
+    pub fn increment_program(&mut self, n: i8) {
+        let program = self.program.map_or(-1, |program| program as i16);
+        let new_program = (program + n as i16).clamp(-1, MIDI_MAX_PROGRAM as i16);
+        self.program = u8::try_from(new_program).ok();
+    }
+
+    pub fn set_time_division(&mut self, time_division: TimeDivision) {
+        self.time_division = time_division;
+        self.params[4]
+            .set(ParamValue::TimeDivision(time_division))
+            .expect("should set track time division param");
+    }
+
+    /// Ticks are scaled by the track's clock mod before the time division is applied, as a
+    /// multiplier and divisor so that steps of multiplied tracks needn't land on whole ticks.
+    fn scaled_tick(&self, tick: u32) -> (u64, u64) {
+        let (mult, div) = self.clock_mod.ratio();
+        let division_length = TimeDivision::division_length_24ppqn(self.time_division) as u64;
+        (tick as u64 * mult as u64, division_length * div as u64)
+    }
+
+    /// Number of steps started by `tick`, counting the step starting at tick 0.
+    fn steps_started(&self, tick: u32) -> u64 {
+        let (scaled_tick, step_ticks) = self.scaled_tick(tick);
+        scaled_tick / step_ticks
+    }
+
+    /// True if a step starts on `tick`. When a multiplied track starts a step between two ticks
+    /// the step is played on the next tick. If it starts more than one step between ticks, only
+    /// the last is played.
     pub fn should_play_on_tick(&self, tick: u32) -> bool {
-        tick % (TimeDivision::division_length_24ppqn(self.time_division) as u32) == 0
+        tick == 0 || self.steps_started(tick) != self.steps_started(tick - 1)
     }
 
+    /// Number of the step playing at `tick`, following the track's direction.
     pub fn step_num(&self, tick: u32) -> u8 {
-        (tick / (TimeDivision::division_length_24ppqn(self.time_division) as u32)
-            % self.length as u32) as u8
+        self.step_num_at_position(self.steps_started(tick))
+    }
+
+    /// True if the track starts a new loop at `tick`, whichever direction it plays in.
+    pub fn starts_loop(&self, tick: u32) -> bool {
+        self.should_play_on_tick(tick)
+            && self.steps_started(tick).is_multiple_of(self.length as u64)
+    }
+
+    /// Number of the step nearest to `tick`, rounding to whichever step starts closest. Used to
+    /// quantize taps which land between steps.
+    pub fn nearest_step_num(&self, tick: u32) -> u8 {
+        let (scaled_tick, step_ticks) = self.scaled_tick(tick);
+        self.step_num_at_position((scaled_tick + step_ticks / 2) / step_ticks)
+    }
+
+    /// Number of the step played after `position` steps have started.
+    fn step_num_at_position(&self, position: u64) -> u8 {
+        let length = self.length as u64;
+        let forward = position % length;
+        let step_num = match self.direction {
+            Direction::Forward => forward,
+            Direction::Reverse => length - 1 - forward,
+            Direction::PingPong => {
+                let period = (length * 2 - 2).max(1);
+                let bounce_position = position % period;
+                if bounce_position < length {
+                    bounce_position
+                } else {
+                    period - bounce_position
+                }
+            }
+            // play forward until a random order has been chosen
+            Direction::Random => self
+                .random_steps
+                .get(forward as usize)
+                .map(|&step_num| step_num as u64)
+                .filter(|&step_num| step_num < length)
+                .unwrap_or(forward),
+        };
+        step_num as u8
+    }
+
+    /// Choose a new order for `Direction::Random`. Each position of the loop plays a step chosen
+    /// independently, so some steps may repeat and others be skipped.
+    pub(crate) fn reroll_random_steps(&mut self, rng: &mut Rng) {
+        let length = self.length as u64;
+        self.random_steps = (0..self.length)
+            .map(|_| (rng.next_u64() % length) as u8)
+            .collect();
+    }
+
+    /// Activate the step nearest to `tick`, so that a rhythm can be played in by tapping in time
+    /// with the clock. Returns the number of the activated step.
+    pub fn tap_step(&mut self, tick: u32) -> u8 {
+        let step_num = self.nearest_step_num(tick);
+        self.sequence.activate_step(step_num as usize);
+        step_num
+    }
+
+    /// Store the current sequence in one of the track's pattern slots, to morph to or from.
+    pub fn store_pattern(&mut self, slot: usize) {
+        if let Some(pattern) = self.patterns.get_mut(slot) {
+            *pattern = Some(self.sequence.clone());
+        }
+    }
+
+    /// Switch to the pattern stored in `slot`, fitted to the track's length. Returns false,
+    /// leaving the sequence unchanged, if nothing is stored there.
+    pub fn recall_pattern(&mut self, slot: usize) -> bool {
+        match self.patterns.get(slot) {
+            Some(Some(pattern)) => {
+                self.sequence = pattern.clone().resize(self.length as usize);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Replace the sequence with a blend of the two stored patterns, see `Sequence::morph`.
+    /// Returns false, leaving the sequence unchanged, unless both patterns have been stored.
+    pub fn morph(&mut self, amount: u8) -> bool {
+        match &self.patterns {
+            [Some(a), Some(b)] => {
+                self.sequence = Sequence::morph(a, b, amount).resize(self.length as usize);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn iter_active_steps(&self) -> impl Iterator<Item = (usize, &Step)> {
+        self.sequence.iter_active()
+    }
+
+    /// Step held by a track with `TimeDivision::Free`, the first unmuted step of its sequence.
+    pub fn drone_step(&self) -> Option<&Step> {
+        self.sequence
+            .iter_active()
+            .take_while(|(i, _)| *i < self.length as usize)
+            .map(|(_, step)| step)
+            .find(|step| !step.muted)
     }
 
     pub fn step_at_tick(&self, tick: u32) -> Option<&Step> {
@@ -363,6 +1268,64 @@ mod tests {
         assert!(s1 < s2);
     }
 
+    #[test]
+    fn note_priority_should_select_representative_note() {
+        let notes = [Note::E3, Note::C3, Note::G3];
+        assert_eq!(Some(Note::E3), NotePriority::Root.select(&notes));
+        assert_eq!(Some(Note::C3), NotePriority::Lowest.select(&notes));
+        assert_eq!(Some(Note::G3), NotePriority::Highest.select(&notes));
+        assert_eq!(None, NotePriority::Highest.select(&[]));
+    }
+
+    #[test]
+    fn step_representative_note_should_be_the_step_note() {
+        let step = Step::new(64).unwrap();
+        let note: u8 = step.representative_note(NotePriority::Highest).into();
+        assert_eq!(64, note);
+    }
+
+    #[test]
+    fn step_sustain_line_should_reach_following_steps_for_long_gates() {
+        let step = Step::new(60).unwrap();
+        assert_eq!(0, step.sustain_line_length(7));
+        let one_step = Step {
+            length_step_cents: 100,
+            ..step.clone()
+        };
+        assert_eq!(0, one_step.sustain_line_length(7));
+        let two_steps = Step {
+            length_step_cents: 200,
+            ..step.clone()
+        };
+        assert_eq!(7, two_steps.sustain_line_length(7));
+        let two_and_a_half_steps = Step {
+            length_step_cents: 250,
+            ..step
+        };
+        assert_eq!(10, two_and_a_half_steps.sustain_line_length(7));
+    }
+
+    #[test]
+    fn step_delay_offset_should_shift_delayed_steps_right() {
+        let undelayed = Step::new(60).unwrap();
+        let delayed = Step {
+            delay: 50,
+            ..Step::new(60).unwrap()
+        };
+        assert_eq!(0, undelayed.delay_offset(7));
+        assert!(delayed.delay_offset(7) > undelayed.delay_offset(7));
+        let very_delayed = Step {
+            delay: 127,
+            ..Step::new(60).unwrap()
+        };
+        assert_eq!(6, very_delayed.delay_offset(7));
+        let early = Step {
+            delay: -50,
+            ..Step::new(60).unwrap()
+        };
+        assert!(early.delay_offset(7) < 0);
+    }
+
     #[test]
     fn track_default_generates_sequence_correctly() {
         let t = Track::default();
@@ -378,4 +1341,793 @@ mod tests {
         let result: Vec<Note, 8> = seq.iter().map(|step| step.as_ref().unwrap().note).collect();
         assert_eq!(notes, result);
     }
+
+    #[test]
+    fn sequence_shift_octaves_should_add_12_to_every_note() {
+        let notes: [Note; 4] = [60, 61, 72, 0].map(|i| i.try_into().unwrap());
+        let seq = SequenceGenerator::initial_sequence(4)
+            .set_notes(notes)
+            .shift_octaves(1, NoteBounds::Clamp);
+        let result: Vec<u8, 4> = seq
+            .iter()
+            .map(|step| step.as_ref().unwrap().note.into())
+            .collect();
+        assert_eq!(&[72, 73, 84, 12], result.as_slice());
+    }
+
+    #[test]
+    fn sequence_shift_octaves_should_clamp_at_top_and_bottom_of_range() {
+        let notes: [Note; 2] = [120, 5].map(|i| i.try_into().unwrap());
+        let seq = SequenceGenerator::initial_sequence(2).set_notes(notes);
+        let up = seq.clone().shift_octaves(1, NoteBounds::Clamp);
+        let down = seq.shift_octaves(-1, NoteBounds::Clamp);
+        let up_note: u8 = up.steps[0].as_ref().unwrap().note.into();
+        let down_note: u8 = down.steps[1].as_ref().unwrap().note.into();
+        assert_eq!(127, up_note);
+        assert_eq!(0, down_note);
+    }
+
+    #[test]
+    fn sequence_transpose_with_fold_should_reflect_notes_above_g8_down_an_octave() {
+        let notes: [Note; 2] = [120, 60].map(|i| i.try_into().unwrap());
+        let seq = SequenceGenerator::initial_sequence(2)
+            .set_notes(notes)
+            .transpose(10, NoteBounds::Fold);
+        let result: Vec<u8, 2> = seq
+            .iter()
+            .map(|step| step.as_ref().unwrap().note.into())
+            .collect();
+        // 130 is out of range, so it folds down to 118 rather than clamping to 127
+        assert_eq!(&[118, 70], result.as_slice());
+    }
+
+    #[test]
+    fn sequence_reverse_range_should_reverse_only_steps_in_range() {
+        let seq: Sequence = (0..16)
+            .map(|i| if i == 5 { None } else { Step::new(i).ok() })
+            .collect();
+        let reversed = seq.clone().reverse_range(4..8);
+        let notes: Vec<Option<u8>, 16> = reversed
+            .iter()
+            .map(|step| step.as_ref().map(|step| step.note.into()))
+            .collect();
+        assert_eq!(Some(7), notes[4]);
+        assert_eq!(Some(6), notes[5]);
+        assert_eq!(None, notes[6]);
+        assert_eq!(Some(4), notes[7]);
+        assert_eq!(&seq.steps[0..4], &reversed.steps[0..4]);
+        assert_eq!(&seq.steps[8..16], &reversed.steps[8..16]);
+    }
+
+    #[test]
+    fn sequence_reverse_range_should_accept_inclusive_and_out_of_bounds_ranges() {
+        let seq: Sequence = (0..16).map(|i| Step::new(i).ok()).collect();
+        assert_eq!(
+            seq.clone().reverse_range(4..8),
+            seq.clone().reverse_range(4..=7)
+        );
+        assert_eq!(seq.clone().reverse(), seq.clone().reverse_range(0..100));
+        assert_eq!(seq.clone(), seq.clone().reverse_range(20..30));
+        let first: u8 = seq.reverse().steps[0].as_ref().unwrap().note.into();
+        assert_eq!(15, first);
+    }
+
+    #[test]
+    fn sequence_steps_eq_should_compare_all_step_data_in_order() {
+        let steps = [Step::new(60).ok(), None, Step::new(64).ok()];
+        let seq: Sequence = steps.iter().cloned().collect();
+        assert!(seq.steps_eq(&steps));
+        assert!(!seq.steps_eq(&[Step::new(60).ok(), Step::new(64).ok(), None]));
+        assert!(!seq.steps_eq(&steps[0..2]));
+    }
+
+    #[test]
+    fn sequence_steps_eq_should_distinguish_steps_with_same_note() {
+        let seq: Sequence = [Step::new(60).ok()].into_iter().collect();
+        let mut quiet_step = Step::new(60).unwrap();
+        quiet_step.velocity = 64.into();
+        let mut long_step = Step::new(60).unwrap();
+        long_step.length_step_cents = 100;
+        // PartialEq only compares notes, steps_eq looks at everything
+        assert_eq!(seq, [Some(quiet_step.clone())].into_iter().collect());
+        assert!(!seq.steps_eq(&[Some(quiet_step)]));
+        assert!(!seq.steps_eq(&[Some(long_step)]));
+    }
+
+    #[test]
+    fn sequence_set_step_muted_should_keep_step_data_and_ignore_rests() {
+        let mut seq: Sequence = [Step::new(60).ok(), None].into_iter().collect();
+        seq.set_step_muted(0, true);
+        seq.toggle_step_muted(1);
+        let step = seq.steps[0].as_ref().unwrap();
+        let note_num: u8 = step.note.into();
+        assert!(step.muted);
+        assert_eq!(60, note_num);
+        assert!(seq.steps[1].is_none());
+        assert_eq!(&[true, false], seq.to_mask().as_slice());
+        seq.toggle_step_muted(0);
+        assert!(!seq.steps[0].as_ref().unwrap().muted);
+    }
+
+    #[test]
+    fn sequence_resize_should_truncate_or_repeat_steps() {
+        let seq: Sequence = [Step::new(60).ok(), None, Step::new(64).ok()]
+            .into_iter()
+            .collect();
+        let shorter = seq.clone().resize(2);
+        assert!(shorter.steps_eq(&seq.steps[0..2]));
+        let longer = seq.clone().resize(7);
+        assert_eq!(7, longer.len());
+        assert!(longer.steps_eq(&[
+            Step::new(60).ok(),
+            None,
+            Step::new(64).ok(),
+            Step::new(60).ok(),
+            None,
+            Step::new(64).ok(),
+            Step::new(60).ok(),
+        ]));
+        assert_eq!(SEQUENCE_MAX_STEPS, seq.resize(100).len());
+        assert_eq!(4, Sequence::new(Vec::new()).resize(4).len());
+    }
+
+    #[test]
+    fn sequence_checksum_should_match_for_unchanged_sequences() {
+        let seq: Sequence = (0..16)
+            .map(|i| if i % 3 == 0 { None } else { Step::new(i).ok() })
+            .collect();
+        assert_eq!(seq.checksum(), seq.clone().checksum());
+        assert_ne!(seq.checksum(), seq.clone().resize(8).checksum());
+    }
+
+    #[test]
+    fn sequence_checksum_should_change_when_any_step_field_changes() {
+        let seq: Sequence = (0..8).map(|i| Step::new(60 + i).ok()).collect();
+        let checksum = seq.checksum();
+        let changes: [fn(&mut Step); 10] = [
+            |step| step.note = Note::C0,
+            |step| step.velocity = 1.into(),
+            |step| step.pitch_bend = 1000u16.into(),
+            |step| step.length_step_cents = 10,
+            |step| step.delay = 10,
+            |step| step.muted = true,
+            |step| step.lock.set(StepLockField::Velocity, Some(1)),
+            |step| step.lock.set(StepLockField::Length, Some(10)),
+            |step| step.lock.set(StepLockField::PitchBend, Some(1000)),
+            |step| step.lock.set(StepLockField::Delay, Some(0)),
+        ];
+        for change in changes {
+            let mut changed_seq = seq.clone();
+            change(changed_seq.steps[3].as_mut().unwrap());
+            assert_ne!(checksum, changed_seq.checksum());
+        }
+        let mut rest_seq = seq.clone();
+        rest_seq.steps[3] = None;
+        assert_ne!(checksum, rest_seq.checksum());
+    }
+
+    #[test]
+    fn step_with_locks_should_play_locked_values_in_place_of_generated_ones() {
+        let mut step = Step::new(60).unwrap();
+        assert!(step.with_locks().lock.is_empty());
+        step.lock.set(StepLockField::Velocity, Some(200));
+        step.lock.set(StepLockField::Delay, Some(-20));
+        let locked = step.with_locks();
+        assert_eq!(127, u8::from(locked.velocity));
+        assert_eq!(-20, locked.delay);
+        assert_eq!(step.length_step_cents, locked.length_step_cents);
+        step.lock.set(StepLockField::Velocity, None);
+        assert_eq!(step.velocity, step.with_locks().velocity);
+    }
+
+    #[test]
+    fn sequence_set_step_lock_should_lock_only_that_step() {
+        let mut seq: Sequence = (0..4)
+            .map(|i| if i == 2 { None } else { Step::new(60).ok() })
+            .collect();
+        seq.set_step_lock(1, StepLockField::Length, Some(150));
+        seq.set_step_lock(2, StepLockField::Length, Some(150));
+        let locks: std::vec::Vec<Option<u8>> = seq
+            .iter()
+            .map(|step| step.as_ref().and_then(|step| step.lock.length_step_cents))
+            .collect();
+        assert_eq!(vec![None, Some(150), None, None], locks);
+    }
+
+    #[test]
+    fn sequence_carry_locks_should_keep_locks_of_steps_which_are_still_active() {
+        let mut seq: Sequence = (0..4).map(|i| Step::new(60 + i).ok()).collect();
+        seq.set_step_lock(0, StepLockField::Velocity, Some(10));
+        seq.set_step_lock(3, StepLockField::Velocity, Some(20));
+        let regenerated: Sequence = (0..4)
+            .map(|i| if i == 3 { None } else { Step::new(72).ok() })
+            .collect();
+        let regenerated = regenerated.carry_locks(&seq);
+        let velocity = |step: &Option<Step>| {
+            step.as_ref()
+                .map(|step| u8::from(step.with_locks().velocity))
+        };
+        assert_eq!(Some(10), velocity(&regenerated.steps[0]));
+        assert_eq!(Some(127), velocity(&regenerated.steps[1]));
+        assert_eq!(None, velocity(&regenerated.steps[3]));
+        assert_eq!(regenerated.steps[0], Step::new(72).ok());
+    }
+
+    #[test]
+    fn sequence_set_velocities_should_tile_repeating_pattern_over_steps() {
+        let seq: Sequence = (0..8)
+            .map(|i| if i == 2 { None } else { Step::new(60).ok() })
+            .collect();
+        let pattern = [100u8, 40, 70];
+        let seq = seq.set_velocities(pattern.iter().cycle().map(|&v| v.into()));
+        let velocities: Vec<Option<u8>, 8> = seq
+            .iter()
+            .map(|step| step.as_ref().map(|step| step.velocity.into()))
+            .collect();
+        assert_eq!(
+            &[
+                Some(100),
+                Some(40),
+                None,
+                Some(100),
+                Some(40),
+                Some(70),
+                Some(100),
+                Some(40)
+            ],
+            velocities.as_slice()
+        );
+    }
+
+    #[test]
+    fn sequence_to_mask_should_be_true_for_active_steps() {
+        let seq: Sequence = [Step::new(60).ok(), None, Step::new(64).ok(), None]
+            .into_iter()
+            .collect();
+        assert_eq!(&[true, false, true, false], seq.to_mask().as_slice());
+    }
+
+    #[test]
+    fn sequence_to_mask_should_round_trip_with_mask_steps() {
+        let seq = SequenceGenerator::initial_sequence(8)
+            .mask_steps([true, false, false, true, true, false, true, false]);
+        let mask = seq.to_mask();
+        let remasked = SequenceGenerator::initial_sequence(8).mask_steps(mask.clone());
+        assert!(remasked.steps_eq(seq.as_slice()));
+        assert_eq!(mask, remasked.to_mask());
+    }
+
+    #[test]
+    fn sequence_active_count_should_count_non_rest_steps() {
+        let seq = SequenceGenerator::initial_sequence(8);
+        assert_eq!(8, seq.active_count());
+        let seq = seq.mask_steps([true, false, true, false, false, false, true, false]);
+        assert_eq!(3, seq.active_count());
+    }
+
+    #[test]
+    fn sequence_iter_active_should_skip_rests() {
+        let seq = SequenceGenerator::initial_sequence(8)
+            .mask_steps([false, true, true, false, false, false, false, true]);
+        let indexes: Vec<usize, 8> = seq.iter_active().map(|(i, _step)| i).collect();
+        assert_eq!(&[1, 2, 7], indexes.as_slice());
+    }
+
+    #[test]
+    fn time_division_should_convert_bars_to_steps() {
+        assert_eq!(
+            32,
+            TimeDivision::bars_to_steps(TimeDivision::ThirtySecond, 1)
+        );
+        assert_eq!(16, TimeDivision::bars_to_steps(TimeDivision::Sixteenth, 1));
+        assert_eq!(32, TimeDivision::bars_to_steps(TimeDivision::Sixteenth, 2));
+        assert_eq!(24, TimeDivision::bars_to_steps(TimeDivision::Eigth, 3));
+        assert_eq!(16, TimeDivision::bars_to_steps(TimeDivision::Quarter, 4));
+        assert_eq!(8, TimeDivision::bars_to_steps(TimeDivision::Whole, 8));
+    }
+
+    #[test]
+    fn time_division_should_convert_steps_to_whole_bars() {
+        assert_eq!(
+            1,
+            TimeDivision::steps_to_bars(TimeDivision::ThirtySecond, 32)
+        );
+        assert_eq!(1, TimeDivision::steps_to_bars(TimeDivision::Sixteenth, 16));
+        assert_eq!(1, TimeDivision::steps_to_bars(TimeDivision::Sixteenth, 31));
+        assert_eq!(0, TimeDivision::steps_to_bars(TimeDivision::Sixteenth, 8));
+        assert_eq!(4, TimeDivision::steps_to_bars(TimeDivision::Eigth, 32));
+        assert_eq!(8, TimeDivision::steps_to_bars(TimeDivision::Quarter, 32));
+        assert_eq!(3, TimeDivision::steps_to_bars(TimeDivision::Whole, 3));
+    }
+
+    #[test]
+    fn track_set_length_in_bars_should_set_length_in_steps() {
+        let mut track = Track::default();
+        track.set_length_in_bars(2);
+        assert_eq!(32, track.length);
+        assert_eq!(2, track.length_in_bars());
+        track.set_time_division(TimeDivision::Quarter);
+        track.set_length_in_bars(3);
+        assert_eq!(12, track.length);
+        let length: u8 = track.params()[1].value().try_into().unwrap();
+        assert_eq!(12, length);
+    }
+
+    fn track_with_length_param(keep_steps_on_resize: bool, length: u8) -> Track {
+        let mut track = Track {
+            keep_steps_on_resize,
+            ..Default::default()
+        };
+        track.sequence = (0..8).map(|i| Step::new(60 + i).ok()).collect();
+        track.params_mut()[1]
+            .set(ParamValue::Number(length))
+            .unwrap();
+        track.apply_params().unwrap();
+        track
+    }
+
+    #[test]
+    fn track_apply_params_with_keep_steps_on_resize_should_truncate_shorter_tracks() {
+        let track = track_with_length_param(true, 4);
+        assert_eq!(4, track.length);
+        let notes: std::vec::Vec<u8> = track
+            .sequence
+            .iter()
+            .flatten()
+            .map(|step| step.note.into())
+            .collect();
+        assert_eq!(vec![60, 61, 62, 63], notes);
+    }
+
+    #[test]
+    fn track_apply_params_with_keep_steps_on_resize_should_repeat_steps_for_longer_tracks() {
+        let track = track_with_length_param(true, 12);
+        assert_eq!(12, track.length);
+        let notes: std::vec::Vec<u8> = track
+            .sequence
+            .iter()
+            .flatten()
+            .map(|step| step.note.into())
+            .collect();
+        assert_eq!(vec![60, 61, 62, 63, 64, 65, 66, 67, 60, 61, 62, 63], notes);
+    }
+
+    #[test]
+    fn track_apply_params_without_keep_steps_on_resize_should_leave_sequence_to_regenerate() {
+        for length in [4, 12] {
+            let track = track_with_length_param(false, length);
+            assert_eq!(length, track.length);
+            assert_eq!(8, track.sequence.len());
+        }
+    }
+
+    #[test]
+    fn time_division_should_count_steps_per_beat() {
+        assert_eq!(8, TimeDivision::steps_per_beat(TimeDivision::ThirtySecond));
+        assert_eq!(4, TimeDivision::steps_per_beat(TimeDivision::Sixteenth));
+        assert_eq!(1, TimeDivision::steps_per_beat(TimeDivision::Quarter));
+        assert_eq!(1, TimeDivision::steps_per_beat(TimeDivision::Whole));
+    }
+
+    #[test]
+    fn track_increment_length_in_beats_should_jump_by_a_beat() {
+        let mut track = Track::default();
+        track.set_length(8);
+        track.increment_length_in_beats(1);
+        assert_eq!(12, track.length);
+        track.increment_length_in_beats(3);
+        assert_eq!(16, track.length);
+        track.increment_length_in_beats(-1);
+        assert_eq!(12, track.length);
+        track.set_time_division(TimeDivision::Eigth);
+        track.increment_length_in_beats(1);
+        assert_eq!(14, track.length);
+    }
+
+    #[test]
+    fn track_increment_length_in_beats_should_snap_to_whole_beats() {
+        let mut track = Track::default();
+        track.set_length(7);
+        track.increment_length_in_beats(1);
+        assert_eq!(8, track.length);
+        track.set_length(7);
+        track.increment_length_in_beats(-1);
+        assert_eq!(4, track.length);
+    }
+
+    #[test]
+    fn track_increment_length_in_beats_should_stay_in_range() {
+        let mut track = Track::default();
+        track.set_length(32);
+        track.increment_length_in_beats(1);
+        assert_eq!(32, track.length);
+        track.set_length(4);
+        track.increment_length_in_beats(-1);
+        assert_eq!(4, track.length);
+        track.set_length(2);
+        track.increment_length_in_beats(-1);
+        assert_eq!(2, track.length);
+    }
+
+    #[test]
+    fn track_set_length_in_bars_should_clamp_to_max_length() {
+        let mut track = Track::default();
+        track.set_time_division(TimeDivision::ThirtySecond);
+        track.set_length_in_bars(4);
+        assert_eq!(TRACK_MAX_LENGTH, track.length);
+    }
+
+    #[test]
+    fn sequence_activate_step_should_unmute_steps_and_fill_rests() {
+        let mut sequence: Sequence = [None, Step::new(64).ok(), None].into_iter().collect();
+        sequence.set_step_muted(1, true);
+        sequence.activate_step(1);
+        assert!(!sequence.as_slice()[1].as_ref().unwrap().muted);
+        sequence.activate_step(2);
+        assert_eq!(Note::E3, sequence.as_slice()[2].as_ref().unwrap().note);
+        sequence.activate_step(3);
+        assert_eq!(3, sequence.len());
+    }
+
+    #[test]
+    fn sequence_activate_step_should_use_middle_c_for_empty_sequence() {
+        let mut sequence: Sequence = [None, None].into_iter().collect();
+        sequence.activate_step(0);
+        assert_eq!(Note::C3, sequence.as_slice()[0].as_ref().unwrap().note);
+    }
+
+    #[test]
+    fn sequence_write_notes_should_write_rests_as_configured() {
+        let sequence: Sequence = [Step::new(60).ok(), None, Step::new(67).ok(), None]
+            .into_iter()
+            .collect();
+        let mut notes = std::string::String::new();
+        sequence.write_notes(&mut notes, "--").unwrap();
+        assert_eq!("60,--,67,--", notes);
+        let mut notes = std::string::String::new();
+        sequence.write_notes(&mut notes, "-1").unwrap();
+        assert_eq!("60,-1,67,-1", notes);
+    }
+
+    fn morph_patterns() -> (Sequence, Sequence) {
+        let a: Sequence = (0..8)
+            .map(|_| {
+                Some(Step {
+                    velocity: 100.into(),
+                    ..Step::new(48).unwrap()
+                })
+            })
+            .collect();
+        let b: Sequence = (0..8)
+            .map(|i| {
+                (i % 2 == 0).then(|| Step {
+                    velocity: 20.into(),
+                    ..Step::new(72).unwrap()
+                })
+            })
+            .collect();
+        (a, b)
+    }
+
+    #[test]
+    fn sequence_morph_should_equal_a_at_zero_and_b_at_max() {
+        let (a, b) = morph_patterns();
+        assert_eq!(a.checksum(), Sequence::morph(&a, &b, 0).checksum());
+        assert_eq!(b.checksum(), Sequence::morph(&a, &b, MORPH_MAX).checksum());
+    }
+
+    #[test]
+    fn sequence_morph_should_blend_steps_and_notes_half_way() {
+        let (a, b) = morph_patterns();
+        let morphed = Sequence::morph(&a, &b, 50);
+        assert_eq!(
+            vec![true, true, true, true, true, false, true, false],
+            morphed.to_mask().to_vec()
+        );
+        let notes: std::vec::Vec<u8> = morphed
+            .iter()
+            .flatten()
+            .map(|step| step.note.into())
+            .collect();
+        assert_eq!(vec![60, 48, 60, 48, 60, 60], notes);
+        let velocities: std::vec::Vec<u8> = morphed
+            .iter()
+            .flatten()
+            .map(|step| step.velocity.into())
+            .collect();
+        assert_eq!(vec![60, 100, 60, 100, 60, 60], velocities);
+    }
+
+    #[test]
+    fn track_morph_should_need_both_patterns() {
+        let (a, b) = morph_patterns();
+        let mut track = Track {
+            sequence: a.clone(),
+            ..Default::default()
+        };
+        track.store_pattern(0);
+        assert!(!track.morph(50));
+        assert_eq!(a.checksum(), track.sequence.checksum());
+        track.sequence = b.clone();
+        track.store_pattern(1);
+        assert!(track.morph(MORPH_MAX));
+        assert_eq!(b.checksum(), track.sequence.checksum());
+    }
+
+    #[test]
+    fn vibrato_pitch_bend_should_follow_triangle_over_one_cycle() {
+        let vibrato = Vibrato {
+            depth: VIBRATO_MAX_DEPTH,
+            rate: TimeDivision::Quarter,
+        };
+        let bends: std::vec::Vec<i16> = (0..=24)
+            .step_by(3)
+            .map(|tick| vibrato.pitch_bend(tick))
+            .collect();
+        assert_eq!(vec![0, 4095, 8191, 4095, 0, -4095, -8191, -4095, 0], bends);
+    }
+
+    #[test]
+    fn vibrato_pitch_bend_should_scale_with_depth_and_stay_centred_when_off() {
+        let vibrato = Vibrato {
+            depth: 50,
+            rate: TimeDivision::Quarter,
+        };
+        assert_eq!(4095, vibrato.pitch_bend(6));
+        assert_eq!(-4095, vibrato.pitch_bend(18));
+        let off = Vibrato::default();
+        assert!(!off.enabled());
+        assert!((0..96).all(|tick| off.pitch_bend(tick) == 0));
+    }
+
+    fn lfo(shape: LfoShape) -> Lfo {
+        Lfo {
+            shape,
+            depth: LFO_MAX_DEPTH,
+            rate: TimeDivision::Quarter,
+            ..Default::default()
+        }
+    }
+
+    fn lfo_values(lfo: &Lfo, ticks: core::ops::Range<u32>) -> std::vec::Vec<u8> {
+        ticks.step_by(3).map(|tick| lfo.value(tick)).collect()
+    }
+
+    #[test]
+    fn lfo_triangle_should_sweep_full_range_over_one_cycle() {
+        assert_eq!(
+            vec![64, 95, 127, 95, 64, 33, 1, 33, 64],
+            lfo_values(&lfo(LfoShape::Triangle), 0..25)
+        );
+    }
+
+    #[test]
+    fn lfo_should_follow_shape_and_stay_centred_when_off() {
+        assert_eq!(
+            vec![1, 17, 33, 49, 64, 79, 95, 111],
+            lfo_values(&lfo(LfoShape::Ramp), 0..24)
+        );
+        assert_eq!(
+            vec![127, 127, 127, 127, 1, 1, 1, 1],
+            lfo_values(&lfo(LfoShape::Square), 0..24)
+        );
+        let off = Lfo::default();
+        assert!(!off.enabled());
+        assert!((0..96).all(|tick| off.value(tick) == 64));
+    }
+
+    #[test]
+    fn lfo_sample_and_hold_should_hold_random_level_for_each_cycle() {
+        let mut lfo = lfo(LfoShape::SampleAndHold);
+        assert!((0..48).all(|tick| lfo.value(tick) == 64));
+        lfo.reroll_samples(&mut MachineResources::with_seed(1));
+        let values: std::vec::Vec<u8> = (0..96).map(|tick| lfo.value(tick)).collect();
+        for cycle in values.chunks(24) {
+            assert!(cycle.iter().all(|&value| value == cycle[0]));
+        }
+        assert!(values.chunks(24).any(|cycle| cycle[0] != values[0]));
+    }
+
+    #[test]
+    fn vibrato_faster_than_sixteenth_should_play_at_sixteenth() {
+        let vibrato = Vibrato {
+            depth: VIBRATO_MAX_DEPTH,
+            rate: TimeDivision::ThirtySecond,
+        };
+        assert_eq!(6, vibrato.cycle_ticks());
+    }
+
+    #[test]
+    fn track_increment_program_should_move_between_off_and_max_program() {
+        let mut track = Track::default();
+        track.increment_program(-1);
+        assert_eq!(None, track.program);
+        track.increment_program(1);
+        assert_eq!(Some(0), track.program);
+        track.increment_program(100);
+        track.increment_program(100);
+        assert_eq!(Some(127), track.program);
+        track.increment_program(-127);
+        track.increment_program(-5);
+        assert_eq!(None, track.program);
+    }
+
+    #[test]
+    fn track_tap_step_should_activate_nearest_step() {
+        let mut track = Track {
+            sequence: (0..8).map(|_| None).collect(),
+            ..Default::default()
+        };
+        assert_eq!(0, track.tap_step(0));
+        assert_eq!(0, track.tap_step(2));
+        assert_eq!(1, track.tap_step(3));
+        assert_eq!(3, track.tap_step(20));
+        assert_eq!(0, track.tap_step(46));
+        assert_eq!(
+            vec![true, true, false, true, false, false, false, false],
+            track.sequence.to_mask().to_vec()
+        );
+    }
+
+    #[test]
+    fn track_tap_step_should_quantize_to_time_division() {
+        let mut track = Track::default();
+        track.set_time_division(TimeDivision::Quarter);
+        assert_eq!(1, track.nearest_step_num(12));
+        assert_eq!(0, track.nearest_step_num(11));
+        assert_eq!(2, track.nearest_step_num(50));
+    }
+
+    fn ticks_played(track: &Track, ticks: u32) -> std::vec::Vec<(u32, u8)> {
+        (0..ticks)
+            .filter(|&tick| track.should_play_on_tick(tick))
+            .map(|tick| (tick, track.step_num(tick)))
+            .collect()
+    }
+
+    #[test]
+    fn track_with_clock_mult_should_play_steps_faster() {
+        let track = Track {
+            clock_mod: ClockMod::Mult(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![
+                (0, 0),
+                (3, 1),
+                (6, 2),
+                (9, 3),
+                (12, 4),
+                (15, 5),
+                (18, 6),
+                (21, 7),
+                (24, 0)
+            ],
+            ticks_played(&track, 25)
+        );
+    }
+
+    #[test]
+    fn track_with_clock_mult_should_play_steps_between_ticks_on_next_tick() {
+        let track = Track {
+            clock_mod: ClockMod::Mult(4),
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![(0, 0), (2, 1), (3, 2), (5, 3), (6, 4)],
+            ticks_played(&track, 7)
+        );
+    }
+
+    #[test]
+    fn track_with_clock_div_should_play_steps_slower() {
+        let track = Track {
+            clock_mod: ClockMod::Div(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![(0, 0), (12, 1), (24, 2), (36, 3)],
+            ticks_played(&track, 48)
+        );
+        assert_eq!(0, track.step_num(12 * 8));
+        assert_eq!(1, track.nearest_step_num(6));
+        assert_eq!(0, track.nearest_step_num(5));
+    }
+
+    #[test]
+    fn clock_mod_increment_should_move_from_slowest_to_fastest() {
+        assert_eq!(ClockMod::Mult(2), ClockMod::default().increment(1));
+        assert_eq!(ClockMod::Div(2), ClockMod::default().increment(-1));
+        assert_eq!(ClockMod::Mult(1), ClockMod::Div(3).increment(2));
+        assert_eq!(ClockMod::Div(2), ClockMod::Mult(2).increment(-2));
+        assert_eq!(
+            ClockMod::Mult(CLOCK_MOD_MAX),
+            ClockMod::default().increment(100)
+        );
+        assert_eq!(
+            ClockMod::Div(CLOCK_MOD_MAX),
+            ClockMod::default().increment(-100)
+        );
+        assert_eq!("x1", ClockMod::default().to_string());
+        assert_eq!("/3", ClockMod::Div(3).to_string());
+    }
+
+    #[test]
+    fn clock_mod_should_scale_step_length() {
+        let step_duration = MicrosDurationU64::micros(1000);
+        assert_eq!(
+            step_duration,
+            ClockMod::default().scale_step_duration(step_duration)
+        );
+        assert_eq!(
+            MicrosDurationU64::micros(500),
+            ClockMod::Mult(2).scale_step_duration(step_duration)
+        );
+        assert_eq!(
+            MicrosDurationU64::micros(2000),
+            ClockMod::Div(2).scale_step_duration(step_duration)
+        );
+        assert_eq!(
+            step_duration,
+            ClockMod::Div(0).scale_step_duration(step_duration)
+        );
+    }
+
+    fn step_nums(track: &Track, steps: u32) -> std::vec::Vec<u8> {
+        (0..steps).map(|step| track.step_num(step * 6)).collect()
+    }
+
+    #[test]
+    fn direction_increment_should_wrap_around() {
+        assert_eq!(Direction::Reverse, Direction::Forward.increment(1));
+        assert_eq!(Direction::Random, Direction::Forward.increment(-1));
+        assert_eq!(Direction::Forward, Direction::Random.increment(1));
+        assert_eq!(Direction::PingPong, Direction::Reverse.increment(5));
+        assert_eq!("PING", Direction::PingPong.to_string());
+    }
+
+    #[test]
+    fn track_with_reverse_direction_should_count_down() {
+        let track = Track {
+            length: 4,
+            direction: Direction::Reverse,
+            ..Default::default()
+        };
+        assert_eq!(vec![3, 2, 1, 0, 3, 2], step_nums(&track, 6));
+        assert!(track.starts_loop(24));
+    }
+
+    #[test]
+    fn track_with_ping_pong_direction_should_bounce_without_repeating_end_steps() {
+        let mut track = Track {
+            length: 4,
+            direction: Direction::PingPong,
+            ..Default::default()
+        };
+        assert_eq!(vec![0, 1, 2, 3, 2, 1, 0, 1, 2, 3, 2], step_nums(&track, 11));
+        track.length = 1;
+        assert_eq!(vec![0, 0, 0], step_nums(&track, 3));
+    }
+
+    #[test]
+    fn track_with_random_direction_should_play_steps_chosen_by_seeded_rng() {
+        let mut track = Track {
+            direction: Direction::Random,
+            ..Default::default()
+        };
+        assert_eq!(vec![0, 1, 2, 3, 4, 5, 6, 7], step_nums(&track, 8));
+
+        track.reroll_random_steps(&mut Rng::new(42));
+        let random_steps = step_nums(&track, 8);
+        assert!(random_steps.iter().all(|&step_num| step_num < track.length));
+        assert_ne!(vec![0, 1, 2, 3, 4, 5, 6, 7], random_steps);
+        assert_eq!(random_steps, step_nums(&track, 16)[8..]);
+
+        let mut other_track = Track {
+            direction: Direction::Random,
+            ..Default::default()
+        };
+        other_track.reroll_random_steps(&mut Rng::new(42));
+        assert_eq!(random_steps, step_nums(&other_track, 8));
+    }
 }
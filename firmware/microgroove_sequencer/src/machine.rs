@@ -2,17 +2,37 @@ use alloc::boxed::Box;
 use core::fmt::{Debug, Display, Formatter, Result as FmtResult};
 use heapless::String;
 
-use crate::{machine_resources::MachineResources, param::ParamList, Sequence};
+use crate::{
+    machine_resources::MachineResources, map_to_range, param::ParamList, Sequence, TimeDivision,
+};
 
+pub mod accent_groove_machine;
+pub mod arp_melody_machine;
 pub mod euclidean_rhythm_machine;
+pub mod ghost_groove_machine;
 pub mod grids_rhythm_machine;
+pub mod markov_machine;
+pub mod preset_rhythm_machine;
 pub mod rand_melody_machine;
+pub mod turing_machine;
 pub mod unit_machine;
+pub mod wolfram_machine;
 
+use accent_groove_machine::AccentGrooveMachine;
+use arp_melody_machine::ArpMelodyMachine;
 use euclidean_rhythm_machine::EuclideanRhythmMachine;
+use ghost_groove_machine::GhostGrooveMachine;
 use grids_rhythm_machine::GridsRhythmMachine;
+use markov_machine::MarkovMachine;
+use preset_rhythm_machine::PresetRhythmMachine;
 use rand_melody_machine::RandMelodyMachine;
+use turing_machine::TuringMachine;
 use unit_machine::UnitMachine;
+use wolfram_machine::WolframMachine;
+
+// range of gate lengths, as % of step time, when note length follows velocity
+const VELOCITY_LENGTH_MIN_CENTS: u8 = 10;
+const VELOCITY_LENGTH_MAX_CENTS: u8 = 100;
 
 #[derive(Debug)]
 pub enum MachineError {
@@ -21,12 +41,100 @@ pub enum MachineError {
 
 /// A `Machine` represents a sequence generator that can be controlled via a list of parameters. In
 /// Microgroove, each `Track` has 2 machines, one to generate the rhythm, one for the melody.
-pub trait Machine: Debug + Send {
+pub trait Machine: Debug + Send + CloneMachine {
     fn name(&self) -> &str; // TODO redundant because Display implmented for machine IDs
     fn generate(&mut self, machine_resources: &mut MachineResources);
     fn apply(&self, sequence: Sequence) -> Sequence;
     fn params(&self) -> &ParamList;
     fn params_mut(&mut self) -> &mut ParamList;
+
+    /// Velocity this machine suggests for the steps of the sequence, e.g. lower for hats. Used in
+    /// place of the generator's base velocity. `None` if the machine has no preference.
+    fn default_velocity(&self) -> Option<u8> {
+        None
+    }
+
+    /// Gate length, as % of step time, this machine suggests for the steps of the sequence.
+    /// `None` if the machine has no preference.
+    fn default_gate(&self) -> Option<u8> {
+        None
+    }
+}
+
+/// A `GrooveMachine` shapes the feel of a sequence, e.g. its velocities and timing. Unlike rhythm
+/// and melody machines, groove machines must not change the notes of a sequence or which of its
+/// steps are active.
+pub trait GrooveMachine: Machine + CloneGrooveMachine {
+    /// Called with the time division of the track the sequence is generated for, for machines
+    /// which follow the beat.
+    fn set_time_division(&mut self, _time_division: TimeDivision) {}
+}
+
+/// Copy a boxed machine, including any state it has generated, e.g. so a variation can be
+/// generated without moving the original machine on. Implemented for every `Machine` which is
+/// `Clone`.
+pub trait CloneMachine {
+    fn clone_machine(&self) -> Box<dyn Machine>;
+}
+
+impl<T: Machine + Clone + 'static> CloneMachine for T {
+    fn clone_machine(&self) -> Box<dyn Machine> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Machine> {
+    fn clone(&self) -> Self {
+        self.clone_machine()
+    }
+}
+
+/// Copy a boxed groove machine, like `CloneMachine`.
+pub trait CloneGrooveMachine {
+    fn clone_groove_machine(&self) -> Box<dyn GrooveMachine>;
+}
+
+impl<T: GrooveMachine + Clone + 'static> CloneGrooveMachine for T {
+    fn clone_groove_machine(&self) -> Box<dyn GrooveMachine> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn GrooveMachine> {
+    fn clone(&self) -> Self {
+        self.clone_groove_machine()
+    }
+}
+
+/// Copy the values of params which `to` shares by name with `from`, so that common settings
+/// survive switching machines. Other params keep their defaults.
+pub fn carry_over_params(from: &dyn Machine, to: &mut dyn Machine) {
+    for param in to.params_mut().iter_mut() {
+        if let Some(other) = from
+            .params()
+            .iter()
+            .find(|other| other.name() == param.name())
+        {
+            param.copy_value_from(other);
+        }
+    }
+}
+
+/// Make louder steps sustain for longer, for groove machines with a VELLEN param. Velocity sets
+/// the gate length of each step, from 10% of the step for the quietest steps to 100% for the
+/// loudest.
+fn apply_velocity_length(mut sequence: Sequence) -> Sequence {
+    for step in sequence.iter_mut().flatten() {
+        let velocity: u8 = step.velocity.into();
+        step.length_step_cents = map_to_range(
+            velocity as i32,
+            1,
+            127,
+            VELOCITY_LENGTH_MIN_CENTS as i32,
+            VELOCITY_LENGTH_MAX_CENTS as i32,
+        ) as u8;
+    }
+    sequence
 }
 
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
@@ -35,6 +143,7 @@ pub enum RhythmMachineId {
     #[default]
     Euclid,
     Grids,
+    Preset,
 }
 
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
@@ -42,6 +151,18 @@ pub enum MelodyMachineId {
     Unit,
     #[default]
     Rand,
+    Arp,
+    Turing,
+    Wolfram,
+    Markov,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum GrooveMachineId {
+    #[default]
+    Unit,
+    Accent,
+    Ghost,
 }
 
 impl From<RhythmMachineId> for Box<dyn Machine> {
@@ -50,6 +171,7 @@ impl From<RhythmMachineId> for Box<dyn Machine> {
             RhythmMachineId::Unit => Box::new(UnitMachine::new()),
             RhythmMachineId::Euclid => Box::new(EuclideanRhythmMachine::new()),
             RhythmMachineId::Grids => Box::new(GridsRhythmMachine::new()),
+            RhythmMachineId::Preset => Box::new(PresetRhythmMachine::new()),
         }
     }
 }
@@ -59,6 +181,57 @@ impl From<MelodyMachineId> for Box<dyn Machine> {
         match value {
             MelodyMachineId::Unit => Box::new(UnitMachine::new()),
             MelodyMachineId::Rand => Box::new(RandMelodyMachine::new()),
+            MelodyMachineId::Arp => Box::new(ArpMelodyMachine::new()),
+            MelodyMachineId::Turing => Box::new(TuringMachine::new()),
+            MelodyMachineId::Wolfram => Box::new(WolframMachine::new()),
+            MelodyMachineId::Markov => Box::new(MarkovMachine::new()),
+        }
+    }
+}
+
+impl From<GrooveMachineId> for Box<dyn GrooveMachine> {
+    fn from(value: GrooveMachineId) -> Self {
+        match value {
+            GrooveMachineId::Unit => Box::new(UnitMachine::new()),
+            GrooveMachineId::Accent => Box::new(AccentGrooveMachine::new()),
+            GrooveMachineId::Ghost => Box::new(GhostGrooveMachine::new()),
+        }
+    }
+}
+
+impl RhythmMachineId {
+    /// One-line summary of what the machine does, short enough to fit across the display.
+    pub fn description(&self) -> &'static str {
+        match self {
+            RhythmMachineId::Unit => "Every step plays",
+            RhythmMachineId::Euclid => "Notes spread evenly over steps",
+            RhythmMachineId::Grids => "Drum patterns from MI Grids",
+            RhythmMachineId::Preset => "Classic drum patterns",
+        }
+    }
+}
+
+impl MelodyMachineId {
+    /// One-line summary of what the machine does, short enough to fit across the display.
+    pub fn description(&self) -> &'static str {
+        match self {
+            MelodyMachineId::Unit => "Notes are left unchanged",
+            MelodyMachineId::Rand => "Random notes around a root",
+            MelodyMachineId::Arp => "Arpeggio of a chord",
+            MelodyMachineId::Turing => "Random note loop which mutates",
+            MelodyMachineId::Wolfram => "Cellular automaton melody",
+            MelodyMachineId::Markov => "Melody in the style of a phrase",
+        }
+    }
+}
+
+impl GrooveMachineId {
+    /// One-line summary of what the machine does, short enough to fit across the display.
+    pub fn description(&self) -> &'static str {
+        match self {
+            GrooveMachineId::Unit => "Velocities are left unchanged",
+            GrooveMachineId::Accent => "Louder steps on the beat",
+            GrooveMachineId::Ghost => "Soft ghost notes between hits",
         }
     }
 }
@@ -69,6 +242,7 @@ impl Display for RhythmMachineId {
             RhythmMachineId::Unit => Display::fmt("UNIT", f),
             RhythmMachineId::Euclid => Display::fmt("EUCLID", f),
             RhythmMachineId::Grids => Display::fmt("GRIDS", f),
+            RhythmMachineId::Preset => Display::fmt("PRESET", f),
         }
     }
 }
@@ -78,6 +252,20 @@ impl Display for MelodyMachineId {
         match self {
             MelodyMachineId::Unit => Display::fmt("UNIT", f),
             MelodyMachineId::Rand => Display::fmt("RAND", f),
+            MelodyMachineId::Arp => Display::fmt("ARP", f),
+            MelodyMachineId::Turing => Display::fmt("TURING", f),
+            MelodyMachineId::Wolfram => Display::fmt("WOLFRM", f),
+            MelodyMachineId::Markov => Display::fmt("MARKOV", f),
+        }
+    }
+}
+
+impl Display for GrooveMachineId {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            GrooveMachineId::Unit => Display::fmt("UNIT", f),
+            GrooveMachineId::Accent => Display::fmt("ACCENT", f),
+            GrooveMachineId::Ghost => Display::fmt("GHOST", f),
         }
     }
 }
@@ -90,6 +278,7 @@ impl TryFrom<u8> for RhythmMachineId {
             0 => Ok(RhythmMachineId::Unit),
             1 => Ok(RhythmMachineId::Euclid),
             2 => Ok(RhythmMachineId::Grids),
+            3 => Ok(RhythmMachineId::Preset),
             _ => Err(()),
         }
     }
@@ -102,6 +291,23 @@ impl TryFrom<u8> for MelodyMachineId {
         match value {
             0 => Ok(MelodyMachineId::Unit),
             1 => Ok(MelodyMachineId::Rand),
+            2 => Ok(MelodyMachineId::Arp),
+            3 => Ok(MelodyMachineId::Turing),
+            4 => Ok(MelodyMachineId::Wolfram),
+            5 => Ok(MelodyMachineId::Markov),
+            _ => Err(()),
+        }
+    }
+}
+
+impl TryFrom<u8> for GrooveMachineId {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(GrooveMachineId::Unit),
+            1 => Ok(GrooveMachineId::Accent),
+            2 => Ok(GrooveMachineId::Ghost),
             _ => Err(()),
         }
     }
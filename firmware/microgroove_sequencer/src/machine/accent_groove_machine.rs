@@ -0,0 +1,210 @@
+/// Groove machine which accents the first step of each beat, making it louder and the other steps
+/// quieter.
+use super::{apply_velocity_length, GrooveMachine, Machine};
+use crate::{
+    machine_resources::MachineResources,
+    param::{Param, ParamList},
+    Sequence, TimeDivision,
+};
+
+use alloc::boxed::Box;
+
+// most velocity accents can add to accented steps, and take from the others
+const ACCENT_MAX: u8 = 64;
+
+#[derive(Clone, Debug)]
+pub struct AccentGrooveMachine {
+    params: ParamList,
+
+    /// Time division of the track, used to find beats.
+    time_division: TimeDivision,
+}
+
+impl AccentGrooveMachine {
+    pub fn new() -> AccentGrooveMachine {
+        AccentGrooveMachine {
+            params: ParamList::from_slice(&[
+                Box::new(Param::new_number_param("ACCENT", 0, ACCENT_MAX, 32)),
+                Box::new(Param::new_number_param("VELLEN", 0, 1, 0)),
+            ])
+            .expect("should create accent groove machine param list from slice"),
+            time_division: Default::default(),
+        }
+    }
+
+    /// Steps of a sequence of `length` steps which are accented, one for each beat, rounded to
+    /// the nearest whole number of beats. When the length is a whole number of beats, these are
+    /// the first step of each beat. Otherwise the accents are spread as evenly as possible, e.g.
+    /// 3+3 for 6 sixteenths, so that they still feel like beats as the sequence loops.
+    fn accented_steps(length: usize, time_division: TimeDivision) -> impl Iterator<Item = bool> {
+        let steps_per_beat = TimeDivision::steps_per_beat(time_division) as usize;
+        let accent_count = ((length + steps_per_beat / 2) / steps_per_beat).max(1);
+        (0..length).map(move |i| (i * accent_count) % length < accent_count)
+    }
+
+    fn process(mut sequence: Sequence, time_division: TimeDivision, accent: u8) -> Sequence {
+        let accented_steps = Self::accented_steps(sequence.len(), time_division);
+        for (step, accented) in sequence.iter_mut().zip(accented_steps) {
+            if let Some(step) = step {
+                let velocity: u8 = step.velocity.into();
+                step.velocity = if accented {
+                    velocity.saturating_add(accent).min(127)
+                } else {
+                    velocity.saturating_sub(accent).max(1)
+                }
+                .into();
+            }
+        }
+        sequence
+    }
+}
+
+impl Default for AccentGrooveMachine {
+    fn default() -> AccentGrooveMachine {
+        AccentGrooveMachine::new()
+    }
+}
+
+impl Machine for AccentGrooveMachine {
+    fn name(&self) -> &str {
+        "ACCENT"
+    }
+
+    fn params(&self) -> &ParamList {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut ParamList {
+        &mut self.params
+    }
+
+    fn generate(&mut self, _machine_resources: &mut MachineResources) {}
+
+    fn apply(&self, sequence: Sequence) -> Sequence {
+        let accent = self.params[0]
+            .value()
+            .try_into()
+            .expect("unexpected accent param for AccentGrooveMachine");
+        let velocity_length: u8 = self.params[1]
+            .value()
+            .try_into()
+            .expect("unexpected velocity length param for AccentGrooveMachine");
+        let sequence = Self::process(sequence, self.time_division, accent);
+        if velocity_length == 1 {
+            apply_velocity_length(sequence)
+        } else {
+            sequence
+        }
+    }
+}
+
+impl GrooveMachine for AccentGrooveMachine {
+    fn set_time_division(&mut self, time_division: TimeDivision) {
+        self.time_division = time_division;
+    }
+}
+
+unsafe impl Send for AccentGrooveMachine {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{param::ParamValue, sequence_generator::SequenceGenerator, Step};
+
+    #[test]
+    fn accent_groove_machine_should_change_velocities_but_not_notes_or_rhythm() {
+        let machine = AccentGrooveMachine::new();
+        let input_sequence: Sequence = (0..8)
+            .map(|i| if i == 5 { None } else { Step::new(60 + i).ok() })
+            .collect();
+        let output_sequence = machine.apply(input_sequence.clone());
+        assert_eq!(input_sequence, output_sequence);
+        assert_eq!(input_sequence.to_mask(), output_sequence.to_mask());
+        let velocities: Vec<Option<u8>> = output_sequence
+            .iter()
+            .map(|step| step.as_ref().map(|step| step.velocity.into()))
+            .collect();
+        assert_eq!(
+            vec![
+                Some(127),
+                Some(95),
+                Some(95),
+                Some(95),
+                Some(127),
+                None,
+                Some(95),
+                Some(95)
+            ],
+            velocities
+        );
+    }
+
+    #[test]
+    fn accent_groove_machine_should_keep_velocities_in_range() {
+        let mut machine = AccentGrooveMachine::new();
+        machine.params_mut()[0]
+            .set(ParamValue::Number(ACCENT_MAX))
+            .unwrap();
+        let output_sequence = machine.apply(SequenceGenerator::initial_sequence(8));
+        assert!(output_sequence.iter().flatten().all(|step| {
+            let velocity: u8 = step.velocity.into();
+            velocity >= 1
+        }));
+    }
+
+    #[test]
+    fn accent_groove_machine_with_max_accent_should_make_first_step_louder_than_second() {
+        let mut machine = AccentGrooveMachine::new();
+        machine.params_mut()[0]
+            .set(ParamValue::Number(ACCENT_MAX))
+            .unwrap();
+        let sequence = machine.apply(SequenceGenerator::initial_sequence(8));
+        let velocity = |i: usize| u8::from(sequence.steps[i].as_ref().unwrap().velocity);
+        assert_eq!(127, velocity(0));
+        assert_eq!(127 - ACCENT_MAX, velocity(1));
+        assert_eq!(velocity(0), velocity(4));
+
+        // beats are longer in steps at finer time divisions
+        machine.set_time_division(TimeDivision::ThirtySecond);
+        let sequence = machine.apply(SequenceGenerator::initial_sequence(16));
+        let velocity = |i: usize| u8::from(sequence.steps[i].as_ref().unwrap().velocity);
+        assert!(velocity(0) > velocity(4));
+        assert_eq!(velocity(0), velocity(8));
+    }
+
+    #[test]
+    fn accent_groove_machine_accented_steps_should_spread_beats_over_any_length() {
+        let accented = |length: usize| -> Vec<usize> {
+            AccentGrooveMachine::accented_steps(length, TimeDivision::Sixteenth)
+                .enumerate()
+                .filter_map(|(i, accented)| accented.then_some(i))
+                .collect()
+        };
+        assert_eq!(vec![0, 4, 8, 12], accented(16));
+        assert_eq!(vec![0, 3], accented(6));
+        assert_eq!(vec![0, 4], accented(7));
+        assert_eq!(vec![0, 4, 7], accented(10));
+        assert_eq!(vec![0], accented(3));
+        assert_eq!(vec![0], accented(1));
+    }
+
+    #[test]
+    fn accent_groove_machine_with_length_following_velocity_should_give_accents_longer_gates() {
+        let mut machine = AccentGrooveMachine::new();
+        let default_lengths: Vec<u8> = machine
+            .apply(SequenceGenerator::initial_sequence(8))
+            .iter()
+            .map(|step| step.as_ref().unwrap().length_step_cents)
+            .collect();
+        machine.params_mut()[1].set(ParamValue::Number(1)).unwrap();
+        let sequence = machine.apply(SequenceGenerator::initial_sequence(8));
+        let length = |i: usize| sequence.steps[i].as_ref().unwrap().length_step_cents;
+        assert_eq!(100, length(0));
+        assert!(length(1) < length(0));
+        assert!((10..=100).contains(&length(1)));
+        assert_eq!(length(0), length(4));
+        assert!(default_lengths
+            .iter()
+            .all(|&length| length == default_lengths[0]));
+    }
+}
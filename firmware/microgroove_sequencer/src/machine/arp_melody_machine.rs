@@ -0,0 +1,137 @@
+/// Machine which arpeggiates a held chord, spreading its notes across the active steps of the
+/// sequence.
+use super::Machine;
+use crate::{
+    machine_resources::MachineResources,
+    midi::{Note, NoteBounds},
+    param::{Param, ParamList, ParamValue},
+    quantizer::Scale,
+    Sequence,
+};
+
+use alloc::boxed::Box;
+
+#[derive(Clone, Debug)]
+pub struct ArpMelodyMachine {
+    params: ParamList,
+}
+
+impl ArpMelodyMachine {
+    pub fn new() -> ArpMelodyMachine {
+        let mut chord_param = Param::new_scale_param("CHORD");
+        chord_param
+            .set(ParamValue::Scale(Scale::MajorTriad))
+            .expect("should set arp chord param");
+        let params = ParamList::from_slice(&[
+            Box::new(Param::new_note_param("ROOT")),
+            Box::new(chord_param),
+            Box::new(Param::new_number_param("RATE", 1, 8, 1)),
+        ])
+        .expect("should create arp melody machine param list from slice");
+        ArpMelodyMachine { params }
+    }
+
+    fn process(sequence: Sequence, root: Note, chord: Scale, rate: u8) -> Sequence {
+        let root_num: u8 = root.into();
+        let chord_tones = chord.degrees();
+        let mut i = 0;
+        sequence.map_notes(|_| {
+            // each chord tone is held for `rate` steps before moving on to the next
+            let degree = chord_tones[(i / rate as usize) % chord_tones.len()];
+            i += 1;
+            NoteBounds::Fold.apply(root_num as i32 + degree as i32)
+        })
+    }
+}
+
+impl Default for ArpMelodyMachine {
+    fn default() -> ArpMelodyMachine {
+        ArpMelodyMachine::new()
+    }
+}
+
+impl Machine for ArpMelodyMachine {
+    fn name(&self) -> &str {
+        "ARP"
+    }
+
+    fn params(&self) -> &ParamList {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut ParamList {
+        &mut self.params
+    }
+
+    fn generate(&mut self, _machine_resources: &mut MachineResources) {}
+
+    fn apply(&self, sequence: Sequence) -> Sequence {
+        let root = self.params[0]
+            .value()
+            .try_into()
+            .expect("unexpected root param for ArpMelodyMachine");
+        let chord = self.params[1]
+            .value()
+            .try_into()
+            .expect("unexpected chord param for ArpMelodyMachine");
+        let rate = self.params[2]
+            .value()
+            .try_into()
+            .expect("unexpected rate param for ArpMelodyMachine");
+        Self::process(sequence, root, chord, rate)
+    }
+}
+
+unsafe impl Send for ArpMelodyMachine {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{sequence_generator::SequenceGenerator, Step};
+
+    fn notes(sequence: &Sequence) -> Vec<Option<Note>> {
+        sequence
+            .iter()
+            .map(|step| step.as_ref().map(|step| step.note))
+            .collect()
+    }
+
+    #[test]
+    fn arp_melody_machine_should_arpeggiate_c_major_triad() {
+        let machine = ArpMelodyMachine::new();
+        let output_sequence = machine.apply(SequenceGenerator::initial_sequence(6));
+        assert_eq!(
+            vec![
+                Some(Note::C3),
+                Some(Note::E3),
+                Some(Note::G3),
+                Some(Note::C3),
+                Some(Note::E3),
+                Some(Note::G3)
+            ],
+            notes(&output_sequence)
+        );
+    }
+
+    #[test]
+    fn arp_melody_machine_should_hold_each_note_for_rate_steps_and_skip_rests() {
+        let mut machine = ArpMelodyMachine::new();
+        machine.params_mut()[2].set(ParamValue::Number(2)).unwrap();
+        let input_sequence: Sequence = (0..7)
+            .map(|i| if i == 2 { None } else { Step::new(60).ok() })
+            .collect();
+        let output_sequence = machine.apply(input_sequence);
+        assert_eq!(
+            vec![
+                Some(Note::C3),
+                Some(Note::C3),
+                None,
+                Some(Note::E3),
+                Some(Note::E3),
+                Some(Note::G3),
+                Some(Note::G3)
+            ],
+            notes(&output_sequence)
+        );
+    }
+}
@@ -44,7 +44,10 @@ const EUCLIDEAN_LUT: [u32; 1024] = [
     0b10000000000000000000000000000000, 0b10000000000000001000000000000000, 0b10000000000100000000001000000000, 0b10000000100000001000000010000000, 0b10000010000001000001000000100000, 0b10000100001000001000010000100000, 0b10000100010000100010000100010000, 0b10001000100010001000100010001000, 0b10001001000100100010010001001000, 0b10010010010010001001001001001000, 0b10010010010010010010010010010010, 0b10010010100100101001001010010010, 0b10100101001010010100101001010010, 0b10101001010100101010100101010010, 0b10101010101010010101010101010010, 0b10101010101010101010101010101010, 0b10101010101010110101010101010110, 0b10101011010101101010101101010110, 0b10101101011010110101101011010110, 0b10110110101101101011011010110110, 0b10110110110110110110110110110110, 0b11011011011011011101101101101101, 0b11011101101110110111011011101101, 0b11101110111011101110111011101110, 0b11101111011101111011101111011101, 0b11110111101111011111011110111101, 0b11111011111011111101111101111110, 0b11111110111111101111111011111110, 0b11111111101111111111011111111110, 0b11111111111111101111111111111110, 0b11111111111111111111111111111110, 0b11111111111111111111111111111111
 ];
 
-#[derive(Debug)]
+/// Longest Euclidean pattern in `EUCLIDEAN_LUT`.
+const PATTERN_MAX_LENGTH: u8 = 32;
+
+#[derive(Clone, Debug)]
 pub struct EuclideanRhythmMachine {
     params: ParamList,
 }
@@ -53,23 +56,38 @@ impl EuclideanRhythmMachine {
     pub fn new() -> EuclideanRhythmMachine {
         EuclideanRhythmMachine {
             params: ParamList::from_slice(&[
-                Box::new(Param::new_number_param("NOTES", 1, 32, 3)),
-                Box::new(Param::new_number_param("ROTATE", 0, 31, 0)),
+                Box::new(Param::new_number_param("STEPS", 1, PATTERN_MAX_LENGTH, 8)),
+                Box::new(Param::new_number_param("FILL", 1, PATTERN_MAX_LENGTH, 3)),
+                Box::new(Param::new_number_param("ROT", 0, PATTERN_MAX_LENGTH - 1, 0)),
             ])
             .expect("should create euclidean rhythm machine param list from slice"),
         }
     }
 
-    fn process(sequence: Sequence, notes: u8, rotate: u8) -> Sequence {
-        let steps = sequence.len();
-        let notes = (notes as usize).min(steps);
-        let address = ((steps - 1) * 32) + (notes - 1);
+    /// Active steps of the pattern E(`fill`, `pattern_length`), rotated right by `rotate`
+    /// steps. Fill is limited to the length of the pattern.
+    fn pattern(pattern_length: u8, fill: u8, rotate: u8) -> impl Iterator<Item = bool> + Clone {
+        let pattern_length = pattern_length.clamp(1, PATTERN_MAX_LENGTH) as usize;
+        let fill = (fill as usize).clamp(1, pattern_length);
+        let address = ((pattern_length - 1) * 32) + (fill - 1);
         let pattern_bits = EUCLIDEAN_LUT[address];
-        let active_steps = (0..sequence.len()).map(|i| (pattern_bits >> (steps - i - 1)) & 1 == 1);
-        let rotate = rotate % steps as u8;
-        sequence
-            .mask_steps(active_steps)
-            .rotate_right(rotate.into())
+        let rotate = rotate as usize % pattern_length;
+        (0..pattern_length).map(move |i| {
+            let bit = (i + pattern_length - rotate) % pattern_length;
+            (pattern_bits >> (pattern_length - bit - 1)) & 1 == 1
+        })
+    }
+
+    /// Mask the sequence with the pattern, repeating the pattern if the sequence is longer.
+    fn process(sequence: Sequence, pattern_length: u8, fill: u8, rotate: u8) -> Sequence {
+        let active_steps = Self::pattern(pattern_length, fill, rotate).cycle();
+        sequence.mask_steps(active_steps)
+    }
+}
+
+impl Default for EuclideanRhythmMachine {
+    fn default() -> EuclideanRhythmMachine {
+        EuclideanRhythmMachine::new()
     }
 }
 
@@ -89,15 +107,19 @@ impl Machine for EuclideanRhythmMachine {
     fn generate(&mut self, _machine_resources: &mut MachineResources) {}
 
     fn apply(&self, sequence: Sequence) -> Sequence {
-        let notes = self.params[0]
+        let pattern_length = self.params[0]
+            .value()
+            .try_into()
+            .expect("unexpected steps param for EuclideanRhythmMachine");
+        let fill = self.params[1]
             .value()
             .try_into()
-            .expect("unexpected notes param for EuclideanRhythmMachine");
-        let rotate = self.params[1]
+            .expect("unexpected fill param for EuclideanRhythmMachine");
+        let rotate = self.params[2]
             .value()
             .try_into()
             .expect("unexpected rotate param for EuclideanRhythmMachine");
-        Self::process(sequence, notes, rotate)
+        Self::process(sequence, pattern_length, fill, rotate)
     }
 }
 
@@ -106,7 +128,23 @@ unsafe impl Send for EuclideanRhythmMachine {}
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::sequence_generator::SequenceGenerator;
+    use crate::{param::ParamValue, sequence_generator::SequenceGenerator};
+
+    fn euclidean_rhythm(
+        pattern_length: u8,
+        fill: u8,
+        rotate: u8,
+        sequence_length: u8,
+    ) -> Vec<bool> {
+        let mut machine = EuclideanRhythmMachine::new();
+        machine.params[0]
+            .set(ParamValue::Number(pattern_length))
+            .unwrap();
+        machine.params[1].set(ParamValue::Number(fill)).unwrap();
+        machine.params[2].set(ParamValue::Number(rotate)).unwrap();
+        let output_sequence = machine.apply(SequenceGenerator::initial_sequence(sequence_length));
+        output_sequence.iter().map(|opt| opt.is_some()).collect()
+    }
 
     #[test]
     fn euclidean_rhythm_machine_should_smash_out_euclidean_bangers_like_it_is_not_a_thing() {
@@ -118,4 +156,38 @@ mod tests {
             [true, false, false, true, false, false, true, false]
         );
     }
+
+    #[test]
+    fn euclidean_rhythm_machine_should_generate_e_5_8() {
+        assert_eq!(
+            euclidean_rhythm(8, 5, 0, 8),
+            [true, false, true, true, false, true, true, false]
+        );
+    }
+
+    #[test]
+    fn euclidean_rhythm_machine_should_rotate_e_5_8() {
+        assert_eq!(
+            euclidean_rhythm(8, 5, 3, 8),
+            [true, true, false, true, false, true, true, false]
+        );
+    }
+
+    #[test]
+    fn euclidean_rhythm_machine_should_wrap_rotation_longer_than_pattern() {
+        assert_eq!(euclidean_rhythm(8, 5, 3, 8), euclidean_rhythm(8, 5, 27, 8));
+        assert_eq!(euclidean_rhythm(5, 2, 1, 5), euclidean_rhythm(5, 2, 31, 5));
+    }
+
+    #[test]
+    fn euclidean_rhythm_machine_should_repeat_pattern_over_longer_sequence() {
+        let active_steps = euclidean_rhythm(8, 3, 0, 20);
+        assert_eq!(active_steps[0..8], active_steps[8..16]);
+        assert_eq!(active_steps[0..4], active_steps[16..20]);
+    }
+
+    #[test]
+    fn euclidean_rhythm_machine_should_limit_fill_to_pattern_length() {
+        assert_eq!(euclidean_rhythm(4, 7, 0, 4), [true, true, true, true]);
+    }
 }
@@ -0,0 +1,131 @@
+/// Groove machine which plays a repeating pattern of loud hits and soft ghost notes.
+use super::{apply_velocity_length, GrooveMachine, Machine};
+use crate::{
+    machine_resources::MachineResources,
+    param::{Param, ParamList},
+    Sequence,
+};
+
+use alloc::boxed::Box;
+
+const PATTERN_MAX_LENGTH: u8 = 8;
+
+// velocities of the ghost note pattern: a loud first step, soft ghost notes on odd steps and
+// medium emphasis on the other even steps, e.g. 100/40/70/40
+const LOUD_VELOCITY: u8 = 100;
+const GHOST_VELOCITY: u8 = 40;
+const EMPHASIS_VELOCITY: u8 = 70;
+
+#[derive(Clone, Debug)]
+pub struct GhostGrooveMachine {
+    params: ParamList,
+}
+
+impl GhostGrooveMachine {
+    pub fn new() -> GhostGrooveMachine {
+        GhostGrooveMachine {
+            params: ParamList::from_slice(&[
+                Box::new(Param::new_number_param("GHOST", 1, PATTERN_MAX_LENGTH, 4)),
+                Box::new(Param::new_number_param("VELLEN", 0, 1, 0)),
+            ])
+            .expect("should create ghost groove machine param list from slice"),
+        }
+    }
+
+    fn process(sequence: Sequence, pattern_length: u8) -> Sequence {
+        let velocities = (0..pattern_length).cycle().map(|i| {
+            match i {
+                0 => LOUD_VELOCITY,
+                i if i % 2 == 1 => GHOST_VELOCITY,
+                _ => EMPHASIS_VELOCITY,
+            }
+            .into()
+        });
+        sequence.set_velocities(velocities)
+    }
+}
+
+impl Default for GhostGrooveMachine {
+    fn default() -> GhostGrooveMachine {
+        GhostGrooveMachine::new()
+    }
+}
+
+impl Machine for GhostGrooveMachine {
+    fn name(&self) -> &str {
+        "GHOST"
+    }
+
+    fn params(&self) -> &ParamList {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut ParamList {
+        &mut self.params
+    }
+
+    fn generate(&mut self, _machine_resources: &mut MachineResources) {}
+
+    fn apply(&self, sequence: Sequence) -> Sequence {
+        let pattern_length = self.params[0]
+            .value()
+            .try_into()
+            .expect("unexpected ghost param for GhostGrooveMachine");
+        let velocity_length: u8 = self.params[1]
+            .value()
+            .try_into()
+            .expect("unexpected velocity length param for GhostGrooveMachine");
+        let sequence = Self::process(sequence, pattern_length);
+        if velocity_length == 1 {
+            apply_velocity_length(sequence)
+        } else {
+            sequence
+        }
+    }
+}
+
+impl GrooveMachine for GhostGrooveMachine {}
+
+unsafe impl Send for GhostGrooveMachine {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{param::ParamValue, sequence_generator::SequenceGenerator};
+
+    fn velocities(machine: &GhostGrooveMachine) -> Vec<u8> {
+        machine
+            .apply(SequenceGenerator::initial_sequence(10))
+            .iter()
+            .map(|step| step.as_ref().unwrap().velocity.into())
+            .collect()
+    }
+
+    #[test]
+    fn ghost_groove_machine_should_tile_velocities_across_sequence() {
+        let mut machine = GhostGrooveMachine::new();
+        assert_eq!(
+            vec![100, 40, 70, 40, 100, 40, 70, 40, 100, 40],
+            velocities(&machine)
+        );
+        machine.params_mut()[0].set(ParamValue::Number(3)).unwrap();
+        assert_eq!(
+            vec![100, 40, 70, 100, 40, 70, 100, 40, 70, 100],
+            velocities(&machine)
+        );
+    }
+
+    #[test]
+    fn ghost_groove_machine_with_length_following_velocity_should_give_ghost_notes_short_gates() {
+        let mut machine = GhostGrooveMachine::new();
+        machine.params_mut()[1].set(ParamValue::Number(1)).unwrap();
+        let lengths: Vec<u8> = machine
+            .apply(SequenceGenerator::initial_sequence(4))
+            .iter()
+            .map(|step| step.as_ref().unwrap().length_step_cents)
+            .collect();
+        assert!(lengths[0] > lengths[2]);
+        assert!(lengths[2] > lengths[1]);
+        assert_eq!(lengths[1], lengths[3]);
+    }
+}
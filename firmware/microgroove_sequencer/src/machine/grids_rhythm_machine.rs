@@ -9,6 +9,9 @@ use crate::{
 use alloc::boxed::Box;
 use core::fmt::{Display, Formatter, Result as FmtResult};
 
+// gate length for hats, short so that they don't ring into the next step
+const HH_GATE_CENTS: u8 = 25;
+
 #[rustfmt::skip]
 const GRIDS_PATTERN_LUT_0: [u8; 96] = [
      255,      0,      0,      0,      0,      0,    145,      0,
@@ -413,6 +416,9 @@ const GRIDS_PATTERNS: [[u8; 96]; 25] = [
     GRIDS_PATTERN_LUT_24,
 ];
 
+/// The pattern tables are nodes on a 5x5 map, addressed as `GRIDS_PATTERNS[x * 5 + y]`.
+const GRIDS_MAP_SIZE: usize = 5;
+
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub enum Instrument {
     #[default]
@@ -448,7 +454,7 @@ impl TryFrom<u8> for Instrument {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct GridsRhythmMachine {
     params: ParamList,
     seed: u64,
@@ -458,7 +464,8 @@ impl GridsRhythmMachine {
     pub fn new() -> GridsRhythmMachine {
         let params = ParamList::from_slice(&[
             Box::new(Param::new_instrument_param("INST")),
-            Box::new(Param::new_number_param("TABLE", 0, 24, 0)),
+            Box::new(Param::new_number_param("MAPX", 0, 255, 0)),
+            Box::new(Param::new_number_param("MAPY", 0, 255, 0)),
             Box::new(Param::new_number_param("FILL", 0, 7, 4)),
             Box::new(Param::new_number_param("PERT", 0, 7, 0)),
         ])
@@ -466,19 +473,34 @@ impl GridsRhythmMachine {
         GridsRhythmMachine { params, seed: 0 }
     }
 
+    /// Level of each step for `instrument` at position `x`, `y` on the map, blended from the four
+    /// closest pattern tables. As in Grids, each node is 64 map units apart, so the last row and
+    /// column of tables are approached but never reached exactly.
+    fn map_levels(x: u8, y: u8, instrument: Instrument) -> impl Iterator<Item = u8> {
+        let (x_node, x_balance) = ((x >> 6) as usize, (x << 2) as u32);
+        let (y_node, y_balance) = ((y >> 6) as usize, (y << 2) as u32);
+        let table = |x, y| &GRIDS_PATTERNS[x * GRIDS_MAP_SIZE + y];
+        let (a, b) = (table(x_node, y_node), table(x_node + 1, y_node));
+        let (c, d) = (table(x_node, y_node + 1), table(x_node + 1, y_node + 1));
+        let pattern_start = 32 * instrument as usize;
+        (pattern_start..pattern_start + 32).map(move |i| {
+            let top = mix(a[i], b[i], x_balance);
+            let bottom = mix(c[i], d[i], x_balance);
+            mix(top, bottom, y_balance)
+        })
+    }
+
     fn process(
         sequence: Sequence,
-        table: u8,
+        x: u8,
+        y: u8,
         instrument: Instrument,
         fill: u8,
         seed: u64,
         perturbation: u8,
     ) -> Sequence {
-        let pattern_start = 32 * instrument as usize;
-        let pattern_end = pattern_start + 32;
-        let pattern = &GRIDS_PATTERNS[table as usize][pattern_start..pattern_end];
         let threshold = 255 - fill * 32;
-        let active_steps = pattern.iter().map(|&step_level| {
+        let active_steps = Self::map_levels(x, y, instrument).map(|step_level| {
             let some_rand = seed >> 56; // 8 bit = 0..=255
             let perturb_delta = (some_rand * perturbation as u64 >> 5) as u8;
             let level = step_level.saturating_add(perturb_delta);
@@ -488,6 +510,17 @@ impl GridsRhythmMachine {
     }
 }
 
+/// Blend from `a` to `b` by `balance`/256.
+fn mix(a: u8, b: u8, balance: u32) -> u8 {
+    ((a as u32 * (256 - balance) + b as u32 * balance) >> 8) as u8
+}
+
+impl Default for GridsRhythmMachine {
+    fn default() -> GridsRhythmMachine {
+        GridsRhythmMachine::new()
+    }
+}
+
 impl Machine for GridsRhythmMachine {
     fn name(&self) -> &str {
         "GRIDS"
@@ -510,19 +543,30 @@ impl Machine for GridsRhythmMachine {
             .value()
             .try_into()
             .expect("unexpected instrument param for GridsRhythmMachine");
-        let table = self.params[1]
+        let x = self.params[1]
+            .value()
+            .try_into()
+            .expect("unexpected map x param for GridsRhythmMachine");
+        let y = self.params[2]
             .value()
             .try_into()
-            .expect("unexpected table param for GridsRhythmMachine");
-        let fill = self.params[2]
+            .expect("unexpected map y param for GridsRhythmMachine");
+        let fill = self.params[3]
             .value()
             .try_into()
             .expect("unexpected fill param for GridsRhythmMachine");
-        let perturbation = self.params[3]
+        let perturbation = self.params[4]
             .value()
             .try_into()
             .expect("unexpected perturbation param for GridsRhythmMachine");
-        Self::process(sequence, table, instrument, fill, self.seed, perturbation)
+        Self::process(sequence, x, y, instrument, fill, self.seed, perturbation)
+    }
+
+    fn default_gate(&self) -> Option<u8> {
+        match self.params[0].value().try_into() {
+            Ok(Instrument::HH) => Some(HH_GATE_CENTS),
+            _ => None,
+        }
     }
 }
 
@@ -553,7 +597,7 @@ mod tests {
     #[test]
     fn grids_rhythm_machine_with_fill_maxxed_should_generate_filled_beat() {
         let mut machine = GridsRhythmMachine::new();
-        machine.params[2].set(ParamValue::Number(7)); // FILL
+        machine.params[3].set(ParamValue::Number(7)).unwrap(); // FILL
         let output_sequence = machine.apply(SequenceGenerator::initial_sequence(32));
         let active_steps: Vec<bool> = output_sequence.iter().map(|opt| opt.is_some()).collect();
         assert_eq!(
@@ -569,9 +613,10 @@ mod tests {
     #[test]
     fn grids_rhythm_machine_with_perturbation_enabled_should_flip_out_and_do_funky_shit() {
         let mut machine = GridsRhythmMachine::new();
-        machine.params[2].set(ParamValue::Number(7)); // FILL
-        machine.params[3].set(ParamValue::Number(7)); // PERT
-        let mut machine_resources = MachineResources::new();
+        machine.params[3].set(ParamValue::Number(7)).unwrap(); // FILL
+        machine.params[4].set(ParamValue::Number(7)).unwrap(); // PERT
+        // some random numbers leave this pattern as it was, so use a seed known to perturb it
+        let mut machine_resources = MachineResources::with_seed(1);
         machine.generate(&mut machine_resources);
         let output_sequence = machine.apply(SequenceGenerator::initial_sequence(32));
         let active_steps: Vec<bool> = output_sequence.iter().map(|opt| opt.is_some()).collect();
@@ -584,4 +629,27 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn grids_rhythm_machine_with_map_on_a_node_should_reproduce_its_table() {
+        for (x, y) in [(0, 0), (1, 3), (2, 1), (3, 3)] {
+            let table = &GRIDS_PATTERNS[x * GRIDS_MAP_SIZE + y];
+            for instrument in [Instrument::BD, Instrument::SD, Instrument::HH] {
+                let levels: Vec<u8> =
+                    GridsRhythmMachine::map_levels(x as u8 * 64, y as u8 * 64, instrument)
+                        .collect();
+                let pattern_start = 32 * instrument as usize;
+                assert_eq!(table[pattern_start..pattern_start + 32], levels);
+            }
+        }
+    }
+
+    #[test]
+    fn grids_rhythm_machine_between_nodes_should_blend_tables() {
+        let levels: Vec<u8> = GridsRhythmMachine::map_levels(32, 0, Instrument::BD).collect();
+        for (i, level) in levels.into_iter().enumerate() {
+            let (a, b) = (GRIDS_PATTERNS[0][i], GRIDS_PATTERNS[GRIDS_MAP_SIZE][i]);
+            assert_eq!(((a as u32 + b as u32) / 2) as u8, level);
+        }
+    }
 }
@@ -0,0 +1,265 @@
+/// Machine which learns how a short random seed melody moves between scale degrees, then walks
+/// those moves as a first-order Markov chain to generate new melodies in the same style.
+use super::Machine;
+use crate::{
+    machine_resources::MachineResources,
+    midi::{Note, NoteBounds},
+    param::{Param, ParamList, ParamValue},
+    quantizer::Scale,
+    Sequence, SEQUENCE_MAX_STEPS,
+};
+
+use alloc::boxed::Box;
+
+/// Number of states in the chain. Degrees past the top of the scale continue into the next
+/// octave, so small scales span more than one octave.
+const DEGREE_COUNT: usize = 12;
+
+/// Number of notes in the melody the chain is trained on.
+const SEED_MELODY_LENGTH: usize = 16;
+
+const ORDER_MAX: u8 = 4;
+
+const VARIATION_MAX: u8 = 100;
+
+#[derive(Clone, Debug)]
+pub struct MarkovMachine {
+    params: ParamList,
+
+    /// Number of times each degree, indexed by row, was followed by each other degree, indexed by
+    /// column, in the seed melody.
+    transitions: [[u8; DEGREE_COUNT]; DEGREE_COUNT],
+
+    /// Degrees of the seed melody, which also start each generated melody.
+    seed_melody: [u8; SEED_MELODY_LENGTH],
+
+    /// Degrees of the generated melody, one for each active step.
+    melody: [u8; SEQUENCE_MAX_STEPS],
+
+    /// Value of the RESEED param when the chain was last trained, or `None` if it hasn't been
+    /// trained yet.
+    trained_reseed: Option<u8>,
+}
+
+impl MarkovMachine {
+    pub fn new() -> MarkovMachine {
+        let mut scale_param = Param::new_scale_param("SCALE");
+        scale_param
+            .set(ParamValue::Scale(Scale::Major))
+            .expect("should set markov scale param");
+        MarkovMachine {
+            params: ParamList::from_slice(&[
+                Box::new(Param::new_note_param("ROOT")),
+                Box::new(scale_param),
+                Box::new(Param::new_number_param("ORDER", 1, ORDER_MAX, 1)),
+                Box::new(Param::new_number_param("VAR", 0, VARIATION_MAX, 50)),
+                Box::new(Param::new_number_param("RESEED", 0, 1, 0)),
+            ])
+            .expect("should create markov machine param list from slice"),
+            transitions: [[0; DEGREE_COUNT]; DEGREE_COUNT],
+            seed_melody: [0; SEED_MELODY_LENGTH],
+            melody: [0; SEQUENCE_MAX_STEPS],
+            trained_reseed: None,
+        }
+    }
+
+    /// Steps back in the melody to the note which chooses the next one. At 1 each note follows
+    /// from the one before it. Higher orders interleave several chains, for longer phrases.
+    fn order(&self) -> usize {
+        let order: u8 = self.params[2]
+            .value()
+            .try_into()
+            .expect("unexpected order param for MarkovMachine");
+        order as usize
+    }
+
+    fn variation(&self) -> u8 {
+        self.params[3]
+            .value()
+            .try_into()
+            .expect("unexpected variation param for MarkovMachine")
+    }
+
+    fn reseed(&self) -> u8 {
+        self.params[4]
+            .value()
+            .try_into()
+            .expect("unexpected reseed param for MarkovMachine")
+    }
+
+    /// Make up a new seed melody, a random walk of small steps up and down the scale, and count
+    /// the transitions between its degrees. The melody is treated as a loop, so every degree it
+    /// visits has at least one transition out.
+    fn train(&mut self, machine_resources: &mut MachineResources) {
+        let mut degree = (machine_resources.random_u64() % DEGREE_COUNT as u64) as i32;
+        for seed_degree in self.seed_melody.iter_mut() {
+            *seed_degree = degree as u8;
+            let step = (machine_resources.random_u64() % 5) as i32 - 2;
+            degree = (degree + step).clamp(0, DEGREE_COUNT as i32 - 1);
+        }
+        let order = self.order();
+        self.transitions = [[0; DEGREE_COUNT]; DEGREE_COUNT];
+        for i in 0..SEED_MELODY_LENGTH {
+            let from = self.seed_melody[i] as usize;
+            let to = self.seed_melody[(i + order) % SEED_MELODY_LENGTH] as usize;
+            self.transitions[from][to] = self.transitions[from][to].saturating_add(1);
+        }
+    }
+
+    /// Choose the degree to follow `from`. At 0 variation, always take the most common
+    /// transition. Higher variation flattens the odds towards every degree being equally likely.
+    fn next_degree(&self, from: u8, variation: u8, random: u64) -> u8 {
+        let counts = &self.transitions[from as usize];
+        if variation == 0 {
+            let most_common = (0..DEGREE_COUNT).max_by_key(|&to| (counts[to], DEGREE_COUNT - to));
+            return most_common.unwrap_or(from as usize) as u8;
+        }
+        let weight =
+            |to: usize| counts[to] as u64 * (VARIATION_MAX - variation) as u64 + variation as u64;
+        let total: u64 = (0..DEGREE_COUNT).map(weight).sum();
+        let mut choice = random % total;
+        for to in 0..DEGREE_COUNT {
+            if choice < weight(to) {
+                return to as u8;
+            }
+            choice -= weight(to);
+        }
+        from
+    }
+
+    /// Note for `degree` of `scale` above `root`, folding back into the MIDI note range.
+    fn note(root: Note, scale: Scale, degree: u8) -> Note {
+        let scale_degrees = scale.degrees();
+        let octave = degree as usize / scale_degrees.len();
+        let semitones = scale_degrees[degree as usize % scale_degrees.len()] as i32;
+        let root_num: u8 = root.into();
+        NoteBounds::Fold.apply(root_num as i32 + octave as i32 * 12 + semitones)
+    }
+
+    fn process(sequence: Sequence, root: Note, scale: Scale, melody: &[u8]) -> Sequence {
+        let mut i = 0;
+        sequence.map_notes(|_| {
+            let note = Self::note(root, scale, melody[i % melody.len()]);
+            i += 1;
+            note
+        })
+    }
+}
+
+impl Default for MarkovMachine {
+    fn default() -> MarkovMachine {
+        MarkovMachine::new()
+    }
+}
+
+impl Machine for MarkovMachine {
+    fn name(&self) -> &str {
+        "MARKOV"
+    }
+
+    fn params(&self) -> &ParamList {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut ParamList {
+        &mut self.params
+    }
+
+    /// Walk the chain to make a new melody, starting from the seed melody. The chain is trained
+    /// the first time, and again whenever RESEED has been changed since it was last trained.
+    fn generate(&mut self, machine_resources: &mut MachineResources) {
+        let reseed = self.reseed();
+        if self.trained_reseed != Some(reseed) {
+            self.train(machine_resources);
+            self.trained_reseed = Some(reseed);
+        }
+        let order = self.order();
+        let variation = self.variation();
+        self.melody[..order].copy_from_slice(&self.seed_melody[..order]);
+        for i in order..SEQUENCE_MAX_STEPS {
+            let random = machine_resources.random_u64();
+            self.melody[i] = self.next_degree(self.melody[i - order], variation, random);
+        }
+    }
+
+    fn apply(&self, sequence: Sequence) -> Sequence {
+        let root = self.params[0]
+            .value()
+            .try_into()
+            .expect("unexpected root param for MarkovMachine");
+        let scale = self.params[1]
+            .value()
+            .try_into()
+            .expect("unexpected scale param for MarkovMachine");
+        Self::process(sequence, root, scale, &self.melody)
+    }
+}
+
+unsafe impl Send for MarkovMachine {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sequence_generator::SequenceGenerator;
+
+    fn notes(sequence: &Sequence) -> std::vec::Vec<u8> {
+        sequence
+            .iter()
+            .map(|step| step.as_ref().unwrap().note.into())
+            .collect()
+    }
+
+    fn in_scale(note_num: u8, root: Note, scale: Scale) -> bool {
+        let root_num: u8 = root.into();
+        let degree = (note_num + 12 - root_num % 12) % 12;
+        scale.degrees().contains(&degree)
+    }
+
+    #[test]
+    fn markov_machine_should_generate_different_melodies_in_scale() {
+        let mut machine_resources = MachineResources::with_seed(1);
+        let mut machine = MarkovMachine::new();
+        machine.params_mut()[1]
+            .set(ParamValue::Scale(Scale::PentatonicMinor))
+            .unwrap();
+        machine.generate(&mut machine_resources);
+        let first = notes(&machine.apply(SequenceGenerator::initial_sequence(16)));
+        machine.generate(&mut machine_resources);
+        let second = notes(&machine.apply(SequenceGenerator::initial_sequence(16)));
+        assert_ne!(first, second);
+        for note_num in first.into_iter().chain(second) {
+            assert!(in_scale(note_num, Note::default(), Scale::PentatonicMinor));
+        }
+    }
+
+    #[test]
+    fn markov_machine_with_min_variation_should_repeat_melody() {
+        let mut machine_resources = MachineResources::with_seed(2);
+        let mut machine = MarkovMachine::new();
+        machine.params_mut()[3].set(ParamValue::Number(0)).unwrap();
+        machine.generate(&mut machine_resources);
+        let melody = machine.melody;
+        machine.generate(&mut machine_resources);
+        assert_eq!(melody, machine.melody);
+    }
+
+    #[test]
+    fn markov_machine_should_retrain_when_reseeded() {
+        let mut machine_resources = MachineResources::with_seed(3);
+        let mut machine = MarkovMachine::new();
+        machine.generate(&mut machine_resources);
+        let transitions = machine.transitions;
+        machine.generate(&mut machine_resources);
+        assert_eq!(transitions, machine.transitions);
+        machine.params_mut()[4].set(ParamValue::Number(1)).unwrap();
+        machine.generate(&mut machine_resources);
+        assert_ne!(transitions, machine.transitions);
+        let total: u32 = machine
+            .transitions
+            .iter()
+            .flatten()
+            .map(|&n| n as u32)
+            .sum();
+        assert_eq!(SEED_MELODY_LENGTH as u32, total);
+    }
+}
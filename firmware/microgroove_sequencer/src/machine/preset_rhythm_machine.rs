@@ -0,0 +1,186 @@
+/// Machine which plays classic drum patterns, for quick starts.
+use super::Machine;
+use crate::{
+    machine_resources::MachineResources,
+    param::{Param, ParamList},
+    Sequence,
+};
+
+use alloc::boxed::Box;
+use core::fmt::{Display, Formatter, Result as FmtResult};
+
+/// Length of the stored patterns. Tracks of other lengths get the pattern scaled to fit.
+const PRESET_PATTERN_LENGTH: usize = 16;
+
+/// Kick and snare hits of each preset, one bit per 16th, first step in the highest bit.
+#[rustfmt::skip]
+const PRESET_PATTERNS: [u16; 3] = [
+    0b1000_1000_1000_1000, // four on the floor: x...x...x...x...
+    0b1000_1001_0100_1000, // boom bap:          x...x..x.x..x...
+    0b1010_1001_0110_1001, // breakbeat:         x.x.x..x.xx.x..x
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Preset {
+    #[default]
+    FourOnFloor,
+    BoomBap,
+    Breakbeat,
+}
+
+impl Preset {
+    fn pattern(&self) -> u16 {
+        PRESET_PATTERNS[*self as usize]
+    }
+}
+
+impl Display for Preset {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Preset::FourOnFloor => "4FLOOR",
+                Preset::BoomBap => "BOOMBP",
+                Preset::Breakbeat => "BREAK",
+            }
+        )
+    }
+}
+
+impl TryFrom<u8> for Preset {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Preset::FourOnFloor),
+            1 => Ok(Preset::BoomBap),
+            2 => Ok(Preset::Breakbeat),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PresetRhythmMachine {
+    params: ParamList,
+}
+
+impl PresetRhythmMachine {
+    pub fn new() -> PresetRhythmMachine {
+        PresetRhythmMachine {
+            params: ParamList::from_slice(&[
+                Box::new(Param::new_preset_param("PATTRN")),
+                Box::new(Param::new_number_param("ROTATE", 0, 31, 0)),
+            ])
+            .expect("should create preset rhythm machine param list from slice"),
+        }
+    }
+
+    /// Mask `sequence` with `preset`. Each hit of the pattern lands on the step at the same
+    /// position through the sequence, so longer sequences spread the hits out and shorter
+    /// sequences squeeze them together.
+    fn process(sequence: Sequence, preset: Preset, rotate: u8) -> Sequence {
+        let steps = sequence.len();
+        if steps == 0 {
+            return sequence;
+        }
+        let pattern = preset.pattern();
+        let active_steps = (0..steps).map(|i| {
+            (0..PRESET_PATTERN_LENGTH).any(|hit| {
+                (pattern >> (PRESET_PATTERN_LENGTH - hit - 1)) & 1 == 1
+                    && hit * steps / PRESET_PATTERN_LENGTH == i
+            })
+        });
+        let rotate = rotate as usize % steps;
+        sequence.mask_steps(active_steps).rotate_right(rotate)
+    }
+}
+
+impl Default for PresetRhythmMachine {
+    fn default() -> PresetRhythmMachine {
+        PresetRhythmMachine::new()
+    }
+}
+
+impl Machine for PresetRhythmMachine {
+    fn name(&self) -> &str {
+        "PRESET"
+    }
+
+    fn params(&self) -> &ParamList {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut ParamList {
+        &mut self.params
+    }
+
+    fn generate(&mut self, _machine_resources: &mut MachineResources) {}
+
+    fn apply(&self, sequence: Sequence) -> Sequence {
+        let preset = self.params[0]
+            .value()
+            .try_into()
+            .expect("unexpected pattern param for PresetRhythmMachine");
+        let rotate = self.params[1]
+            .value()
+            .try_into()
+            .expect("unexpected rotate param for PresetRhythmMachine");
+        Self::process(sequence, preset, rotate)
+    }
+}
+
+unsafe impl Send for PresetRhythmMachine {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{param::ParamValue, sequence_generator::SequenceGenerator};
+
+    fn active_steps(machine: &PresetRhythmMachine, length: u8) -> std::string::String {
+        machine
+            .apply(SequenceGenerator::initial_sequence(length))
+            .iter()
+            .map(|step| if step.is_some() { 'x' } else { '.' })
+            .collect()
+    }
+
+    #[test]
+    fn preset_rhythm_machine_should_play_each_preset_at_length_16() {
+        let mut machine = PresetRhythmMachine::new();
+        for (preset, expected) in [
+            (Preset::FourOnFloor, "x...x...x...x..."),
+            (Preset::BoomBap, "x...x..x.x..x..."),
+            (Preset::Breakbeat, "x.x.x..x.xx.x..x"),
+        ] {
+            machine.params_mut()[0]
+                .set(ParamValue::Preset(preset))
+                .unwrap();
+            assert_eq!(expected, active_steps(&machine, 16), "{}", preset);
+        }
+    }
+
+    #[test]
+    fn preset_rhythm_machine_should_scale_presets_to_other_lengths() {
+        let mut machine = PresetRhythmMachine::new();
+        assert_eq!("x.x.x.x.", active_steps(&machine, 8));
+        assert_eq!(
+            "x.......x.......x.......x.......",
+            active_steps(&machine, 32)
+        );
+        machine.params_mut()[0]
+            .set(ParamValue::Preset(Preset::BoomBap))
+            .unwrap();
+        assert_eq!("x.xxx.x.", active_steps(&machine, 8));
+    }
+
+    #[test]
+    fn preset_rhythm_machine_should_rotate_preset() {
+        let mut machine = PresetRhythmMachine::new();
+        machine.params_mut()[1].set(ParamValue::Number(2)).unwrap();
+        assert_eq!("..x...x...x...x.", active_steps(&machine, 16));
+        machine.params_mut()[1].set(ParamValue::Number(17)).unwrap();
+        assert_eq!(".x...x...x...x..", active_steps(&machine, 16));
+    }
+}
@@ -3,14 +3,14 @@ use super::Machine;
 use crate::{
     machine_resources::MachineResources,
     map_to_range,
-    midi::Note,
+    midi::{Note, NoteBounds},
     param::{Param, ParamList},
     Sequence,
 };
 
 use alloc::boxed::Box;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct RandMelodyMachine {
     params: ParamList,
     seed: u64,
@@ -32,11 +32,10 @@ impl RandMelodyMachine {
         let mut i = 0;
         sequence.map_notes(|_| {
             let rand_note_num = ((seed >> i) & 127) as i32;
-            let note_num = map_to_range(rand_note_num, 0, 127, min_note, max_note) as u8;
+            let note_num = map_to_range(rand_note_num, 0, 127, min_note, max_note);
             i += 1;
-            note_num
-                .try_into()
-                .expect("note number should go into note")
+            // a high root and wide range can reach past the top note, fold back into range
+            NoteBounds::Fold.apply(note_num)
         })
     }
 }
@@ -76,7 +75,10 @@ unsafe impl Send for RandMelodyMachine {}
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{machine_resources::MachineResources, sequence_generator::SequenceGenerator};
+    use crate::{
+        machine_resources::MachineResources, param::ParamValue,
+        sequence_generator::SequenceGenerator,
+    };
 
     #[test]
     fn rand_melody_machine_should_generate_stable_sequence() {
@@ -116,4 +118,20 @@ mod tests {
             note >= root_note && note <= max_note
         }));
     }
+
+    #[test]
+    fn rand_melody_machine_should_fold_notes_above_the_top_of_the_range() {
+        let mut machine_resources = MachineResources::new();
+        let mut machine = RandMelodyMachine::new();
+        machine.params_mut()[0]
+            .set(ParamValue::Note(Note::C8))
+            .unwrap();
+        machine.params_mut()[1].set(ParamValue::Number(24)).unwrap();
+        machine.generate(&mut machine_resources);
+        let output_sequence = machine.apply(SequenceGenerator::initial_sequence(16));
+        assert!(output_sequence.iter().all(|step| {
+            let note: u8 = step.as_ref().unwrap().note.into();
+            note >= 108
+        }));
+    }
 }
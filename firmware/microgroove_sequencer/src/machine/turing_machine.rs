@@ -0,0 +1,215 @@
+/// Machine modelled on the Turing Machine eurorack module: a loop of random bits which can
+/// slowly mutate. Each step reads a window of the loop as a note, so melodies repeat every LEN
+/// steps and drift as bits flip.
+use super::Machine;
+use crate::{
+    machine_resources::MachineResources,
+    midi::NoteBounds,
+    param::{Param, ParamList},
+    Sequence,
+};
+
+use alloc::boxed::Box;
+
+/// Longest loop of bits, one per step.
+const REGISTER_MAX_LENGTH: u8 = 16;
+
+/// Number of bits read from the loop for each step's note.
+const WINDOW_BITS: usize = 8;
+
+const PROBABILITY_MAX: u8 = 100;
+
+#[derive(Clone, Debug)]
+pub struct TuringMachine {
+    params: ParamList,
+
+    /// The loop of bits. Only the lowest LEN bits are played.
+    register: u16,
+
+    /// False until the register has been filled with random bits by the first call to
+    /// `generate`.
+    seeded: bool,
+}
+
+impl TuringMachine {
+    pub fn new() -> TuringMachine {
+        TuringMachine {
+            params: ParamList::from_slice(&[
+                Box::new(Param::new_number_param("LEN", 2, REGISTER_MAX_LENGTH, 8)),
+                Box::new(Param::new_number_param("PROB", 0, PROBABILITY_MAX, 10)),
+                Box::new(Param::new_number_param("RANGE", 1, 60, 12)),
+            ])
+            .expect("should create turing machine param list from slice"),
+            register: 0,
+            seeded: false,
+        }
+    }
+
+    fn length(&self) -> u8 {
+        self.params[0]
+            .value()
+            .try_into()
+            .expect("unexpected length param for TuringMachine")
+    }
+
+    fn probability(&self) -> u8 {
+        self.params[1]
+            .value()
+            .try_into()
+            .expect("unexpected probability param for TuringMachine")
+    }
+
+    /// Bits read for `step_num`, starting at its own bit of the loop and wrapping around the
+    /// loop, so that step `step_num + length` reads the same bits.
+    fn window(register: u16, length: u8, step_num: usize) -> u32 {
+        (0..WINDOW_BITS).fold(0, |window, bit| {
+            let register_bit = (step_num + bit) % length as usize;
+            (window << 1) | ((register >> register_bit) as u32 & 1)
+        })
+    }
+
+    /// Offset each step's note by the window of bits for its position, spread over `range`
+    /// semitones. Steps whose window ends in a 0 are played softer.
+    fn process(sequence: Sequence, register: u16, length: u8, range: u8) -> Sequence {
+        let mut steps = sequence.steps.clone();
+        for (step_num, step) in steps.iter_mut().enumerate() {
+            if let Some(step) = step {
+                let window = Self::window(register, length, step_num);
+                let offset = (window * range as u32) >> WINDOW_BITS;
+                let note_num: u8 = step.note.into();
+                step.note = NoteBounds::Fold.apply(note_num as i32 + offset as i32);
+                if window & 1 == 0 {
+                    step.velocity = ((u8::from(step.velocity) as u16 * 3 / 4) as u8).into();
+                }
+            }
+        }
+        sequence.set_steps(steps)
+    }
+}
+
+impl Default for TuringMachine {
+    fn default() -> TuringMachine {
+        TuringMachine::new()
+    }
+}
+
+impl Machine for TuringMachine {
+    fn name(&self) -> &str {
+        "TURING"
+    }
+
+    fn params(&self) -> &ParamList {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut ParamList {
+        &mut self.params
+    }
+
+    /// Fill the loop with random bits the first time, then flip each bit of the loop with a
+    /// chance of PROB%. At 0% the loop is locked, at 100% every bit is inverted.
+    fn generate(&mut self, machine_resources: &mut MachineResources) {
+        if !self.seeded {
+            self.register = machine_resources.random_u64() as u16;
+            self.seeded = true;
+            return;
+        }
+        let probability = self.probability() as u64;
+        for bit in 0..self.length() {
+            if machine_resources.random_u64() % (PROBABILITY_MAX as u64) < probability {
+                self.register ^= 1 << bit;
+            }
+        }
+    }
+
+    fn apply(&self, sequence: Sequence) -> Sequence {
+        let range = self.params[2]
+            .value()
+            .try_into()
+            .expect("unexpected range param for TuringMachine");
+        Self::process(sequence, self.register, self.length(), range)
+    }
+}
+
+unsafe impl Send for TuringMachine {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{param::ParamValue, sequence_generator::SequenceGenerator};
+
+    fn notes(sequence: &Sequence) -> std::vec::Vec<u8> {
+        sequence
+            .iter()
+            .map(|step| step.as_ref().unwrap().note.into())
+            .collect()
+    }
+
+    fn turing_machine(length: u8, probability: u8) -> TuringMachine {
+        let mut machine = TuringMachine::new();
+        machine.params_mut()[0]
+            .set(ParamValue::Number(length))
+            .unwrap();
+        machine.params_mut()[1]
+            .set(ParamValue::Number(probability))
+            .unwrap();
+        machine
+    }
+
+    #[test]
+    fn turing_machine_should_loop_after_len_steps() {
+        let mut machine_resources = MachineResources::with_seed(1);
+        let mut machine = turing_machine(5, 0);
+        machine.generate(&mut machine_resources);
+        let notes = notes(&machine.apply(SequenceGenerator::initial_sequence(20)));
+        assert_eq!(notes[0..5], notes[5..10]);
+        assert_eq!(notes[0..5], notes[15..20]);
+        assert!(notes[0..5].iter().any(|&note| note != notes[0]));
+    }
+
+    #[test]
+    fn turing_machine_with_min_probability_should_lock_loop() {
+        let mut machine_resources = MachineResources::with_seed(2);
+        let mut machine = turing_machine(8, 0);
+        machine.generate(&mut machine_resources);
+        let sequence = machine.apply(SequenceGenerator::initial_sequence(16));
+        for _ in 0..4 {
+            machine.generate(&mut machine_resources);
+            assert_eq!(
+                sequence,
+                machine.apply(SequenceGenerator::initial_sequence(16))
+            );
+        }
+    }
+
+    #[test]
+    fn turing_machine_with_max_probability_should_invert_loop_and_keep_looping() {
+        let mut machine_resources = MachineResources::with_seed(3);
+        let mut machine = turing_machine(8, PROBABILITY_MAX);
+        machine.generate(&mut machine_resources);
+        let register = machine.register;
+        machine.generate(&mut machine_resources);
+        assert_eq!(register ^ 0xff, machine.register);
+        let notes = notes(&machine.apply(SequenceGenerator::initial_sequence(16)));
+        assert_eq!(notes[0..8], notes[8..16]);
+        machine.generate(&mut machine_resources);
+        assert_eq!(register, machine.register);
+    }
+
+    #[test]
+    fn turing_machine_should_keep_notes_within_range() {
+        let mut machine_resources = MachineResources::with_seed(4);
+        let mut machine = turing_machine(16, 50);
+        machine.params_mut()[2].set(ParamValue::Number(7)).unwrap();
+        let root: u8 = SequenceGenerator::initial_sequence(1).steps[0]
+            .as_ref()
+            .unwrap()
+            .note
+            .into();
+        for _ in 0..8 {
+            machine.generate(&mut machine_resources);
+            let notes = notes(&machine.apply(SequenceGenerator::initial_sequence(16)));
+            assert!(notes.iter().all(|&note| (root..root + 7).contains(&note)));
+        }
+    }
+}
@@ -1,8 +1,8 @@
 /// Reference machine which passes sequence input through unmodified.
-use super::Machine;
+use super::{GrooveMachine, Machine};
 use crate::{machine_resources::MachineResources, param::ParamList, Sequence};
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct UnitMachine {
     params: ParamList,
 }
@@ -35,6 +35,8 @@ impl Machine for UnitMachine {
     }
 }
 
+impl GrooveMachine for UnitMachine {}
+
 unsafe impl Send for UnitMachine {}
 
 #[cfg(test)]
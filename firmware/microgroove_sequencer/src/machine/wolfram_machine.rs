@@ -0,0 +1,206 @@
+/// Machine which plays a row of an elementary cellular automaton, as described by Stephen
+/// Wolfram. Each step plays if its cell is alive, and the row evolves by RULE every time the
+/// machine generates, so the melody grows and decays over time.
+use super::Machine;
+use crate::{
+    machine_resources::MachineResources,
+    midi::NoteBounds,
+    param::{Param, ParamList},
+    Sequence, SEQUENCE_MAX_STEPS,
+};
+
+use alloc::boxed::Box;
+
+/// Number of cells in the row, one per step. The ends of the row wrap around.
+const ROW_WIDTH: usize = SEQUENCE_MAX_STEPS;
+
+/// Number of cells read from the row for each step's note.
+const WINDOW_BITS: usize = 8;
+
+const DENSITY_MAX: u8 = 100;
+
+#[derive(Clone, Debug)]
+pub struct WolframMachine {
+    params: ParamList,
+
+    /// One bit per cell, set if the cell is alive.
+    row: u32,
+
+    /// False until the row has been filled with random cells by the first call to `generate`.
+    seeded: bool,
+}
+
+impl WolframMachine {
+    pub fn new() -> WolframMachine {
+        WolframMachine {
+            params: ParamList::from_slice(&[
+                Box::new(Param::new_number_param("RULE", 0, 255, 30)),
+                Box::new(Param::new_number_param("SEED", 1, DENSITY_MAX, 50)),
+                Box::new(Param::new_number_param("RANGE", 1, 60, 12)),
+            ])
+            .expect("should create wolfram machine param list from slice"),
+            // every cell alive, so the machine plays every step until it first generates
+            row: u32::MAX,
+            seeded: false,
+        }
+    }
+
+    fn rule(&self) -> u8 {
+        self.params[0]
+            .value()
+            .try_into()
+            .expect("unexpected rule param for WolframMachine")
+    }
+
+    fn density(&self) -> u8 {
+        self.params[1]
+            .value()
+            .try_into()
+            .expect("unexpected seed density param for WolframMachine")
+    }
+
+    fn cell(row: u32, cell_num: usize) -> u32 {
+        (row >> (cell_num % ROW_WIDTH)) & 1
+    }
+
+    /// The next generation of `row`. Each cell's next state is the bit of `rule` indexed by the
+    /// current states of its left neighbour, itself and its right neighbour.
+    fn next_generation(row: u32, rule: u8) -> u32 {
+        (0..ROW_WIDTH).fold(0, |next_row, cell_num| {
+            let neighbourhood = (Self::cell(row, cell_num + ROW_WIDTH - 1) << 2)
+                | (Self::cell(row, cell_num) << 1)
+                | Self::cell(row, cell_num + 1);
+            next_row | ((rule as u32 >> neighbourhood) & 1) << cell_num
+        })
+    }
+
+    /// Cells read for `step_num`, starting at its own cell and wrapping around the row.
+    fn window(row: u32, step_num: usize) -> u32 {
+        (0..WINDOW_BITS).fold(0, |window, bit| {
+            (window << 1) | Self::cell(row, step_num + bit)
+        })
+    }
+
+    /// Silence steps whose cell is dead, and offset the notes of the others by the cells around
+    /// them, spread over `range` semitones.
+    fn process(sequence: Sequence, row: u32, range: u8) -> Sequence {
+        let mut steps = sequence.steps.clone();
+        for (step_num, step) in steps.iter_mut().enumerate() {
+            if Self::cell(row, step_num) == 0 {
+                step.take();
+                continue;
+            }
+            if let Some(step) = step {
+                let offset = (Self::window(row, step_num) * range as u32) >> WINDOW_BITS;
+                let note_num: u8 = step.note.into();
+                step.note = NoteBounds::Fold.apply(note_num as i32 + offset as i32);
+            }
+        }
+        sequence.set_steps(steps)
+    }
+}
+
+impl Default for WolframMachine {
+    fn default() -> WolframMachine {
+        WolframMachine::new()
+    }
+}
+
+impl Machine for WolframMachine {
+    fn name(&self) -> &str {
+        "WOLFRM"
+    }
+
+    fn params(&self) -> &ParamList {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut ParamList {
+        &mut self.params
+    }
+
+    /// Advance the row by one generation. The row is filled with random cells, SEED% of them
+    /// alive, the first time, and again whenever every cell has died.
+    fn generate(&mut self, machine_resources: &mut MachineResources) {
+        if !self.seeded || self.row == 0 {
+            let density = self.density() as u64;
+            self.row = (0..ROW_WIDTH).fold(0, |row, cell_num| {
+                let alive = machine_resources.random_u64() % (DENSITY_MAX as u64) < density;
+                row | (alive as u32) << cell_num
+            });
+            self.seeded = true;
+        }
+        self.row = Self::next_generation(self.row, self.rule());
+    }
+
+    fn apply(&self, sequence: Sequence) -> Sequence {
+        let range = self.params[2]
+            .value()
+            .try_into()
+            .expect("unexpected range param for WolframMachine");
+        Self::process(sequence, self.row, range)
+    }
+}
+
+unsafe impl Send for WolframMachine {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{param::ParamValue, sequence_generator::SequenceGenerator};
+
+    fn wolfram_machine(rule: u8) -> WolframMachine {
+        let mut machine = WolframMachine::new();
+        machine.params_mut()[0]
+            .set(ParamValue::Number(rule))
+            .unwrap();
+        machine
+    }
+
+    fn active_steps(sequence: &Sequence) -> std::vec::Vec<bool> {
+        sequence.iter().map(|step| step.is_some()).collect()
+    }
+
+    #[test]
+    fn wolfram_machine_with_rule_0_should_be_silent() {
+        let mut machine_resources = MachineResources::with_seed(1);
+        let mut machine = wolfram_machine(0);
+        for _ in 0..4 {
+            machine.generate(&mut machine_resources);
+            let sequence = machine.apply(SequenceGenerator::initial_sequence(16));
+            assert!(active_steps(&sequence).iter().all(|&active| !active));
+        }
+    }
+
+    #[test]
+    fn wolfram_machine_with_rule_255_should_fill_every_step() {
+        let mut machine_resources = MachineResources::with_seed(2);
+        let mut machine = wolfram_machine(255);
+        for _ in 0..4 {
+            machine.generate(&mut machine_resources);
+            let sequence = machine.apply(SequenceGenerator::initial_sequence(32));
+            assert!(active_steps(&sequence).iter().all(|&active| active));
+        }
+    }
+
+    #[test]
+    fn wolfram_machine_should_evolve_row_between_generations() {
+        let mut machine_resources = MachineResources::with_seed(3);
+        let mut machine = wolfram_machine(30);
+        machine.generate(&mut machine_resources);
+        let row = machine.row;
+        machine.generate(&mut machine_resources);
+        assert_eq!(WolframMachine::next_generation(row, 30), machine.row);
+        assert_ne!(row, machine.row);
+    }
+
+    #[test]
+    fn wolfram_machine_next_generation_should_follow_rule() {
+        // rule 90 is the XOR of each cell's neighbours, so a single cell splits in two
+        assert_eq!(0b1010 << 8, WolframMachine::next_generation(0b100 << 8, 90));
+        // rule 204 leaves each cell as it is
+        assert_eq!(0xdeadbeef, WolframMachine::next_generation(0xdeadbeef, 204));
+        // cells at the ends of the row are neighbours
+        assert_eq!(0b11 | 1 << 31, WolframMachine::next_generation(1, 254));
+    }
+}
@@ -0,0 +1,117 @@
+/// Browse every rhythm, groove and melody machine in turn, to audition them on a track.
+use crate::{
+    machine::{GrooveMachineId, MelodyMachineId, RhythmMachineId},
+    param::wrapping_add,
+    sequence_generator::SequenceGenerator,
+};
+
+use core::fmt::{Display, Formatter, Result as FmtResult};
+
+/// A machine listed in the browser.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BrowserEntry {
+    Rhythm(RhythmMachineId),
+    Groove(GrooveMachineId),
+    Melody(MelodyMachineId),
+}
+
+/// Every machine, in the order they're browsed.
+pub const BROWSER_ENTRIES: [BrowserEntry; 13] = [
+    BrowserEntry::Rhythm(RhythmMachineId::Unit),
+    BrowserEntry::Rhythm(RhythmMachineId::Euclid),
+    BrowserEntry::Rhythm(RhythmMachineId::Grids),
+    BrowserEntry::Rhythm(RhythmMachineId::Preset),
+    BrowserEntry::Groove(GrooveMachineId::Unit),
+    BrowserEntry::Groove(GrooveMachineId::Accent),
+    BrowserEntry::Groove(GrooveMachineId::Ghost),
+    BrowserEntry::Melody(MelodyMachineId::Unit),
+    BrowserEntry::Melody(MelodyMachineId::Rand),
+    BrowserEntry::Melody(MelodyMachineId::Arp),
+    BrowserEntry::Melody(MelodyMachineId::Turing),
+    BrowserEntry::Melody(MelodyMachineId::Wolfram),
+    BrowserEntry::Melody(MelodyMachineId::Markov),
+];
+
+impl BrowserEntry {
+    pub fn description(&self) -> &'static str {
+        match self {
+            BrowserEntry::Rhythm(id) => id.description(),
+            BrowserEntry::Groove(id) => id.description(),
+            BrowserEntry::Melody(id) => id.description(),
+        }
+    }
+
+    /// Switch the generator to this machine, replacing the rhythm, groove or melody machine as
+    /// appropriate.
+    pub fn select(&self, generator: &mut SequenceGenerator) {
+        match *self {
+            BrowserEntry::Rhythm(id) => generator.set_rhythm_machine(id),
+            BrowserEntry::Groove(id) => generator.set_groove_machine(id),
+            BrowserEntry::Melody(id) => generator.set_melody_machine(id),
+        }
+    }
+}
+
+impl Display for BrowserEntry {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            BrowserEntry::Rhythm(id) => Display::fmt(id, f),
+            BrowserEntry::Groove(id) => Display::fmt(id, f),
+            BrowserEntry::Melody(id) => Display::fmt(id, f),
+        }
+    }
+}
+
+/// Position in the list of machines. Scrolling wraps around at either end.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MachineBrowser {
+    index: usize,
+}
+
+impl MachineBrowser {
+    pub fn selected(&self) -> BrowserEntry {
+        BROWSER_ENTRIES[self.index]
+    }
+
+    pub fn scroll(&mut self, n: i32) {
+        self.index = wrapping_add(self.index as i32, n, BROWSER_ENTRIES.len() as i32 - 1) as usize;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn machine_browser_select_should_instantiate_each_machine() {
+        let mut browser = MachineBrowser::default();
+        let mut generator = SequenceGenerator::default();
+        for entry in BROWSER_ENTRIES {
+            assert_eq!(entry, browser.selected());
+            entry.select(&mut generator);
+            let machine_name = match entry {
+                BrowserEntry::Rhythm(_) => generator.rhythm_machine.name(),
+                BrowserEntry::Groove(_) => generator.groove_machine.name(),
+                BrowserEntry::Melody(_) => generator.melody_machine.name(),
+            };
+            assert_eq!(entry.to_string(), machine_name);
+            assert!(!entry.description().is_empty());
+            browser.scroll(1);
+        }
+    }
+
+    #[test]
+    fn machine_browser_should_wrap_around() {
+        let mut browser = MachineBrowser::default();
+        browser.scroll(-1);
+        assert_eq!(
+            BrowserEntry::Melody(MelodyMachineId::Markov),
+            browser.selected()
+        );
+        browser.scroll(2);
+        assert_eq!(
+            BrowserEntry::Rhythm(RhythmMachineId::Euclid),
+            browser.selected()
+        );
+    }
+}
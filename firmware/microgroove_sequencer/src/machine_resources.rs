@@ -7,31 +7,93 @@ use rand::prelude::*;
 #[cfg(feature = "target_release")]
 use rand_core::RngCore;
 
+/// Small, fast PRNG (SplitMix64), for random numbers which can be repeated exactly from a seed,
+/// e.g. to humanize notes as they're played, or to generate a sequence again.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
 /// `MachineResources` defines a set of methods that machines can use when generating sequences,
 /// e.g a source of random numbers.
 pub struct MachineResources {
     #[cfg(feature = "target_release")]
     rosc: RingOscillator<Enabled>,
+
+    #[cfg(feature = "host_testing")]
+    rng: StdRng,
+
+    /// Seeded generator used in place of the usual random source, see `with_seed_random`.
+    seeded_rng: Option<Rng>,
 }
 
 impl MachineResources {
     #[cfg(feature = "target_release")]
     pub fn new(rosc: RingOscillator<Enabled>) -> MachineResources {
-        MachineResources { rosc }
+        MachineResources {
+            rosc,
+            seeded_rng: None,
+        }
     }
 
     #[cfg(feature = "host_testing")]
     pub fn new() -> MachineResources {
-        MachineResources {}
+        MachineResources {
+            rng: StdRng::from_entropy(),
+            seeded_rng: None,
+        }
+    }
+
+    /// Resources which always produce the same random numbers, for repeatable tests.
+    #[cfg(feature = "host_testing")]
+    pub fn with_seed(seed: u64) -> MachineResources {
+        MachineResources {
+            rng: StdRng::seed_from_u64(seed),
+            seeded_rng: None,
+        }
     }
 
-    #[cfg(feature = "target_release")]
     pub fn random_u64(&mut self) -> u64 {
+        match self.seeded_rng.as_mut() {
+            Some(seeded_rng) => seeded_rng.next_u64(),
+            None => self.source_random_u64(),
+        }
+    }
+
+    /// Run `f` with random numbers from a generator seeded with `seed`, so the same seed always
+    /// gives the same numbers. With `None`, `f` gets the usual random numbers.
+    pub fn with_seed_random<T>(
+        &mut self,
+        seed: Option<u64>,
+        f: impl FnOnce(&mut MachineResources) -> T,
+    ) -> T {
+        let previous_rng = core::mem::replace(&mut self.seeded_rng, seed.map(Rng::new));
+        let result = f(self);
+        self.seeded_rng = previous_rng;
+        result
+    }
+
+    #[cfg(feature = "target_release")]
+    fn source_random_u64(&mut self) -> u64 {
         self.rosc.next_u64()
     }
 
     #[cfg(feature = "host_testing")]
-    pub fn random_u64(&mut self) -> u64 {
-        random()
+    fn source_random_u64(&mut self) -> u64 {
+        self.rng.next_u64()
     }
 }
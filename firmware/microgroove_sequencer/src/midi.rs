@@ -1,5 +1,8 @@
-use core::fmt::{Display, Formatter, Result as FmtResult};
-use midi_types;
+use core::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    ops::{BitOr, BitOrAssign},
+};
+use midi_types::{self, MidiMessage};
 
 #[rustfmt::skip]
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
@@ -167,6 +170,90 @@ impl TryFrom<u8> for Note {
     }
 }
 
+/// Policy for bringing a note number which is outside the MIDI note range back into range, e.g.
+/// after transposing.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum NoteBounds {
+    /// Pin the note to the lowest or highest note.
+    #[default]
+    Clamp,
+
+    /// Move the note by whole octaves until it is back in range, so it keeps its pitch class.
+    Fold,
+}
+
+impl NoteBounds {
+    pub fn apply(&self, note_num: i32) -> Note {
+        let max = Note::G8 as i32;
+        let note_num = match self {
+            NoteBounds::Clamp => note_num.clamp(0, max),
+            NoteBounds::Fold if note_num > max => note_num - ((note_num - max + 11) / 12) * 12,
+            NoteBounds::Fold if note_num < 0 => note_num + ((-note_num + 11) / 12) * 12,
+            NoteBounds::Fold => note_num,
+        };
+        (note_num as u8)
+            .try_into()
+            .expect("note number in bounds should be valid note")
+    }
+}
+
+/// Kinds of message blocked from passing from MIDI in to MIDI out ("soft thru"). Flags combine
+/// with `|`, e.g. `ThruFilter::CLOCK | ThruFilter::CC`. Everything passes through by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ThruFilter(u8);
+
+impl ThruFilter {
+    pub const NONE: ThruFilter = ThruFilter(0);
+
+    /// Timing clock, transport and song position.
+    pub const CLOCK: ThruFilter = ThruFilter(1 << 0);
+
+    /// Note on, note off and polyphonic aftertouch.
+    pub const NOTES: ThruFilter = ThruFilter(1 << 1);
+
+    /// Control change.
+    pub const CC: ThruFilter = ThruFilter(1 << 2);
+
+    pub fn contains(&self, other: ThruFilter) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn remove(&mut self, other: ThruFilter) {
+        self.0 &= !other.0;
+    }
+
+    /// True if `message` should be passed through.
+    pub fn allows(&self, message: &MidiMessage) -> bool {
+        let kind = match message {
+            MidiMessage::TimingClock
+            | MidiMessage::Start
+            | MidiMessage::Stop
+            | MidiMessage::Continue
+            | MidiMessage::SongPositionPointer(_) => ThruFilter::CLOCK,
+            MidiMessage::NoteOn(..) | MidiMessage::NoteOff(..) | MidiMessage::KeyPressure(..) => {
+                ThruFilter::NOTES
+            }
+            MidiMessage::ControlChange(..) => ThruFilter::CC,
+            _ => return true,
+        };
+        !self.contains(kind)
+    }
+}
+
+impl BitOr for ThruFilter {
+    type Output = ThruFilter;
+
+    fn bitor(self, other: ThruFilter) -> ThruFilter {
+        ThruFilter(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for ThruFilter {
+    fn bitor_assign(&mut self, other: ThruFilter) {
+        self.0 |= other.0;
+    }
+}
+
 impl Into<midi_types::Note> for Note {
     fn into(self) -> midi_types::Note {
         let note_num: u8 = self.into();
@@ -321,3 +408,78 @@ impl Display for Note {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn note_bounds_clamp_should_pin_notes_to_range() {
+        assert_eq!(Note::G8, NoteBounds::Clamp.apply(130));
+        assert_eq!(Note::CMinus2, NoteBounds::Clamp.apply(-5));
+        assert_eq!(Note::C3, NoteBounds::Clamp.apply(60));
+    }
+
+    #[test]
+    fn note_bounds_fold_should_reflect_notes_by_octaves() {
+        // A#8 doesn't exist, so fold down to A#7
+        assert_eq!(Note::ASharp7, NoteBounds::Fold.apply(130));
+        // G9 folds to G8, the nearest G in range
+        assert_eq!(Note::G8, NoteBounds::Fold.apply(127 + 12));
+        assert_eq!(Note::G8, NoteBounds::Fold.apply(127));
+        assert_eq!(Note::GMinus2, NoteBounds::Fold.apply(-5));
+        assert_eq!(Note::C3, NoteBounds::Fold.apply(60));
+    }
+
+    #[test]
+    fn thru_filter_should_block_only_flagged_messages() {
+        let clock = [
+            MidiMessage::TimingClock,
+            MidiMessage::Start,
+            MidiMessage::Stop,
+            MidiMessage::Continue,
+            MidiMessage::SongPositionPointer(0u16.into()),
+        ];
+        let notes = [
+            MidiMessage::NoteOn(0.into(), 60.into(), 100.into()),
+            MidiMessage::NoteOff(0.into(), 60.into(), 0.into()),
+            MidiMessage::KeyPressure(0.into(), 60.into(), 50.into()),
+        ];
+        let cc = [MidiMessage::ControlChange(0.into(), 1.into(), 64.into())];
+        let other = [
+            MidiMessage::ProgramChange(0.into(), 5.into()),
+            MidiMessage::PitchBendChange(0.into(), 0i16.into()),
+        ];
+        for bits in 0..8 {
+            let filter = ThruFilter(bits);
+            for (flag, messages) in [
+                (ThruFilter::CLOCK, &clock[..]),
+                (ThruFilter::NOTES, &notes[..]),
+                (ThruFilter::CC, &cc[..]),
+                (ThruFilter::NONE, &other[..]),
+            ] {
+                let blocked = flag != ThruFilter::NONE && filter.contains(flag);
+                for message in messages {
+                    assert_eq!(
+                        !blocked,
+                        filter.allows(message),
+                        "{:?} {:?}",
+                        filter,
+                        message
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn thru_filter_flags_should_combine_and_remove() {
+        let mut filter = ThruFilter::CLOCK | ThruFilter::CC;
+        assert!(filter.contains(ThruFilter::CLOCK));
+        assert!(!filter.contains(ThruFilter::NOTES));
+        filter |= ThruFilter::NOTES;
+        filter.remove(ThruFilter::CLOCK);
+        assert_eq!(ThruFilter::NOTES | ThruFilter::CC, filter);
+        assert_eq!(ThruFilter::NONE, ThruFilter::default());
+    }
+}
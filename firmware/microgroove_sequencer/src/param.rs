@@ -1,11 +1,14 @@
 /// Model parameters as mutable values with metadata (name).
 use alloc::boxed::Box;
 use core::cmp::PartialEq;
-use core::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use core::fmt::{Debug, Display, Formatter, Result as FmtResult, Write};
 use heapless::{String, Vec};
 
 use crate::{
-    machine::{grids_rhythm_machine::Instrument, MelodyMachineId, RhythmMachineId},
+    machine::{
+        grids_rhythm_machine::Instrument, preset_rhythm_machine::Preset, GrooveMachineId,
+        MelodyMachineId, RhythmMachineId,
+    },
     midi::Note,
     part::Part,
     quantizer::{Key, Scale},
@@ -24,12 +27,14 @@ pub enum ParamValue {
     TimeDivision(TimeDivision),
     RhythmMachineId(RhythmMachineId),
     MelodyMachineId(MelodyMachineId),
+    GrooveMachineId(GrooveMachineId),
     Note(Note),
     Scale(Scale),
     Key(Key),
     Swing(Swing),
     Instrument(Instrument),
     Part(Part),
+    Preset(Preset),
 }
 
 impl Display for ParamValue {
@@ -39,12 +44,25 @@ impl Display for ParamValue {
             ParamValue::TimeDivision(time_div) => Display::fmt(&time_div, f),
             ParamValue::RhythmMachineId(id) => Display::fmt(&id, f),
             ParamValue::MelodyMachineId(id) => Display::fmt(&id, f),
+            ParamValue::GrooveMachineId(id) => Display::fmt(&id, f),
             ParamValue::Note(note) => Display::fmt(&note, f),
             ParamValue::Scale(scale) => Display::fmt(&scale, f),
             ParamValue::Key(key) => Display::fmt(&key, f),
             ParamValue::Swing(swing) => Display::fmt(&swing, f),
             ParamValue::Instrument(instrument) => Display::fmt(&instrument, f),
             ParamValue::Part(part) => Display::fmt(&part, f),
+            ParamValue::Preset(preset) => Display::fmt(&preset, f),
+        }
+    }
+}
+
+impl ParamValue {
+    /// Write the value for display, naming keys as is conventional in `scale`, e.g. "Eb" rather
+    /// than "D#" in Eb major. Other values are written as normal.
+    pub fn write_in_scale(&self, out: &mut impl Write, scale: Scale) -> FmtResult {
+        match self {
+            ParamValue::Key(key) => out.write_str(key.name(key.accidental_in(scale))),
+            value => write!(out, "{}", value),
         }
     }
 }
@@ -56,12 +74,14 @@ impl From<ParamValue> for i32 {
             ParamValue::TimeDivision(time_div) => time_div as i32,
             ParamValue::RhythmMachineId(id) => id as i32,
             ParamValue::MelodyMachineId(id) => id as i32,
+            ParamValue::GrooveMachineId(id) => id as i32,
             ParamValue::Note(note) => note as i32,
             ParamValue::Scale(scale) => scale as i32,
             ParamValue::Key(key) => key as i32,
             ParamValue::Swing(swing) => swing as i32,
             ParamValue::Instrument(instrument) => instrument as i32,
             ParamValue::Part(part) => part as i32,
+            ParamValue::Preset(preset) => preset as i32,
         }
     }
 }
@@ -100,7 +120,17 @@ impl Param {
             name: name.into(),
             value: ParamValue::TimeDivision(TimeDivision::default()),
             min: ParamValue::TimeDivision(TimeDivision::ThirtySecond),
-            max: ParamValue::TimeDivision(TimeDivision::Whole),
+            max: ParamValue::TimeDivision(TimeDivision::Free),
+        }
+    }
+
+    /// Time division for repeating a note while performing, from 1/32 to 1/8.
+    pub fn new_note_repeat_param(name: &str) -> Param {
+        Param {
+            name: name.into(),
+            value: ParamValue::TimeDivision(TimeDivision::Sixteenth),
+            min: ParamValue::TimeDivision(TimeDivision::ThirtySecond),
+            max: ParamValue::TimeDivision(TimeDivision::Eigth),
         }
     }
 
@@ -109,7 +139,7 @@ impl Param {
             name: name.into(),
             value: ParamValue::RhythmMachineId(RhythmMachineId::default()),
             min: ParamValue::RhythmMachineId(RhythmMachineId::Unit),
-            max: ParamValue::RhythmMachineId(RhythmMachineId::Euclid),
+            max: ParamValue::RhythmMachineId(RhythmMachineId::Preset),
         }
     }
 
@@ -118,7 +148,16 @@ impl Param {
             name: name.into(),
             value: ParamValue::MelodyMachineId(MelodyMachineId::default()),
             min: ParamValue::MelodyMachineId(MelodyMachineId::Unit),
-            max: ParamValue::MelodyMachineId(MelodyMachineId::Rand),
+            max: ParamValue::MelodyMachineId(MelodyMachineId::Markov),
+        }
+    }
+
+    pub fn new_groove_machine_id_param(name: &str) -> Param {
+        Param {
+            name: name.into(),
+            value: ParamValue::GrooveMachineId(GrooveMachineId::default()),
+            min: ParamValue::GrooveMachineId(GrooveMachineId::Unit),
+            max: ParamValue::GrooveMachineId(GrooveMachineId::Ghost),
         }
     }
 
@@ -136,7 +175,7 @@ impl Param {
             name: name.into(),
             value: ParamValue::Scale(Scale::default()),
             min: ParamValue::Scale(Scale::Chromatic),
-            max: ParamValue::Scale(Scale::OctaveAndFifth),
+            max: ParamValue::Scale(Scale::AugmentedTriad),
         }
     }
 
@@ -172,7 +211,16 @@ impl Param {
             name: name.into(),
             value: ParamValue::Part(Part::default()),
             min: ParamValue::Part(Part::Sequence),
-            max: ParamValue::Part(Part::Turnaround),
+            max: ParamValue::Part(Part::BuildUp),
+        }
+    }
+
+    pub fn new_preset_param(name: &str) -> Param {
+        Param {
+            name: name.into(),
+            value: ParamValue::Preset(Preset::default()),
+            min: ParamValue::Preset(Preset::FourOnFloor),
+            max: ParamValue::Preset(Preset::Breakbeat),
         }
     }
 
@@ -184,12 +232,20 @@ impl Param {
         self.value.clone()
     }
 
-    pub fn set(&mut self, new_value: ParamValue) {
-        // panic!("unexpected ParamValue variant");
-        // if new_value < self.min || new_value > self.max {
-        //     panic!("param default out of bounds");
-        // }
+    /// Set a new value, which must be the same kind of value as the param's and in its range. If
+    /// it isn't, the param keeps its old value.
+    pub fn set(&mut self, new_value: ParamValue) -> Result<(), ParamError> {
+        if core::mem::discriminant(&self.value) != core::mem::discriminant(&new_value) {
+            return Err(ParamError::UnexpectedValue(new_value));
+        }
+        let value_i32: i32 = new_value.into();
+        let min_i32: i32 = self.min.into();
+        let max_i32: i32 = self.max.into();
+        if value_i32 < min_i32 || value_i32 > max_i32 {
+            return Err(ParamError::ValueOutOfRange);
+        }
         self.value = new_value;
+        Ok(())
     }
 
     pub fn set_from_u8(&mut self, new_value: u8) -> Result<(), ParamError> {
@@ -207,6 +263,10 @@ impl Param {
                 .try_into()
                 .map(|val| self.value = ParamValue::MelodyMachineId(val))
                 .map_err(|_| ParamError::ValueOutOfRange)?,
+            ParamValue::GrooveMachineId(_) => new_value
+                .try_into()
+                .map(|val| self.value = ParamValue::GrooveMachineId(val))
+                .map_err(|_| ParamError::ValueOutOfRange)?,
             ParamValue::Note(_) => new_value
                 .try_into()
                 .map(|val| self.value = ParamValue::Note(val))
@@ -231,6 +291,10 @@ impl Param {
                 .try_into()
                 .map(|val| self.value = ParamValue::Part(val))
                 .map_err(|_| ParamError::ValueOutOfRange)?,
+            ParamValue::Preset(_) => new_value
+                .try_into()
+                .map(|val| self.value = ParamValue::Preset(val))
+                .map_err(|_| ParamError::ValueOutOfRange)?,
         };
         Ok(())
     }
@@ -242,6 +306,44 @@ impl Param {
         let new_value = (wrapping_add(value_i32 - min_i32, n, max_i32 - min_i32) + min_i32) as u8;
         self.set_from_u8(new_value)
     }
+
+    /// Set to a value chosen from the param's range by `random`, e.g. the output of
+    /// `MachineResources::random_u64`.
+    pub fn set_random(&mut self, random: u64) -> Result<(), ParamError> {
+        let min_i32: i32 = self.min.into();
+        let max_i32: i32 = self.max.into();
+        let range = (max_i32 - min_i32 + 1) as u64;
+        let new_value = (min_i32 + (random % range) as i32) as u8;
+        self.set_from_u8(new_value)
+    }
+
+    /// Copy the value of `other` if it has the same name and type of value, and the value is in
+    /// this param's range. Returns true if the value was copied.
+    pub fn copy_value_from(&mut self, other: &Param) -> bool {
+        self.name == other.name && self.set(other.value).is_ok()
+    }
+
+    /// Every value the param can take, as the number it's set from with `set_from_u8` and its
+    /// display string, in order from min to max. Used to list choices in menus, rather than
+    /// hard-coding them in the UI.
+    pub fn options(&self) -> impl Iterator<Item = (u8, ParamName)> + '_ {
+        let min_i32: i32 = self.min.into();
+        let max_i32: i32 = self.max.into();
+        (min_i32..=max_i32).filter_map(|value_i32| {
+            let value = value_i32 as u8;
+            let mut option = self.clone();
+            option.set_from_u8(value).ok()?;
+            let mut name = ParamName::new();
+            write!(name, "{}", option.value).ok()?;
+            Some((value, name))
+        })
+    }
+
+    /// Increment by the smallest possible amount in the direction of `n`, ignoring its
+    /// magnitude. Used for fine adjustments of params with large ranges.
+    pub fn increment_fine(&mut self, n: i32) -> Result<(), ParamError> {
+        self.increment(n.signum())
+    }
 }
 
 impl TryInto<u8> for ParamValue {
@@ -288,6 +390,17 @@ impl TryInto<MelodyMachineId> for ParamValue {
     }
 }
 
+impl TryInto<GrooveMachineId> for ParamValue {
+    type Error = ParamError;
+
+    fn try_into(self) -> Result<GrooveMachineId, Self::Error> {
+        match self {
+            ParamValue::GrooveMachineId(id) => Ok(id),
+            unexpected => Err(ParamError::UnexpectedValue(unexpected)),
+        }
+    }
+}
+
 impl TryInto<Note> for ParamValue {
     type Error = ParamError;
 
@@ -354,6 +467,17 @@ impl TryInto<Part> for ParamValue {
     }
 }
 
+impl TryInto<Preset> for ParamValue {
+    type Error = ParamError;
+
+    fn try_into(self) -> Result<Preset, Self::Error> {
+        match self {
+            ParamValue::Preset(preset) => Ok(preset),
+            unexpected => Err(ParamError::UnexpectedValue(unexpected)),
+        }
+    }
+}
+
 pub type ParamList = Vec<Box<Param>, 6>;
 
 #[cfg(test)]
@@ -384,6 +508,27 @@ mod tests {
         assert_eq!(7, param_number.value().try_into().unwrap());
     }
 
+    #[test]
+    fn param_number_should_increment_fine_by_one_regardless_of_delta() {
+        let mut param_number = Param::new_number_param("NUM", 0, 127, 60);
+        param_number.increment_fine(5).unwrap();
+        let value: u8 = param_number.value().try_into().unwrap();
+        assert_eq!(61, value);
+        param_number.increment_fine(-12).unwrap();
+        let value: u8 = param_number.value().try_into().unwrap();
+        assert_eq!(60, value);
+    }
+
+    #[test]
+    fn param_set_random_should_stay_in_range() {
+        let mut param_number = Param::new_number_param("NUM", 3, 5, 3);
+        for random in [0, 1, 2, 3, 1000, u64::MAX] {
+            param_number.set_random(random).unwrap();
+            let value: u8 = param_number.value().try_into().unwrap();
+            assert!((3..=5).contains(&value));
+        }
+    }
+
     #[test]
     fn param_time_division_should_increment() {
         let mut param_time_div = Param::new_time_division_param("SPD");
@@ -392,7 +537,7 @@ mod tests {
             TimeDivision::Eigth,
             param_time_div.value().try_into().unwrap()
         );
-        param_time_div.increment(9).unwrap();
+        param_time_div.increment(11).unwrap();
         assert_eq!(
             TimeDivision::Sixteenth,
             param_time_div.value().try_into().unwrap()
@@ -402,13 +547,31 @@ mod tests {
             TimeDivision::ThirtySecond,
             param_time_div.value().try_into().unwrap()
         );
-        param_time_div.increment(-11).unwrap();
+        param_time_div.increment(-13).unwrap();
         assert_eq!(
-            TimeDivision::Whole,
+            TimeDivision::Free,
             param_time_div.value().try_into().unwrap()
         );
     }
 
+    #[test]
+    fn param_key_value_should_use_flats_in_flat_scales() {
+        let value = ParamValue::Key(Key::DSharp);
+        let mut name = std::string::String::new();
+        value.write_in_scale(&mut name, Scale::Major).unwrap();
+        assert_eq!("Eb", name);
+        let mut name = std::string::String::new();
+        value
+            .write_in_scale(&mut name, Scale::NaturalMinor)
+            .unwrap();
+        assert_eq!("D#", name);
+        let mut name = std::string::String::new();
+        ParamValue::Number(3)
+            .write_in_scale(&mut name, Scale::Major)
+            .unwrap();
+        assert_eq!("3", name);
+    }
+
     #[test]
     fn param_enum_value_should_have_to_string() {
         let param_time_div = Param::new_time_division_param("SPD");
@@ -416,6 +579,68 @@ mod tests {
         assert_eq!("1/16", value.to_string());
     }
 
+    fn variant_count<T: TryFrom<u8>>() -> usize {
+        (0..=u8::MAX)
+            .filter(|&value| T::try_from(value).is_ok())
+            .count()
+    }
+
+    #[test]
+    fn param_options_should_list_every_variant_of_enum_params() {
+        let params_and_counts = [
+            (
+                Param::new_time_division_param("SPD"),
+                variant_count::<TimeDivision>(),
+            ),
+            (
+                Param::new_rhythm_machine_id_param("RHYTHM"),
+                variant_count::<RhythmMachineId>(),
+            ),
+            (
+                Param::new_melody_machine_id_param("MELODY"),
+                variant_count::<MelodyMachineId>(),
+            ),
+            (
+                Param::new_groove_machine_id_param("GROOVE"),
+                variant_count::<GrooveMachineId>(),
+            ),
+            (Param::new_note_param("NOTE"), variant_count::<Note>()),
+            (Param::new_scale_param("SCALE"), variant_count::<Scale>()),
+            (Param::new_key_param("KEY"), variant_count::<Key>()),
+            (Param::new_swing_param("SWING"), variant_count::<Swing>()),
+            (
+                Param::new_instrument_param("INST"),
+                variant_count::<Instrument>(),
+            ),
+            (Param::new_part_param("PART"), variant_count::<Part>()),
+            (Param::new_preset_param("PATTRN"), variant_count::<Preset>()),
+        ];
+        for (param, count) in params_and_counts {
+            assert_eq!(count, param.options().count(), "{}", param.name());
+        }
+    }
+
+    #[test]
+    fn param_options_should_have_display_strings_and_settable_values() {
+        let param = Param::new_time_division_param("SPD");
+        let options: std::vec::Vec<(u8, std::string::String)> = param
+            .options()
+            .map(|(value, name)| (value, name.as_str().into()))
+            .collect();
+        assert_eq!((0, "1/32".into()), options[0]);
+        assert_eq!((5, "FREE".into()), options[5]);
+        let mut param = Param::new_key_param("KEY");
+        for (value, name) in param.clone().options() {
+            param.set_from_u8(value).unwrap();
+            assert_eq!(name.as_str(), param.value().to_string());
+        }
+        let numbers: std::vec::Vec<u8> = Param::new_number_param("NUM", 1, 4, 1)
+            .options()
+            .map(|(value, _)| value)
+            .collect();
+        assert_eq!(vec![1, 2, 3, 4], numbers);
+    }
+
     #[test]
     fn param_list_can_store_different_param_types() {
         let param_number = Param::new_number_param("NUM", 0, 10, 0);
@@ -424,26 +649,45 @@ mod tests {
             ParamList::from_slice(&[Box::new(param_number), Box::new(param_time_div)]);
     }
 
+    #[test]
+    fn param_copy_value_from_should_only_copy_matching_in_range_values() {
+        let mut param = Param::new_number_param("NUM", 0, 10, 0);
+        assert!(param.copy_value_from(&Param::new_number_param("NUM", 0, 20, 5)));
+        assert_eq!(ParamValue::Number(5), param.value());
+        assert!(!param.copy_value_from(&Param::new_number_param("NUM", 0, 20, 15)));
+        assert!(!param.copy_value_from(&Param::new_number_param("OTHER", 0, 10, 7)));
+        assert!(!param.copy_value_from(&Param::new_key_param("NUM")));
+        assert_eq!(ParamValue::Number(5), param.value());
+    }
+
     #[test]
     fn param_value_can_be_set() {
         let mut param_number = Param::new_number_param("NUM", 0, 10, 0);
-        param_number.set(ParamValue::Number(1));
+        param_number.set(ParamValue::Number(1)).unwrap();
         assert_eq!(1, param_number.value().try_into().unwrap())
     }
 
     #[test]
-    #[should_panic]
-    #[ignore = "unimplemented"]
     fn param_value_cant_be_set_to_value_out_of_range() {
         let mut param_number = Param::new_number_param("NUM", 0, 10, 0);
-        param_number.set(ParamValue::Number(11));
+        assert!(matches!(
+            param_number.set(ParamValue::Number(11)),
+            Err(ParamError::ValueOutOfRange)
+        ));
+        assert_eq!(ParamValue::Number(0), param_number.value());
+        param_number.set(ParamValue::Number(10)).unwrap();
+        assert_eq!(ParamValue::Number(10), param_number.value());
     }
 
     #[test]
-    #[should_panic]
-    #[ignore = "unimplemented"]
     fn param_value_cant_be_set_to_different_paramvalue_variant() {
         let mut param_number = Param::new_number_param("NUM", 0, 10, 0);
-        param_number.set(ParamValue::TimeDivision(TimeDivision::Sixteenth));
+        assert!(matches!(
+            param_number.set(ParamValue::TimeDivision(TimeDivision::Sixteenth)),
+            Err(ParamError::UnexpectedValue(ParamValue::TimeDivision(
+                TimeDivision::Sixteenth
+            )))
+        ));
+        assert_eq!(ParamValue::Number(0), param_number.value());
     }
 }
@@ -14,6 +14,7 @@ pub enum Part {
     C,
     Hook,
     Turnaround,
+    BuildUp,
 }
 
 impl Display for Part {
@@ -30,6 +31,7 @@ impl Display for Part {
                 Part::C => "___C",
                 Part::Hook => "HOOK",
                 Part::Turnaround => "TURN",
+                Part::BuildUp => "BUILD",
             }
         )
     }
@@ -48,6 +50,7 @@ impl TryFrom<u8> for Part {
             5 => Ok(Part::C),
             6 => Ok(Part::Hook),
             7 => Ok(Part::Turnaround),
+            8 => Ok(Part::BuildUp),
             _ => Err(()),
         }
     }
@@ -125,6 +128,17 @@ impl Part {
                 let suffix_mask = infinite_trues.take(suffix_len);
                 prefix_mask.chain(suffix_mask).cloned().collect()
             }
+            Part::BuildUp => {
+                // BuildUp => X_______X___X___X_X_X_X_XXXXXXXX
+                // density doubles in each quarter of the loop, until every step plays
+                (0..mask_len)
+                    .map(|i| {
+                        let quarter = i * 4 / mask_len;
+                        let interval = 1 << (3 - quarter);
+                        i % interval == 0
+                    })
+                    .collect()
+            }
         }
     }
 }
@@ -226,4 +240,27 @@ mod tests {
         let actual = Part::new_mask(Part::Turnaround, 16);
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn part_build_up_mask_should_correct_for_len_32() {
+        let expected: Vec<bool, 32> = Vec::from_slice(&[
+            true, false, false, false, false, false, false, false, true, false, false, false, true,
+            false, false, false, true, false, true, false, true, false, true, false, true, true,
+            true, true, true, true, true, true,
+        ])
+        .unwrap();
+        let actual = Part::new_mask(Part::BuildUp, 32);
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn part_build_up_mask_should_get_denser_towards_end_of_loop() {
+        let mask = Part::new_mask(Part::BuildUp, 32);
+        let active_counts: std::vec::Vec<usize> = mask
+            .chunks(8)
+            .map(|quarter| quarter.iter().filter(|active| **active).count())
+            .collect();
+        assert_eq!(vec![1, 2, 4, 8], active_counts);
+        assert_eq!(5, Part::new_mask(Part::BuildUp, 5).len());
+    }
 }
@@ -0,0 +1,45 @@
+//! Commonly used types, re-exported so that code outside the crate can import them in one go
+//! with `use microgroove_sequencer::prelude::*`.
+
+pub use crate::{
+    machine::{GrooveMachine, GrooveMachineId, Machine, MelodyMachineId, RhythmMachineId},
+    machine_resources::MachineResources,
+    part::Part,
+    quantizer::{Key, Scale},
+    sequence_generator::SequenceGenerator,
+    sequencer::{ScheduledMidiMessage, Sequencer, Swing},
+    Lfo, LfoShape, Sequence, Step, StepLock, StepLockField, TimeDivision, Track, TRACK_COUNT,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prelude_should_export_everything_needed_to_build_a_track() {
+        let mut machine_resources = MachineResources::new();
+        let mut generator = SequenceGenerator::default();
+        generator.rhythm_machine = RhythmMachineId::Euclid.into();
+        generator.melody_machine = MelodyMachineId::Rand.into();
+        generator.set_part(Part::Sequence);
+        generator.generate(&mut machine_resources);
+
+        let mut track = Track::default();
+        track.set_time_division(TimeDivision::Eigth);
+        track.sequence = generator.apply(track.length);
+        let sequence: &Sequence = &track.sequence;
+        let first_step: Option<&Step> = sequence.iter_active().map(|(_, step)| step).next();
+        assert_eq!(8, sequence.len());
+        assert!(first_step.is_some());
+
+        let mut sequencer = Sequencer::default();
+        sequencer.set_swing(Swing::Mpc54);
+        sequencer.enable_track(0, track);
+        assert!(sequencer.tracks[0].is_some());
+        assert_eq!(TRACK_COUNT, sequencer.tracks.len());
+
+        let (scale, key): (Scale, Key) = (Default::default(), Default::default());
+        assert_eq!(Scale::Chromatic, scale);
+        assert_eq!(Key::C, key);
+    }
+}
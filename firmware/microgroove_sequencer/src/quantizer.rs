@@ -25,6 +25,8 @@ pub enum Scale {
     Lydian,
     Mixolydian,
     Locrian,
+    DiminishedTriad,
+    AugmentedTriad,
 }
 
 impl Into<u8> for Scale {
@@ -58,6 +60,8 @@ impl TryFrom<u8> for Scale {
             17 => Ok(Scale::Lydian),
             18 => Ok(Scale::Mixolydian),
             19 => Ok(Scale::Locrian),
+            20 => Ok(Scale::DiminishedTriad),
+            21 => Ok(Scale::AugmentedTriad),
             _ => Err(()),
         }
     }
@@ -90,11 +94,61 @@ impl Display for Scale {
                 Scale::Lydian =>            "LYD",
                 Scale::Mixolydian =>        "MIX",
                 Scale::Locrian =>           "LOC",
+                Scale::DiminishedTriad =>   "3DI",
+                Scale::AugmentedTriad =>    "3AU",
             }
         )
     }
 }
 
+impl Scale {
+    /// The triad built on the root of this scale, used to restrict melodies to chord tones (root,
+    /// third and fifth). Scales which are already a subset of a triad, and the chromatic scale,
+    /// which has no chord of its own, are returned unchanged.
+    pub fn triad(&self) -> Scale {
+        match self {
+            Scale::NaturalMinor
+            | Scale::HarmonicMinor
+            | Scale::MelodicMinor
+            | Scale::PentatonicMinor
+            | Scale::HexatonicBlues
+            | Scale::MinorTriad
+            | Scale::Dorian
+            | Scale::Phrygian => Scale::MinorTriad,
+            Scale::DiminishedSeventh | Scale::Locrian => Scale::DiminishedTriad,
+            Scale::WholeTone => Scale::AugmentedTriad,
+            Scale::Chromatic
+            | Scale::Octave
+            | Scale::OctaveAndFifth
+            | Scale::DiminishedTriad
+            | Scale::AugmentedTriad => *self,
+            _ => Scale::MajorTriad,
+        }
+    }
+
+    /// Semitones from the key up to the major scale which shares this scale's key signature, e.g.
+    /// 3 for natural minor, because A minor has the same notes as C major.
+    fn relative_major_offset(&self) -> u8 {
+        match self {
+            Scale::Dorian => 10,
+            Scale::Phrygian => 8,
+            Scale::Lydian => 7,
+            Scale::Mixolydian => 5,
+            Scale::Locrian => 1,
+            _ if matches!(self.triad(), Scale::MinorTriad | Scale::DiminishedTriad) => 3,
+            _ => 0,
+        }
+    }
+
+    /// Notes of the scale within one octave, as ascending semitones above the key.
+    pub fn degrees(&self) -> heapless::Vec<u8, 12> {
+        let scale_map: ScaleMap = (*self).into();
+        (0..12u8)
+            .filter(|&degree| scale_map[degree as usize] == degree)
+            .collect()
+    }
+}
+
 /// Type to capture the mapping of notes in a chromatic octave to the quantized equivalent of
 /// those notea in given scale. Each entry is an array of 12 values. The input note is used to
 /// index into the array. The array value returned is the quantized note. This format allows for
@@ -125,6 +179,8 @@ impl From<Scale> for ScaleMap {
             Scale::Lydian =>            [0,  2,  2,  4,  4,  6,  6,  7,  9,  9,  11, 11],
             Scale::Mixolydian =>        [0,  2,  2,  4,  4,  5,  7,  7,  9,  9,  10, 10],
             Scale::Locrian =>           [0,  1,  1,  3,  3,  5,  6,  6,  8,  8,  10, 10],
+            Scale::DiminishedTriad =>   [0,  0,  0,  3,  3,  3,  6,  6,  6,  6,  6,  6 ],
+            Scale::AugmentedTriad =>    [0,  0,  0,  0,  4,  4,  4,  4,  8,  8,  8,  8 ],
         }
     }
 }
@@ -174,46 +230,173 @@ impl TryFrom<u8> for Key {
     }
 }
 
-impl Display for Key {
+/// Whether black keys are named as sharps, e.g. "D#", or as flats, e.g. "Eb".
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Accidental {
+    #[default]
+    Sharp,
+    Flat,
+}
+
+impl Key {
     #[rustfmt::skip]
+    pub fn name(&self, accidental: Accidental) -> &'static str {
+        match (*self, accidental) {
+            (Key::C, _) =>                        "C",
+            (Key::CSharp, Accidental::Sharp) =>   "C#",
+            (Key::CSharp, Accidental::Flat) =>    "Db",
+            (Key::D, _) =>                        "D",
+            (Key::DSharp, Accidental::Sharp) =>   "D#",
+            (Key::DSharp, Accidental::Flat) =>    "Eb",
+            (Key::E, _) =>                        "E",
+            (Key::F, _) =>                        "F",
+            (Key::FSharp, Accidental::Sharp) =>   "F#",
+            (Key::FSharp, Accidental::Flat) =>    "Gb",
+            (Key::G, _) =>                        "G",
+            (Key::GSharp, Accidental::Sharp) =>   "G#",
+            (Key::GSharp, Accidental::Flat) =>    "Ab",
+            (Key::A, _) =>                        "A",
+            (Key::ASharp, Accidental::Sharp) =>   "A#",
+            (Key::ASharp, Accidental::Flat) =>    "Bb",
+            (Key::B, _) =>                        "B",
+        }
+    }
+
+    /// How this key is conventionally named in `scale`. Keys whose key signature has flats use
+    /// flats, e.g. Eb major and Bb minor, all others use sharps.
+    pub fn accidental_in(&self, scale: Scale) -> Accidental {
+        let key_num: u8 = (*self).into();
+        let relative_major = (key_num + scale.relative_major_offset()) % 12;
+        match relative_major.try_into() {
+            Ok(Key::F | Key::ASharp | Key::DSharp | Key::GSharp | Key::CSharp) => Accidental::Flat,
+            _ => Accidental::Sharp,
+        }
+    }
+}
+
+impl Display for Key {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
-        write!(
-            f,
-            "{}",
-            match *self {
-                Key::C => "C",
-                Key::CSharp => "C#",
-                Key::D => "D",
-                Key::DSharp => "D#",
-                Key::E => "E",
-                Key::F => "F",
-                Key::FSharp => "F#",
-                Key::G => "G",
-                Key::GSharp => "G#",
-                Key::A => "A",
-                Key::ASharp => "A#",
-                Key::B => "B",
-            }
-        )
+        write!(f, "{}", self.name(Accidental::Sharp))
     }
 }
 
 pub fn quantize(note: Note, scale: Scale, key: Key) -> Note {
+    quantize_with_map(note, scale.into(), key)
+}
+
+/// Quantize with the scale map reversed, so that low degrees of the octave map to high notes of
+/// the scale and vice versa, e.g. in C major, C becomes B and B becomes C. Melodies stay in the
+/// scale, but are turned upside down within each octave.
+pub fn quantize_reversed(note: Note, scale: Scale, key: Key) -> Note {
+    let mut interval_map: ScaleMap = scale.into();
+    interval_map.reverse();
+    quantize_with_map(note, interval_map, key)
+}
+
+fn quantize_with_map(note: Note, interval_map: ScaleMap, key: Key) -> Note {
     let key_num: u8 = key.into();
-    let offset = 12 - key_num;
+    let offset = 12 - key_num as i16;
     let note_num: u8 = note.into();
-    let note_num_offset = note_num + offset;
+    let note_num_offset = note_num as i16 + offset;
     let octave = note_num_offset / 12;
     let degree = note_num_offset % 12;
-    let interval_map: ScaleMap = scale.into();
-    let quantized_degree = interval_map[degree as usize] as u8;
-    let quantized_note_num = ((quantized_degree + octave * 12) - offset) as u8;
-    quantized_note_num
-        .min(127)
+    let quantized_degree = interval_map[degree as usize] as i16;
+    let mut quantized_note_num = quantized_degree + octave * 12 - offset;
+    // notes quantized past either end of the MIDI range move by an octave, to stay in the scale
+    if quantized_note_num < 0 {
+        quantized_note_num += 12;
+    }
+    if quantized_note_num > 127 {
+        quantized_note_num -= 12;
+    }
+    (quantized_note_num as u8)
         .try_into()
         .expect("note number should be valid note")
 }
 
+/// Quality of a chord, i.e. the intervals between its notes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChordQuality {
+    Major,
+    Minor,
+    Diminished,
+    MajorSeventh,
+    DominantSeventh,
+    MinorSeventh,
+    HalfDiminishedSeventh,
+}
+
+impl ChordQuality {
+    /// Pitch classes of the chord relative to its root, as a bitmask where bit 0 is the root.
+    fn pitch_classes(&self) -> u16 {
+        match self {
+            ChordQuality::Major => 0b000010010001,
+            ChordQuality::Minor => 0b000010001001,
+            ChordQuality::Diminished => 0b000001001001,
+            ChordQuality::MajorSeventh => 0b100010010001,
+            ChordQuality::DominantSeventh => 0b010010010001,
+            ChordQuality::MinorSeventh => 0b010010001001,
+            ChordQuality::HalfDiminishedSeventh => 0b010001001001,
+        }
+    }
+}
+
+const CHORD_QUALITIES: [ChordQuality; 7] = [
+    ChordQuality::Major,
+    ChordQuality::Minor,
+    ChordQuality::Diminished,
+    ChordQuality::MajorSeventh,
+    ChordQuality::DominantSeventh,
+    ChordQuality::MinorSeventh,
+    ChordQuality::HalfDiminishedSeventh,
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Chord {
+    pub root: Key,
+    pub quality: ChordQuality,
+}
+
+impl Chord {
+    /// Work out which chord a group of notes forms, in any octave or inversion. Only triads and
+    /// 7th chords are detected, other groups of notes return `None`.
+    pub fn detect(notes: &[Note]) -> Option<Chord> {
+        let pitch_classes = notes.iter().fold(0u16, |pitch_classes, &note| {
+            let note_num: u8 = note.into();
+            pitch_classes | 1 << (note_num % 12)
+        });
+        (0..12u8).find_map(|root| {
+            if pitch_classes & 1 << root == 0 {
+                return None;
+            }
+            let relative_pitch_classes =
+                (pitch_classes >> root | pitch_classes << (12 - root)) & 0xfff;
+            CHORD_QUALITIES
+                .iter()
+                .find(|quality| quality.pitch_classes() == relative_pitch_classes)
+                .map(|&quality| Chord {
+                    root: root.try_into().expect("root should be a valid key"),
+                    quality,
+                })
+        })
+    }
+}
+
+impl Display for Chord {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let suffix = match self.quality {
+            ChordQuality::Major => "",
+            ChordQuality::Minor => "m",
+            ChordQuality::Diminished => "dim",
+            ChordQuality::MajorSeventh => "maj7",
+            ChordQuality::DominantSeventh => "7",
+            ChordQuality::MinorSeventh => "m7",
+            ChordQuality::HalfDiminishedSeventh => "m7b5",
+        };
+        write!(f, "{}{}", self.root, suffix)
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -278,6 +461,39 @@ pub mod tests {
         assert_eq!(expected_notes, quantized_notes);
     }
 
+    #[test]
+    fn scale_triad_should_choose_major_or_minor_triad() {
+        assert_eq!(Scale::MajorTriad, Scale::Major.triad());
+        assert_eq!(Scale::MajorTriad, Scale::Mixolydian.triad());
+        assert_eq!(Scale::MinorTriad, Scale::NaturalMinor.triad());
+        assert_eq!(Scale::MinorTriad, Scale::Dorian.triad());
+        assert_eq!(Scale::Octave, Scale::Octave.triad());
+    }
+
+    #[test]
+    fn scale_triad_should_choose_diminished_or_augmented_triad() {
+        assert_eq!(Scale::DiminishedTriad, Scale::Locrian.triad());
+        assert_eq!(Scale::DiminishedTriad, Scale::DiminishedSeventh.triad());
+        assert_eq!(Scale::AugmentedTriad, Scale::WholeTone.triad());
+        assert_eq!(&[0, 3, 6], Scale::DiminishedTriad.degrees().as_slice());
+        assert_eq!(&[0, 4, 8], Scale::AugmentedTriad.degrees().as_slice());
+    }
+
+    #[test]
+    fn scale_triad_should_be_in_scale() {
+        for scale in ALL_SCALES {
+            let scale_degrees = scale.degrees();
+            for degree in scale.triad().degrees() {
+                assert!(scale_degrees.contains(&degree), "{:?}", scale);
+            }
+        }
+    }
+
+    #[test]
+    fn scale_triad_should_leave_chromatic_scale_unchanged() {
+        assert_eq!(Scale::Chromatic, Scale::Chromatic.triad());
+    }
+
     fn input_notes() -> [Note; 12] {
         [
             Note::C3,
@@ -303,4 +519,201 @@ pub mod tests {
             .try_into()
             .unwrap()
     }
+
+    #[test]
+    fn quantize_reversed_should_map_bottom_of_octave_to_top_of_scale() {
+        let expected_notes = [
+            Note::B3,
+            Note::B3,
+            Note::A3,
+            Note::A3,
+            Note::G3,
+            Note::G3,
+            Note::F3,
+            Note::E3,
+            Note::E3,
+            Note::D3,
+            Note::D3,
+            Note::C3,
+        ];
+        let quantized_notes: Vec<Note> = input_notes()
+            .iter()
+            .map(|&note| quantize_reversed(note, Scale::Major, Key::C))
+            .collect();
+        assert_eq!(expected_notes.as_slice(), quantized_notes.as_slice());
+        assert_eq!(
+            Note::FSharp4,
+            quantize_reversed(Note::G3, Scale::Major, Key::G)
+        );
+    }
+
+    const ALL_SCALES: [Scale; 22] = [
+        Scale::Chromatic,
+        Scale::Major,
+        Scale::NaturalMinor,
+        Scale::HarmonicMinor,
+        Scale::MelodicMinor,
+        Scale::PentatonicMajor,
+        Scale::PentatonicMinor,
+        Scale::HexatonicBlues,
+        Scale::WholeTone,
+        Scale::MajorTriad,
+        Scale::MinorTriad,
+        Scale::DominantSeventh,
+        Scale::DiminishedSeventh,
+        Scale::Octave,
+        Scale::OctaveAndFifth,
+        Scale::Dorian,
+        Scale::Phrygian,
+        Scale::Lydian,
+        Scale::Mixolydian,
+        Scale::Locrian,
+        Scale::DiminishedTriad,
+        Scale::AugmentedTriad,
+    ];
+
+    /// Call `f` with every scale, key and input note, and the note it is quantized to.
+    fn quantize_everything(quantize: fn(Note, Scale, Key) -> Note, f: impl Fn(Scale, Key, u8, u8)) {
+        for scale in ALL_SCALES {
+            for key_num in 0..12u8 {
+                let key: Key = key_num.try_into().unwrap();
+                for note_num in 0..=127u8 {
+                    let quantized: u8 = quantize(note_num.try_into().unwrap(), scale, key).into();
+                    f(scale, key, note_num, quantized);
+                }
+            }
+        }
+    }
+
+    fn in_scale(note_num: u8, scale: Scale, key: Key) -> bool {
+        let key_num: u8 = key.into();
+        let degree = (note_num + 12 - key_num) % 12;
+        scale.degrees().contains(&degree)
+    }
+
+    #[test]
+    fn quantize_should_stay_in_scale_and_close_to_the_input_across_the_midi_range() {
+        quantize_everything(quantize, |scale, key, note_num, quantized| {
+            assert!(
+                in_scale(quantized, scale, key),
+                "{} in {} {} quantized to {}, out of scale",
+                note_num,
+                key,
+                scale,
+                quantized
+            );
+            assert!(
+                (quantized as i16 - note_num as i16).abs() < 12,
+                "{} in {} {} quantized to {}, more than an octave away",
+                note_num,
+                key,
+                scale,
+                quantized
+            );
+            let requantized: u8 = quantize(quantized.try_into().unwrap(), scale, key).into();
+            assert_eq!(quantized, requantized);
+        });
+    }
+
+    #[test]
+    fn quantize_reversed_should_stay_in_scale_across_the_midi_range() {
+        quantize_everything(quantize_reversed, |scale, key, note_num, quantized| {
+            assert!(
+                in_scale(quantized, scale, key),
+                "{} in {} {} reverse quantized to {}, out of scale",
+                note_num,
+                key,
+                scale,
+                quantized
+            );
+        });
+    }
+
+    #[test]
+    fn quantize_should_move_notes_below_the_midi_range_up_an_octave() {
+        let note: u8 = quantize(0.try_into().unwrap(), Scale::Octave, Key::B).into();
+        assert_eq!(11, note);
+    }
+
+    #[test]
+    fn quantize_should_move_notes_above_the_midi_range_down_an_octave() {
+        let note: u8 = quantize(127.try_into().unwrap(), Scale::Major, Key::CSharp).into();
+        assert_eq!(116, note);
+    }
+
+    #[test]
+    fn key_name_should_use_flats_if_preferred() {
+        assert_eq!("D#", Key::DSharp.name(Accidental::Sharp));
+        assert_eq!("Eb", Key::DSharp.name(Accidental::Flat));
+        assert_eq!("D", Key::D.name(Accidental::Flat));
+        assert_eq!("D#", Key::DSharp.to_string());
+    }
+
+    #[test]
+    fn key_accidental_should_follow_key_signature_of_scale() {
+        assert_eq!(Accidental::Flat, Key::DSharp.accidental_in(Scale::Major));
+        assert_eq!(
+            Accidental::Flat,
+            Key::ASharp.accidental_in(Scale::NaturalMinor)
+        );
+        assert_eq!(
+            Accidental::Sharp,
+            Key::DSharp.accidental_in(Scale::NaturalMinor)
+        );
+        assert_eq!(Accidental::Sharp, Key::FSharp.accidental_in(Scale::Major));
+        assert_eq!(Accidental::Flat, Key::G.accidental_in(Scale::Dorian));
+        assert_eq!(Accidental::Sharp, Key::E.accidental_in(Scale::Mixolydian));
+    }
+
+    #[test]
+    fn scale_degrees_should_list_notes_in_scale() {
+        assert_eq!(&[0, 4, 7], Scale::MajorTriad.degrees().as_slice());
+        assert_eq!(&[0, 3, 6, 9], Scale::DiminishedSeventh.degrees().as_slice());
+        assert_eq!(12, Scale::Chromatic.degrees().len());
+    }
+
+    #[test]
+    fn chord_detect_should_name_triads_and_sevenths() {
+        let chord_name = |notes: &[Note]| Chord::detect(notes).map(|chord| chord.to_string());
+        assert_eq!(
+            Some("C".into()),
+            chord_name(&[Note::C3, Note::E3, Note::G3])
+        );
+        assert_eq!(
+            Some("Cm".into()),
+            chord_name(&[Note::C3, Note::DSharp3, Note::G3])
+        );
+        assert_eq!(
+            Some("Bdim".into()),
+            chord_name(&[Note::B2, Note::D3, Note::F3])
+        );
+        assert_eq!(
+            Some("G7".into()),
+            chord_name(&[Note::G2, Note::B2, Note::D3, Note::F3])
+        );
+        assert_eq!(
+            Some("Am7".into()),
+            chord_name(&[Note::A2, Note::C3, Note::E3, Note::G3])
+        );
+    }
+
+    #[test]
+    fn chord_detect_should_ignore_octaves_and_inversions() {
+        let chord = Chord::detect(&[Note::E2, Note::G3, Note::C4, Note::C5]);
+        assert_eq!(
+            Some(Chord {
+                root: Key::C,
+                quality: ChordQuality::Major
+            }),
+            chord
+        );
+    }
+
+    #[test]
+    fn chord_detect_should_return_none_for_other_note_groups() {
+        assert_eq!(None, Chord::detect(&[]));
+        assert_eq!(None, Chord::detect(&[Note::C3]));
+        assert_eq!(None, Chord::detect(&[Note::C3, Note::G3]));
+        assert_eq!(None, Chord::detect(&[Note::C3, Note::CSharp3, Note::D3]));
+    }
 }
@@ -0,0 +1,153 @@
+/// Everything needed to draw a track's sequence, copied out of the sequencer in one go. Plain
+/// fixed-size data, so it's cheap to hand to the render task, and rendering doesn't need to clone
+/// the sequence or hold the sequencer lock.
+use crate::{NotePriority, Step, Track, SEQUENCE_MAX_STEPS};
+
+/// What's drawn for one active step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderStep {
+    /// The step's representative note, which sets how high the step is drawn.
+    pub note: u8,
+    pub velocity: u8,
+    pub muted: bool,
+    delay: i8,
+    length_step_cents: u8,
+}
+
+impl RenderStep {
+    fn new(step: &Step, note_priority: NotePriority) -> RenderStep {
+        let step = step.with_locks();
+        RenderStep {
+            note: step.representative_note(note_priority).into(),
+            velocity: step.velocity.into(),
+            muted: step.muted,
+            delay: step.delay,
+            length_step_cents: step.length_step_cents,
+        }
+    }
+
+    /// See `Step::delay_offset`.
+    pub fn delay_offset(&self, width: i32) -> i32 {
+        delay_offset(self.delay, width)
+    }
+
+    /// See `Step::sustain_line_length`.
+    pub fn sustain_line_length(&self, width: i32) -> i32 {
+        sustain_line_length(self.length_step_cents, width)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderSnapshot {
+    steps: [Option<RenderStep>; SEQUENCE_MAX_STEPS],
+    len: u8,
+
+    /// The step playing at the time of the snapshot.
+    pub active_step_num: u8,
+
+    /// Lowest and highest notes of the active steps, for scaling the steps to the display.
+    pub note_min_max: (u8, u8),
+}
+
+impl RenderSnapshot {
+    pub fn new(track: &Track, tick: u32, note_priority: NotePriority) -> RenderSnapshot {
+        let mut steps = [None; SEQUENCE_MAX_STEPS];
+        for (render_step, step) in steps.iter_mut().zip(track.sequence.iter()) {
+            *render_step = step
+                .as_ref()
+                .map(|step| RenderStep::new(step, note_priority));
+        }
+        let len = track.sequence.len().min(SEQUENCE_MAX_STEPS);
+        let note_min_max = steps[..len]
+            .iter()
+            .flatten()
+            .fold((127, 0), |(min, max), step| {
+                (step.note.min(min), step.note.max(max))
+            });
+        RenderSnapshot {
+            steps,
+            len: len as u8,
+            active_step_num: track.step_num(tick),
+            note_min_max,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn steps(&self) -> &[Option<RenderStep>] {
+        &self.steps[..self.len()]
+    }
+}
+
+pub(crate) fn delay_offset(delay: i8, width: i32) -> i32 {
+    (delay as i32 * width / 100).clamp(1 - width, width - 1)
+}
+
+pub(crate) fn sustain_line_length(length_step_cents: u8, width: i32) -> i32 {
+    (length_step_cents as i32 - 100).max(0) * width / 100
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track_with_steps() -> Track {
+        Track {
+            sequence: (0..8)
+                .map(|i| {
+                    if i % 3 == 2 {
+                        return None;
+                    }
+                    let mut step = Step::new(40 + i * 5).unwrap();
+                    step.velocity = (30 + i * 10).into();
+                    step.muted = i == 4;
+                    step.delay = i as i8 * 5;
+                    step.length_step_cents = 50 + i * 20;
+                    Some(step)
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn render_snapshot_should_match_track_steps() {
+        let track = track_with_steps();
+        let snapshot = RenderSnapshot::new(&track, 0, NotePriority::Root);
+        assert_eq!(track.sequence.len(), snapshot.len());
+        for (step, render_step) in track.sequence.iter().zip(snapshot.steps()) {
+            match (step, render_step) {
+                (Some(step), Some(render_step)) => {
+                    let note: u8 = step.note.into();
+                    let velocity: u8 = step.velocity.into();
+                    assert_eq!(note, render_step.note);
+                    assert_eq!(velocity, render_step.velocity);
+                    assert_eq!(step.muted, render_step.muted);
+                    assert_eq!(step.delay_offset(7), render_step.delay_offset(7));
+                    assert_eq!(
+                        step.sustain_line_length(7),
+                        render_step.sustain_line_length(7)
+                    );
+                }
+                (None, None) => {}
+                _ => panic!("snapshot step should be active only if track step is active"),
+            }
+        }
+        assert_eq!((40, 75), snapshot.note_min_max);
+    }
+
+    #[test]
+    fn render_snapshot_should_have_active_step_at_tick() {
+        let track = track_with_steps();
+        for tick in [0, 6, 13, 47, 50] {
+            let snapshot = RenderSnapshot::new(&track, tick, NotePriority::Root);
+            assert_eq!(track.step_num(tick), snapshot.active_step_num);
+        }
+    }
+}
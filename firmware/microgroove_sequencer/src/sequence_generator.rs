@@ -1,22 +1,53 @@
 use crate::{
     machine::unit_machine::UnitMachine,
-    machine::Machine,
+    machine::{
+        carry_over_params, GrooveMachine, GrooveMachineId, Machine, MelodyMachineId,
+        RhythmMachineId,
+    },
     machine_resources::MachineResources,
+    midi::NoteBounds,
     param::{Param, ParamList, ParamValue},
     part::Part,
-    quantizer::quantize,
-    Sequence, Step, SEQUENCE_MAX_STEPS,
+    quantizer::{quantize, quantize_reversed, Key, Scale},
+    Sequence, Step, TimeDivision, SEQUENCE_MAX_STEPS, STEP_PROBABILITY_MAX, STEP_RATCHET_MAX,
 };
 
 use alloc::boxed::Box;
 use heapless::Vec;
 
-#[derive(Debug)]
+const MAX_OCTAVE_SHIFT: i8 = 4;
+
+// evolve amount is a percentage of the sequence
+const EVOLVE_MAX: u8 = 100;
+
+#[derive(Clone, Debug)]
 pub struct SequenceGenerator {
     pub rhythm_machine: Box<dyn Machine>,
     pub melody_machine: Box<dyn Machine>,
+    pub groove_machine: Box<dyn GrooveMachine>,
     groove_params: ParamList,
     harmony_params: ParamList,
+
+    /// Number of octaves to shift the generated melody up or down.
+    octave_shift: i8,
+
+    /// How to handle notes shifted out of range.
+    note_bounds: NoteBounds,
+
+    /// Quantize with the scale turned upside down, see `quantizer::quantize_reversed`.
+    reverse_scale: bool,
+
+    /// Always play the first step, whatever the rhythm machine generates, so that the one is
+    /// never missing, e.g. from a randomised kick pattern.
+    first_step_active: bool,
+
+    /// Time division of the track the sequence is generated for, passed on to the groove machine
+    /// to find beats.
+    time_division: TimeDivision,
+
+    /// Seed for the random numbers used to generate sequences, so a pattern can be generated
+    /// again. With `None`, every sequence generated is new.
+    seed: Option<u64>,
 }
 
 impl Default for SequenceGenerator {
@@ -24,13 +55,33 @@ impl Default for SequenceGenerator {
         SequenceGenerator {
             rhythm_machine: Box::new(UnitMachine::new()),
             melody_machine: Box::new(UnitMachine::new()),
-            groove_params: ParamList::from_slice(&[Box::new(Param::new_part_param("PART"))])
-                .expect("should create groove param list from slice"),
+            groove_machine: Box::new(UnitMachine::new()),
+            groove_params: ParamList::from_slice(&[
+                Box::new(Param::new_part_param("PART")),
+                Box::new(Param::new_number_param("VEL", 1, 127, 127)),
+                Box::new(Param::new_number_param("EVOLVE", 0, EVOLVE_MAX, 0)),
+                Box::new(Param::new_groove_machine_id_param("GROOVE")),
+                Box::new(Param::new_number_param(
+                    "PROB",
+                    0,
+                    STEP_PROBABILITY_MAX,
+                    STEP_PROBABILITY_MAX,
+                )),
+                Box::new(Param::new_number_param("RATCH", 1, STEP_RATCHET_MAX, 1)),
+            ])
+            .expect("should create groove param list from slice"),
             harmony_params: ParamList::from_slice(&[
                 Box::new(Param::new_scale_param("SCALE")),
                 Box::new(Param::new_key_param("KEY")),
+                Box::new(Param::new_number_param("CHORD", 0, 1, 0)),
             ])
             .expect("should create harmony param list from slice"),
+            octave_shift: 0,
+            note_bounds: Default::default(),
+            reverse_scale: false,
+            first_step_active: false,
+            time_division: Default::default(),
+            seed: None,
         }
     }
 }
@@ -40,6 +91,39 @@ impl SequenceGenerator {
         (0..length).map(|_i| Step::new(60).ok()).collect()
     }
 
+    /// Switch to a new rhythm machine, keeping the values of any params it shares with the old
+    /// one.
+    pub fn set_rhythm_machine(&mut self, id: RhythmMachineId) {
+        let mut machine: Box<dyn Machine> = id.into();
+        carry_over_params(self.rhythm_machine.as_ref(), machine.as_mut());
+        self.rhythm_machine = machine;
+    }
+
+    /// Switch to a new melody machine, keeping the values of any params it shares with the old
+    /// one, e.g. ROOT.
+    pub fn set_melody_machine(&mut self, id: MelodyMachineId) {
+        let mut machine: Box<dyn Machine> = id.into();
+        carry_over_params(self.melody_machine.as_ref(), machine.as_mut());
+        self.melody_machine = machine;
+    }
+
+    /// Switch to a new groove machine, keeping the values of any params it shares with the old
+    /// one, e.g. VELLEN.
+    pub fn set_groove_machine(&mut self, id: GrooveMachineId) {
+        let mut machine: Box<dyn GrooveMachine> = id.into();
+        carry_over_params(self.groove_machine.as_ref(), machine.as_mut());
+        machine.set_time_division(self.time_division);
+        self.groove_machine = machine;
+        self.groove_params[3]
+            .set(ParamValue::GrooveMachineId(id))
+            .expect("should set groove machine id");
+    }
+
+    /// Groove machine selected by the GROOVE param.
+    pub fn groove_machine_id(&self) -> GrooveMachineId {
+        self.groove_params[3].value().try_into().unwrap()
+    }
+
     pub fn groove_params(&self) -> &ParamList {
         &self.groove_params
     }
@@ -61,35 +145,328 @@ impl SequenceGenerator {
     }
 
     pub fn set_part(&mut self, part: Part) {
-        self.groove_params[0].set(ParamValue::Part(part));
+        self.groove_params[0]
+            .set(ParamValue::Part(part))
+            .expect("should set part");
+    }
+
+    /// Choose a new part at random, shuffling the structure of the sequence.
+    pub fn randomise_part(&mut self, machine_resources: &mut MachineResources) {
+        self.groove_params[0]
+            .set_random(machine_resources.random_u64())
+            .expect("should set random part");
+    }
+
+    /// Velocity given to every step of the initial sequence, before machines are applied.
+    pub fn base_velocity(&self) -> u8 {
+        self.groove_params[1].value().try_into().unwrap()
+    }
+
+    /// Set the base velocity, clamped to 1-127 so that steps are never silent.
+    pub fn set_base_velocity(&mut self, velocity: u8) {
+        self.groove_params[1]
+            .set(ParamValue::Number(velocity.clamp(1, 127)))
+            .expect("should set base velocity");
+    }
+
+    /// Percentage of steps which are regenerated each time the track loops.
+    pub fn evolve_amount(&self) -> u8 {
+        self.groove_params[2].value().try_into().unwrap()
+    }
+
+    pub fn set_evolve_amount(&mut self, amount: u8) {
+        self.groove_params[2]
+            .set(ParamValue::Number(amount.min(EVOLVE_MAX)))
+            .expect("should set evolve amount");
+    }
+
+    /// Regenerate a random subset of the steps of `sequence`, so that the pattern slowly evolves.
+    /// The number of steps regenerated is at most `evolve_amount`% of the sequence, rounded up.
+    pub fn evolve(&self, sequence: Sequence, machine_resources: &mut MachineResources) -> Sequence {
+        let amount = self.evolve_amount();
+        self.generate_variation(sequence, amount, machine_resources)
+    }
+
+    /// Generate a variation of `sequence` which is musically related to it. `amount`% of the
+    /// steps, rounded up, are picked at random and replaced by freshly generated steps, the rest
+    /// are kept. Replacement steps can match the originals, so fewer steps may actually change.
+    /// The fresh steps are generated by a copy of the machines, so that the machines' own state,
+    /// e.g. the register of a Turing machine, isn't moved on.
+    pub fn generate_variation(
+        &self,
+        sequence: Sequence,
+        amount: u8,
+        machine_resources: &mut MachineResources,
+    ) -> Sequence {
+        let amount = amount.min(100) as usize;
+        let length = sequence.len();
+        if amount == 0 || length == 0 {
+            return sequence;
+        }
+        let mut scratch_generator = self.clone();
+        scratch_generator.generate(machine_resources);
+        let fresh_sequence = scratch_generator.apply(length as u8);
+        let steps_to_change = (length * amount).div_ceil(100);
+
+        // partial shuffle of step numbers, so that each step is picked at most once
+        let mut step_nums: Vec<usize, SEQUENCE_MAX_STEPS> = (0..length).collect();
+        let mut steps = sequence.steps.clone();
+        for i in 0..steps_to_change {
+            let j = i + (machine_resources.random_u64() % (length - i) as u64) as usize;
+            step_nums.swap(i, j);
+            let step_num = step_nums[i];
+            steps[step_num] = fresh_sequence.steps[step_num].clone();
+        }
+        sequence.set_steps(steps)
+    }
+
+    pub fn scale(&self) -> Scale {
+        self.harmony_params[0]
+            .value()
+            .try_into()
+            .expect("unexpected scale value for quantizer")
+    }
+
+    pub fn set_scale(&mut self, scale: Scale) {
+        self.harmony_params[0]
+            .set(ParamValue::Scale(scale))
+            .expect("should set scale");
+    }
+
+    pub fn key(&self) -> Key {
+        self.harmony_params[1]
+            .value()
+            .try_into()
+            .expect("unexpected key value for quantizer")
+    }
+
+    pub fn set_key(&mut self, key: Key) {
+        self.harmony_params[1]
+            .set(ParamValue::Key(key))
+            .expect("should set key");
+    }
+
+    pub fn reverse_scale(&self) -> bool {
+        self.reverse_scale
+    }
+
+    pub fn set_reverse_scale(&mut self, reverse_scale: bool) {
+        self.reverse_scale = reverse_scale;
+    }
+
+    pub fn first_step_active(&self) -> bool {
+        self.first_step_active
+    }
+
+    pub fn set_first_step_active(&mut self, first_step_active: bool) {
+        self.first_step_active = first_step_active;
+    }
+
+    /// Probability given to every generated step, as %. See `Step::probability`.
+    pub fn probability(&self) -> u8 {
+        self.groove_params[4].value().try_into().unwrap()
+    }
+
+    /// Set the chance of each generated step playing each time round the sequence, from 0 to
+    /// `STEP_PROBABILITY_MAX`, which always plays them.
+    pub fn set_probability(&mut self, probability: u8) {
+        self.groove_params[4]
+            .set(ParamValue::Number(probability.min(STEP_PROBABILITY_MAX)))
+            .expect("should set probability");
+    }
+
+    /// Number of notes every generated step plays, evenly spaced across the step. See
+    /// `Step::ratchet`.
+    pub fn ratchet(&self) -> u8 {
+        self.groove_params[5].value().try_into().unwrap()
+    }
+
+    pub fn set_ratchet(&mut self, ratchet: u8) {
+        self.groove_params[5]
+            .set(ParamValue::Number(ratchet.clamp(1, STEP_RATCHET_MAX)))
+            .expect("should set ratchet");
+    }
+
+    /// If true, melodies are quantized to the chord tones (root, third and fifth) of the
+    /// configured scale and key, rather than every note of the scale.
+    pub fn chord_tones_only(&self) -> bool {
+        let chord: u8 = self.harmony_params[2].value().try_into().unwrap();
+        chord == 1
+    }
+
+    pub fn set_chord_tones_only(&mut self, chord_tones_only: bool) {
+        self.harmony_params[2]
+            .set(ParamValue::Number(chord_tones_only as u8))
+            .expect("should set chord tones only");
+    }
+
+    pub fn octave_shift(&self) -> i8 {
+        self.octave_shift
+    }
+
+    /// Shift the generated melody up or down by a number of octaves, relative to the current
+    /// shift. The total shift is limited to +/- 4 octaves.
+    pub fn shift_octave(&mut self, octaves: i8) {
+        self.octave_shift =
+            (self.octave_shift + octaves).clamp(-MAX_OCTAVE_SHIFT, MAX_OCTAVE_SHIFT);
+    }
+
+    pub fn note_bounds(&self) -> NoteBounds {
+        self.note_bounds
+    }
+
+    pub fn set_note_bounds(&mut self, note_bounds: NoteBounds) {
+        self.note_bounds = note_bounds;
+    }
+
+    pub fn time_division(&self) -> TimeDivision {
+        self.time_division
+    }
+
+    /// Set the time division of the track that sequences are generated for. This should be kept
+    /// in sync with the track.
+    pub fn set_time_division(&mut self, time_division: TimeDivision) {
+        self.time_division = time_division;
+        self.groove_machine.set_time_division(time_division);
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Generate from `seed`, so each time the machines generate with the same params they produce
+    /// the same sequence. With `None`, go back to generating a new sequence each time.
+    pub fn set_seed(&mut self, seed: Option<u64>) {
+        self.seed = seed;
     }
 
     pub fn generate(&mut self, machine_resources: &mut MachineResources) {
-        self.melody_machine.generate(machine_resources);
-        self.rhythm_machine.generate(machine_resources);
+        machine_resources.with_seed_random(self.seed, |machine_resources| {
+            self.melody_machine.generate(machine_resources);
+            self.rhythm_machine.generate(machine_resources);
+            self.groove_machine.generate(machine_resources);
+        });
+    }
+
+    /// Keep generating sequences until one satisfies `predicate`, e.g. has at least 4 active
+    /// steps, for hitting "randomise" until a pattern is good. Gives up after `max_tries` and
+    /// returns the last sequence generated, which the caller can check again if it needs to.
+    pub fn generate_matching(
+        &mut self,
+        length: u8,
+        predicate: impl Fn(&Sequence) -> bool,
+        max_tries: usize,
+        machine_resources: &mut MachineResources,
+    ) -> Sequence {
+        let mut sequence = self.apply(length);
+        for _ in 0..max_tries {
+            self.generate(machine_resources);
+            sequence = self.apply(length);
+            if predicate(&sequence) {
+                break;
+            }
+        }
+        sequence
     }
 
     /// Generate a sequence by piping the initial sequence through the set of configured machines.
     pub fn apply(&self, length: u8) -> Sequence {
         // a pipe operator would be nice to have here
-        self.apply_part(
-            self.apply_quantizer(
-                self.melody_machine
-                    .apply(self.rhythm_machine.apply(Self::initial_sequence(length))),
-            ),
-        )
+        let sequence = self.apply_step_defaults(Self::initial_sequence(length));
+        let sequence = self.rhythm_machine.apply(sequence);
+        let sequence = self.apply_first_step_active(sequence);
+        let sequence = self.groove_machine.apply(sequence);
+        let sequence = self.melody_machine.apply(sequence);
+        let sequence = self.apply_quantizer(sequence);
+        let sequence = sequence.shift_octaves(self.octave_shift, self.note_bounds);
+        let sequence = self.apply_part(sequence);
+        let sequence = self.apply_probability(sequence);
+        self.apply_ratchet(sequence)
+    }
+
+    /// Generate a sequence of `length` steps which keeps the steps of `sequence` before `start`,
+    /// and regenerates the rest. Used when lengthening a track, so that only the newly exposed
+    /// steps change.
+    pub fn regenerate_suffix(&self, sequence: Sequence, start: usize, length: u8) -> Sequence {
+        let fresh_sequence = self.apply(length);
+        let start = start.min(sequence.len());
+        sequence
+            .steps
+            .into_iter()
+            .take(start)
+            .chain(fresh_sequence.steps.into_iter().skip(start))
+            .collect()
+    }
+
+    /// Velocity and gate length for new steps. Suggestions from the rhythm machine take priority
+    /// over the melody machine, then the base velocity and the default gate are used.
+    fn step_defaults(&self) -> (u8, Option<u8>) {
+        let machines = [self.rhythm_machine.as_ref(), self.melody_machine.as_ref()];
+        let velocity = machines
+            .iter()
+            .find_map(|machine| machine.default_velocity())
+            .unwrap_or_else(|| self.base_velocity());
+        let gate = machines.iter().find_map(|machine| machine.default_gate());
+        (velocity, gate)
+    }
+
+    fn apply_step_defaults(&self, sequence: Sequence) -> Sequence {
+        let (velocity, gate) = self.step_defaults();
+        let mut sequence = sequence.map_velocities(|_| velocity.into());
+        if let Some(gate) = gate {
+            for step in sequence.iter_mut().flatten() {
+                step.length_step_cents = gate;
+            }
+        }
+        sequence
+    }
+
+    fn apply_probability(&self, mut sequence: Sequence) -> Sequence {
+        for step in sequence.iter_mut().flatten() {
+            step.probability = self.probability();
+        }
+        sequence
+    }
+
+    fn apply_ratchet(&self, mut sequence: Sequence) -> Sequence {
+        for step in sequence.iter_mut().flatten() {
+            step.ratchet = self.ratchet();
+        }
+        sequence
+    }
+
+    /// Restore the first step if the rhythm machine left it empty. Parts which rest at the start of
+    /// the sequence still silence it, as they're applied later.
+    fn apply_first_step_active(&self, mut sequence: Sequence) -> Sequence {
+        if !self.first_step_active {
+            return sequence;
+        }
+        if let Some(first_step) = sequence.steps.first_mut() {
+            if first_step.is_none() {
+                let (velocity, gate) = self.step_defaults();
+                *first_step = Step::new(60).ok().map(|mut step| {
+                    step.velocity = velocity.into();
+                    if let Some(gate) = gate {
+                        step.length_step_cents = gate;
+                    }
+                    step
+                });
+            }
+        }
+        sequence
     }
 
     fn apply_quantizer(&self, sequence: Sequence) -> Sequence {
-        let scale = self.harmony_params[0]
-            .value()
-            .try_into()
-            .expect("unexpected scale value for quantizer");
-        let key = self.harmony_params[1]
-            .value()
-            .try_into()
-            .expect("unexpected key value for quantizer");
-        sequence.map_notes(|note| quantize(note.into(), scale, key).into())
+        let mut scale = self.scale();
+        if self.chord_tones_only() {
+            scale = scale.triad();
+        }
+        let key = self.key();
+        if self.reverse_scale {
+            sequence.map_notes(|note| quantize_reversed(note, scale, key))
+        } else {
+            sequence.map_notes(|note| quantize(note.into(), scale, key).into())
+        }
     }
 
     fn apply_part(&self, sequence: Sequence) -> Sequence {
@@ -116,21 +493,138 @@ impl SequenceGenerator {
 mod tests {
     use super::*;
 
+    use core::iter::zip;
+
     use crate::{
-        machine::rand_melody_machine::RandMelodyMachine,
+        machine::{
+            grids_rhythm_machine::Instrument, rand_melody_machine::RandMelodyMachine,
+            GrooveMachineId,
+        },
         machine_resources::MachineResources,
         midi::Note,
         param::ParamValue,
-        quantizer::{Key, Scale},
+        quantizer::Key,
     };
 
     #[test]
     fn sequence_generator_default_should_create_a_new_generator() {
         let generator = SequenceGenerator::default();
         assert_eq!("UNIT", generator.rhythm_machine.name());
+        assert_eq!("UNIT", generator.groove_machine.name());
         assert_eq!("UNIT", generator.melody_machine.name());
     }
 
+    #[test]
+    fn sequence_generator_set_melody_machine_should_carry_over_shared_params() {
+        let mut generator = SequenceGenerator::default();
+        generator.set_melody_machine(MelodyMachineId::Rand);
+        generator.melody_machine.params_mut()[0]
+            .set(ParamValue::Note(Note::A4))
+            .unwrap();
+        generator.melody_machine.params_mut()[1]
+            .set(ParamValue::Number(24))
+            .unwrap();
+        generator.set_melody_machine(MelodyMachineId::Arp);
+        assert_eq!("ARP", generator.melody_machine.name());
+        assert_eq!(
+            ParamValue::Note(Note::A4),
+            generator.melody_machine.params()[0].value()
+        );
+        assert_eq!(
+            ParamValue::Number(1),
+            generator.melody_machine.params()[2].value()
+        );
+    }
+
+    #[test]
+    fn sequence_generator_set_rhythm_machine_should_reset_unshared_params() {
+        let mut generator = SequenceGenerator::default();
+        generator.set_rhythm_machine(RhythmMachineId::Euclid);
+        generator.rhythm_machine.params_mut()[0]
+            .set(ParamValue::Number(7))
+            .unwrap();
+        generator.set_rhythm_machine(RhythmMachineId::Grids);
+        generator.set_rhythm_machine(RhythmMachineId::Euclid);
+        assert_eq!(
+            ParamValue::Number(8),
+            generator.rhythm_machine.params()[0].value()
+        );
+    }
+
+    #[test]
+    fn sequence_generator_with_reverse_scale_should_quantize_upside_down() {
+        let mut generator = SequenceGenerator::default();
+        generator.set_scale(Scale::Major);
+        generator.set_reverse_scale(true);
+        let sequence = generator.apply(4);
+        assert!(sequence.iter().flatten().all(|step| step.note == Note::B3));
+    }
+
+    #[test]
+    fn sequence_generator_ratchet_should_apply_to_every_step() {
+        let mut generator = SequenceGenerator::default();
+        assert!(generator
+            .apply(8)
+            .iter()
+            .flatten()
+            .all(|step| step.ratchet == 1));
+        generator.set_ratchet(0);
+        assert_eq!(1, generator.ratchet());
+        generator.set_ratchet(20);
+        assert_eq!(STEP_RATCHET_MAX, generator.ratchet());
+        generator.set_ratchet(3);
+        let sequence = generator.apply(8);
+        assert!(sequence.iter().flatten().all(|step| step.ratchet == 3));
+    }
+
+    #[test]
+    fn sequence_generator_probability_should_apply_to_every_step() {
+        let mut generator = SequenceGenerator::default();
+        assert!(generator
+            .apply(8)
+            .iter()
+            .flatten()
+            .all(|step| step.probability == STEP_PROBABILITY_MAX));
+        generator.set_probability(150);
+        assert_eq!(STEP_PROBABILITY_MAX, generator.probability());
+        generator.set_probability(25);
+        let sequence = generator.apply(8);
+        assert_eq!(8, sequence.iter().flatten().count());
+        assert!(sequence.iter().flatten().all(|step| step.probability == 25));
+    }
+
+    #[test]
+    fn sequence_generator_with_first_step_active_should_always_play_first_step() {
+        let mut generator = SequenceGenerator::default();
+        generator.set_rhythm_machine(RhythmMachineId::Euclid);
+        generator.rhythm_machine.params_mut()[2]
+            .set(ParamValue::Number(1))
+            .unwrap();
+        generator.set_base_velocity(90);
+        let unforced = generator.apply(8);
+        assert!(unforced.steps[0].is_none());
+        generator.set_first_step_active(true);
+        let sequence = generator.apply(8);
+        let first_step = sequence.steps[0].as_ref().unwrap();
+        assert_eq!(90, u8::from(first_step.velocity));
+        assert_eq!(unforced.steps[1..], sequence.steps[1..]);
+    }
+
+    #[test]
+    fn sequence_generator_with_first_step_active_should_play_first_step_for_any_rhythm() {
+        let mut generator = SequenceGenerator::default();
+        let mut machine_resources = MachineResources::new();
+        generator.set_rhythm_machine(RhythmMachineId::Grids);
+        generator.set_first_step_active(true);
+        for _ in 0..32 {
+            for param in generator.rhythm_machine.params_mut().iter_mut() {
+                param.set_random(machine_resources.random_u64()).unwrap();
+            }
+            generator.generate(&mut machine_resources);
+            assert!(generator.apply(16).steps[0].is_some());
+        }
+    }
+
     #[test]
     fn sequence_generator_apply_should_generate_a_sequence() {
         let generator = SequenceGenerator::default();
@@ -147,13 +641,218 @@ mod tests {
         }));
     }
 
+    #[test]
+    fn sequence_generator_generate_matching_should_stop_once_predicate_is_satisfied() {
+        let mut generator = SequenceGenerator::default();
+        let mut machine_resources = MachineResources::new();
+        let tries = core::cell::Cell::new(0);
+        let sequence = generator.generate_matching(
+            8,
+            |sequence| {
+                tries.set(tries.get() + 1);
+                sequence.iter_active().count() >= 4
+            },
+            10,
+            &mut machine_resources,
+        );
+        assert_eq!(1, tries.get());
+        assert!(sequence.iter_active().count() >= 4);
+    }
+
+    #[test]
+    fn sequence_generator_generate_matching_should_regenerate_until_out_of_tries() {
+        let mut generator = SequenceGenerator {
+            melody_machine: Box::new(RandMelodyMachine::new()),
+            ..Default::default()
+        };
+        let mut machine_resources = MachineResources::new();
+        let checksums = core::cell::RefCell::new(std::vec::Vec::new());
+        let sequence = generator.generate_matching(
+            8,
+            |sequence| {
+                checksums.borrow_mut().push(sequence.checksum());
+                sequence.iter_active().count() > 8
+            },
+            10,
+            &mut machine_resources,
+        );
+        let checksums = checksums.into_inner();
+        assert_eq!(10, checksums.len());
+        assert!(checksums.iter().any(|&checksum| checksum != checksums[0]));
+        assert_eq!(checksums[9], sequence.checksum());
+        assert_eq!(8, sequence.len());
+    }
+
+    #[test]
+    fn sequence_generator_randomise_part_should_choose_valid_parts_and_change_mask() {
+        let mut generator = SequenceGenerator::default();
+        let mut machine_resources = MachineResources::new();
+        let unmasked = generator.apply(16);
+        let mut mask_changed = false;
+        for _ in 0..32 {
+            generator.randomise_part(&mut machine_resources);
+            let part: u8 = generator.part() as u8;
+            assert!(Part::try_from(part).is_ok());
+            if generator.apply(16) != unmasked {
+                mask_changed = true;
+            }
+        }
+        assert!(mask_changed);
+    }
+
+    #[test]
+    fn sequence_generator_should_apply_base_velocity_to_all_steps() {
+        let mut generator = SequenceGenerator::default();
+        generator.set_base_velocity(90);
+        let sequence = generator.apply(8);
+        assert!(sequence.iter().all(|step| {
+            let velocity: u8 = step.as_ref().unwrap().velocity.into();
+            velocity == 90
+        }));
+    }
+
+    #[derive(Clone, Debug)]
+    struct QuietShortMachine {
+        params: ParamList,
+    }
+
+    impl Machine for QuietShortMachine {
+        fn name(&self) -> &str {
+            "QUIET"
+        }
+
+        fn generate(&mut self, _machine_resources: &mut MachineResources) {}
+
+        fn apply(&self, sequence: Sequence) -> Sequence {
+            sequence
+        }
+
+        fn params(&self) -> &ParamList {
+            &self.params
+        }
+
+        fn params_mut(&mut self) -> &mut ParamList {
+            &mut self.params
+        }
+
+        fn default_velocity(&self) -> Option<u8> {
+            Some(50)
+        }
+
+        fn default_gate(&self) -> Option<u8> {
+            Some(20)
+        }
+    }
+
+    #[test]
+    fn sequence_generator_should_use_velocity_and_gate_suggested_by_machine() {
+        let mut generator = SequenceGenerator {
+            rhythm_machine: Box::new(QuietShortMachine {
+                params: ParamList::new(),
+            }),
+            ..Default::default()
+        };
+        generator.set_base_velocity(90);
+        let sequence = generator.apply(8);
+        assert!(sequence.iter().all(|step| {
+            let step = step.as_ref().unwrap();
+            u8::from(step.velocity) == 50 && step.length_step_cents == 20
+        }));
+        let default_step = Step::new(60).unwrap();
+        generator.rhythm_machine = Box::new(UnitMachine::new());
+        let sequence = generator.apply(8);
+        assert!(sequence.iter().all(|step| {
+            let step = step.as_ref().unwrap();
+            u8::from(step.velocity) == 90
+                && step.length_step_cents == default_step.length_step_cents
+        }));
+    }
+
+    #[test]
+    fn sequence_generator_should_give_grids_hats_short_gates() {
+        let mut generator = SequenceGenerator::default();
+        generator.set_rhythm_machine(RhythmMachineId::Grids);
+        generator.rhythm_machine.params_mut()[0]
+            .set(ParamValue::Instrument(Instrument::HH))
+            .unwrap();
+        let sequence = generator.apply(16);
+        assert!(sequence.iter_active().count() > 0);
+        assert!(sequence
+            .iter_active()
+            .all(|(_, step)| step.length_step_cents == 25));
+    }
+
+    #[test]
+    fn sequence_generator_groove_machine_should_change_velocities_but_not_notes_or_rhythm() {
+        let mut generator = SequenceGenerator::default();
+        let mut machine_resources = MachineResources::new();
+        generator.rhythm_machine = RhythmMachineId::Euclid.into();
+        generator.melody_machine = Box::new(RandMelodyMachine::new());
+        generator.generate(&mut machine_resources);
+        let sequence = generator.apply(16);
+        generator.set_groove_machine(GrooveMachineId::Accent);
+        let grooved_sequence = generator.apply(16);
+        assert_eq!(sequence, grooved_sequence);
+        assert_eq!(sequence.to_mask(), grooved_sequence.to_mask());
+        let velocity = |step: &Option<Step>| step.as_ref().map(|step| u8::from(step.velocity));
+        assert!(zip(sequence.iter(), grooved_sequence.iter())
+            .any(|(step, grooved_step)| velocity(step) != velocity(grooved_step)));
+    }
+
+    #[test]
+    fn sequence_generator_set_groove_machine_should_carry_over_shared_params() {
+        let mut generator = SequenceGenerator::default();
+        generator.set_time_division(TimeDivision::Eigth);
+        generator.set_groove_machine(GrooveMachineId::Accent);
+        generator.groove_machine.params_mut()[1]
+            .set(ParamValue::Number(1))
+            .unwrap();
+        generator.set_groove_machine(GrooveMachineId::Ghost);
+        assert_eq!(GrooveMachineId::Ghost, generator.groove_machine_id());
+        assert_eq!("GHOST", generator.groove_machine.name());
+        assert_eq!("VELLEN", generator.groove_machine.params()[1].name());
+        assert_eq!(
+            ParamValue::Number(1),
+            generator.groove_machine.params()[1].value()
+        );
+
+        // accents follow the beat of the track's time division, 2 eighths
+        generator.set_groove_machine(GrooveMachineId::Accent);
+        let velocities: Vec<u8, 4> = generator
+            .apply(4)
+            .iter()
+            .map(|step| step.as_ref().unwrap().velocity.into())
+            .collect();
+        assert_eq!(velocities[0], velocities[2]);
+        assert!(velocities[0] > velocities[1]);
+    }
+
+    #[test]
+    fn sequence_generator_regenerate_suffix_should_only_change_new_steps() {
+        let mut generator = SequenceGenerator::default();
+        let mut machine_resources = MachineResources::new();
+        generator.rhythm_machine = RhythmMachineId::Euclid.into();
+        generator.melody_machine = Box::new(RandMelodyMachine::new());
+        generator.generate(&mut machine_resources);
+        let sequence = generator.apply(8);
+        generator.generate(&mut machine_resources);
+        let fresh_sequence = generator.apply(16);
+        let lengthened = generator.regenerate_suffix(sequence.clone(), 8, 16);
+        assert_eq!(16, lengthened.len());
+        assert!(sequence.steps_eq(&lengthened.steps[0..8]));
+        assert!(
+            Sequence::new(Vec::from_slice(&fresh_sequence.steps[8..16]).unwrap())
+                .steps_eq(&lengthened.steps[8..16])
+        );
+    }
+
     #[test]
     fn sequence_generator_should_quantize_melodies_if_configured_to_do_so() {
         let mut generator = SequenceGenerator::default();
         generator.set_part(Part::Sequence);
         let params = generator.harmony_params_mut();
-        params[0].set(ParamValue::Scale(Scale::Major));
-        params[1].set(ParamValue::Key(Key::B));
+        params[0].set(ParamValue::Scale(Scale::Major)).unwrap();
+        params[1].set(ParamValue::Key(Key::B)).unwrap();
         let sequence = generator.apply(8);
         assert!(sequence.steps[0].is_some());
         let step0 = sequence.steps[0].as_ref().unwrap();
@@ -162,6 +861,116 @@ mod tests {
         assert_eq!(expected, step0_note_num); // exp
     }
 
+    #[test]
+    fn sequence_generator_with_chord_tones_only_should_limit_notes_to_triad() {
+        for (scale, key, triad) in [
+            (Scale::Major, Key::D, [2, 6, 9]),
+            (Scale::NaturalMinor, Key::A, [9, 0, 4]),
+        ] {
+            let mut generator = SequenceGenerator::default();
+            let mut machine_resources = MachineResources::new();
+            generator.set_part(Part::Sequence);
+            generator.melody_machine = Box::new(RandMelodyMachine::new());
+            let params = generator.harmony_params_mut();
+            params[0].set(ParamValue::Scale(scale)).unwrap();
+            params[1].set(ParamValue::Key(key)).unwrap();
+            generator.set_chord_tones_only(true);
+            for _ in 0..8 {
+                generator.generate(&mut machine_resources);
+                let sequence = generator.apply(16);
+                assert!(sequence.iter_active().all(|(_, step)| {
+                    let note_num: u8 = step.note.into();
+                    triad.contains(&(note_num % 12))
+                }));
+            }
+        }
+    }
+
+    #[test]
+    fn sequence_generator_shift_octave_should_transpose_whole_sequence() {
+        let mut generator = SequenceGenerator::default();
+        generator.shift_octave(1);
+        let sequence = generator.apply(8);
+        assert!(sequence.iter().all(|step| {
+            let note_num: u8 = step.as_ref().unwrap().note.into();
+            note_num == 72
+        }));
+        generator.shift_octave(-10);
+        assert_eq!(-4, generator.octave_shift());
+    }
+
+    #[test]
+    fn sequence_generator_evolve_with_zero_amount_should_keep_sequence_fixed() {
+        let mut generator = SequenceGenerator::default();
+        let mut machine_resources = MachineResources::new();
+        generator.melody_machine = Box::new(RandMelodyMachine::new());
+        generator.generate(&mut machine_resources);
+        let sequence = generator.apply(16);
+        let evolved = generator.evolve(sequence.clone(), &mut machine_resources);
+        assert_eq!(sequence, evolved);
+    }
+
+    #[test]
+    fn sequence_generator_evolve_should_change_a_bounded_number_of_steps() {
+        let mut generator = SequenceGenerator::default();
+        let mut machine_resources = MachineResources::new();
+        generator.melody_machine = Box::new(RandMelodyMachine::new());
+        generator.set_evolve_amount(25);
+        generator.generate(&mut machine_resources);
+        let mut sequence = generator.apply(16);
+        let mut any_changed = false;
+        for _ in 0..8 {
+            let evolved = generator.evolve(sequence.clone(), &mut machine_resources);
+            let changed_steps = zip(sequence.iter(), evolved.iter())
+                .filter(|(step, evolved_step)| step != evolved_step)
+                .count();
+            assert!(changed_steps <= 4);
+            any_changed |= changed_steps > 0;
+            sequence = evolved;
+        }
+        assert!(any_changed);
+    }
+
+    #[test]
+    fn sequence_generator_variation_should_change_steps_in_proportion_to_amount() {
+        let mut generator = SequenceGenerator::default();
+        let mut machine_resources = MachineResources::new();
+        generator.melody_machine = Box::new(RandMelodyMachine::new());
+        generator.generate(&mut machine_resources);
+        let sequence = generator.apply(16);
+        let changed_steps = |variation: &Sequence| {
+            zip(sequence.iter(), variation.iter())
+                .filter(|(step, variation_step)| step != variation_step)
+                .count()
+        };
+
+        let variation = generator.generate_variation(sequence.clone(), 0, &mut machine_resources);
+        assert_eq!(sequence, variation);
+
+        for _ in 0..8 {
+            let small_variation =
+                generator.generate_variation(sequence.clone(), 10, &mut machine_resources);
+            assert_eq!(16, small_variation.len());
+            assert!(changed_steps(&small_variation) <= 2);
+
+            let large_variation =
+                generator.generate_variation(sequence.clone(), 100, &mut machine_resources);
+            assert!(changed_steps(&large_variation) >= 8);
+        }
+    }
+
+    #[test]
+    fn sequence_generator_variation_should_not_change_machine_state() {
+        let mut generator = SequenceGenerator::default();
+        let mut machine_resources = MachineResources::new();
+        generator.melody_machine = Box::new(RandMelodyMachine::new());
+        generator.generate(&mut machine_resources);
+        let sequence = generator.apply(16);
+        let _variation =
+            generator.generate_variation(sequence.clone(), 100, &mut machine_resources);
+        assert_eq!(sequence, generator.apply(16));
+    }
+
     #[test]
     fn sequence_generator_with_part_equal_call_should_only_have_active_steps_in_first_half_of_sequence(
     ) {
@@ -209,4 +1018,36 @@ mod tests {
         let sequence2 = generator.apply(8);
         assert_ne!(sequence1, sequence2);
     }
+
+    #[test]
+    fn sequence_generators_with_the_same_seed_should_generate_the_same_sequence() {
+        let sequences: Vec<Sequence, 2> = (0..2)
+            .map(|_| {
+                let mut generator = SequenceGenerator::default();
+                generator.set_rhythm_machine(RhythmMachineId::Grids);
+                generator.set_melody_machine(MelodyMachineId::Rand);
+                generator.set_seed(Some(0x5eed));
+                generator.generate(&mut MachineResources::new());
+                generator.apply(16)
+            })
+            .collect();
+        assert!(sequences[0].steps_eq(&sequences[1].steps));
+    }
+
+    #[test]
+    fn sequence_generator_without_seed_should_generate_new_sequences() {
+        let mut generator = SequenceGenerator::default();
+        generator.set_melody_machine(MelodyMachineId::Rand);
+        let mut machine_resources = MachineResources::new();
+        generator.set_seed(Some(0x5eed));
+        generator.generate(&mut machine_resources);
+        let seeded_sequence = generator.apply(16);
+        generator.generate(&mut machine_resources);
+        assert!(seeded_sequence.steps_eq(&generator.apply(16).steps));
+        assert_eq!(Some(0x5eed), generator.seed());
+
+        generator.set_seed(None);
+        generator.generate(&mut machine_resources);
+        assert!(!seeded_sequence.steps_eq(&generator.apply(16).steps));
+    }
 }
@@ -1,22 +1,73 @@
 use alloc::boxed::Box;
-use core::fmt::{Display, Formatter, Result as FmtResult};
+use core::fmt::{Display, Formatter, Result as FmtResult, Write};
 use fugit::{ExtU64, MicrosDurationU64};
-use heapless::{HistoryBuffer, Vec};
-use midi_types::MidiMessage;
+use heapless::{HistoryBuffer, String, Vec};
+use midi_types::{Channel, Control, MidiMessage, Note, Value7};
 
 use crate::{
-    param::{Param, ParamList, ParamValue},
-    TimeDivision, Track, TRACK_COUNT,
+    machine_resources::{MachineResources, Rng},
+    midi::ThruFilter,
+    param::{wrapping_add, Param, ParamError, ParamList, ParamValue},
+    quantizer::{Chord, Key, Scale},
+    render_snapshot::RenderSnapshot,
+    sequence_generator::SequenceGenerator,
+    song::{PatternRef, Song},
+    Direction, LfoShape, NotePriority, Sequence, Step, TimeDivision, Track, SEQUENCE_MAX_STEPS,
+    STEP_PROBABILITY_MAX, STEP_RATCHET_MAX, TICKS_PER_BAR_24PPQN, TICKS_PER_BEAT_24PPQN,
+    TRACK_COUNT,
 };
 
-// TODO will cause issues if polyphony
-const MAX_MESSAGES_PER_TICK: usize = TRACK_COUNT * 2;
+// note on/off for every ratcheted sub-note, vibrato pitch bend, LFO CC and program change for each
+// track, note offs for any notes choked by mute groups or voice stealing, plus a transport message
+// when acting as clock master and a clock tick when running on the internal clock
+pub const MAX_MESSAGES_PER_TICK: usize =
+    TRACK_COUNT * (STEP_RATCHET_MAX as usize * 2 + 3) + MAX_ACTIVE_NOTES + 2;
+
+// gate of ratcheted sub-notes, as % of their slice of the step, is limited so that each sub-note
+// is released before the next retriggers it
+const RATCHET_MAX_GATE_CENTS: u8 = 90;
 
 const MIDI_HISTORY_SAMPLE_COUNT: usize = 6;
 
+/// Longest tempo smoothing window, one quarter note of MIDI clock ticks.
+pub const MIDI_HISTORY_MAX_SAMPLE_COUNT: usize = 24;
+
+const MAX_ACTIVE_NOTES: usize = TRACK_COUNT * 4;
+
+// stolen voices' note-offs which haven't been seen this long after they fell due are forgotten,
+// e.g. if they were never sent
+const STOLEN_NOTE_OFF_GRACE_US: u64 = 100_000;
+
+pub const SCENE_COUNT: usize = 4;
+
+const HUMANIZE_MAX: u8 = 100;
+
+// velocity added to steps which coincide with an active step of their accent track
+const ACCENT_VELOCITY_BOOST: u8 = 32;
+
+// vibrato pitch bend is sent at most every other tick for each track, so that it doesn't crowd
+// notes out of MIDI out
+const VIBRATO_UPDATE_TICKS: u32 = 2;
+
+// velocity is moved up or down by at most this much at full humanize
+const HUMANIZE_MAX_VELOCITY_JITTER: i32 = 32;
+
+// shortest gate left to a note whose humanized note on is delayed towards its fixed note off
+const HUMANIZE_MIN_GATE_US: u64 = 1000;
+
+// mixed into the humanize seed to seed step probability and the random order of
+// `Direction::Random` tracks, so that each follows its own numbers
+const PROBABILITY_SEED_MIX: u64 = 0x2545_f491_4f6c_dd1d;
+const RANDOM_ORDER_SEED_MIX: u64 = 0x5851_f42d_4c95_7f2d;
+
 #[derive(Debug)]
 pub enum SequencerError {
     EnableTrackError(),
+    TrackDisabledError(),
+    InvalidTrackError(),
+    TooManyMessagesError(),
+    InvalidSceneError(),
+    InvalidPatternError(),
 }
 
 #[derive(Debug, PartialEq)]
@@ -25,9 +76,90 @@ pub enum ScheduledMidiMessage {
     Delayed(MidiMessage, MicrosDurationU64),
 }
 
+// short arpeggio used to check that MIDI out is wired up correctly
+const MIDI_TEST_NOTES: [u8; 4] = [60, 64, 67, 72];
+const MIDI_TEST_NOTE_INTERVAL_US: u64 = 250_000;
+const MIDI_TEST_NOTE_LENGTH_US: u64 = 200_000;
+const MIDI_TEST_MESSAGE_COUNT: usize = MIDI_TEST_NOTES.len() * 2;
+
+// length of notes played while editing a note, long enough to hear but short enough to scroll
+const PREVIEW_NOTE_LENGTH_US: u64 = 150_000;
+
 const DEFAULT_BPM: u64 = 130;
 const DEFAULT_TICK_DURATION_US: u64 = (60_000_000 / DEFAULT_BPM) / 24;
 
+const INTERNAL_CLOCK_MIN_BPM: u16 = 30;
+const INTERNAL_CLOCK_MAX_BPM: u16 = 300;
+
+// in auto mode, fall back to the internal clock if no external clock tick arrives for this long.
+// This is longer than the gap between ticks at the slowest sensible tempo.
+const EXTERNAL_CLOCK_TIMEOUT_US: u64 = 500_000;
+
+// external clock ticks arriving sooner than this % of the average tick duration after the last
+// tick are taken to be glitches
+const EARLY_TICK_PERCENT: u64 = 50;
+
+/// Where the sequencer gets its clock from.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ClockSource {
+    /// Follow MIDI clock from MIDI in.
+    #[default]
+    External,
+
+    /// Generate clock internally at a set tempo.
+    Internal,
+
+    /// Follow MIDI clock while it's arriving, fall back to the internal clock if it stops.
+    Auto,
+}
+
+const CLOCK_SOURCES: [ClockSource; 3] = [
+    ClockSource::External,
+    ClockSource::Internal,
+    ClockSource::Auto,
+];
+
+impl ClockSource {
+    /// Move `n` places through the clock sources, wrapping around at either end.
+    pub fn increment(&self, n: i8) -> ClockSource {
+        let index = wrapping_add(*self as i32, n as i32, CLOCK_SOURCES.len() as i32 - 1);
+        CLOCK_SOURCES[index as usize]
+    }
+}
+
+impl Display for ClockSource {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        Display::fmt(
+            match self {
+                ClockSource::External => "EXT",
+                ClockSource::Internal => "INT",
+                ClockSource::Auto => "AUTO",
+            },
+            f,
+        )
+    }
+}
+
+/// When changes made while playing take effect: newly enabled tracks, pattern switches and scene
+/// recalls.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LaunchQuant {
+    Immediate,
+    NextBeat,
+    #[default]
+    NextBar,
+}
+
+impl LaunchQuant {
+    fn is_launch_tick(&self, tick: u32) -> bool {
+        match self {
+            LaunchQuant::Immediate => true,
+            LaunchQuant::NextBeat => tick.is_multiple_of(TICKS_PER_BEAT_24PPQN as u32),
+            LaunchQuant::NextBar => tick.is_multiple_of(TICKS_PER_BAR_24PPQN as u32),
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub enum Swing {
     #[default]
@@ -83,13 +215,199 @@ impl TryFrom<u8> for Swing {
     }
 }
 
+/// Messages for a short arpeggio on MIDI channel 1, which can be sent at boot to check that
+/// MIDI out is working without setting up a pattern.
+pub fn midi_test_messages() -> Vec<ScheduledMidiMessage, MIDI_TEST_MESSAGE_COUNT> {
+    let mut messages = Vec::new();
+    for (i, &note) in MIDI_TEST_NOTES.iter().enumerate() {
+        let note_on_time = i as u64 * MIDI_TEST_NOTE_INTERVAL_US;
+        let note_on_message = MidiMessage::NoteOn(0.into(), note.into(), 127.into());
+        let note_on = if note_on_time == 0 {
+            ScheduledMidiMessage::Immediate(note_on_message)
+        } else {
+            ScheduledMidiMessage::Delayed(note_on_message, note_on_time.micros())
+        };
+        let note_off = ScheduledMidiMessage::Delayed(
+            MidiMessage::NoteOff(0.into(), note.into(), 0.into()),
+            (note_on_time + MIDI_TEST_NOTE_LENGTH_US).micros(),
+        );
+        messages
+            .push(note_on)
+            .expect("should push message to messages vec");
+        messages
+            .push(note_off)
+            .expect("should push message to messages vec");
+    }
+    messages
+}
+
+/// Messages to play a single short note, so that a note can be heard while it's being edited.
+pub fn preview_note_messages(channel: Channel, note: Note) -> [ScheduledMidiMessage; 2] {
+    [
+        ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(channel, note, 127.into())),
+        ScheduledMidiMessage::Delayed(
+            MidiMessage::NoteOff(channel, note, 0.into()),
+            PREVIEW_NOTE_LENGTH_US.micros(),
+        ),
+    ]
+}
+
+/// A note which has been sent to MIDI out and not yet released.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ActiveNote {
+    channel: Channel,
+    note: Note,
+    note_off_us: u64,
+    mute_group: Option<u8>,
+}
+
+/// A track's step repeating in place of the track's normal playback.
+#[derive(Clone, Debug)]
+struct NoteRepeat {
+    track_num: u8,
+    rate: TimeDivision,
+
+    /// Step being repeated, or `None` until the track has a step to repeat.
+    step: Option<Step>,
+}
+
+/// Snapshot of the performance state of the sequencer, which can be recalled to jump between
+/// sections of a live set.
+#[derive(Clone, Debug)]
+pub struct Scene {
+    swing: Swing,
+    swing_resolution_override: Option<TimeDivision>,
+    internal_clock_bpm: u16,
+    humanize: u8,
+
+    /// Which steps of each track are muted, indexed by track number.
+    step_mutes: [Option<Vec<bool, SEQUENCE_MAX_STEPS>>; TRACK_COUNT],
+}
+
 pub struct Sequencer {
     pub tracks: Vec<Option<Track>, TRACK_COUNT>,
     tick: u32,
     playing: bool,
     params: ParamList,
     last_tick_instant_us: Option<u64>,
-    midi_tick_history: HistoryBuffer<u64, MIDI_HISTORY_SAMPLE_COUNT>,
+    midi_tick_history: HistoryBuffer<u64, MIDI_HISTORY_MAX_SAMPLE_COUNT>,
+
+    /// Number of recent MIDI clock ticks averaged to estimate tempo.
+    tempo_smoothing: usize,
+
+    /// Swing every other step at this time division on all tracks. When `None`, each track is
+    /// swung at its own time division.
+    swing_resolution_override: Option<TimeDivision>,
+
+    /// Pairs of linked tracks, indexed by track number. Linked tracks share length and time
+    /// division.
+    links: [Option<u8>; TRACK_COUNT],
+
+    /// Track whose steps accent each track, indexed by track number. A step which plays at the
+    /// same time as an active step of its accent track is played louder.
+    accent_tracks: [Option<u8>; TRACK_COUNT],
+
+    /// Scale and key which the generators of new tracks quantize to.
+    default_scale: Scale,
+    default_key: Key,
+
+    /// Notes held by tracks with `TimeDivision::Free`, indexed by track number.
+    drone_notes: [Option<(Channel, Note)>; TRACK_COUNT],
+
+    /// Track whose step is being repeated in place of its normal playback, see
+    /// `set_note_repeat`.
+    note_repeat: Option<NoteRepeat>,
+
+    /// Notes which are currently sounding, used to limit polyphony.
+    active_notes: Vec<ActiveNote, MAX_ACTIVE_NOTES>,
+
+    /// Notes released early to steal their voice, whose scheduled note-off is still to come. That
+    /// note-off is dropped when it falls due, see `should_send`.
+    stolen_notes: Vec<ActiveNote, MAX_ACTIVE_NOTES>,
+
+    /// Pitch bend last sent by each track's vibrato, or `None` if it's at centre.
+    vibrato_bends: [Option<(Channel, i16)>; TRACK_COUNT],
+
+    /// Program change last sent by each track, or `None` if the track's program hasn't been sent
+    /// yet.
+    sent_programs: [Option<(Channel, u8)>; TRACK_COUNT],
+
+    /// CC last sent by each track's LFO, used to only send a CC when its value changes.
+    lfo_values: [Option<(Channel, Control, u8)>; TRACK_COUNT],
+
+    /// Look one tick ahead for steps with a negative delay, so they can be played early.
+    look_ahead: bool,
+
+    /// Maximum number of notes which can sound at once on each MIDI channel, or `None` for no
+    /// limit.
+    max_polyphony: Option<u8>,
+
+    /// Lowest and highest notes sent to MIDI out. Notes outside the range are clamped to it.
+    note_range: (u8, u8),
+
+    /// Amount of random velocity and timing variation applied to notes as they're played, from 0
+    /// (off) to 100.
+    humanize: u8,
+    humanize_seed: u64,
+
+    /// If true, humanized timing delays note offs as well as note ons, keeping gate lengths
+    /// consistent. Otherwise note offs stay on the grid, so humanized notes are shorter.
+    humanize_note_off: bool,
+
+    humanize_rng: Rng,
+
+    /// Decides whether steps with less than full probability play. Kept apart from
+    /// `humanize_rng`, so that changing the humanize amount doesn't change which steps play.
+    /// Seeded from the humanize seed, so that the same seed always gives the same performance.
+    probability_rng: Rng,
+
+    /// Chooses the order of steps for tracks playing in `Direction::Random`, seeded from the
+    /// humanize seed, so that the same seed always gives the same order.
+    random_order_rng: Rng,
+
+    /// When acting as clock master, transport changes are sent to MIDI out so that other devices
+    /// follow.
+    clock_master: bool,
+
+    /// Transport message waiting to be sent on the next call to `advance`.
+    pending_transport_message: Option<MidiMessage>,
+
+    /// Tracks which have started a new loop since their sequences were last evolved.
+    looped_tracks: [bool; TRACK_COUNT],
+
+    /// Soloed tracks, indexed by track number. While any track is soloed, only soloed tracks
+    /// play.
+    soloed: [bool; TRACK_COUNT],
+
+    clock_source: ClockSource,
+    internal_clock_bpm: u16,
+
+    /// Messages from MIDI in which aren't passed through to MIDI out.
+    thru_filter: ThruFilter,
+
+    /// Saved scenes, and a scene waiting to be recalled at the next launch point.
+    scenes: [Option<Scene>; SCENE_COUNT],
+    pending_scene: Option<usize>,
+
+    /// Song arranging the tracks' patterns, or `None` to loop the current sequences.
+    song: Option<Song>,
+
+    /// Tracks and pattern switches waiting for the next launch point, indexed by track number.
+    launch_quantize: LaunchQuant,
+    pending_tracks: [Option<Track>; TRACK_COUNT],
+    pending_patterns: [Option<usize>; TRACK_COUNT],
+
+    /// Time the last MIDI clock tick arrived from MIDI in, used to detect external clock
+    /// stopping in auto mode.
+    last_external_tick_us: Option<u64>,
+
+    /// True if the last external tick was ignored for arriving too early, see
+    /// `reject_early_tick`.
+    early_tick_rejected: bool,
+
+    /// True if the last tick came from the external clock. Used to detect switching between
+    /// clocks.
+    last_tick_external: bool,
 }
 
 impl Default for Sequencer {
@@ -108,10 +426,49 @@ impl Default for Sequencer {
             params: ParamList::from_slice(&[
                 // if ordering changes, need to update getters and setters, e.g. swing/set_swing
                 Box::new(Param::new_swing_param("SWING")),
+                Box::new(Param::new_note_repeat_param("REPEAT")),
             ])
             .expect("should create sequencer param list from slice"),
             last_tick_instant_us: None,
-            midi_tick_history: HistoryBuffer::<u64, MIDI_HISTORY_SAMPLE_COUNT>::new(),
+            midi_tick_history: HistoryBuffer::<u64, MIDI_HISTORY_MAX_SAMPLE_COUNT>::new(),
+            tempo_smoothing: MIDI_HISTORY_SAMPLE_COUNT,
+            swing_resolution_override: None,
+            links: [None; TRACK_COUNT],
+            accent_tracks: [None; TRACK_COUNT],
+            default_scale: Default::default(),
+            default_key: Default::default(),
+            drone_notes: [None; TRACK_COUNT],
+            note_repeat: None,
+            active_notes: Vec::new(),
+            stolen_notes: Vec::new(),
+            vibrato_bends: [None; TRACK_COUNT],
+            sent_programs: [None; TRACK_COUNT],
+            lfo_values: [None; TRACK_COUNT],
+            look_ahead: false,
+            max_polyphony: None,
+            note_range: (0, 127),
+            humanize: 0,
+            humanize_seed: 0,
+            humanize_note_off: true,
+            humanize_rng: Rng::new(0),
+            probability_rng: Rng::new(PROBABILITY_SEED_MIX),
+            random_order_rng: Rng::new(RANDOM_ORDER_SEED_MIX),
+            clock_master: false,
+            pending_transport_message: None,
+            looped_tracks: [false; TRACK_COUNT],
+            soloed: [false; TRACK_COUNT],
+            clock_source: Default::default(),
+            internal_clock_bpm: DEFAULT_BPM as u16,
+            thru_filter: Default::default(),
+            scenes: Default::default(),
+            pending_scene: None,
+            song: None,
+            launch_quantize: Default::default(),
+            pending_tracks: Default::default(),
+            pending_patterns: [None; TRACK_COUNT],
+            last_external_tick_us: None,
+            early_tick_rejected: false,
+            last_tick_external: true,
         }
     }
 }
@@ -135,15 +492,64 @@ impl Sequencer {
 
     pub fn start_playing(&mut self) {
         self.tick = 0;
-        self.playing = true
+        self.reseed_rngs();
+        self.rewind_song();
+        // resend programs, in case the synth was changed while stopped
+        self.sent_programs = [None; TRACK_COUNT];
+        self.playing = true;
+        self.queue_transport_message(MidiMessage::Start);
     }
 
     pub fn stop_playing(&mut self) {
         self.playing = false;
+        self.queue_transport_message(MidiMessage::Stop);
     }
 
     pub fn continue_playing(&mut self) {
-        self.playing = true
+        self.playing = true;
+        self.queue_transport_message(MidiMessage::Continue);
+    }
+
+    /// Return the sequencer to its stopped state, clearing the playhead, clock timing history and
+    /// any sounding notes. Tracks and settings are kept, so one sequencer can be reused, e.g.
+    /// across test scenarios.
+    pub fn reset(&mut self) {
+        self.tick = 0;
+        self.playing = false;
+        self.last_tick_instant_us = None;
+        self.midi_tick_history.clear();
+        self.drone_notes = [None; TRACK_COUNT];
+        self.note_repeat = None;
+        self.active_notes.clear();
+        self.stolen_notes.clear();
+        self.vibrato_bends = [None; TRACK_COUNT];
+        self.sent_programs = [None; TRACK_COUNT];
+        self.lfo_values = [None; TRACK_COUNT];
+        self.reseed_rngs();
+        self.pending_transport_message = None;
+        self.looped_tracks = [false; TRACK_COUNT];
+        if let Some(song) = self.song.as_mut() {
+            song.rewind();
+        }
+        self.last_external_tick_us = None;
+        self.early_tick_rejected = false;
+        self.last_tick_external = true;
+    }
+
+    pub fn clock_master(&self) -> bool {
+        self.clock_master
+    }
+
+    /// Act as clock master. Starting, stopping and continuing the sequencer will send MIDI
+    /// `Start`, `Stop` and `Continue` messages on the next call to `advance`.
+    pub fn set_clock_master(&mut self, clock_master: bool) {
+        self.clock_master = clock_master;
+    }
+
+    fn queue_transport_message(&mut self, message: MidiMessage) {
+        if self.clock_master {
+            self.pending_transport_message = Some(message);
+        }
     }
 
     pub fn swing(&self) -> Swing {
@@ -154,195 +560,3554 @@ impl Sequencer {
     }
 
     pub fn set_swing(&mut self, swing: Swing) {
-        self.params[0].set(ParamValue::Swing(swing));
+        self.params[0]
+            .set(ParamValue::Swing(swing))
+            .expect("should set swing");
     }
 
-    pub fn enable_track(&mut self, track_num: u8, new_track: Track) -> &mut Track {
-        self.tracks[track_num as usize].insert(new_track)
+    /// Time division to repeat notes at when a note repeat starts, see `set_note_repeat`.
+    pub fn note_repeat_rate(&self) -> TimeDivision {
+        self.params[1]
+            .value()
+            .try_into()
+            .expect("invalid note repeat parameter for sequencer")
     }
 
-    pub fn advance(&mut self, now_us: u64) -> Vec<ScheduledMidiMessage, MAX_MESSAGES_PER_TICK> {
-        let tick_duration = self.average_tick_duration(now_us);
+    /// Set the time division to repeat notes at. Rates outside 1/32 to 1/8 are refused and the
+    /// rate is unchanged.
+    pub fn set_note_repeat_rate(&mut self, rate: TimeDivision) -> Result<(), ParamError> {
+        self.params[1].set(ParamValue::TimeDivision(rate))
+    }
 
-        let mut output_messages = Vec::new();
+    /// Track being repeated and the time division it repeats at, or `None` if no note repeat is
+    /// active.
+    pub fn note_repeat(&self) -> Option<(u8, TimeDivision)> {
+        self.note_repeat
+            .as_ref()
+            .map(|note_repeat| (note_repeat.track_num, note_repeat.rate))
+    }
 
-        if !self.playing {
-            return output_messages;
+    /// Start repeating the current step of a track every `rate`, or stop with `None`. While
+    /// repeating, the track's own steps are silent but its playhead keeps moving, so playback
+    /// carries on in time when the repeat stops. The step sounding when the repeat starts is the
+    /// one repeated, or the track's last step to have played if it's between steps. Stopping always
+    /// succeeds, even if the track has since been disabled.
+    pub fn set_note_repeat(
+        &mut self,
+        track_num: u8,
+        rate: Option<TimeDivision>,
+    ) -> Result<(), SequencerError> {
+        if rate.is_some() && self.tracks[track_num as usize].is_none() {
+            return Err(SequencerError::TrackDisabledError());
         }
+        self.note_repeat = rate.map(|rate| NoteRepeat {
+            track_num,
+            rate,
+            step: None,
+        });
+        Ok(())
+    }
 
-        let apply_swing = self.swing() != Swing::None && self.tick % 12 == 6;
-        let swing_delay = (tick_duration * (self.swing().as_percentage() - 50) as u32) / 8;
+    pub fn swing_resolution_override(&self) -> Option<TimeDivision> {
+        self.swing_resolution_override
+    }
 
-        for track in &self.tracks {
-            if let Some(track) = track {
-                if let Some(step) = track.step_at_tick(self.tick) {
-                    let note_on_message =
-                        MidiMessage::NoteOn(track.midi_channel, step.note.into(), step.velocity);
-                    if apply_swing {
-                        output_messages
-                            .push(ScheduledMidiMessage::Delayed(note_on_message, swing_delay))
-                            .expect("should push message to output_messages vec");
-                    } else {
-                        output_messages
-                            .push(ScheduledMidiMessage::Immediate(note_on_message))
-                            .expect("should push message to output_messages vec");
-                    }
+    /// Force every track to swing at the same time division, e.g. `Some(TimeDivision::Sixteenth)`
+    /// for classic 1/16 swing. With `None`, tracks swing at their own time division.
+    pub fn set_swing_resolution_override(&mut self, swing_resolution: Option<TimeDivision>) {
+        self.swing_resolution_override = swing_resolution;
+    }
 
-                    let note_off_message =
-                        MidiMessage::NoteOff(track.midi_channel, step.note.into(), 0.into());
-                    let mut note_off_time = ((tick_duration.to_micros()
-                        * (TimeDivision::division_length_24ppqn(track.time_division) as u64)
-                        * step.length_step_cents as u64)
-                        / 100)
-                        .micros();
-                    if apply_swing {
-                        note_off_time += swing_delay;
-                    }
-                    output_messages
-                        .push(ScheduledMidiMessage::Delayed(
-                            note_off_message,
-                            note_off_time,
-                        ))
-                        .expect("should push message to output_messages vec");
-                }
-            }
+    /// Time division at which a track swings, every second step at this division is delayed.
+    fn swing_resolution(&self, track: &Track) -> TimeDivision {
+        self.swing_resolution_override
+            .unwrap_or(track.time_division)
+    }
+
+    pub fn tempo_smoothing(&self) -> usize {
+        self.tempo_smoothing
+    }
+
+    /// Set the number of recent clock ticks averaged to estimate tempo, from 1 to
+    /// `MIDI_HISTORY_MAX_SAMPLE_COUNT`. Longer windows smooth out clock jitter, but follow tempo
+    /// changes more slowly.
+    pub fn set_tempo_smoothing(&mut self, tempo_smoothing: usize) {
+        self.tempo_smoothing = tempo_smoothing.clamp(1, MIDI_HISTORY_MAX_SAMPLE_COUNT);
+    }
+
+    /// Tempo estimated from the average time between recent clock ticks, at 24 PPQN, rounded to
+    /// the nearest BPM. `None` until a full `tempo_smoothing` window of ticks has been timed, so
+    /// the default tempo assumed before then is never reported. Also `None` once no tick has
+    /// arrived for as long as auto clock waits before giving up on MIDI in, so a clock which has
+    /// stopped isn't reported at its last tempo.
+    pub fn bpm(&self, now_us: u64) -> Option<u16> {
+        let last_tick_us = self.last_tick_instant_us?;
+        if now_us.saturating_sub(last_tick_us) > EXTERNAL_CLOCK_TIMEOUT_US
+            || self.midi_tick_history.len() < self.tempo_smoothing
+        {
+            return None;
+        }
+        let beat_us = self.smoothed_tick_duration_us()? * TICKS_PER_BEAT_24PPQN as u64;
+        if beat_us == 0 {
+            return None;
         }
+        let bpm = (60_000_000 + beat_us / 2) / beat_us;
+        Some(bpm.min(u16::MAX as u64) as u16)
+    }
 
-        self.tick += 1;
+    pub fn max_polyphony(&self) -> Option<u8> {
+        self.max_polyphony
+    }
 
-        output_messages
+    /// Limit the number of notes which can sound at once on each MIDI channel, to protect
+    /// downstream synths. Once a channel is at its limit, each new note steals the voice of the
+    /// oldest note sounding on the channel, which is released as the new note plays.
+    pub fn set_max_polyphony(&mut self, max_polyphony: Option<u8>) {
+        self.max_polyphony = max_polyphony;
     }
 
-    /// Calculate average time between last k MIDI ticks. Defaults to tick frequency of
-    /// 19,230ms, which is equivalent to 130BPM.
-    fn average_tick_duration(&mut self, now_us: u64) -> MicrosDurationU64 {
-        let mut tick_duration = DEFAULT_TICK_DURATION_US.micros();
+    pub fn note_range(&self) -> (u8, u8) {
+        self.note_range
+    }
 
-        if let Some(last_tick_instant_us) = self.last_tick_instant_us {
-            let last_tick_duration = now_us - last_tick_instant_us;
-            self.midi_tick_history.write(last_tick_duration);
-            tick_duration = (self.midi_tick_history.as_slice().iter().sum::<u64>()
-                / self.midi_tick_history.len() as u64)
-                .micros();
-        }
+    /// Limit the notes sent to MIDI out to `min..=max`, whatever the machines generate, to
+    /// protect downstream synths. Notes outside the range are clamped to it, the sequences
+    /// themselves are left alone.
+    pub fn set_note_range(&mut self, min: u8, max: u8) {
+        let (min, max) = (min.min(127), max.min(127));
+        self.note_range = (min.min(max), min.max(max));
+    }
 
-        self.last_tick_instant_us = Some(now_us);
+    fn clamp_note(note: Note, (min, max): (u8, u8)) -> Note {
+        let note: u8 = note.into();
+        note.clamp(min, max).into()
+    }
 
-        tick_duration
+    pub fn humanize(&self) -> u8 {
+        self.humanize
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::sequence_generator::SequenceGenerator;
+    /// Set the amount of random variation in velocity and timing of notes, from 0 (off) to 100.
+    pub fn set_humanize(&mut self, humanize: u8) {
+        self.humanize = humanize.min(HUMANIZE_MAX);
+    }
 
-    #[test]
-    fn sequencer_default_should_have_empty_tracks() {
-        let sequencer = Sequencer::default();
-        assert!(sequencer.tracks.iter().all(|track| track.is_none()));
+    pub fn humanize_note_off(&self) -> bool {
+        self.humanize_note_off
     }
 
-    #[test]
-    fn sequencer_enable_track_should_insert_new_track() {
-        let generator = SequenceGenerator::default();
-        let mut new_track = Track::default();
-        new_track.sequence = generator.apply(new_track.length);
-        let mut sequencer = Sequencer::default();
-        sequencer.enable_track(0, new_track);
-        assert!(sequencer.tracks[0].is_some());
-        assert!(sequencer.tracks[1..TRACK_COUNT]
-            .iter()
-            .all(|track| track.is_none()));
+    /// Choose whether humanized timing shifts note offs along with note ons. On by default.
+    pub fn set_humanize_note_off(&mut self, humanize_note_off: bool) {
+        self.humanize_note_off = humanize_note_off;
     }
 
-    #[test]
-    fn sequencer_should_start_stop_and_continue_playing() {
-        let mut sequencer = Sequencer::default();
-        assert_eq!(false, sequencer.playing());
-        assert_eq!(0, sequencer.tick);
-        sequencer.start_playing();
-        assert_eq!(true, sequencer.playing());
+    pub fn look_ahead(&self) -> bool {
+        self.look_ahead
+    }
 
-        sequencer.advance(1);
-        sequencer.stop_playing();
-        assert_eq!(false, sequencer.playing());
+    /// Turn on look-ahead to play steps with a negative delay early, by up to one tick. When
+    /// off, those steps play on time.
+    pub fn set_look_ahead(&mut self, look_ahead: bool) {
+        self.look_ahead = look_ahead;
+    }
 
-        sequencer.advance(1); // should be ignored because sequencer stopped
-        sequencer.continue_playing();
-        sequencer.advance(1);
-        assert_eq!(true, sequencer.playing());
-        assert_eq!(2, sequencer.tick);
+    pub fn humanize_seed(&self) -> u64 {
+        self.humanize_seed
+    }
 
-        sequencer.stop_playing();
-        assert_eq!(2, sequencer.tick);
+    /// Set the seed for humanization. Every performance started with the same seed has the same
+    /// velocity and timing variations, and leaves out the same steps.
+    pub fn set_humanize_seed(&mut self, seed: u64) {
+        self.humanize_seed = seed;
+        self.reseed_rngs();
+    }
 
-        sequencer.start_playing();
-        assert_eq!(true, sequencer.playing());
-        assert_eq!(0, sequencer.tick);
+    /// Restart the random numbers used while playing from the humanize seed.
+    fn reseed_rngs(&mut self) {
+        self.humanize_rng = Rng::new(self.humanize_seed);
+        self.probability_rng = Rng::new(self.humanize_seed ^ PROBABILITY_SEED_MIX);
+        self.random_order_rng = Rng::new(self.humanize_seed ^ RANDOM_ORDER_SEED_MIX);
     }
 
-    #[test]
-    fn sequencer_should_calculate_average_tick_duration() {
-        let mut sequencer = Sequencer::default();
-        let tick_duration = sequencer.average_tick_duration(0);
-        assert_eq!(DEFAULT_TICK_DURATION_US, tick_duration.to_micros());
+    pub fn default_scale(&self) -> Scale {
+        self.default_scale
+    }
 
-        let tick_duration = sequencer.average_tick_duration(100);
-        assert_eq!(100, tick_duration.to_micros());
+    pub fn default_key(&self) -> Key {
+        self.default_key
+    }
 
-        sequencer.average_tick_duration(200);
-        sequencer.average_tick_duration(300);
-        sequencer.average_tick_duration(350);
-        sequencer.average_tick_duration(400);
-        let tick_duration = sequencer.average_tick_duration(450);
-        assert_eq!(75, tick_duration.to_micros());
+    /// Set the scale and key for new tracks, e.g. to the key of the jam. Existing tracks aren't
+    /// changed.
+    pub fn set_default_harmony(&mut self, scale: Scale, key: Key) {
+        self.default_scale = scale;
+        self.default_key = key;
     }
 
-    #[test]
-    fn sequencer_advance_should_output_immediate_note_on_and_delayed_note_off_messages() {
-        let mut now_us = 0;
-        let mut sequencer = Sequencer::default();
-        let generator = SequenceGenerator::default();
-        let mut new_track = Track::default();
-        new_track.sequence = generator.apply(new_track.length);
-        sequencer.enable_track(0, new_track);
-        sequencer.start_playing();
-        let mut output_messages = vec![];
-        for _ in 0..48 {
-            let step_messages = sequencer.advance(now_us);
-            output_messages.extend(step_messages.into_iter());
-            now_us += DEFAULT_TICK_DURATION_US;
+    /// Create a generator for a new track, quantizing to the default scale and key.
+    pub fn new_sequence_generator(&self) -> SequenceGenerator {
+        let mut generator = SequenceGenerator::default();
+        generator.set_scale(self.default_scale);
+        generator.set_key(self.default_key);
+        generator
+    }
+
+    /// True if `generator` quantizes to a different scale or key from the default, e.g. because
+    /// its track was given its own harmony after it was created.
+    pub fn has_own_harmony(&self, generator: &SequenceGenerator) -> bool {
+        generator.scale() != self.default_scale || generator.key() != self.default_key
+    }
+
+    pub fn launch_quantize(&self) -> LaunchQuant {
+        self.launch_quantize
+    }
+
+    pub fn set_launch_quantize(&mut self, launch_quantize: LaunchQuant) {
+        self.launch_quantize = launch_quantize;
+    }
+
+    /// True if changes made now should wait for the next launch point.
+    fn should_queue_launch(&self) -> bool {
+        self.playing && self.launch_quantize != LaunchQuant::Immediate
+    }
+
+    /// Enable a track, replacing any existing track with the same number. While playing, the
+    /// track is launched at the next launch point, see `LaunchQuant`. Returns the new track, which
+    /// can still be changed before it's launched. The track's program is sent before its first
+    /// note.
+    pub fn enable_track(&mut self, track_num: u8, new_track: Track) -> &mut Track {
+        if self.should_queue_launch() {
+            return self.pending_tracks[track_num as usize].insert(new_track);
         }
-        assert_eq!(16, output_messages.len()); // 8 note on/note off pairs
-        let expected_note_on =
-            ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(0.into(), 60.into(), 127.into()));
-        let expected_note_off = ScheduledMidiMessage::Delayed(
-            MidiMessage::NoteOff(0.into(), 60.into(), 0.into()),
-            92304.micros(),
-        );
-        assert_eq!(expected_note_on, output_messages[0]);
-        assert_eq!(expected_note_off, output_messages[1]);
-        assert_eq!(expected_note_on, output_messages[2]);
-        assert_eq!(expected_note_off, output_messages[3]);
-        assert_eq!(expected_note_on, output_messages[4]);
-        assert_eq!(expected_note_off, output_messages[5]);
-        assert_eq!(expected_note_on, output_messages[6]);
-        assert_eq!(expected_note_off, output_messages[7]);
-        assert_eq!(expected_note_on, output_messages[8]);
-        assert_eq!(expected_note_off, output_messages[9]);
-        assert_eq!(expected_note_on, output_messages[10]);
-        assert_eq!(expected_note_off, output_messages[11]);
-        assert_eq!(expected_note_on, output_messages[12]);
-        assert_eq!(expected_note_off, output_messages[13]);
-        assert_eq!(expected_note_on, output_messages[14]);
-        assert_eq!(expected_note_off, output_messages[15]);
+        self.sent_programs[track_num as usize] = None;
+        self.tracks[track_num as usize].insert(new_track)
     }
 
-    #[test]
-    fn sequencer_advance_with_swing_enabled_should_output_delayed_note_on_messages_for_swung_steps()
-    {
-        let mut now_us = 0;
+    /// Switch a track to one of its stored patterns, see `Track::recall_pattern`. While playing,
+    /// the switch happens at the next launch point.
+    pub fn recall_pattern(&mut self, track_num: u8, slot: usize) -> Result<(), SequencerError> {
+        let queue_launch = self.should_queue_launch();
+        let track = self
+            .tracks
+            .get_mut(track_num as usize)
+            .ok_or(SequencerError::InvalidTrackError())?
+            .as_mut()
+            .ok_or(SequencerError::TrackDisabledError())?;
+        if !matches!(track.patterns.get(slot), Some(Some(_))) {
+            return Err(SequencerError::InvalidPatternError());
+        }
+        if queue_launch {
+            self.pending_patterns[track_num as usize] = Some(slot);
+        } else {
+            track.recall_pattern(slot);
+        }
+        Ok(())
+    }
+
+    pub fn song(&self) -> Option<&Song> {
+        self.song.as_ref()
+    }
+
+    /// Play a song, switching every track to the pattern of each part in turn, or go back to
+    /// looping the current sequences with `None`. The song starts from its first part, which is
+    /// recalled straight away. Parts move on when the first enabled track finishes a loop, so
+    /// it sets the length of each part.
+    pub fn set_song(&mut self, song: Option<Song>) {
+        self.song = song;
+        self.rewind_song();
+    }
+
+    /// Go back to the first part of the song, if there is one, and recall its pattern.
+    fn rewind_song(&mut self) {
+        if let Some(song) = self.song.as_mut() {
+            song.rewind();
+            if let Some(pattern) = song.pattern() {
+                self.recall_song_pattern(pattern);
+            }
+        }
+    }
+
+    /// Move the song on when the first enabled track finishes a loop, recalling the next part's
+    /// pattern once the current part has played all its loops.
+    fn advance_song(&mut self) {
+        let tick = self.tick;
+        let lead_track_loops = tick > 0
+            && matches!(self.tracks.iter().flatten().next(), Some(track) if track.starts_loop(tick));
+        if !lead_track_loops {
+            return;
+        }
+        if let Some(pattern) = self.song.as_mut().and_then(Song::next_loop) {
+            self.recall_song_pattern(pattern);
+        }
+    }
+
+    fn recall_song_pattern(&mut self, PatternRef(slot): PatternRef) {
+        for track in self.tracks.iter_mut().flatten() {
+            track.recall_pattern(slot);
+        }
+    }
+
+    /// Apply tracks, pattern switches and scenes which are waiting to launch.
+    fn launch_pending(&mut self) {
+        for (track_num, (track, pending_track)) in self
+            .tracks
+            .iter_mut()
+            .zip(self.pending_tracks.iter_mut())
+            .enumerate()
+        {
+            if let Some(pending_track) = pending_track.take() {
+                *track = Some(pending_track);
+                self.sent_programs[track_num] = None;
+            }
+        }
+        for (track, pending_pattern) in self.tracks.iter_mut().zip(self.pending_patterns.iter_mut())
+        {
+            if let (Some(track), Some(slot)) = (track, pending_pattern.take()) {
+                track.recall_pattern(slot);
+            }
+        }
+        if let Some(slot) = self.pending_scene.take() {
+            self.apply_scene(slot);
+        }
+    }
+
+    /// Link two tracks so that changing the length or time division of one changes the other.
+    /// Any existing links for either track are removed.
+    pub fn link_tracks(&mut self, track_num: u8, other_track_num: u8) {
+        self.unlink_track(track_num);
+        self.unlink_track(other_track_num);
+        if track_num != other_track_num {
+            self.links[track_num as usize] = Some(other_track_num);
+            self.links[other_track_num as usize] = Some(track_num);
+        }
+    }
+
+    pub fn unlink_track(&mut self, track_num: u8) {
+        if let Some(other_track_num) = self.links[track_num as usize].take() {
+            self.links[other_track_num as usize] = None;
+        }
+    }
+
+    pub fn linked_track(&self, track_num: u8) -> Option<u8> {
+        self.links[track_num as usize]
+    }
+
+    /// Accent `track_num` with the steps of `accent_track_num`, or stop accenting it if `None`.
+    /// A track can't accent itself.
+    pub fn set_accent_track(&mut self, track_num: u8, accent_track_num: Option<u8>) {
+        self.accent_tracks[track_num as usize] =
+            accent_track_num.filter(|&accent_track_num| accent_track_num != track_num);
+    }
+
+    pub fn accent_track(&self, track_num: u8) -> Option<u8> {
+        self.accent_tracks[track_num as usize]
+    }
+
+    /// Copy the length and time division of a track to its linked track. Returns the number of
+    /// the linked track if it was changed, so that its sequence can be regenerated.
+    pub fn sync_linked_track(&mut self, track_num: u8) -> Option<u8> {
+        let other_track_num = self.linked_track(track_num)?;
+        let (length, time_division) = self.tracks[track_num as usize]
+            .as_ref()
+            .map(|track| (track.length, track.time_division))?;
+        let other_track = self.tracks[other_track_num as usize].as_mut()?;
+        if other_track.length == length && other_track.time_division == time_division {
+            return None;
+        }
+        other_track.set_length(length);
+        other_track.set_time_division(time_division);
+        Some(other_track_num)
+    }
+
+    /// Save the swing, tempo, humanize and step mutes of every track to a scene slot.
+    pub fn save_scene(&mut self, slot: usize) -> Result<(), SequencerError> {
+        let mut step_mutes: [Option<Vec<bool, SEQUENCE_MAX_STEPS>>; TRACK_COUNT] =
+            Default::default();
+        for (mutes, track) in step_mutes.iter_mut().zip(self.tracks.iter()) {
+            *mutes = track.as_ref().map(|track| {
+                track
+                    .sequence
+                    .iter()
+                    .map(|step| matches!(step, Some(step) if step.muted))
+                    .collect()
+            });
+        }
+        let scene = Scene {
+            swing: self.swing(),
+            swing_resolution_override: self.swing_resolution_override,
+            internal_clock_bpm: self.internal_clock_bpm,
+            humanize: self.humanize,
+            step_mutes,
+        };
+        *self
+            .scenes
+            .get_mut(slot)
+            .ok_or(SequencerError::InvalidSceneError())? = Some(scene);
+        Ok(())
+    }
+
+    /// Recall a saved scene. While playing, the scene is recalled at the next launch point, by
+    /// default the start of the next bar, so that changes land on the beat. Returns an error if
+    /// nothing has been saved to `slot`.
+    pub fn recall_scene(&mut self, slot: usize) -> Result<(), SequencerError> {
+        if !matches!(self.scenes.get(slot), Some(Some(_))) {
+            return Err(SequencerError::InvalidSceneError());
+        }
+        if self.should_queue_launch() {
+            self.pending_scene = Some(slot);
+        } else {
+            self.apply_scene(slot);
+        }
+        Ok(())
+    }
+
+    fn apply_scene(&mut self, slot: usize) {
+        let scene = match self.scenes.get(slot) {
+            Some(Some(scene)) => scene.clone(),
+            _ => return,
+        };
+        self.set_swing(scene.swing);
+        self.swing_resolution_override = scene.swing_resolution_override;
+        self.internal_clock_bpm = scene.internal_clock_bpm;
+        self.humanize = scene.humanize;
+        for (track, mutes) in self.tracks.iter_mut().zip(scene.step_mutes.iter()) {
+            if let (Some(track), Some(mutes)) = (track, mutes) {
+                for (step_num, &muted) in mutes.iter().enumerate() {
+                    track.sequence.set_step_muted(step_num, muted);
+                }
+            }
+        }
+    }
+
+    /// Copy the sequence of one track to another. The destination track keeps its length, so the
+    /// copied sequence is truncated or repeated to fit.
+    pub fn copy_sequence(&mut self, src: u8, dst: u8) -> Result<(), SequencerError> {
+        let sequence = self.tracks[src as usize]
+            .as_ref()
+            .ok_or(SequencerError::TrackDisabledError())?
+            .sequence
+            .clone();
+        let dst_track = self.tracks[dst as usize]
+            .as_mut()
+            .ok_or(SequencerError::TrackDisabledError())?;
+        dst_track.sequence = sequence.resize(dst_track.length as usize);
+        Ok(())
+    }
+
+    /// Activate the step of a track nearest to the current tick, like tap recording on a drum
+    /// machine. Returns the number of the activated step.
+    pub fn tap_step(&mut self, track_num: u8) -> Result<u8, SequencerError> {
+        let tick = self.tick;
+        let track = self.tracks[track_num as usize]
+            .as_mut()
+            .ok_or(SequencerError::TrackDisabledError())?;
+        Ok(track.tap_step(tick))
+    }
+
+    /// Copy what's needed to draw a track's sequence at the current tick.
+    pub fn render_snapshot(
+        &self,
+        track_num: u8,
+        note_priority: NotePriority,
+    ) -> Result<RenderSnapshot, SequencerError> {
+        let track = self.tracks[track_num as usize]
+            .as_ref()
+            .ok_or(SequencerError::TrackDisabledError())?;
+        Ok(RenderSnapshot::new(track, self.tick, note_priority))
+    }
+
+    /// Set the direction a track plays its steps in. A track switched to `Direction::Random` gets
+    /// a new order of steps straight away, rather than waiting for its next loop.
+    pub fn set_direction(
+        &mut self,
+        track_num: u8,
+        direction: Direction,
+    ) -> Result<(), SequencerError> {
+        let track = self.tracks[track_num as usize]
+            .as_mut()
+            .ok_or(SequencerError::TrackDisabledError())?;
+        if direction == Direction::Random && track.direction != Direction::Random {
+            track.reroll_random_steps(&mut self.random_order_rng);
+        }
+        track.direction = direction;
+        Ok(())
+    }
+
+    /// Mute or unmute a track. Returns true if the track is now muted.
+    pub fn toggle_mute(&mut self, track_num: u8) -> Result<bool, SequencerError> {
+        let track = self.tracks[track_num as usize]
+            .as_mut()
+            .ok_or(SequencerError::TrackDisabledError())?;
+        track.muted = !track.muted;
+        Ok(track.muted)
+    }
+
+    /// Solo or unsolo a track. Returns true if the track is now soloed.
+    pub fn toggle_solo(&mut self, track_num: u8) -> Result<bool, SequencerError> {
+        if self.tracks[track_num as usize].is_none() {
+            return Err(SequencerError::TrackDisabledError());
+        }
+        let soloed = &mut self.soloed[track_num as usize];
+        *soloed = !*soloed;
+        Ok(*soloed)
+    }
+
+    pub fn soloed(&self, track_num: u8) -> bool {
+        self.soloed[track_num as usize]
+    }
+
+    /// True if a track is enabled and will play, i.e. it isn't muted and isn't silenced by
+    /// another track being soloed.
+    pub fn track_audible(&self, track_num: u8) -> bool {
+        let any_soloed = self.any_soloed();
+        self.tracks[track_num as usize]
+            .as_ref()
+            .is_some_and(|track| Self::audible(track, self.soloed[track_num as usize], any_soloed))
+    }
+
+    fn any_soloed(&self) -> bool {
+        self.tracks
+            .iter()
+            .zip(self.soloed.iter())
+            .any(|(track, &soloed)| track.is_some() && soloed)
+    }
+
+    fn audible(track: &Track, soloed: bool, any_soloed: bool) -> bool {
+        !track.muted && (soloed || !any_soloed)
+    }
+
+    pub fn clock_source(&self) -> ClockSource {
+        self.clock_source
+    }
+
+    pub fn set_clock_source(&mut self, clock_source: ClockSource) {
+        self.clock_source = clock_source;
+    }
+
+    pub fn thru_filter(&self) -> ThruFilter {
+        self.thru_filter
+    }
+
+    pub fn set_thru_filter(&mut self, thru_filter: ThruFilter) {
+        self.thru_filter = thru_filter;
+    }
+
+    /// True if a message from MIDI in should be passed through to MIDI out, see `ThruFilter`.
+    /// Clock is never passed through while the sequencer runs on its internal clock, which is
+    /// already sent to MIDI out, so that two clocks don't fight.
+    pub fn passes_thru(&self, message: &MidiMessage) -> bool {
+        let thru_filter = match self.clock_source {
+            ClockSource::Internal => self.thru_filter | ThruFilter::CLOCK,
+            _ => self.thru_filter,
+        };
+        thru_filter.allows(message)
+    }
+
+    /// True if a scheduled message should still be sent to MIDI out when it falls due at
+    /// `now_us`. The note-off of a note whose voice was stolen is dropped, because the note was
+    /// already released, and the note-off could cut short a newer note of the same pitch.
+    pub fn should_send(&mut self, message: &MidiMessage, now_us: u64) -> bool {
+        if let MidiMessage::NoteOff(channel, note, _) = message {
+            if let Some(stolen_index) = self.stolen_notes.iter().position(|stolen_note| {
+                stolen_note.channel == *channel
+                    && stolen_note.note == *note
+                    && stolen_note.note_off_us <= now_us
+            }) {
+                self.stolen_notes.remove(stolen_index);
+                return false;
+            }
+        }
+        true
+    }
+
+    pub fn internal_clock_bpm(&self) -> u16 {
+        self.internal_clock_bpm
+    }
+
+    pub fn set_internal_clock_bpm(&mut self, bpm: u16) {
+        self.internal_clock_bpm = bpm.clamp(INTERNAL_CLOCK_MIN_BPM, INTERNAL_CLOCK_MAX_BPM);
+    }
+
+    /// Time between ticks of the internal clock, at 24 PPQN.
+    pub fn internal_tick_duration(&self) -> MicrosDurationU64 {
+        (60_000_000 / self.internal_clock_bpm as u64 / 24).micros()
+    }
+
+    /// True if ticks from the external clock should drive the sequencer at time `now_us`.
+    pub fn using_external_clock(&self, now_us: u64) -> bool {
+        match self.clock_source {
+            ClockSource::External => true,
+            ClockSource::Internal => false,
+            ClockSource::Auto => self
+                .last_external_tick_us
+                .map(|last_tick_us| {
+                    now_us.saturating_sub(last_tick_us) <= EXTERNAL_CLOCK_TIMEOUT_US
+                })
+                .unwrap_or(false),
+        }
+    }
+
+    /// Handle a MIDI clock tick from MIDI in. Advances the sequencer if the external clock is in
+    /// use, otherwise the tick is ignored.
+    pub fn external_tick(
+        &mut self,
+        now_us: u64,
+    ) -> Result<Vec<ScheduledMidiMessage, MAX_MESSAGES_PER_TICK>, SequencerError> {
+        let previous_tick_us = self.last_external_tick_us.replace(now_us);
+        if !self.using_external_clock(now_us) {
+            return Ok(Vec::new());
+        }
+        self.switch_clock(true);
+        if self.reject_early_tick(now_us, previous_tick_us) {
+            return Ok(Vec::new());
+        }
+        self.try_advance(now_us)
+    }
+
+    /// Handle a tick of the internal clock. Advances the sequencer if the internal clock is in
+    /// use, otherwise the tick is ignored, so the two clocks never both advance the sequencer.
+    /// While the internal clock is in use, each tick is also sent to MIDI out as a MIDI clock
+    /// message, so other devices can follow, whether or not the sequencer is playing.
+    pub fn internal_tick(
+        &mut self,
+        now_us: u64,
+    ) -> Result<Vec<ScheduledMidiMessage, MAX_MESSAGES_PER_TICK>, SequencerError> {
+        if self.using_external_clock(now_us) {
+            return Ok(Vec::new());
+        }
+        self.switch_clock(false);
+        self.try_advance(now_us)
+    }
+
+    /// Filter out external ticks which arrive implausibly early, less than half the average tick
+    /// duration after the last tick played, e.g. from noise on MIDI in. They'd otherwise skew the
+    /// average tick duration and play steps early. If the tick after a rejected one is also
+    /// early, the tempo really has jumped up: the tempo history is restarted from the rejected
+    /// tick and playback carries on, one tick behind.
+    fn reject_early_tick(&mut self, now_us: u64, previous_tick_us: Option<u64>) -> bool {
+        let min_tick_duration_us = match self.smoothed_tick_duration_us() {
+            Some(average_us) => average_us * EARLY_TICK_PERCENT / 100,
+            None => return false,
+        };
+        let early_after = |tick_us: Option<u64>| matches!(tick_us, Some(tick_us) if now_us.saturating_sub(tick_us) < min_tick_duration_us);
+        let rejected_previous_tick = self.early_tick_rejected;
+        self.early_tick_rejected = early_after(self.last_tick_instant_us);
+        if self.early_tick_rejected {
+            return true;
+        }
+        if rejected_previous_tick && early_after(previous_tick_us) {
+            self.midi_tick_history.clear();
+            self.last_tick_instant_us = previous_tick_us;
+        }
+        false
+    }
+
+    /// Forget the last tick time when switching between clocks, so that the gap doesn't skew the
+    /// average tick duration.
+    fn switch_clock(&mut self, external: bool) {
+        if self.last_tick_external != external {
+            self.last_tick_instant_us = None;
+            self.last_tick_external = external;
+        }
+    }
+
+    /// Advance the sequencer by one tick, like `try_advance`. Panics if the sequencer is in an
+    /// invalid state.
+    pub fn advance(&mut self, now_us: u64) -> Vec<ScheduledMidiMessage, MAX_MESSAGES_PER_TICK> {
+        self.try_advance(now_us).expect("should advance sequencer")
+    }
+
+    /// Advance the sequencer by one tick, returning the MIDI messages to send. Returns an error
+    /// rather than panicking if a track is invalid, e.g. its sequence is shorter than its length.
+    /// Tracks are checked first, so the sequencer isn't advanced if they're invalid. If more
+    /// messages are due than fit in one tick, `TooManyMessagesError` is returned part way through
+    /// the tick: the sequencer may already have advanced, and the messages for the tick are lost.
+    pub fn try_advance(
+        &mut self,
+        now_us: u64,
+    ) -> Result<Vec<ScheduledMidiMessage, MAX_MESSAGES_PER_TICK>, SequencerError> {
+        let tracks_valid = self
+            .tracks
+            .iter()
+            .chain(self.pending_tracks.iter())
+            .flatten()
+            .all(|track| track.length > 0 && track.sequence.len() >= track.length as usize);
+        if !tracks_valid {
+            return Err(SequencerError::InvalidTrackError());
+        }
+
+        let tick_duration = self.average_tick_duration(now_us);
+
+        let mut output_messages = Vec::new();
+
+        if let Some(message) = self.pending_transport_message.take() {
+            output_messages
+                .push(ScheduledMidiMessage::Immediate(message))
+                .map_err(|_| SequencerError::TooManyMessagesError())?;
+        }
+
+        // external clock is already passed through to MIDI out, the internal clock isn't. After
+        // a transport message, so that a start is followed by the clock for the first step
+        if !self.last_tick_external {
+            output_messages
+                .push(ScheduledMidiMessage::Immediate(MidiMessage::TimingClock))
+                .map_err(|_| SequencerError::TooManyMessagesError())?;
+        }
+
+        if self.playing && self.launch_quantize.is_launch_tick(self.tick) {
+            self.launch_pending();
+        }
+
+        self.send_program_changes(&mut output_messages)?;
+        self.update_drones(&mut output_messages)?;
+        self.update_vibrato(&mut output_messages, now_us)?;
+        self.update_lfos(&mut output_messages)?;
+
+        if !self.playing {
+            return Ok(output_messages);
+        }
+
+        self.advance_song();
+
+        let swing = self.swing();
+        let any_soloed = self.any_soloed();
+
+        self.active_notes
+            .retain(|active_note| active_note.note_off_us > now_us);
+        for track in self.tracks.iter_mut().flatten() {
+            if track.direction == Direction::Random && track.starts_loop(self.tick) {
+                track.reroll_random_steps(&mut self.random_order_rng);
+            }
+        }
+        self.stolen_notes
+            .retain(|stolen_note| stolen_note.note_off_us + STOLEN_NOTE_OFF_GRACE_US > now_us);
+
+        for (track_num, track) in self.tracks.iter().enumerate() {
+            if let Some(track) = track {
+                if self.tick > 0 && track.starts_loop(self.tick) {
+                    self.looped_tracks[track_num] = true;
+                }
+
+                if track.time_division == TimeDivision::Free
+                    || !Self::audible(track, self.soloed[track_num], any_soloed)
+                {
+                    continue;
+                }
+
+                if let Some(note_repeat) = self
+                    .note_repeat
+                    .as_mut()
+                    .filter(|note_repeat| note_repeat.track_num as usize == track_num)
+                {
+                    if note_repeat.step.is_none() {
+                        note_repeat.step = Self::last_step_started(track, self.tick);
+                    }
+                    // repeats land on the grid of the repeat's time division, each gated within
+                    // its own slice like a ratchet, so it's released before the next starts
+                    let repeat_ticks =
+                        TimeDivision::division_length_24ppqn(note_repeat.rate) as u32;
+                    if let Some(step) = note_repeat
+                        .step
+                        .as_ref()
+                        .filter(|_| self.tick.is_multiple_of(repeat_ticks))
+                    {
+                        let note = Self::clamp_note(step.note.into(), self.note_range);
+                        let gate_cents = step.length_step_cents.min(RATCHET_MAX_GATE_CENTS);
+                        let gate = tick_duration * repeat_ticks * gate_cents as u32 / 100;
+                        Self::push_note(
+                            &mut output_messages,
+                            (track.midi_channel, note, step.velocity),
+                            0.micros(),
+                            gate,
+                        )?;
+                        Self::track_active_note(
+                            &mut self.active_notes,
+                            ActiveNote {
+                                channel: track.midi_channel,
+                                note,
+                                note_off_us: now_us + gate.to_micros(),
+                                mute_group: track.mute_group,
+                            },
+                        );
+                    }
+                    continue;
+                }
+
+                if let Some((step, step_tick, early_delay)) =
+                    Self::step_to_play(track, self.tick, self.look_ahead, tick_duration)
+                {
+                    if step.muted || !Self::roll_probability(&mut self.probability_rng, &step) {
+                        continue;
+                    }
+
+                    let swing_ticks =
+                        TimeDivision::division_length_24ppqn(self.swing_resolution(track)) as u32;
+                    let apply_swing =
+                        swing != Swing::None && Self::is_swung_tick(step_tick, swing_ticks);
+                    let swing_delay = Self::swing_delay(swing, swing_ticks, tick_duration);
+
+                    let note = Self::clamp_note(step.note.into(), self.note_range);
+                    let step_duration =
+                        track
+                            .clock_mod
+                            .scale_step_duration(Self::swung_step_duration(
+                                swing,
+                                step_tick,
+                                track.time_division,
+                                swing_ticks,
+                                tick_duration,
+                                swing_delay,
+                            ));
+                    let mut note_off_time =
+                        ((step_duration.to_micros() * step.length_step_cents as u64) / 100)
+                            .micros();
+                    note_off_time += early_delay;
+                    let mut note_on_us = now_us + early_delay.to_micros();
+                    if apply_swing {
+                        note_off_time += swing_delay;
+                        note_on_us += swing_delay.to_micros();
+                    }
+
+                    // notes which end before a swung step starts don't overlap it
+                    let sounding_at_note_on = |active_note: &ActiveNote| {
+                        active_note.channel == track.midi_channel
+                            && active_note.note_off_us > note_on_us
+                    };
+
+                    // another track on the same channel is already sounding this note. Merge
+                    // the two rather than retriggering, otherwise the first note-off would cut
+                    // the sustained note short
+                    if let Some(active_note_index) =
+                        self.active_notes.iter().position(|active_note| {
+                            sounding_at_note_on(active_note) && active_note.note == note
+                        })
+                    {
+                        if Self::extend_note_off(
+                            &mut output_messages,
+                            &mut self.active_notes[active_note_index],
+                            now_us,
+                            note_off_time,
+                        ) {
+                            continue;
+                        }
+                        // the sounding note's note-off was sent on an earlier tick and can't be
+                        // moved, so release it now and retrigger, rather than dropping the new note
+                        let active_note = self.active_notes.remove(active_note_index);
+                        output_messages
+                            .push(ScheduledMidiMessage::Immediate(MidiMessage::NoteOff(
+                                active_note.channel,
+                                active_note.note,
+                                0.into(),
+                            )))
+                            .map_err(|_| SequencerError::TooManyMessagesError())?;
+                    }
+
+                    let channel_voices = self
+                        .active_notes
+                        .iter()
+                        .filter(|active_note| sounding_at_note_on(active_note))
+                        .count();
+                    if let Some(max_polyphony) = self.max_polyphony {
+                        if channel_voices >= max_polyphony as usize {
+                            // active notes are tracked in the order they started, so the first
+                            // one sounding on the channel is the oldest
+                            if let Some(oldest_index) =
+                                self.active_notes.iter().position(sounding_at_note_on)
+                            {
+                                let oldest_note = self.active_notes.remove(oldest_index);
+                                output_messages
+                                    .push(ScheduledMidiMessage::Immediate(MidiMessage::NoteOff(
+                                        oldest_note.channel,
+                                        oldest_note.note,
+                                        0.into(),
+                                    )))
+                                    .map_err(|_| SequencerError::TooManyMessagesError())?;
+                                // its scheduled note-off can't be taken back, so remember to drop
+                                // it, otherwise it could cut short the same pitch played again
+                                Self::track_active_note(&mut self.stolen_notes, oldest_note);
+                            }
+                        }
+                    }
+
+                    let accent_step = self.accent_tracks[track_num]
+                        .and_then(|accent_track_num| self.tracks.get(accent_track_num as usize))
+                        .and_then(|accent_track| accent_track.as_ref())
+                        .and_then(|accent_track| accent_track.step_at_tick(step_tick));
+                    let velocity = if matches!(accent_step, Some(accent_step) if !accent_step.muted)
+                    {
+                        let velocity: u8 = step.velocity.into();
+                        velocity
+                            .saturating_add(ACCENT_VELOCITY_BOOST)
+                            .min(127)
+                            .into()
+                    } else {
+                        step.velocity
+                    };
+                    let (velocity, humanize_delay) = Self::humanize_note(
+                        &mut self.humanize_rng,
+                        self.humanize,
+                        velocity,
+                        tick_duration,
+                    );
+                    let mut note_on_delay = humanize_delay + early_delay;
+                    if apply_swing {
+                        note_on_delay += swing_delay;
+                    }
+                    if let Some(mute_group) = track.mute_group {
+                        Self::choke_mute_group(
+                            &mut output_messages,
+                            &mut self.active_notes,
+                            mute_group,
+                            (track.midi_channel, note),
+                            now_us,
+                            note_on_delay,
+                        )?;
+                    }
+                    if self.humanize_note_off {
+                        note_off_time += humanize_delay;
+                    } else {
+                        // a note off fixed on the grid mustn't land before its delayed note on
+                        note_off_time =
+                            note_off_time.max(note_on_delay + HUMANIZE_MIN_GATE_US.micros());
+                    }
+                    let ratchet = step.ratchet.clamp(1, STEP_RATCHET_MAX) as u32;
+                    if ratchet == 1 {
+                        Self::push_note(
+                            &mut output_messages,
+                            (track.midi_channel, note, velocity),
+                            note_on_delay,
+                            note_off_time,
+                        )?;
+                    } else {
+                        // retrigger the note evenly across the step, each sub-note gated within
+                        // its own slice so that it's released before the next starts
+                        let slice = step_duration / ratchet;
+                        let gate_cents = step.length_step_cents.min(RATCHET_MAX_GATE_CENTS);
+                        let gate = slice * gate_cents as u32 / 100;
+                        for sub_note in 0..ratchet {
+                            let sub_note_on_delay = note_on_delay + slice * sub_note;
+                            note_off_time = sub_note_on_delay + gate;
+                            Self::push_note(
+                                &mut output_messages,
+                                (track.midi_channel, note, velocity),
+                                sub_note_on_delay,
+                                note_off_time,
+                            )?;
+                        }
+                    }
+
+                    Self::track_active_note(
+                        &mut self.active_notes,
+                        ActiveNote {
+                            channel: track.midi_channel,
+                            note,
+                            note_off_us: now_us + note_off_time.to_micros(),
+                            mute_group: track.mute_group,
+                        },
+                    );
+                }
+            }
+        }
+
+        self.tick += 1;
+
+        Ok(output_messages)
+    }
+
+    /// Remember that `active_note` is sounding. If too many notes are sounding to track another,
+    /// the oldest is forgotten, so that it no longer counts towards the polyphony limit.
+    fn track_active_note(
+        active_notes: &mut Vec<ActiveNote, MAX_ACTIVE_NOTES>,
+        active_note: ActiveNote,
+    ) {
+        if active_notes.is_full() {
+            active_notes.remove(0);
+        }
+        active_notes
+            .push(active_note)
+            .expect("should track active note after making room");
+    }
+
+    /// Last unmuted step of `track` to start at or before `tick`, with its locks applied, looking
+    /// back up to one loop of the track.
+    fn last_step_started(track: &Track, tick: u32) -> Option<Step> {
+        let loop_ticks =
+            track.length as u32 * TimeDivision::division_length_24ppqn(TimeDivision::Whole) as u32;
+        (tick.saturating_sub(loop_ticks)..=tick)
+            .rev()
+            .find_map(|tick| track.step_at_tick(tick).filter(|step| !step.muted))
+            .map(Step::with_locks)
+    }
+
+    /// Schedule a note on after `note_on_delay`, immediately if there's no delay, and its note off
+    /// after `note_off_delay`.
+    fn push_note(
+        output_messages: &mut Vec<ScheduledMidiMessage, MAX_MESSAGES_PER_TICK>,
+        (channel, note, velocity): (Channel, Note, Value7),
+        note_on_delay: MicrosDurationU64,
+        note_off_delay: MicrosDurationU64,
+    ) -> Result<(), SequencerError> {
+        let note_on_message = MidiMessage::NoteOn(channel, note, velocity);
+        if note_on_delay.to_micros() > 0 {
+            output_messages
+                .push(ScheduledMidiMessage::Delayed(
+                    note_on_message,
+                    note_on_delay,
+                ))
+                .map_err(|_| SequencerError::TooManyMessagesError())?;
+        } else {
+            output_messages
+                .push(ScheduledMidiMessage::Immediate(note_on_message))
+                .map_err(|_| SequencerError::TooManyMessagesError())?;
+        }
+        output_messages
+            .push(ScheduledMidiMessage::Delayed(
+                MidiMessage::NoteOff(channel, note, 0.into()),
+                note_off_delay,
+            ))
+            .map_err(|_| SequencerError::TooManyMessagesError())?;
+        Ok(())
+    }
+
+    /// Step of `track` to play on `tick`, with its locks applied, the tick the step falls on and
+    /// how long after `tick` to play it. With look-ahead on, a step with a negative delay is
+    /// played from the tick before its own, early by up to one tick, and skipped on its own tick.
+    fn step_to_play(
+        track: &Track,
+        tick: u32,
+        look_ahead: bool,
+        tick_duration: MicrosDurationU64,
+    ) -> Option<(Step, u32, MicrosDurationU64)> {
+        if look_ahead {
+            let next_tick = tick + 1;
+            if let Some(step) = track
+                .step_at_tick(next_tick)
+                .map(Step::with_locks)
+                .filter(|step| step.delay < 0)
+            {
+                let step_ticks = TimeDivision::division_length_24ppqn(track.time_division) as u32;
+                let step_duration = track
+                    .clock_mod
+                    .scale_step_duration(tick_duration * step_ticks);
+                let early =
+                    (step_duration * step.delay.unsigned_abs() as u32 / 100).min(tick_duration);
+                return Some((step, next_tick, tick_duration - early));
+            }
+        }
+        track
+            .step_at_tick(tick)
+            .map(Step::with_locks)
+            .filter(|step| !(look_ahead && tick > 0 && step.delay < 0))
+            .map(|step| (step, tick, 0.micros()))
+    }
+
+    /// Start, change or release the notes held by drone tracks. A drone note is only retriggered
+    /// when its note or channel changes. All drones are released when playback stops.
+    fn update_drones(
+        &mut self,
+        output_messages: &mut Vec<ScheduledMidiMessage, MAX_MESSAGES_PER_TICK>,
+    ) -> Result<(), SequencerError> {
+        let any_soloed = self.any_soloed();
+        for ((track, held_note), &soloed) in self
+            .tracks
+            .iter()
+            .zip(self.drone_notes.iter_mut())
+            .zip(self.soloed.iter())
+        {
+            let drone_step = track
+                .as_ref()
+                .filter(|track| {
+                    self.playing
+                        && track.time_division == TimeDivision::Free
+                        && Self::audible(track, soloed, any_soloed)
+                })
+                .and_then(|track| {
+                    track.drone_step().map(|step| {
+                        let note = Self::clamp_note(step.note.into(), self.note_range);
+                        (track.midi_channel, note, step.with_locks().velocity)
+                    })
+                });
+            let drone_note = drone_step.map(|(channel, note, _)| (channel, note));
+            if drone_note == *held_note {
+                continue;
+            }
+            if let Some((channel, note)) = held_note.take() {
+                output_messages
+                    .push(ScheduledMidiMessage::Immediate(MidiMessage::NoteOff(
+                        channel,
+                        note,
+                        0.into(),
+                    )))
+                    .map_err(|_| SequencerError::TooManyMessagesError())?;
+            }
+            if let Some((channel, note, velocity)) = drone_step {
+                output_messages
+                    .push(ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(
+                        channel, note, velocity,
+                    )))
+                    .map_err(|_| SequencerError::TooManyMessagesError())?;
+                *held_note = Some((channel, note));
+            }
+        }
+        Ok(())
+    }
+
+    /// Send pitch bend for tracks with vibrato while they have a note sounding, and return the
+    /// pitch bend to centre once their notes have been released or playback stops.
+    fn update_vibrato(
+        &mut self,
+        output_messages: &mut Vec<ScheduledMidiMessage, MAX_MESSAGES_PER_TICK>,
+        now_us: u64,
+    ) -> Result<(), SequencerError> {
+        for (track_num, (track, sent_bend)) in self
+            .tracks
+            .iter()
+            .zip(self.vibrato_bends.iter_mut())
+            .enumerate()
+        {
+            let bend = track
+                .as_ref()
+                .filter(|track| self.playing && track.vibrato.enabled())
+                .filter(|track| {
+                    self.drone_notes[track_num].is_some()
+                        || self.active_notes.iter().any(|active_note| {
+                            active_note.channel == track.midi_channel
+                                && active_note.note_off_us > now_us
+                        })
+                })
+                .map(|track| (track.midi_channel, track.vibrato.pitch_bend(self.tick)));
+            if bend == *sent_bend
+                || (bend.is_some() && !self.tick.is_multiple_of(VIBRATO_UPDATE_TICKS))
+            {
+                continue;
+            }
+            let (channel, value) = match (bend, *sent_bend) {
+                (Some(bend), _) => bend,
+                (None, Some((channel, _))) => (channel, 0),
+                (None, None) => continue,
+            };
+            output_messages
+                .push(ScheduledMidiMessage::Immediate(
+                    MidiMessage::PitchBendChange(channel, value.into()),
+                ))
+                .map_err(|_| SequencerError::TooManyMessagesError())?;
+            *sent_bend = bend;
+        }
+        Ok(())
+    }
+
+    /// Send the program of each track which has a program that hasn't been sent yet, or has
+    /// changed program or MIDI channel since it was last sent.
+    fn send_program_changes(
+        &mut self,
+        output_messages: &mut Vec<ScheduledMidiMessage, MAX_MESSAGES_PER_TICK>,
+    ) -> Result<(), SequencerError> {
+        for (track, sent_program) in self.tracks.iter().zip(self.sent_programs.iter_mut()) {
+            let program = track
+                .as_ref()
+                .and_then(|track| track.program.map(|program| (track.midi_channel, program)));
+            if program.is_none() || program == *sent_program {
+                continue;
+            }
+            if let Some((channel, program)) = program {
+                output_messages
+                    .push(ScheduledMidiMessage::Immediate(MidiMessage::ProgramChange(
+                        channel,
+                        program.into(),
+                    )))
+                    .map_err(|_| SequencerError::TooManyMessagesError())?;
+            }
+            *sent_program = program;
+        }
+        Ok(())
+    }
+
+    /// Send the CC of each audible track's LFO while playing, whenever its value changes.
+    fn update_lfos(
+        &mut self,
+        output_messages: &mut Vec<ScheduledMidiMessage, MAX_MESSAGES_PER_TICK>,
+    ) -> Result<(), SequencerError> {
+        if !self.playing {
+            return Ok(());
+        }
+        let any_soloed = self.any_soloed();
+        for (track_num, (track, sent_value)) in self
+            .tracks
+            .iter()
+            .zip(self.lfo_values.iter_mut())
+            .enumerate()
+        {
+            let value = track
+                .as_ref()
+                .filter(|track| track.lfo.enabled())
+                .filter(|track| Self::audible(track, self.soloed[track_num], any_soloed))
+                .map(|track| {
+                    (
+                        track.lfo.midi_channel,
+                        track.lfo.control,
+                        track.lfo.value(self.tick),
+                    )
+                });
+            if value.is_none() || value == *sent_value {
+                continue;
+            }
+            if let Some((channel, control, value)) = value {
+                output_messages
+                    .push(ScheduledMidiMessage::Immediate(MidiMessage::ControlChange(
+                        channel,
+                        control,
+                        value.into(),
+                    )))
+                    .map_err(|_| SequencerError::TooManyMessagesError())?;
+            }
+            *sent_value = value;
+        }
+        Ok(())
+    }
+
+    /// Time from the start of the step on `tick` to the start of the next step, taking swing into
+    /// account. Swing shortens swung steps and lengthens the straight steps before them, so gate
+    /// lengths are calculated from this rather than the nominal step length. If swing would push a
+    /// step past the start of the next one, the nominal step length is used.
+    fn swung_step_duration(
+        swing: Swing,
+        tick: u32,
+        time_division: TimeDivision,
+        swing_ticks: u32,
+        tick_duration: MicrosDurationU64,
+        swing_delay: MicrosDurationU64,
+    ) -> MicrosDurationU64 {
+        let step_ticks = TimeDivision::division_length_24ppqn(time_division) as u32;
+        let step_duration = tick_duration * step_ticks;
+        if swing == Swing::None {
+            return step_duration;
+        }
+        let mut swung_duration = step_duration;
+        if Self::is_swung_tick(tick + step_ticks, swing_ticks) {
+            swung_duration += swing_delay;
+        }
+        if Self::is_swung_tick(tick, swing_ticks) {
+            return swung_duration
+                .checked_sub(swing_delay)
+                .filter(|duration| duration.to_micros() > 0)
+                .unwrap_or(step_duration);
+        }
+        swung_duration
+    }
+
+    /// True if `tick` starts the second step of a pair of steps of `swing_ticks` length.
+    fn is_swung_tick(tick: u32, swing_ticks: u32) -> bool {
+        tick % (swing_ticks * 2) == swing_ticks
+    }
+
+    /// Time that swung steps are delayed by, roughly the swing percentage above 50% of a pair of
+    /// steps. At 1/16, 54% swing delays swung steps by half a tick.
+    fn swing_delay(
+        swing: Swing,
+        swing_ticks: u32,
+        tick_duration: MicrosDurationU64,
+    ) -> MicrosDurationU64 {
+        (tick_duration * ((swing.as_percentage() - 50) as u32 * swing_ticks)) / 48
+    }
+
+    /// Name of the chord implied by the steps of all tracks at the current tick, e.g. "Cm", or
+    /// `None` if the notes don't form a triad or 7th chord. Muted steps are ignored.
+    pub fn current_chord_name(&self) -> Option<String<8>> {
+        let notes: Vec<crate::midi::Note, TRACK_COUNT> = self
+            .tracks
+            .iter()
+            .flatten()
+            .filter_map(|track| {
+                track
+                    .sequence
+                    .steps
+                    .get(track.step_num(self.tick) as usize)?
+                    .as_ref()
+            })
+            .filter(|step| !step.muted)
+            .map(|step| step.note)
+            .collect();
+        let chord = Chord::detect(&notes)?;
+        let mut chord_name = String::new();
+        write!(chord_name, "{}", chord).ok()?;
+        Some(chord_name)
+    }
+
+    /// True if any track has started a new loop since the last call to `take_looped_tracks`.
+    pub fn has_looped_tracks(&self) -> bool {
+        self.looped_tracks.iter().any(|&looped| looped)
+    }
+
+    /// Evolve the sequence of each track which has started a new loop, by regenerating some of
+    /// its steps with the track's generator. How many steps change is set by the generator's
+    /// evolve amount. Sample and hold LFOs also get new random levels.
+    pub fn evolve_tracks(
+        &mut self,
+        generators: &mut [SequenceGenerator],
+        machine_resources: &mut MachineResources,
+    ) {
+        for track_num in self.take_looped_tracks() {
+            if let (Some(Some(track)), Some(generator)) = (
+                self.tracks.get(track_num as usize),
+                generators.get(track_num as usize),
+            ) {
+                let sequence = track.sequence.clone();
+                let evolved_sequence = generator.evolve(sequence.clone(), machine_resources);
+                self.swap_evolved_sequence(
+                    track_num,
+                    &sequence,
+                    evolved_sequence,
+                    machine_resources,
+                );
+            }
+        }
+    }
+
+    /// Numbers of the tracks which have started a new loop since the last call, ready to be
+    /// evolved. Generating an evolved sequence is slow, so it can be done without holding the
+    /// sequencer, see `swap_evolved_sequence`.
+    pub fn take_looped_tracks(&mut self) -> Vec<u8, TRACK_COUNT> {
+        self.looped_tracks
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(track_num, looped)| core::mem::take(looped).then_some(track_num as u8))
+            .collect()
+    }
+
+    /// Replace the sequence of a track with `evolved_sequence`, generated from `sequence`, keeping
+    /// the locks of the original steps. Sample and hold LFOs also get new random levels. If the
+    /// track's sequence has changed since `sequence` was taken, e.g. because it was edited, the
+    /// evolved sequence is out of date and is dropped. Returns true if the sequence was swapped.
+    pub fn swap_evolved_sequence(
+        &mut self,
+        track_num: u8,
+        sequence: &Sequence,
+        evolved_sequence: Sequence,
+        machine_resources: &mut MachineResources,
+    ) -> bool {
+        let Some(Some(track)) = self.tracks.get_mut(track_num as usize) else {
+            return false;
+        };
+        // `Step`s compare by note alone, so compare every field to catch other edits
+        if track.sequence.checksum() != sequence.checksum() {
+            return false;
+        }
+        track.sequence = evolved_sequence.carry_locks(sequence);
+        if track.lfo.shape == LfoShape::SampleAndHold {
+            track.lfo.reroll_samples(machine_resources);
+        }
+        true
+    }
+
+    /// Decide whether `step` plays this time round. Steps with full probability always play,
+    /// without using a random number, so they don't change whether other steps play.
+    fn roll_probability(rng: &mut Rng, step: &Step) -> bool {
+        step.probability >= STEP_PROBABILITY_MAX
+            || rng.next_u64() % (STEP_PROBABILITY_MAX as u64) < step.probability as u64
+    }
+
+    /// Randomly vary the velocity of a note and delay it by up to one tick, by an amount scaled
+    /// by `humanize`.
+    fn humanize_note(
+        rng: &mut Rng,
+        humanize: u8,
+        velocity: Value7,
+        tick_duration: MicrosDurationU64,
+    ) -> (Value7, MicrosDurationU64) {
+        if humanize == 0 {
+            return (velocity, 0.micros());
+        }
+        let jitter_range = HUMANIZE_MAX_VELOCITY_JITTER * humanize as i32 / HUMANIZE_MAX as i32;
+        let jitter = (rng.next_u64() % (jitter_range as u64 * 2 + 1)) as i32 - jitter_range;
+        let velocity_num: u8 = velocity.into();
+        let velocity = (velocity_num as i32 + jitter).clamp(1, 127) as u8;
+        let max_delay_us = tick_duration.to_micros() * humanize as u64 / HUMANIZE_MAX as u64;
+        let delay_us = rng.next_u64() % (max_delay_us + 1);
+        (velocity.into(), delay_us.micros())
+    }
+
+    /// Release notes sounding in `mute_group` when a new note in the group starts, e.g. so that a
+    /// closed hi-hat cuts off an open one. The new note itself isn't choked.
+    fn choke_mute_group(
+        output_messages: &mut Vec<ScheduledMidiMessage, MAX_MESSAGES_PER_TICK>,
+        active_notes: &mut Vec<ActiveNote, MAX_ACTIVE_NOTES>,
+        mute_group: u8,
+        (channel, note): (Channel, Note),
+        now_us: u64,
+        note_on_delay: MicrosDurationU64,
+    ) -> Result<(), SequencerError> {
+        let note_on_us = now_us + note_on_delay.to_micros();
+        for active_note in active_notes.iter_mut().filter(|active_note| {
+            active_note.mute_group == Some(mute_group)
+                && active_note.note_off_us > note_on_us
+                && !(active_note.channel == channel && active_note.note == note)
+        }) {
+            let note_off_message =
+                MidiMessage::NoteOff(active_note.channel, active_note.note, 0.into());
+            let scheduled_message = if note_on_delay.to_micros() > 0 {
+                ScheduledMidiMessage::Delayed(note_off_message, note_on_delay)
+            } else {
+                ScheduledMidiMessage::Immediate(note_off_message)
+            };
+            output_messages
+                .push(scheduled_message)
+                .map_err(|_| SequencerError::TooManyMessagesError())?;
+            active_note.note_off_us = note_on_us;
+        }
+        Ok(())
+    }
+
+    /// Extend a sounding note so that it lasts at least as long as a colliding note. Returns true
+    /// if the sounding note already lasts long enough, or was extended. Only a note-off scheduled
+    /// on this tick can be moved, so notes started on earlier ticks can't be extended. Just the
+    /// sounding note's own note-off is moved, so earlier ratchet sub-notes keep theirs.
+    fn extend_note_off(
+        output_messages: &mut Vec<ScheduledMidiMessage, MAX_MESSAGES_PER_TICK>,
+        active_note: &mut ActiveNote,
+        now_us: u64,
+        note_off_time: MicrosDurationU64,
+    ) -> bool {
+        let note_off_us = now_us + note_off_time.to_micros();
+        if note_off_us <= active_note.note_off_us {
+            return true;
+        }
+        let active_note_off_delay_us = active_note.note_off_us.saturating_sub(now_us);
+        let note_off_delay = output_messages
+            .iter_mut()
+            .rev()
+            .find_map(|message| match message {
+                ScheduledMidiMessage::Delayed(MidiMessage::NoteOff(channel, note, _), delay)
+                    if *channel == active_note.channel
+                        && *note == active_note.note
+                        && delay.to_micros() == active_note_off_delay_us =>
+                {
+                    Some(delay)
+                }
+                _ => None,
+            });
+        match note_off_delay {
+            Some(delay) => {
+                *delay = note_off_time;
+                active_note.note_off_us = note_off_us;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Calculate average time between the last `tempo_smoothing` MIDI ticks. Defaults to tick
+    /// frequency of 19,230ms, which is equivalent to 130BPM.
+    fn average_tick_duration(&mut self, now_us: u64) -> MicrosDurationU64 {
+        let mut tick_duration = DEFAULT_TICK_DURATION_US.micros();
+
+        if let Some(last_tick_instant_us) = self.last_tick_instant_us {
+            let last_tick_duration = now_us.saturating_sub(last_tick_instant_us);
+            self.midi_tick_history.write(last_tick_duration);
+            if let Some(average_us) = self.smoothed_tick_duration_us() {
+                tick_duration = average_us.micros();
+            }
+        }
+
+        self.last_tick_instant_us = Some(now_us);
+
+        tick_duration
+    }
+
+    /// Average of the last `tempo_smoothing` tick durations, or `None` if none have been measured.
+    fn smoothed_tick_duration_us(&self) -> Option<u64> {
+        let sample_count = self.midi_tick_history.len().min(self.tempo_smoothing);
+        if sample_count == 0 {
+            return None;
+        }
+        let skip_count = self.midi_tick_history.len() - sample_count;
+        Some(
+            self.midi_tick_history
+                .oldest_ordered()
+                .skip(skip_count)
+                .sum::<u64>()
+                / sample_count as u64,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::rand_melody_machine::RandMelodyMachine;
+    use crate::{Lfo, Sequence, Step, StepLockField, LFO_MAX_DEPTH, MORPH_PATTERN_COUNT};
+
+    #[test]
+    fn sequencer_default_should_have_empty_tracks() {
+        let sequencer = Sequencer::default();
+        assert!(sequencer.tracks.iter().all(|track| track.is_none()));
+    }
+
+    #[test]
+    fn sequencer_new_sequence_generator_should_use_default_scale_and_key() {
+        let mut sequencer = Sequencer::default();
+        let generator = sequencer.new_sequence_generator();
+        assert_eq!(Scale::Chromatic, generator.scale());
+        assert_eq!(Key::C, generator.key());
+
+        sequencer.set_default_harmony(Scale::NaturalMinor, Key::FSharp);
+        let generator = sequencer.new_sequence_generator();
+        assert_eq!(Scale::NaturalMinor, generator.scale());
+        assert_eq!(Key::FSharp, generator.key());
+        let sequence = generator.apply(8);
+        assert!(sequence.iter().flatten().all(|step| {
+            crate::quantizer::quantize(step.note, Scale::NaturalMinor, Key::FSharp) == step.note
+        }));
+    }
+
+    #[test]
+    fn sequencer_has_own_harmony_should_compare_generator_to_default_harmony() {
+        let mut sequencer = Sequencer::default();
+        sequencer.set_default_harmony(Scale::Major, Key::D);
+        let mut generator = sequencer.new_sequence_generator();
+        assert!(!sequencer.has_own_harmony(&generator));
+        generator.set_key(Key::E);
+        assert!(sequencer.has_own_harmony(&generator));
+        generator.set_key(Key::D);
+        generator.set_scale(Scale::Dorian);
+        assert!(sequencer.has_own_harmony(&generator));
+        sequencer.set_default_harmony(Scale::Dorian, Key::D);
+        assert!(!sequencer.has_own_harmony(&generator));
+    }
+
+    #[test]
+    fn sequencer_enable_track_should_insert_new_track() {
+        let generator = SequenceGenerator::default();
+        let mut new_track = Track::default();
+        new_track.sequence = generator.apply(new_track.length);
+        let mut sequencer = Sequencer::default();
+        sequencer.enable_track(0, new_track);
+        assert!(sequencer.tracks[0].is_some());
+        assert!(sequencer.tracks[1..TRACK_COUNT]
+            .iter()
+            .all(|track| track.is_none()));
+    }
+
+    #[test]
+    fn sequencer_linked_tracks_should_share_length_and_time_division() {
+        let mut sequencer = Sequencer::default();
+        sequencer.enable_track(0, Track::default());
+        sequencer.enable_track(1, Track::default());
+        sequencer.link_tracks(0, 1);
+        assert_eq!(Some(1), sequencer.linked_track(0));
+        assert_eq!(Some(0), sequencer.linked_track(1));
+
+        let track = sequencer.tracks[0].as_mut().unwrap();
+        track.set_length(16);
+        track.set_time_division(TimeDivision::Eigth);
+        assert_eq!(Some(1), sequencer.sync_linked_track(0));
+        let partner = sequencer.tracks[1].as_ref().unwrap();
+        assert_eq!(16, partner.length);
+        assert_eq!(TimeDivision::Eigth, partner.time_division);
+
+        // nothing to do once tracks are in sync
+        assert_eq!(None, sequencer.sync_linked_track(0));
+    }
+
+    #[test]
+    fn sequencer_unlinked_tracks_should_not_share_length() {
+        let mut sequencer = Sequencer::default();
+        sequencer.enable_track(0, Track::default());
+        sequencer.enable_track(1, Track::default());
+        sequencer.link_tracks(0, 1);
+        sequencer.unlink_track(1);
+        assert_eq!(None, sequencer.linked_track(0));
+        sequencer.tracks[0].as_mut().unwrap().set_length(16);
+        assert_eq!(None, sequencer.sync_linked_track(0));
+        assert_eq!(8, sequencer.tracks[1].as_ref().unwrap().length);
+    }
+
+    #[test]
+    fn sequencer_copy_sequence_should_truncate_to_shorter_track() {
+        let mut sequencer = Sequencer::default();
+        let mut src_track = Track::default();
+        src_track.set_length(16);
+        src_track.sequence = (0..16).map(|i| Step::new(60 + i).ok()).collect();
+        sequencer.enable_track(0, src_track);
+        sequencer.enable_track(1, Track::default());
+        sequencer.copy_sequence(0, 1).unwrap();
+        let dst_track = sequencer.tracks[1].as_ref().unwrap();
+        assert_eq!(8, dst_track.length);
+        let expected: Sequence = (0..8).map(|i| Step::new(60 + i).ok()).collect();
+        assert_eq!(expected, dst_track.sequence);
+    }
+
+    #[test]
+    fn sequencer_copy_sequence_should_repeat_to_fill_longer_track() {
+        let mut sequencer = Sequencer::default();
+        let mut src_track = Track::default();
+        src_track.set_length(16);
+        src_track.sequence = (0..16)
+            .map(|i| {
+                if i % 4 == 3 {
+                    None
+                } else {
+                    Step::new(60 + i).ok()
+                }
+            })
+            .collect();
+        sequencer.enable_track(0, src_track);
+        let mut dst_track = Track::default();
+        dst_track.set_length(32);
+        sequencer.enable_track(1, dst_track);
+        sequencer.copy_sequence(0, 1).unwrap();
+        let dst_track = sequencer.tracks[1].as_ref().unwrap();
+        assert_eq!(32, dst_track.length);
+        assert_eq!(32, dst_track.sequence.len());
+        let src_track = sequencer.tracks[0].as_ref().unwrap();
+        assert!(src_track
+            .sequence
+            .steps_eq(&dst_track.sequence.steps[0..16]));
+        assert!(src_track
+            .sequence
+            .steps_eq(&dst_track.sequence.steps[16..32]));
+    }
+
+    #[test]
+    fn sequencer_copy_sequence_should_fail_for_disabled_track() {
+        let mut sequencer = Sequencer::default();
+        sequencer.enable_track(0, Track::default());
+        assert!(sequencer.copy_sequence(0, 1).is_err());
+        assert!(sequencer.copy_sequence(1, 0).is_err());
+    }
+
+    #[test]
+    fn sequencer_tap_step_should_activate_step_nearest_current_tick() {
+        let mut now_us = 0;
+        let mut sequencer = Sequencer::default();
+        let mut track = Track::default();
+        track.sequence = (0..track.length).map(|_| None).collect();
+        sequencer.enable_track(0, track);
+        sequencer.start_playing();
+        let mut tapped_steps = std::vec::Vec::new();
+        for tick in 0..48 {
+            if tick == 1 || tick == 11 || tick == 25 {
+                tapped_steps.push(sequencer.tap_step(0).unwrap());
+            }
+            sequencer.advance(now_us);
+            now_us += 20_000;
+        }
+        assert_eq!(vec![0, 2, 4], tapped_steps);
+        let mask = sequencer.tracks[0].as_ref().unwrap().sequence.to_mask();
+        assert_eq!(
+            vec![true, false, true, false, true, false, false, false],
+            mask.to_vec()
+        );
+        assert!(sequencer.tap_step(1).is_err());
+    }
+
+    #[test]
+    fn sequencer_reset_should_clear_playback_state_and_keep_tracks() {
+        let mut now_us = 0;
+        let mut sequencer = Sequencer::default();
+        let mut new_track = Track::default();
+        new_track.sequence = SequenceGenerator::default().apply(new_track.length);
+        sequencer.enable_track(0, new_track);
+        sequencer.start_playing();
+        for _ in 0..3 {
+            sequencer.advance(now_us);
+            now_us += DEFAULT_TICK_DURATION_US;
+        }
+        assert!(!sequencer.active_notes.is_empty());
+
+        sequencer.reset();
+        assert_eq!(0, sequencer.tick());
+        assert!(!sequencer.playing());
+        assert!(sequencer.active_notes.is_empty());
+        assert!(sequencer.tracks[0].is_some());
+
+        // the first step plays again rather than merging with the note from before the reset
+        sequencer.start_playing();
+        let output_messages = sequencer.advance(now_us);
+        assert_eq!(
+            ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(0.into(), 60.into(), 127.into())),
+            output_messages[0]
+        );
+    }
+
+    fn chord_track(note: u8) -> Track {
+        let mut track = Track::default();
+        track.sequence = (0..track.length).map(|_| Step::new(note).ok()).collect();
+        track
+    }
+
+    #[test]
+    fn sequencer_current_chord_name_should_detect_chord_across_tracks() {
+        let mut sequencer = Sequencer::default();
+        assert_eq!(None, sequencer.current_chord_name());
+        sequencer.enable_track(0, chord_track(60));
+        sequencer.enable_track(1, chord_track(64));
+        sequencer.enable_track(2, chord_track(67));
+        assert_eq!(Some("C".into()), sequencer.current_chord_name());
+
+        sequencer.enable_track(1, chord_track(63));
+        assert_eq!(Some("Cm".into()), sequencer.current_chord_name());
+
+        sequencer.tracks[2]
+            .as_mut()
+            .unwrap()
+            .sequence
+            .set_step_muted(0, true);
+        assert_eq!(None, sequencer.current_chord_name());
+    }
+
+    #[test]
+    fn sequencer_try_advance_should_return_error_for_sequence_shorter_than_track() {
+        let mut sequencer = Sequencer::default();
+        let mut new_track = Track::default();
+        new_track.sequence = SequenceGenerator::default().apply(new_track.length);
+        new_track.set_length(16);
+        sequencer.enable_track(0, new_track);
+        sequencer.start_playing();
+        assert!(matches!(
+            sequencer.try_advance(0),
+            Err(SequencerError::InvalidTrackError())
+        ));
+        assert_eq!(0, sequencer.tick());
+    }
+
+    #[test]
+    fn sequencer_try_advance_should_return_error_for_zero_length_track() {
+        let mut sequencer = Sequencer::default();
+        let new_track = Track {
+            length: 0,
+            ..Default::default()
+        };
+        sequencer.enable_track(0, new_track);
+        sequencer.start_playing();
+        assert!(sequencer.try_advance(0).is_err());
+        assert!(sequencer.internal_tick(0).is_ok());
+        sequencer.set_clock_source(ClockSource::Internal);
+        assert!(sequencer.internal_tick(0).is_err());
+    }
+
+    #[test]
+    fn sequencer_try_advance_should_tolerate_time_going_backwards() {
+        let mut sequencer = Sequencer::default();
+        sequencer.start_playing();
+        assert!(sequencer.try_advance(1_000_000).is_ok());
+        assert!(sequencer.try_advance(0).is_ok());
+    }
+
+    #[test]
+    fn sequencer_should_start_stop_and_continue_playing() {
+        let mut sequencer = Sequencer::default();
+        assert_eq!(false, sequencer.playing());
+        assert_eq!(0, sequencer.tick);
+        sequencer.start_playing();
+        assert_eq!(true, sequencer.playing());
+
+        sequencer.advance(1);
+        sequencer.stop_playing();
+        assert_eq!(false, sequencer.playing());
+
+        sequencer.advance(1); // should be ignored because sequencer stopped
+        sequencer.continue_playing();
+        sequencer.advance(1);
+        assert_eq!(true, sequencer.playing());
+        assert_eq!(2, sequencer.tick);
+
+        sequencer.stop_playing();
+        assert_eq!(2, sequencer.tick);
+
+        sequencer.start_playing();
+        assert_eq!(true, sequencer.playing());
+        assert_eq!(0, sequencer.tick);
+    }
+
+    #[test]
+    fn sequencer_in_clock_master_mode_should_emit_transport_messages() {
+        let mut sequencer = Sequencer::default();
+        sequencer.set_clock_master(true);
+        sequencer.start_playing();
+        let output_messages = sequencer.advance(0);
+        assert_eq!(
+            Some(&ScheduledMidiMessage::Immediate(MidiMessage::Start)),
+            output_messages.first()
+        );
+        assert!(sequencer.advance(1).is_empty());
+
+        sequencer.stop_playing();
+        assert_eq!(
+            ScheduledMidiMessage::Immediate(MidiMessage::Stop),
+            sequencer.advance(2)[0]
+        );
+
+        sequencer.continue_playing();
+        assert_eq!(
+            ScheduledMidiMessage::Immediate(MidiMessage::Continue),
+            sequencer.advance(3)[0]
+        );
+    }
+
+    #[test]
+    fn sequencer_in_clock_slave_mode_should_not_emit_transport_messages() {
+        let mut sequencer = Sequencer::default();
+        sequencer.start_playing();
+        assert!(sequencer.advance(0).is_empty());
+        sequencer.stop_playing();
+        assert!(sequencer.advance(1).is_empty());
+    }
+
+    #[test]
+    fn midi_test_messages_should_play_a_short_arpeggio_on_channel_1() {
+        let messages = midi_test_messages();
+        assert_eq!(8, messages.len());
+        assert_eq!(
+            ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(0.into(), 60.into(), 127.into())),
+            messages[0]
+        );
+        assert_eq!(
+            ScheduledMidiMessage::Delayed(
+                MidiMessage::NoteOff(0.into(), 60.into(), 0.into()),
+                200_000.micros()
+            ),
+            messages[1]
+        );
+        assert_eq!(
+            ScheduledMidiMessage::Delayed(
+                MidiMessage::NoteOn(0.into(), 64.into(), 127.into()),
+                250_000.micros()
+            ),
+            messages[2]
+        );
+        assert_eq!(
+            ScheduledMidiMessage::Delayed(
+                MidiMessage::NoteOff(0.into(), 72.into(), 0.into()),
+                950_000.micros()
+            ),
+            messages[7]
+        );
+    }
+
+    #[test]
+    fn preview_note_messages_should_be_a_note_on_off_pair() {
+        let [note_on, note_off] = preview_note_messages(3.into(), 64.into());
+        assert_eq!(
+            ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(3.into(), 64.into(), 127.into())),
+            note_on
+        );
+        assert_eq!(
+            ScheduledMidiMessage::Delayed(
+                MidiMessage::NoteOff(3.into(), 64.into(), 0.into()),
+                PREVIEW_NOTE_LENGTH_US.micros()
+            ),
+            note_off
+        );
+    }
+
+    #[test]
+    fn sequencer_with_external_clock_should_ignore_internal_ticks() {
+        let mut sequencer = Sequencer::default();
+        sequencer.start_playing();
+        sequencer.external_tick(0).unwrap();
+        sequencer.internal_tick(10_000_000).unwrap();
+        assert_eq!(1, sequencer.tick());
+    }
+
+    #[test]
+    fn sequencer_with_internal_clock_should_ignore_external_ticks() {
+        let mut sequencer = Sequencer::default();
+        sequencer.set_clock_source(ClockSource::Internal);
+        sequencer.start_playing();
+        sequencer.external_tick(0).unwrap();
+        sequencer.internal_tick(1).unwrap();
+        assert_eq!(1, sequencer.tick());
+        assert_eq!(19230, sequencer.internal_tick_duration().to_micros());
+    }
+
+    #[test]
+    fn sequencer_with_internal_clock_should_send_clock_to_midi_out() {
+        let mut sequencer = Sequencer::default();
+        sequencer.set_clock_source(ClockSource::Internal);
+        sequencer.set_clock_master(true);
+        assert_eq!(
+            [ScheduledMidiMessage::Immediate(MidiMessage::TimingClock)],
+            sequencer.internal_tick(0).unwrap().as_slice()
+        );
+        sequencer.start_playing();
+        assert_eq!(
+            [
+                ScheduledMidiMessage::Immediate(MidiMessage::Start),
+                ScheduledMidiMessage::Immediate(MidiMessage::TimingClock)
+            ],
+            sequencer.internal_tick(1).unwrap().as_slice()
+        );
+        sequencer.stop_playing();
+        assert_eq!(
+            [
+                ScheduledMidiMessage::Immediate(MidiMessage::Stop),
+                ScheduledMidiMessage::Immediate(MidiMessage::TimingClock)
+            ],
+            sequencer.internal_tick(2).unwrap().as_slice()
+        );
+    }
+
+    #[test]
+    fn sequencer_with_external_clock_should_not_send_clock_to_midi_out() {
+        let mut sequencer = Sequencer::default();
+        sequencer.start_playing();
+        assert!(sequencer.external_tick(0).unwrap().is_empty());
+        assert!(sequencer.internal_tick(1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn sequencer_should_filter_thru_and_block_clock_with_internal_clock() {
+        let mut sequencer = Sequencer::default();
+        let note_on = MidiMessage::NoteOn(0.into(), 60.into(), 100.into());
+        assert!(sequencer.passes_thru(&MidiMessage::TimingClock));
+        assert!(sequencer.passes_thru(&note_on));
+        sequencer.set_thru_filter(ThruFilter::NOTES);
+        assert!(sequencer.passes_thru(&MidiMessage::TimingClock));
+        assert!(!sequencer.passes_thru(&note_on));
+        sequencer.set_clock_source(ClockSource::Internal);
+        assert!(!sequencer.passes_thru(&MidiMessage::TimingClock));
+        assert!(!sequencer.passes_thru(&MidiMessage::Start));
+        assert_eq!(ThruFilter::NOTES, sequencer.thru_filter());
+    }
+
+    #[test]
+    fn sequencer_with_auto_clock_should_fall_back_to_internal_clock_after_timeout() {
+        let mut now_us = 0;
+        let mut sequencer = Sequencer::default();
+        sequencer.set_clock_source(ClockSource::Auto);
+        sequencer.start_playing();
+
+        // no external clock yet, so internal clock drives the sequencer
+        assert!(!sequencer.using_external_clock(now_us));
+        sequencer.internal_tick(now_us).unwrap();
+        assert_eq!(1, sequencer.tick());
+
+        // external clock arrives, internal ticks are ignored so the sequencer doesn't double
+        // advance
+        for _ in 0..4 {
+            now_us += DEFAULT_TICK_DURATION_US;
+            sequencer.external_tick(now_us).unwrap();
+            sequencer.internal_tick(now_us + 1).unwrap();
+        }
+        assert_eq!(5, sequencer.tick());
+
+        // external clock stops, internal clock takes over once the timeout has passed
+        let last_external_tick_us = now_us;
+        now_us = last_external_tick_us + EXTERNAL_CLOCK_TIMEOUT_US;
+        sequencer.internal_tick(now_us).unwrap();
+        assert_eq!(5, sequencer.tick());
+        now_us = last_external_tick_us + EXTERNAL_CLOCK_TIMEOUT_US + 1;
+        sequencer.internal_tick(now_us).unwrap();
+        assert_eq!(6, sequencer.tick());
+
+        // external clock comes back and takes over again
+        now_us += 1;
+        sequencer.external_tick(now_us).unwrap();
+        sequencer.internal_tick(now_us + 1).unwrap();
+        assert_eq!(7, sequencer.tick());
+    }
+
+    #[test]
+    fn sequencer_should_ignore_spurious_early_external_ticks() {
+        let mut sequencer = Sequencer::default();
+        sequencer.start_playing();
+        let mut now_us = 0;
+        for tick in 0..16 {
+            sequencer.external_tick(now_us).unwrap();
+            if tick % 4 == 1 {
+                sequencer.external_tick(now_us + 100).unwrap();
+            }
+            if tick == 9 {
+                sequencer.external_tick(now_us + 200).unwrap();
+                sequencer.external_tick(now_us + 300).unwrap();
+            }
+            now_us += 1000;
+        }
+        assert_eq!(16, sequencer.tick());
+        assert_eq!(Some(1000), sequencer.smoothed_tick_duration_us());
+    }
+
+    #[test]
+    fn sequencer_should_follow_external_clock_jumping_to_double_tempo() {
+        let mut sequencer = Sequencer::default();
+        sequencer.start_playing();
+        let mut now_us = 0;
+        for _ in 0..8 {
+            sequencer.external_tick(now_us).unwrap();
+            now_us += 1000;
+        }
+        for _ in 0..16 {
+            sequencer.external_tick(now_us).unwrap();
+            now_us += 400;
+        }
+        // the first tick at the new tempo is lost while the jump is detected
+        assert_eq!(23, sequencer.tick());
+        assert_eq!(Some(400), sequencer.smoothed_tick_duration_us());
+    }
+
+    fn step_mutes(sequencer: &Sequencer, track_num: usize) -> std::vec::Vec<bool> {
+        sequencer.tracks[track_num]
+            .as_ref()
+            .unwrap()
+            .sequence
+            .iter()
+            .map(|step| step.as_ref().unwrap().muted)
+            .collect()
+    }
+
+    #[test]
+    fn sequencer_recall_scene_should_restore_mutes_and_swing() {
+        let mut sequencer = Sequencer::default();
+        sequencer.enable_track(0, Track::default());
+        sequencer.enable_track(3, Track::default());
+        sequencer.set_swing(Swing::Mpc62);
+        let sequence = &mut sequencer.tracks[3].as_mut().unwrap().sequence;
+        sequence.set_step_muted(1, true);
+        sequence.set_step_muted(6, true);
+        sequencer.save_scene(2).unwrap();
+        let saved_mutes = step_mutes(&sequencer, 3);
+
+        sequencer.set_swing(Swing::None);
+        let sequence = &mut sequencer.tracks[3].as_mut().unwrap().sequence;
+        sequence.set_step_muted(1, false);
+        sequence.set_step_muted(2, true);
+        sequencer.tracks[0]
+            .as_mut()
+            .unwrap()
+            .sequence
+            .set_step_muted(0, true);
+
+        sequencer.recall_scene(2).unwrap();
+        assert_eq!(Swing::Mpc62, sequencer.swing());
+        assert_eq!(saved_mutes, step_mutes(&sequencer, 3));
+        assert_eq!(vec![false; 8], step_mutes(&sequencer, 0));
+    }
+
+    #[test]
+    fn sequencer_recall_scene_while_playing_should_wait_for_next_bar() {
+        let mut sequencer = Sequencer::default();
+        sequencer.enable_track(0, Track::default());
+        sequencer.save_scene(0).unwrap();
+        sequencer.set_swing(Swing::Mpc75);
+        sequencer.start_playing();
+        let mut now_us = 0;
+        for _ in 0..10 {
+            sequencer.advance(now_us);
+            now_us += DEFAULT_TICK_DURATION_US;
+        }
+        sequencer.recall_scene(0).unwrap();
+        for _ in 10..96 {
+            sequencer.advance(now_us);
+            now_us += DEFAULT_TICK_DURATION_US;
+        }
+        assert_eq!(Swing::Mpc75, sequencer.swing());
+        sequencer.advance(now_us);
+        assert_eq!(Swing::None, sequencer.swing());
+    }
+
+    fn note_on_velocities(sequencer: &mut Sequencer, channel: u8) -> std::vec::Vec<u8> {
+        let mut velocities = vec![];
+        for tick in 0..48 {
+            for message in sequencer.advance(tick * DEFAULT_TICK_DURATION_US) {
+                if let ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(
+                    note_channel,
+                    _,
+                    velocity,
+                )) = message
+                {
+                    if note_channel == channel.into() {
+                        velocities.push(velocity.into());
+                    }
+                }
+            }
+        }
+        velocities
+    }
+
+    #[test]
+    fn sequencer_should_boost_velocity_of_steps_coinciding_with_accent_track() {
+        let mut sequencer = Sequencer::default();
+        let mut step = Step::new(60).unwrap();
+        step.velocity = 80.into();
+        let hats: Sequence = (0..8).map(|_| Some(step.clone())).collect();
+        let accents: Sequence = (0..8)
+            .map(|i| if i % 4 == 2 { Step::new(36).ok() } else { None })
+            .collect();
+        sequencer.enable_track(
+            0,
+            Track {
+                sequence: hats,
+                ..Default::default()
+            },
+        );
+        sequencer.enable_track(
+            1,
+            Track {
+                sequence: accents,
+                midi_channel: 1.into(),
+                ..Default::default()
+            },
+        );
+        sequencer.set_accent_track(0, Some(1));
+        sequencer.start_playing();
+        assert_eq!(
+            vec![80, 80, 112, 80, 80, 80, 112, 80],
+            note_on_velocities(&mut sequencer, 0)
+        );
+    }
+
+    #[test]
+    fn sequencer_should_play_locked_velocity_for_just_that_step() {
+        let mut sequencer = Sequencer::default();
+        let mut generator = SequenceGenerator::default();
+        generator.set_base_velocity(90);
+        let mut sequence = generator.apply(8);
+        sequence.set_step_lock(2, StepLockField::Velocity, Some(30));
+        let generated: std::vec::Vec<u8> = sequence
+            .iter()
+            .flatten()
+            .map(|step| step.velocity.into())
+            .collect();
+        sequencer.enable_track(
+            0,
+            Track {
+                sequence,
+                ..Default::default()
+            },
+        );
+        sequencer.start_playing();
+        let velocities = note_on_velocities(&mut sequencer, 0);
+        assert_eq!(8, velocities.len());
+        for (step_num, (&velocity, &generated)) in velocities.iter().zip(&generated).enumerate() {
+            assert_eq!(if step_num == 2 { 30 } else { generated }, velocity);
+        }
+    }
+
+    /// Notes played on each tick from `ticks`, and the number of note offs scheduled.
+    fn notes_by_tick(
+        sequencer: &mut Sequencer,
+        ticks: core::ops::Range<u32>,
+    ) -> (std::vec::Vec<(u32, u8)>, usize) {
+        let mut notes = vec![];
+        let mut note_offs = 0;
+        for tick in ticks {
+            for message in sequencer.advance(tick as u64 * DEFAULT_TICK_DURATION_US) {
+                match message {
+                    ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(_, note, _)) => {
+                        notes.push((tick, note.into()));
+                    }
+                    ScheduledMidiMessage::Delayed(MidiMessage::NoteOff(..), _) => note_offs += 1,
+                    _ => (),
+                }
+            }
+        }
+        (notes, note_offs)
+    }
+
+    #[test]
+    fn sequencer_note_repeat_should_repeat_current_step_then_resume() {
+        let mut sequencer = Sequencer::default();
+        sequencer.enable_track(
+            0,
+            Track {
+                length: 8,
+                sequence: (0..8).map(|i| Step::new(60 + i).ok()).collect(),
+                ..Default::default()
+            },
+        );
+        sequencer.start_playing();
+        let (notes, _) = notes_by_tick(&mut sequencer, 0..12);
+        assert_eq!(vec![(0, 60), (6, 61)], notes);
+
+        sequencer
+            .set_note_repeat(0, Some(TimeDivision::ThirtySecond))
+            .unwrap();
+        assert_eq!(
+            Some((0, TimeDivision::ThirtySecond)),
+            sequencer.note_repeat()
+        );
+        let (notes, _) = notes_by_tick(&mut sequencer, 12..24);
+        assert_eq!(vec![(12, 62), (15, 62), (18, 62), (21, 62)], notes);
+
+        // the playhead kept moving while the step repeated
+        sequencer.set_note_repeat(0, None).unwrap();
+        let (notes, _) = notes_by_tick(&mut sequencer, 24..36);
+        assert_eq!(vec![(24, 64), (30, 65)], notes);
+    }
+
+    #[test]
+    fn sequencer_note_repeat_should_release_every_note() {
+        let mut sequencer = Sequencer::default();
+        sequencer.enable_track(0, Default::default());
+        assert!(matches!(
+            sequencer.set_note_repeat(1, Some(TimeDivision::Eigth)),
+            Err(SequencerError::TrackDisabledError())
+        ));
+        sequencer.start_playing();
+        let mut tick = 0;
+        let mut note_ons = 0;
+        let mut note_offs = 0;
+        // start and stop the repeat between steps, and at a different rate to the track
+        for (ticks, rate) in [
+            (7, None),
+            (33, Some(TimeDivision::Eigth)),
+            (13, None),
+            (17, Some(sequencer.note_repeat_rate())),
+            (20, None),
+        ] {
+            sequencer.set_note_repeat(0, rate).unwrap();
+            let (notes, offs) = notes_by_tick(&mut sequencer, tick..tick + ticks);
+            note_ons += notes.len();
+            note_offs += offs;
+            tick += ticks;
+        }
+        assert!(note_ons > 0);
+        assert_eq!(note_ons, note_offs);
+    }
+
+    /// One bar track, with a pattern of a single note stored in each pattern slot.
+    fn song_track(pattern_notes: [u8; MORPH_PATTERN_COUNT]) -> Track {
+        let mut track = Track {
+            length: 16,
+            sequence: SequenceGenerator::initial_sequence(16),
+            ..Default::default()
+        };
+        for (pattern, note) in track.patterns.iter_mut().zip(pattern_notes) {
+            *pattern = Some((0..16).map(|_| Step::new(note).ok()).collect());
+        }
+        track
+    }
+
+    #[test]
+    fn sequencer_song_should_switch_pattern_at_the_bar() {
+        let mut sequencer = Sequencer::default();
+        sequencer.enable_track(0, song_track([48, 72]));
+        let song = Song::from_parts(&[(PatternRef(0), 1), (PatternRef(1), 1)]).unwrap();
+        sequencer.set_song(Some(song));
+        sequencer.start_playing();
+        let bar = TICKS_PER_BAR_24PPQN as u32;
+        for (bar_num, expected_note) in [48, 72, 48].into_iter().enumerate() {
+            let bar_start = bar_num as u32 * bar;
+            let (notes, _) = notes_by_tick(&mut sequencer, bar_start..bar_start + bar);
+            assert_eq!(16, notes.len());
+            assert!(notes.iter().all(|&(_, note)| note == expected_note));
+        }
+        assert_eq!(Some(0), sequencer.song().and_then(Song::part_num));
+    }
+
+    #[test]
+    fn sequencer_song_should_repeat_parts_and_follow_the_first_track() {
+        let mut sequencer = Sequencer::default();
+        // a half bar track loops twice as often as the first track, but doesn't move the song on
+        sequencer.enable_track(2, song_track([48, 72]));
+        sequencer.enable_track(
+            3,
+            Track {
+                length: 8,
+                ..song_track([50, 74])
+            },
+        );
+        let song = Song::from_parts(&[(PatternRef(1), 2), (PatternRef(0), 1)]).unwrap();
+        sequencer.set_song(Some(song));
+        sequencer.start_playing();
+        let bar = TICKS_PER_BAR_24PPQN as u32;
+        let (notes, _) = notes_by_tick(&mut sequencer, 0..bar * 2);
+        assert!(notes.iter().all(|&(_, note)| note == 72 || note == 74));
+        let (notes, _) = notes_by_tick(&mut sequencer, bar * 2..bar * 3);
+        assert!(notes.iter().all(|&(_, note)| note == 48 || note == 50));
+
+        // without a song, the current sequences keep looping
+        sequencer.set_song(None);
+        let (notes, _) = notes_by_tick(&mut sequencer, bar * 3..bar * 4);
+        assert!(notes.iter().all(|&(_, note)| note == 48 || note == 50));
+    }
+
+    /// CC values sent on each tick from `ticks`.
+    fn control_changes(
+        sequencer: &mut Sequencer,
+        ticks: core::ops::Range<u32>,
+    ) -> std::vec::Vec<(u32, u8, u8)> {
+        let mut control_changes = vec![];
+        for tick in ticks {
+            for message in sequencer.advance(tick as u64 * DEFAULT_TICK_DURATION_US) {
+                if let ScheduledMidiMessage::Immediate(MidiMessage::ControlChange(
+                    _,
+                    control,
+                    value,
+                )) = message
+                {
+                    control_changes.push((tick, control.into(), value.into()));
+                }
+            }
+        }
+        control_changes
+    }
+
+    #[test]
+    fn sequencer_lfo_should_send_triangle_cycle_as_changed_ccs() {
+        let mut sequencer = Sequencer::default();
+        sequencer.enable_track(
+            0,
+            Track {
+                lfo: Lfo {
+                    depth: LFO_MAX_DEPTH,
+                    rate: TimeDivision::Quarter,
+                    control: 74.into(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        assert!(control_changes(&mut sequencer, 0..24).is_empty());
+
+        sequencer.start_playing();
+        let control_changes = control_changes(&mut sequencer, 0..48);
+        assert!(control_changes.iter().all(|&(_, control, _)| control == 74));
+        // a CC for every tick of the quarter note cycle, the value changes on every tick
+        assert_eq!(48, control_changes.len());
+        let values: std::vec::Vec<u8> = control_changes
+            .iter()
+            .filter(|&&(tick, _, _)| tick % 6 == 0)
+            .map(|&(_, _, value)| value)
+            .collect();
+        assert_eq!(vec![64, 127, 64, 1, 64, 127, 64, 1], values);
+    }
+
+    #[test]
+    fn sequencer_lfo_should_only_send_cc_when_value_changes() {
+        let mut sequencer = Sequencer::default();
+        sequencer.enable_track(
+            0,
+            Track {
+                lfo: Lfo {
+                    shape: LfoShape::Square,
+                    depth: 50,
+                    rate: TimeDivision::Quarter,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        sequencer.start_playing();
+        assert_eq!(
+            vec![(0, 1, 95), (12, 1, 33), (24, 1, 95), (36, 1, 33)],
+            control_changes(&mut sequencer, 0..48)
+        );
+
+        // muted tracks don't send their LFO
+        sequencer.toggle_mute(0).unwrap();
+        assert!(control_changes(&mut sequencer, 48..96).is_empty());
+    }
+
+    /// Program changes sent on each tick from `ticks`, and the tick of the first note.
+    fn sent_program_changes(
+        sequencer: &mut Sequencer,
+        ticks: core::ops::Range<u32>,
+    ) -> (std::vec::Vec<(u32, u8, u8)>, Option<u32>) {
+        let mut program_changes = vec![];
+        let mut first_note_tick = None;
+        for tick in ticks {
+            for message in sequencer.advance(tick as u64 * DEFAULT_TICK_DURATION_US) {
+                match message {
+                    ScheduledMidiMessage::Immediate(MidiMessage::ProgramChange(
+                        channel,
+                        program,
+                    )) => program_changes.push((tick, channel.into(), program.into())),
+                    ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(..)) => {
+                        first_note_tick.get_or_insert(tick);
+                    }
+                    _ => (),
+                }
+            }
+        }
+        (program_changes, first_note_tick)
+    }
+
+    #[test]
+    fn sequencer_should_send_program_change_once_and_when_it_changes() {
+        let mut sequencer = Sequencer::default();
+        sequencer.enable_track(
+            0,
+            Track {
+                program: Some(5),
+                midi_channel: 2.into(),
+                ..Default::default()
+            },
+        );
+        sequencer.enable_track(1, Default::default());
+        let (program_changes, _) = sent_program_changes(&mut sequencer, 0..24);
+        assert_eq!(vec![(0, 2, 5)], program_changes);
+
+        sequencer.start_playing();
+        let (program_changes, first_note_tick) = sent_program_changes(&mut sequencer, 0..24);
+        assert_eq!(vec![(0, 2, 5)], program_changes);
+        assert_eq!(Some(0), first_note_tick);
+
+        sequencer.tracks[0].as_mut().unwrap().increment_program(1);
+        let (program_changes, _) = sent_program_changes(&mut sequencer, 24..48);
+        assert_eq!(vec![(24, 2, 6)], program_changes);
+    }
+
+    #[test]
+    fn sequencer_enable_track_should_send_program_before_first_note() {
+        let mut sequencer = Sequencer::default();
+        sequencer.start_playing();
+        let (program_changes, _) = sent_program_changes(&mut sequencer, 0..12);
+        assert!(program_changes.is_empty());
+        // while playing, the track waits for the next bar to launch
+        sequencer.enable_track(
+            3,
+            Track {
+                program: Some(0),
+                ..Default::default()
+            },
+        );
+        let bar = TICKS_PER_BAR_24PPQN as u32;
+        let (program_changes, first_note_tick) = sent_program_changes(&mut sequencer, 12..bar + 12);
+        assert_eq!(vec![(bar, 0, 0)], program_changes);
+        assert_eq!(Some(bar), first_note_tick);
+    }
+
+    #[test]
+    fn sequencer_accent_track_should_be_cleared_and_not_accent_itself() {
+        let mut sequencer = Sequencer::default();
+        sequencer.set_accent_track(0, Some(0));
+        assert_eq!(None, sequencer.accent_track(0));
+        sequencer.set_accent_track(0, Some(1));
+        assert_eq!(Some(1), sequencer.accent_track(0));
+        sequencer.set_accent_track(0, None);
+        assert_eq!(None, sequencer.accent_track(0));
+    }
+
+    fn advance_ticks(sequencer: &mut Sequencer, ticks: u32) {
+        for _ in 0..ticks {
+            sequencer.advance(sequencer.tick() as u64 * DEFAULT_TICK_DURATION_US);
+        }
+    }
+
+    /// Track whose sequence is generated by `generator`.
+    fn generated_track(generator: &SequenceGenerator) -> Track {
+        let mut track = Track::default();
+        track.sequence = generator.apply(track.length);
+        track
+    }
+
+    /// Play `track` on its own for `ticks` ticks, after `configure` has set up the sequencer.
+    /// Returns each message sent, with the time of the tick it was sent on.
+    fn timed_messages(
+        track: Track,
+        ticks: u32,
+        configure: impl FnOnce(&mut Sequencer),
+    ) -> std::vec::Vec<(u64, ScheduledMidiMessage)> {
+        let mut sequencer = Sequencer::default();
+        sequencer.enable_track(0, track);
+        configure(&mut sequencer);
+        sequencer.start_playing();
+        let mut messages = vec![];
+        let mut now_us = 0;
+        for _ in 0..ticks {
+            messages.extend(
+                sequencer
+                    .advance(now_us)
+                    .into_iter()
+                    .map(|message| (now_us, message)),
+            );
+            now_us += DEFAULT_TICK_DURATION_US;
+        }
+        messages
+    }
+
+    /// Time from the tick a message was sent on until it should be played.
+    fn message_delay_us(message: &ScheduledMidiMessage) -> u64 {
+        match message {
+            ScheduledMidiMessage::Immediate(_) => 0,
+            ScheduledMidiMessage::Delayed(_, delay) => delay.to_micros(),
+        }
+    }
+
+    #[test]
+    fn sequencer_enable_track_while_playing_should_launch_at_next_bar() {
+        let mut sequencer = Sequencer::default();
+        sequencer.start_playing();
+        advance_ticks(&mut sequencer, 10);
+        sequencer.enable_track(0, Track::default()).midi_channel = 3.into();
+        advance_ticks(&mut sequencer, 86);
+        assert!(sequencer.tracks[0].is_none());
+        let messages = sequencer.advance(96 * DEFAULT_TICK_DURATION_US);
+        assert_eq!(
+            Some(3.into()),
+            sequencer.tracks[0].as_ref().map(|track| track.midi_channel)
+        );
+        assert!(messages.iter().any(|message| matches!(
+            message,
+            ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(channel, ..)) if *channel == 3.into()
+        )));
+    }
+
+    #[test]
+    fn sequencer_with_immediate_launch_should_enable_track_straight_away() {
+        let mut sequencer = Sequencer::default();
+        sequencer.set_launch_quantize(LaunchQuant::Immediate);
+        sequencer.start_playing();
+        advance_ticks(&mut sequencer, 10);
+        sequencer.enable_track(0, Track::default());
+        assert!(sequencer.tracks[0].is_some());
+    }
+
+    #[test]
+    fn sequencer_recall_pattern_with_next_beat_launch_should_switch_on_the_beat() {
+        let mut sequencer = Sequencer::default();
+        sequencer.set_launch_quantize(LaunchQuant::NextBeat);
+        let track = sequencer.enable_track(0, Track::default());
+        let pattern: Sequence = (0..track.length).map(|_| Step::new(72).ok()).collect();
+        track.patterns[1] = Some(pattern.clone());
+        sequencer.start_playing();
+        advance_ticks(&mut sequencer, 30);
+        sequencer.recall_pattern(0, 1).unwrap();
+        advance_ticks(&mut sequencer, 18);
+        assert_ne!(pattern, sequencer.tracks[0].as_ref().unwrap().sequence);
+        advance_ticks(&mut sequencer, 1);
+        assert_eq!(pattern, sequencer.tracks[0].as_ref().unwrap().sequence);
+    }
+
+    #[test]
+    fn sequencer_render_snapshot_should_match_track_at_current_tick() {
+        let mut sequencer = Sequencer::default();
+        sequencer.enable_track(0, Track::default());
+        sequencer.start_playing();
+        advance_ticks(&mut sequencer, 20);
+        let snapshot = sequencer.render_snapshot(0, NotePriority::Root).unwrap();
+        let track = sequencer.tracks[0].as_ref().unwrap();
+        assert_eq!(track.step_num(sequencer.tick()), snapshot.active_step_num);
+        assert_eq!(track.sequence.len(), snapshot.len());
+        assert!(track
+            .sequence
+            .iter()
+            .zip(snapshot.steps())
+            .all(|(step, render_step)| step.is_some() == render_step.is_some()));
+        assert!(matches!(
+            sequencer.render_snapshot(1, NotePriority::Root),
+            Err(SequencerError::TrackDisabledError())
+        ));
+    }
+
+    #[test]
+    fn sequencer_recall_pattern_should_return_error_for_missing_track_or_pattern() {
+        let mut sequencer = Sequencer::default();
+        assert!(matches!(
+            sequencer.recall_pattern(0, 0),
+            Err(SequencerError::TrackDisabledError())
+        ));
+        sequencer.enable_track(0, Track::default());
+        assert!(matches!(
+            sequencer.recall_pattern(0, 0),
+            Err(SequencerError::InvalidPatternError())
+        ));
+    }
+
+    #[test]
+    fn sequencer_recall_scene_should_return_error_for_empty_or_invalid_slot() {
+        let mut sequencer = Sequencer::default();
+        assert!(sequencer.recall_scene(0).is_err());
+        assert!(sequencer.save_scene(SCENE_COUNT).is_err());
+        assert!(sequencer.recall_scene(SCENE_COUNT).is_err());
+    }
+
+    #[test]
+    fn sequencer_set_internal_clock_bpm_should_limit_tempo() {
+        let mut sequencer = Sequencer::default();
+        sequencer.set_internal_clock_bpm(1000);
+        assert_eq!(INTERNAL_CLOCK_MAX_BPM, sequencer.internal_clock_bpm());
+        sequencer.set_internal_clock_bpm(0);
+        assert_eq!(INTERNAL_CLOCK_MIN_BPM, sequencer.internal_clock_bpm());
+    }
+
+    #[test]
+    fn clock_source_increment_should_wrap_around() {
+        assert_eq!(ClockSource::Internal, ClockSource::External.increment(1));
+        assert_eq!(ClockSource::Auto, ClockSource::External.increment(-1));
+        assert_eq!(ClockSource::External, ClockSource::Auto.increment(1));
+        assert_eq!("INT", ClockSource::Internal.to_string());
+    }
+
+    #[test]
+    fn sequencer_should_calculate_average_tick_duration() {
+        let mut sequencer = Sequencer::default();
+        let tick_duration = sequencer.average_tick_duration(0);
+        assert_eq!(DEFAULT_TICK_DURATION_US, tick_duration.to_micros());
+
+        let tick_duration = sequencer.average_tick_duration(100);
+        assert_eq!(100, tick_duration.to_micros());
+
+        sequencer.average_tick_duration(200);
+        sequencer.average_tick_duration(300);
+        sequencer.average_tick_duration(350);
+        sequencer.average_tick_duration(400);
+        let tick_duration = sequencer.average_tick_duration(450);
+        assert_eq!(75, tick_duration.to_micros());
+    }
+
+    #[test]
+    fn sequencer_bpm_should_estimate_tempo_from_tick_interval() {
+        let mut sequencer = Sequencer::default();
+        assert_eq!(None, sequencer.bpm(0));
+        let tick_interval_us = 60_000_000 / 97 / 24;
+        let mut now_us = 0;
+        for _ in 0..MIDI_HISTORY_SAMPLE_COUNT {
+            sequencer.average_tick_duration(now_us);
+            now_us += tick_interval_us;
+            assert_eq!(None, sequencer.bpm(now_us));
+        }
+        sequencer.average_tick_duration(now_us);
+        let bpm = sequencer.bpm(now_us).unwrap();
+        assert!((96..=98).contains(&bpm), "bpm={}", bpm);
+    }
+
+    #[test]
+    fn sequencer_bpm_should_be_none_once_clock_stops() {
+        let mut sequencer = Sequencer::default();
+        let tick_interval_us = 60_000_000 / 120 / 24;
+        let mut now_us = 0;
+        for _ in 0..=MIDI_HISTORY_SAMPLE_COUNT {
+            sequencer.average_tick_duration(now_us);
+            now_us += tick_interval_us;
+        }
+        let last_tick_us = now_us - tick_interval_us;
+        assert_eq!(
+            Some(120),
+            sequencer.bpm(last_tick_us + EXTERNAL_CLOCK_TIMEOUT_US)
+        );
+        assert_eq!(
+            None,
+            sequencer.bpm(last_tick_us + EXTERNAL_CLOCK_TIMEOUT_US + 1)
+        );
+    }
+
+    fn tick_duration_after_tempo_change(tempo_smoothing: usize) -> u64 {
+        let mut sequencer = Sequencer::default();
+        sequencer.set_tempo_smoothing(tempo_smoothing);
+        let mut now_us = 0;
+        for _ in 0..=MIDI_HISTORY_MAX_SAMPLE_COUNT {
+            sequencer.average_tick_duration(now_us);
+            now_us += 100;
+        }
+        sequencer.average_tick_duration(now_us + 100).to_micros()
+    }
+
+    #[test]
+    fn sequencer_with_tempo_smoothing_of_one_should_track_tick_duration_instantly() {
+        assert_eq!(200, tick_duration_after_tempo_change(1));
+    }
+
+    #[test]
+    fn sequencer_with_longer_tempo_smoothing_should_smooth_more() {
+        let short = tick_duration_after_tempo_change(2);
+        let long = tick_duration_after_tempo_change(MIDI_HISTORY_MAX_SAMPLE_COUNT);
+        assert_eq!(150, short);
+        assert!(long < short);
+        assert!(long > 100);
+    }
+
+    #[test]
+    fn sequencer_set_tempo_smoothing_should_clamp_to_range() {
+        let mut sequencer = Sequencer::default();
+        sequencer.set_tempo_smoothing(0);
+        assert_eq!(1, sequencer.tempo_smoothing());
+        sequencer.set_tempo_smoothing(1000);
+        assert_eq!(MIDI_HISTORY_MAX_SAMPLE_COUNT, sequencer.tempo_smoothing());
+    }
+
+    #[test]
+    fn sequencer_advance_should_output_immediate_note_on_and_delayed_note_off_messages() {
+        let mut now_us = 0;
+        let mut sequencer = Sequencer::default();
+        let generator = SequenceGenerator::default();
+        let mut new_track = Track::default();
+        new_track.sequence = generator.apply(new_track.length);
+        sequencer.enable_track(0, new_track);
+        sequencer.start_playing();
+        let mut output_messages = vec![];
+        for _ in 0..48 {
+            let step_messages = sequencer.advance(now_us);
+            output_messages.extend(step_messages.into_iter());
+            now_us += DEFAULT_TICK_DURATION_US;
+        }
+        assert_eq!(16, output_messages.len()); // 8 note on/note off pairs
+        let expected_note_on =
+            ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(0.into(), 60.into(), 127.into()));
+        let expected_note_off = ScheduledMidiMessage::Delayed(
+            MidiMessage::NoteOff(0.into(), 60.into(), 0.into()),
+            92304.micros(),
+        );
+        assert_eq!(expected_note_on, output_messages[0]);
+        assert_eq!(expected_note_off, output_messages[1]);
+        assert_eq!(expected_note_on, output_messages[2]);
+        assert_eq!(expected_note_off, output_messages[3]);
+        assert_eq!(expected_note_on, output_messages[4]);
+        assert_eq!(expected_note_off, output_messages[5]);
+        assert_eq!(expected_note_on, output_messages[6]);
+        assert_eq!(expected_note_off, output_messages[7]);
+        assert_eq!(expected_note_on, output_messages[8]);
+        assert_eq!(expected_note_off, output_messages[9]);
+        assert_eq!(expected_note_on, output_messages[10]);
+        assert_eq!(expected_note_off, output_messages[11]);
+        assert_eq!(expected_note_on, output_messages[12]);
+        assert_eq!(expected_note_off, output_messages[13]);
+        assert_eq!(expected_note_on, output_messages[14]);
+        assert_eq!(expected_note_off, output_messages[15]);
+    }
+
+    #[test]
+    fn sequencer_advance_should_not_play_muted_steps() {
+        let mut now_us = 0;
+        let mut sequencer = Sequencer::default();
+        let mut new_track = Track::default();
+        new_track.sequence = SequenceGenerator::default().apply(new_track.length);
+        new_track.sequence.set_step_muted(0, true);
+        sequencer.enable_track(0, new_track);
+        sequencer.start_playing();
+
+        // muted step keeps its data
+        let step = sequencer.tracks[0].as_ref().unwrap().sequence.steps[0].clone();
+        let note_num: u8 = step.as_ref().unwrap().note.into();
+        assert_eq!(60, note_num);
+
+        let mut output_messages = vec![];
+        for _ in 0..48 {
+            output_messages.extend(sequencer.advance(now_us));
+            now_us += DEFAULT_TICK_DURATION_US;
+        }
+        assert_eq!(14, output_messages.len()); // 7 note on/note off pairs
+
+        // unmuted step plays again on the next loop
+        sequencer.tracks[0]
+            .as_mut()
+            .unwrap()
+            .sequence
+            .set_step_muted(0, false);
+        assert_eq!(2, sequencer.advance(now_us).len());
+    }
+
+    #[test]
+    fn sequencer_advance_should_skip_muted_tracks_and_keep_them_in_time() {
+        let mut sequencer = Sequencer::default();
+        for channel in 0..2 {
+            sequencer.enable_track(
+                channel,
+                Track {
+                    midi_channel: channel.into(),
+                    ..Default::default()
+                },
+            );
+        }
+        sequencer.start_playing();
+        assert!(sequencer.toggle_mute(1).unwrap());
+        assert!(!sequencer.track_audible(1));
+        assert_eq!(8, note_on_velocities(&mut sequencer, 0).len());
+
+        let mut sequencer = Sequencer::default();
+        sequencer.enable_track(
+            1,
+            Track {
+                midi_channel: 1.into(),
+                ..Default::default()
+            },
+        );
+        sequencer.start_playing();
+        sequencer.toggle_mute(1).unwrap();
+        assert!(note_on_velocities(&mut sequencer, 1).is_empty());
+        assert_eq!(0, sequencer.tracks[1].as_ref().unwrap().step_num(48));
+
+        // unmuted track picks up from the first step of its next loop
+        assert!(!sequencer.toggle_mute(1).unwrap());
+        let messages = sequencer.advance(48 * DEFAULT_TICK_DURATION_US);
+        assert!(messages.iter().any(|message| matches!(
+            message,
+            ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(channel, ..)) if *channel == 1.into()
+        )));
+    }
+
+    #[test]
+    fn sequencer_advance_with_soloed_tracks_should_only_play_soloed_tracks() {
+        let mut sequencer = Sequencer::default();
+        for channel in 0..3 {
+            sequencer.enable_track(
+                channel,
+                Track {
+                    midi_channel: channel.into(),
+                    ..Default::default()
+                },
+            );
+        }
+        sequencer.start_playing();
+        assert!(sequencer.toggle_solo(1).unwrap());
+        assert!(sequencer.toggle_solo(2).unwrap());
+        sequencer.toggle_mute(2).unwrap();
+        assert_eq!(
+            [false, true, false],
+            [0, 1, 2].map(|track_num| sequencer.track_audible(track_num))
+        );
+        let mut note_on_channels = vec![];
+        for tick in 0..48 {
+            for message in sequencer.advance(tick * DEFAULT_TICK_DURATION_US) {
+                if let ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(channel, ..)) = message {
+                    note_on_channels.push(u8::from(channel));
+                }
+            }
+        }
+        assert_eq!(vec![1; 8], note_on_channels);
+
+        // unsoloing the last soloed track lets every unmuted track play
+        sequencer.toggle_solo(1).unwrap();
+        sequencer.toggle_solo(2).unwrap();
+        assert!(sequencer.track_audible(0));
+        assert!(!sequencer.track_audible(2));
+    }
+
+    #[test]
+    fn sequencer_toggle_mute_and_solo_should_return_error_for_disabled_track() {
+        let mut sequencer = Sequencer::default();
+        assert!(matches!(
+            sequencer.toggle_mute(0),
+            Err(SequencerError::TrackDisabledError())
+        ));
+        assert!(matches!(
+            sequencer.toggle_solo(0),
+            Err(SequencerError::TrackDisabledError())
+        ));
+        assert!(!sequencer.soloed(0));
+        assert!(!sequencer.track_audible(0));
+    }
+
+    #[test]
+    fn sequencer_advance_with_max_polyphony_should_steal_oldest_voice() {
+        let mut sequencer = Sequencer::default();
+        let generator = SequenceGenerator::default();
+        for (track_num, note) in [60, 64, 67].into_iter().enumerate() {
+            let mut new_track = Track::default();
+            new_track.sequence = generator
+                .apply(new_track.length)
+                .map_notes(|_| note.try_into().unwrap());
+            sequencer.enable_track(track_num as u8, new_track);
+        }
+        sequencer.set_max_polyphony(Some(2));
+        sequencer.start_playing();
+        let output_messages = sequencer.advance(0);
+        assert_eq!(7, output_messages.len()); // 3 note on/note off pairs and a stolen voice
+        assert_eq!(
+            ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(0.into(), 60.into(), 127.into())),
+            output_messages[0]
+        );
+        assert_eq!(
+            ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(0.into(), 64.into(), 127.into())),
+            output_messages[2]
+        );
+        assert_eq!(
+            ScheduledMidiMessage::Immediate(MidiMessage::NoteOff(0.into(), 60.into(), 0.into())),
+            output_messages[4]
+        );
+        assert_eq!(
+            ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(0.into(), 67.into(), 127.into())),
+            output_messages[5]
+        );
+    }
+
+    #[test]
+    fn sequencer_track_active_note_should_forget_oldest_note_when_full() {
+        let mut active_notes = Vec::new();
+        for note in 0..=MAX_ACTIVE_NOTES as u8 {
+            Sequencer::track_active_note(
+                &mut active_notes,
+                ActiveNote {
+                    channel: 0.into(),
+                    note: note.into(),
+                    note_off_us: 1000,
+                    mute_group: None,
+                },
+            );
+        }
+        assert_eq!(MAX_ACTIVE_NOTES, active_notes.len());
+        let first_note: u8 = active_notes[0].note.into();
+        assert_eq!(1, first_note);
+    }
+
+    #[test]
+    fn sequencer_advance_with_note_range_should_clamp_emitted_notes() {
+        let mut sequencer = Sequencer::default();
+        let generator = SequenceGenerator::default();
+        for (track_num, note) in [24, 60, 110].into_iter().enumerate() {
+            let mut new_track = Track::default();
+            new_track.sequence = generator
+                .apply(new_track.length)
+                .map_notes(|_| note.try_into().unwrap());
+            sequencer.enable_track(track_num as u8, new_track);
+        }
+        sequencer.set_note_range(96, 36);
+        assert_eq!((36, 96), sequencer.note_range());
+        sequencer.start_playing();
+        let output_messages = sequencer.advance(0);
+        let notes: std::vec::Vec<u8> = output_messages
+            .iter()
+            .map(|message| match message {
+                ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(_, note, _))
+                | ScheduledMidiMessage::Delayed(MidiMessage::NoteOff(_, note, _), _) => {
+                    (*note).into()
+                }
+                _ => panic!("unexpected message"),
+            })
+            .collect();
+        assert_eq!(vec![36, 36, 60, 60, 96, 96], notes);
+        let track_notes: std::vec::Vec<u8> = (0..3)
+            .map(|track_num| {
+                let track = sequencer.tracks[track_num].as_ref().unwrap();
+                track.sequence.steps[0].as_ref().unwrap().note.into()
+            })
+            .collect();
+        assert_eq!(vec![24, 60, 110], track_notes);
+    }
+
+    #[test]
+    fn sequencer_default_note_range_should_not_change_notes() {
+        let mut sequencer = Sequencer::default();
+        let mut new_track = Track::default();
+        new_track.sequence = SequenceGenerator::default()
+            .apply(new_track.length)
+            .map_notes(|_| 127.try_into().unwrap());
+        sequencer.enable_track(0, new_track);
+        sequencer.start_playing();
+        assert_eq!(
+            ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(0.into(), 127.into(), 127.into())),
+            sequencer.advance(0)[0]
+        );
+    }
+
+    #[test]
+    fn sequencer_advance_with_max_polyphony_should_only_steal_voices_still_sounding() {
+        let mut now_us = 0;
+        let mut sequencer = Sequencer::default();
+        let generator = SequenceGenerator::default();
+        for (track_num, note) in [60, 64, 67].into_iter().enumerate() {
+            let mut new_track = Track::default();
+            new_track.sequence = generator
+                .apply(new_track.length)
+                .map_notes(|_| note.try_into().unwrap());
+            sequencer.enable_track(track_num as u8, new_track);
+        }
+        sequencer.set_max_polyphony(Some(2));
+        sequencer.start_playing();
+        let mut output_messages = vec![];
+        for _ in 0..12 {
+            output_messages.extend(sequencer.advance(now_us));
+            now_us += DEFAULT_TICK_DURATION_US;
+        }
+        // 80% gate means voices are free again by the next step, so each step steals 1 voice
+        assert_eq!(14, output_messages.len());
+    }
+
+    #[test]
+    fn sequencer_should_drop_note_off_of_stolen_voice_when_pitch_is_retriggered() {
+        let mut now_us = 0;
+        let mut sequencer = Sequencer::default();
+        // track 0 plays a long note on every beat. On the same channel, track 1 steals its voice
+        // on the 2nd 16th, then track 2 plays the stolen pitch again on the 3rd
+        let mut long_track = Track::default();
+        long_track.set_time_division(TimeDivision::Quarter);
+        long_track.sequence = (0..long_track.length).map(|_| Step::new(60).ok()).collect();
+        sequencer.enable_track(0, long_track);
+        for (track_num, note, step_num) in [(1, 64, 1), (2, 60, 2)] {
+            let mut new_track = Track::default();
+            new_track.sequence = (0..new_track.length)
+                .map(|i| {
+                    if i == step_num {
+                        Step::new(note).ok()
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            sequencer.enable_track(track_num, new_track);
+        }
+        sequencer.set_max_polyphony(Some(1));
+        sequencer.start_playing();
+        let mut scheduled_messages = vec![];
+        for _ in 0..24 {
+            for message in sequencer.advance(now_us) {
+                scheduled_messages.push(match message {
+                    ScheduledMidiMessage::Immediate(message) => (now_us, message),
+                    ScheduledMidiMessage::Delayed(message, delay) => {
+                        (now_us + delay.to_micros(), message)
+                    }
+                });
+            }
+            now_us += DEFAULT_TICK_DURATION_US;
+        }
+        scheduled_messages.sort_by_key(|(due_us, _)| *due_us);
+        let sent_note_offs: std::vec::Vec<u64> = scheduled_messages
+            .into_iter()
+            .filter(|(due_us, message)| {
+                matches!(message, MidiMessage::NoteOff(_, note, _) if u8::from(*note) == 60)
+                    && sequencer.should_send(message, *due_us)
+            })
+            .map(|(due_us, _)| due_us)
+            .collect();
+        // the stolen voice is released straight away, and the retriggered note lasts its full
+        // length, rather than being cut short by the long note's original note-off
+        let step_us = DEFAULT_TICK_DURATION_US * 6;
+        assert_eq!(
+            vec![step_us, step_us * 2 + step_us * 80 / 100],
+            sent_note_offs
+        );
+    }
+
+    #[test]
+    fn sequencer_advance_with_colliding_notes_should_not_cut_sustained_note_short() {
+        let mut now_us = 0;
+        let mut sequencer = Sequencer::default();
+        let generator = SequenceGenerator::default();
+        // track 0 plays a long note on every beat, track 1 plays the same note on every 16th
+        let mut long_track = Track::default();
+        long_track.set_time_division(TimeDivision::Quarter);
+        long_track.sequence = generator.apply(long_track.length);
+        sequencer.enable_track(0, long_track);
+        let mut short_track = Track::default();
+        short_track.sequence = generator.apply(short_track.length);
+        sequencer.enable_track(1, short_track);
+        sequencer.start_playing();
+        let mut output_messages = vec![];
+        // the 4th 16th outlasts the sustained note, so stop before it
+        for _ in 0..18 {
+            output_messages.extend(sequencer.advance(now_us));
+            now_us += DEFAULT_TICK_DURATION_US;
+        }
+        // the notes merge into a single note, held for the length of the sustained note
+        assert_eq!(2, output_messages.len());
+        assert_eq!(
+            ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(0.into(), 60.into(), 127.into())),
+            output_messages[0]
+        );
+        assert_eq!(
+            ScheduledMidiMessage::Delayed(
+                MidiMessage::NoteOff(0.into(), 60.into(), 0.into()),
+                369216.micros()
+            ),
+            output_messages[1]
+        );
+    }
+
+    #[test]
+    fn sequencer_advance_with_colliding_notes_should_only_extend_last_ratchet_sub_note() {
+        let mut sequencer = Sequencer::default();
+        let generator = SequenceGenerator::default();
+        // track 0 ratchets each 16th, track 1 plays a long note on every beat
+        let mut ratchet_track = Track::default();
+        ratchet_track.sequence = generator.apply(ratchet_track.length);
+        for step in ratchet_track.sequence.iter_mut().flatten() {
+            step.ratchet = 2;
+        }
+        sequencer.enable_track(0, ratchet_track);
+        let mut long_track = Track::default();
+        long_track.set_time_division(TimeDivision::Quarter);
+        long_track.sequence = generator.apply(long_track.length);
+        sequencer.enable_track(1, long_track);
+        sequencer.start_playing();
+        let output_messages = sequencer.advance(0);
+        assert_eq!(4, output_messages.len());
+        let sub_note_gate_us = (DEFAULT_TICK_DURATION_US * 6 / 2) * 80 / 100;
+        assert_eq!(
+            ScheduledMidiMessage::Delayed(
+                MidiMessage::NoteOff(0.into(), 60.into(), 0.into()),
+                sub_note_gate_us.micros()
+            ),
+            output_messages[1]
+        );
+        assert_eq!(
+            ScheduledMidiMessage::Delayed(
+                MidiMessage::NoteOff(0.into(), 60.into(), 0.into()),
+                369216.micros()
+            ),
+            output_messages[3]
+        );
+    }
+
+    #[test]
+    fn sequencer_advance_with_note_colliding_with_earlier_note_should_retrigger() {
+        let mut now_us = 0;
+        let mut sequencer = Sequencer::default();
+        let generator = SequenceGenerator::default();
+        // track 0 plays a short note on the beat, track 1 plays the same note on the next 16th
+        // while it's still sounding, and outlasts it
+        let mut beat_track = Track::default();
+        beat_track.set_time_division(TimeDivision::Quarter);
+        beat_track.sequence = generator.apply(beat_track.length);
+        for step in beat_track.sequence.iter_mut().flatten() {
+            step.length_step_cents = 30;
+        }
+        sequencer.enable_track(0, beat_track);
+        let mut offbeat_track = Track::default();
+        offbeat_track.sequence = generator.apply(offbeat_track.length);
+        offbeat_track.sequence.steps[0] = None;
+        sequencer.enable_track(1, offbeat_track);
+        sequencer.start_playing();
+        for _ in 0..6 {
+            sequencer.advance(now_us);
+            now_us += DEFAULT_TICK_DURATION_US;
+        }
+        let output_messages = sequencer.advance(now_us);
+        assert_eq!(
+            vec![
+                ScheduledMidiMessage::Immediate(MidiMessage::NoteOff(
+                    0.into(),
+                    60.into(),
+                    0.into()
+                )),
+                ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(
+                    0.into(),
+                    60.into(),
+                    127.into()
+                )),
+                ScheduledMidiMessage::Delayed(
+                    MidiMessage::NoteOff(0.into(), 60.into(), 0.into()),
+                    92304.micros()
+                ),
+            ],
+            output_messages.into_iter().collect::<std::vec::Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn sequencer_advance_with_colliding_notes_on_different_channels_should_play_both() {
+        let mut sequencer = Sequencer::default();
+        let generator = SequenceGenerator::default();
+        for track_num in 0..2 {
+            let mut new_track = Track::default();
+            new_track.midi_channel = track_num.into();
+            new_track.sequence = generator.apply(new_track.length);
+            sequencer.enable_track(track_num, new_track);
+        }
+        sequencer.start_playing();
+        assert_eq!(4, sequencer.advance(0).len());
+    }
+
+    fn ratcheted_messages(ratchet: u8, length_step_cents: u8) -> std::vec::Vec<(bool, u64)> {
+        let mut track = generated_track(&SequenceGenerator::default());
+        for step in track.sequence.iter_mut().flatten() {
+            step.ratchet = ratchet;
+            step.length_step_cents = length_step_cents;
+        }
+        timed_messages(track, 1, |_| {})
+            .into_iter()
+            .map(|(_, message)| match message {
+                ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(..)) => (true, 0),
+                ScheduledMidiMessage::Delayed(MidiMessage::NoteOn(..), delay) => {
+                    (true, delay.to_micros())
+                }
+                ScheduledMidiMessage::Delayed(MidiMessage::NoteOff(..), delay) => {
+                    (false, delay.to_micros())
+                }
+                _ => panic!("unexpected message"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sequencer_advance_with_ratchet_should_retrigger_note_evenly_across_step() {
+        let step_us = DEFAULT_TICK_DURATION_US * 6;
+        let slice_us = step_us / 4;
+        let gate_us = slice_us * 80 / 100;
+        let expected: std::vec::Vec<(bool, u64)> = (0..4)
+            .flat_map(|i| [(true, slice_us * i), (false, slice_us * i + gate_us)])
+            .collect();
+        assert_eq!(expected, ratcheted_messages(4, 80));
+    }
+
+    #[test]
+    fn sequencer_advance_with_ratchet_should_release_each_sub_note_before_the_next() {
+        let messages = ratcheted_messages(STEP_RATCHET_MAX, 200);
+        assert_eq!(STEP_RATCHET_MAX as usize * 2, messages.len());
+        for pair in messages.windows(2) {
+            assert!(pair[0].1 < pair[1].1);
+        }
+        assert!(messages.last().unwrap().1 < DEFAULT_TICK_DURATION_US * 6);
+    }
+
+    #[test]
+    fn sequencer_advance_without_ratchet_should_play_note_once() {
+        assert_eq!(
+            vec![(true, 0), (false, DEFAULT_TICK_DURATION_US * 6 * 80 / 100)],
+            ratcheted_messages(1, 80)
+        );
+    }
+
+    fn probability_performance(probability: u8, seed: u64) -> std::vec::Vec<ScheduledMidiMessage> {
+        let mut generator = SequenceGenerator::default();
+        generator.set_probability(probability);
+        timed_messages(generated_track(&generator), 48 * 8, |sequencer| {
+            sequencer.set_humanize_seed(seed)
+        })
+        .into_iter()
+        .map(|(_, message)| message)
+        .collect()
+    }
+
+    fn count_note_ons_and_offs(messages: &[ScheduledMidiMessage]) -> (usize, usize) {
+        let note_ons = messages
+            .iter()
+            .filter(|message| {
+                matches!(
+                    message,
+                    ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(..))
+                )
+            })
+            .count();
+        let note_offs = messages
+            .iter()
+            .filter(|message| {
+                matches!(
+                    message,
+                    ScheduledMidiMessage::Delayed(MidiMessage::NoteOff(..), _)
+                )
+            })
+            .count();
+        (note_ons, note_offs)
+    }
+
+    #[test]
+    fn sequencer_advance_with_full_step_probability_should_play_every_step() {
+        assert_eq!(
+            (64, 64),
+            count_note_ons_and_offs(&probability_performance(100, 1))
+        );
+        assert_eq!(
+            probability_performance(100, 1),
+            probability_performance(100, 2)
+        );
+    }
+
+    #[test]
+    fn sequencer_advance_with_zero_step_probability_should_play_nothing() {
+        assert!(probability_performance(0, 1).is_empty());
+    }
+
+    #[test]
+    fn sequencer_advance_with_step_probability_should_skip_note_on_and_note_off_together() {
+        let performance = probability_performance(50, 1234);
+        let (note_ons, note_offs) = count_note_ons_and_offs(&performance);
+        assert!(note_ons > 0 && note_ons < 64);
+        assert_eq!(note_ons, note_offs);
+        assert_eq!(performance, probability_performance(50, 1234));
+    }
+
+    #[test]
+    fn sequencer_advance_with_step_probability_should_skip_same_steps_however_humanized() {
+        let note_on_ticks = |humanize: u8| -> std::vec::Vec<u64> {
+            let mut generator = SequenceGenerator::default();
+            generator.set_probability(50);
+            timed_messages(generated_track(&generator), 48 * 8, |sequencer| {
+                sequencer.set_humanize(humanize);
+                sequencer.set_humanize_seed(1234);
+            })
+            .into_iter()
+            .filter(|(_, message)| {
+                matches!(
+                    message,
+                    ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(..))
+                        | ScheduledMidiMessage::Delayed(MidiMessage::NoteOn(..), _)
+                )
+            })
+            .map(|(now_us, _)| now_us)
+            .collect()
+        };
+        assert_eq!(note_on_ticks(0), note_on_ticks(100));
+    }
+
+    fn humanized_performance(seed: u64) -> std::vec::Vec<ScheduledMidiMessage> {
+        let track = generated_track(&SequenceGenerator::default());
+        timed_messages(track, 48, |sequencer| {
+            sequencer.set_humanize(100);
+            sequencer.set_humanize_seed(seed);
+        })
+        .into_iter()
+        .map(|(_, message)| message)
+        .collect()
+    }
+
+    #[test]
+    fn sequencer_advance_with_same_humanize_seed_should_produce_identical_jitter() {
+        let performance1 = humanized_performance(1234);
+        let performance2 = humanized_performance(1234);
+        assert_eq!(16, performance1.len());
+        assert_eq!(performance1, performance2);
+    }
+
+    #[test]
+    fn sequencer_advance_with_different_humanize_seeds_should_produce_different_jitter() {
+        assert_ne!(humanized_performance(1), humanized_performance(2));
+    }
+
+    #[test]
+    fn sequencer_advance_with_humanize_should_vary_notes_within_limits() {
+        let performance = humanized_performance(42);
+        let unhumanized =
+            ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(0.into(), 60.into(), 127.into()));
+        assert!(performance.iter().any(|message| *message != unhumanized));
+        for message in performance.iter().step_by(2) {
+            match message {
+                ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(_, _, velocity)) => {
+                    let velocity: u8 = (*velocity).into();
+                    assert!(velocity >= 127 - HUMANIZE_MAX_VELOCITY_JITTER as u8);
+                }
+                ScheduledMidiMessage::Delayed(MidiMessage::NoteOn(_, _, velocity), delay) => {
+                    let velocity: u8 = (*velocity).into();
+                    assert!(velocity >= 127 - HUMANIZE_MAX_VELOCITY_JITTER as u8);
+                    assert!(delay.to_micros() <= DEFAULT_TICK_DURATION_US);
+                }
+                _ => panic!("expected note on message"),
+            }
+        }
+    }
+
+    fn humanized_gates(humanize_note_off: bool) -> std::vec::Vec<(u64, u64)> {
+        let track = generated_track(&SequenceGenerator::default());
+        let messages = timed_messages(track, 48, |sequencer| {
+            sequencer.set_humanize(100);
+            sequencer.set_humanize_seed(1234);
+            sequencer.set_humanize_note_off(humanize_note_off);
+        });
+        // each step sends a note on followed by its note off
+        messages
+            .chunks(2)
+            .map(|pair| match pair {
+                [(_, note_on), (_, ScheduledMidiMessage::Delayed(MidiMessage::NoteOff(..), note_off))] => {
+                    (message_delay_us(note_on), note_off.to_micros())
+                }
+                _ => panic!("expected note on and note off"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sequencer_advance_with_humanize_note_off_should_keep_gate_length() {
+        let gate_us = DEFAULT_TICK_DURATION_US * 6 * 80 / 100;
+        let gates = humanized_gates(true);
+        assert_eq!(8, gates.len());
+        assert!(gates.iter().any(|&(note_on_us, _)| note_on_us > 0));
+        for (note_on_us, note_off_us) in gates {
+            assert_eq!(gate_us, note_off_us - note_on_us);
+        }
+    }
+
+    #[test]
+    fn sequencer_advance_without_humanize_note_off_should_keep_note_off_on_grid() {
+        let gate_us = DEFAULT_TICK_DURATION_US * 6 * 80 / 100;
+        let gates = humanized_gates(false);
+        assert_eq!(8, gates.len());
+        assert!(gates.iter().any(|&(note_on_us, _)| note_on_us > 0));
+        for (note_on_us, note_off_us) in gates {
+            assert_eq!(gate_us, note_off_us);
+            assert!(note_off_us > note_on_us);
+        }
+    }
+
+    #[test]
+    fn sequencer_start_playing_should_restart_humanization_from_seed() {
+        let mut sequencer = Sequencer::default();
+        let mut new_track = Track::default();
+        new_track.sequence = SequenceGenerator::default().apply(new_track.length);
+        sequencer.enable_track(0, new_track);
+        sequencer.set_humanize(100);
+        sequencer.set_humanize_seed(99);
+        let mut now_us = 0;
+        let mut runs = vec![];
+        for _ in 0..2 {
+            sequencer.start_playing();
+            let mut output_messages = vec![];
+            for _ in 0..24 {
+                output_messages.extend(sequencer.advance(now_us));
+                now_us += DEFAULT_TICK_DURATION_US;
+            }
+            runs.push(output_messages);
+        }
+        assert_eq!(runs[0], runs[1]);
+    }
+
+    #[test]
+    fn sequencer_evolve_tracks_should_only_evolve_tracks_which_have_looped() {
+        let mut now_us = 0;
+        let mut sequencer = Sequencer::default();
+        let mut machine_resources = MachineResources::new();
+        let mut generators = vec![SequenceGenerator::default(), SequenceGenerator::default()];
+        generators[0].melody_machine = Box::new(RandMelodyMachine::new());
+        generators[0].set_evolve_amount(100);
+        generators[0].generate(&mut machine_resources);
+        let mut new_track = Track::default();
+        new_track.sequence = generators[0].apply(new_track.length);
+        let original_sequence = new_track.sequence.clone();
+        sequencer.enable_track(0, new_track);
+        sequencer.start_playing();
+
+        // first loop of 8 16th notes hasn't finished yet
+        for _ in 0..48 {
+            sequencer.advance(now_us);
+            now_us += DEFAULT_TICK_DURATION_US;
+        }
+        assert!(!sequencer.has_looped_tracks());
+        sequencer.evolve_tracks(&mut generators, &mut machine_resources);
+        assert_eq!(
+            original_sequence,
+            sequencer.tracks[0].as_ref().unwrap().sequence
+        );
+
+        // track loops on the next tick
+        sequencer.advance(now_us);
+        assert!(sequencer.has_looped_tracks());
+        sequencer.evolve_tracks(&mut generators, &mut machine_resources);
+        assert!(!sequencer.has_looped_tracks());
+        assert_ne!(
+            original_sequence,
+            sequencer.tracks[0].as_ref().unwrap().sequence
+        );
+    }
+
+    #[test]
+    fn sequencer_swap_evolved_sequence_should_drop_out_of_date_sequence() {
+        let mut sequencer = Sequencer::default();
+        let mut machine_resources = MachineResources::new();
+        let mut generator = SequenceGenerator::default();
+        generator.melody_machine = Box::new(RandMelodyMachine::new());
+        generator.generate(&mut machine_resources);
+        sequencer.enable_track(0, Track::default());
+        let sequence = sequencer.tracks[0].as_ref().unwrap().sequence.clone();
+        let evolved_sequence =
+            generator.generate_variation(sequence.clone(), 100, &mut machine_resources);
+
+        // the track is edited while the evolved sequence is generated
+        let edited_sequence = generator.apply(sequence.len() as u8);
+        sequencer.tracks[0].as_mut().unwrap().sequence = edited_sequence.clone();
+        assert!(!sequencer.swap_evolved_sequence(
+            0,
+            &sequence,
+            evolved_sequence.clone(),
+            &mut machine_resources
+        ));
+        assert_eq!(
+            edited_sequence,
+            sequencer.tracks[0].as_ref().unwrap().sequence
+        );
+
+        assert!(sequencer.swap_evolved_sequence(
+            0,
+            &edited_sequence,
+            evolved_sequence.clone(),
+            &mut machine_resources
+        ));
+        assert_eq!(
+            evolved_sequence,
+            sequencer.tracks[0].as_ref().unwrap().sequence
+        );
+    }
+
+    #[test]
+    fn sequencer_swap_evolved_sequence_should_drop_sequence_when_velocity_was_edited() {
+        let mut sequencer = Sequencer::default();
+        let mut machine_resources = MachineResources::new();
+        let mut step = Step::new(60).unwrap();
+        step.velocity = 80.into();
+        let sequence: Sequence = (0..8).map(|_| Some(step.clone())).collect();
+        sequencer.enable_track(
+            0,
+            Track {
+                sequence: sequence.clone(),
+                ..Default::default()
+            },
+        );
+        let evolved_sequence: Sequence = (0..8).map(|_| Step::new(64).ok()).collect();
+
+        // only the velocity of the steps is edited while the evolved sequence is generated
+        step.velocity = 100.into();
+        let edited_sequence: Sequence = (0..8).map(|_| Some(step.clone())).collect();
+        sequencer.tracks[0].as_mut().unwrap().sequence = edited_sequence.clone();
+        assert!(!sequencer.swap_evolved_sequence(
+            0,
+            &sequence,
+            evolved_sequence,
+            &mut machine_resources
+        ));
+        assert_eq!(
+            edited_sequence.checksum(),
+            sequencer.tracks[0].as_ref().unwrap().sequence.checksum()
+        );
+    }
+
+    #[test]
+    fn sequencer_set_direction_to_random_should_choose_order_straight_away() {
+        let mut sequencer = Sequencer::default();
+        sequencer.enable_track(0, Track::default());
+        sequencer.set_direction(0, Direction::Random).unwrap();
+        let track = sequencer.tracks[0].as_ref().unwrap();
+        assert_eq!(Direction::Random, track.direction);
+        assert_eq!(track.length as usize, track.random_steps.len());
+        assert!(matches!(
+            sequencer.set_direction(1, Direction::Random),
+            Err(SequencerError::TrackDisabledError())
+        ));
+    }
+
+    #[test]
+    fn sequencer_advance_with_random_direction_should_choose_new_order_each_loop() {
+        let mut now_us = 0;
+        let mut sequencer = Sequencer::default();
+        sequencer.enable_track(
+            0,
+            Track {
+                direction: Direction::Random,
+                ..Default::default()
+            },
+        );
+        let track = sequencer.tracks[0].as_ref().unwrap();
+        let length = track.length;
+        let loop_ticks =
+            length as u32 * TimeDivision::division_length_24ppqn(track.time_division) as u32;
+        sequencer.start_playing();
+        let mut orders = vec![];
+        for tick in 0..loop_ticks * 2 {
+            sequencer.advance(now_us);
+            if tick % loop_ticks == 0 {
+                orders.push(sequencer.tracks[0].as_ref().unwrap().random_steps.clone());
+            }
+            now_us += DEFAULT_TICK_DURATION_US;
+        }
+        assert_eq!(length as usize, orders[0].len());
+        assert_ne!(orders[0], orders[1]);
+    }
+
+    #[test]
+    fn sequencer_evolve_tracks_with_evolve_zero_should_keep_pattern_fixed() {
+        let mut now_us = 0;
+        let mut sequencer = Sequencer::default();
+        let mut machine_resources = MachineResources::new();
+        let mut generators = vec![SequenceGenerator::default()];
+        generators[0].melody_machine = Box::new(RandMelodyMachine::new());
+        generators[0].generate(&mut machine_resources);
+        let mut new_track = Track::default();
+        new_track.sequence = generators[0].apply(new_track.length);
+        let original_sequence = new_track.sequence.clone();
+        sequencer.enable_track(0, new_track);
+        sequencer.start_playing();
+        for _ in 0..(48 * 4) {
+            sequencer.advance(now_us);
+            sequencer.evolve_tracks(&mut generators, &mut machine_resources);
+            now_us += DEFAULT_TICK_DURATION_US;
+        }
+        assert_eq!(
+            original_sequence,
+            sequencer.tracks[0].as_ref().unwrap().sequence
+        );
+    }
+
+    #[test]
+    fn sequencer_advance_with_swing_enabled_should_output_delayed_note_on_messages_for_swung_steps()
+    {
+        let mut now_us = 0;
         let mut sequencer = Sequencer::default();
         let generator = SequenceGenerator::default();
         let mut new_track = Track::default();
@@ -363,13 +4128,15 @@ mod tests {
             MidiMessage::NoteOn(0.into(), 60.into(), 127.into()),
             9615.micros(),
         );
+        // gates are 80% of the swung step lengths, 6 ticks + 9615us for straight steps and
+        // 6 ticks - 9615us for swung steps
         let expected_note_off = ScheduledMidiMessage::Delayed(
             MidiMessage::NoteOff(0.into(), 60.into(), 0.into()),
-            92304.micros(),
+            99996.micros(),
         );
         let expected_note_off_with_swing = ScheduledMidiMessage::Delayed(
             MidiMessage::NoteOff(0.into(), 60.into(), 0.into()),
-            (92304 + 9615).micros(),
+            (9615 + 84612).micros(),
         );
         assert_eq!(expected_note_on, output_messages[0]);
         assert_eq!(expected_note_off, output_messages[1]);
@@ -388,4 +4155,346 @@ mod tests {
         assert_eq!(expected_note_on_with_swing, output_messages[14]);
         assert_eq!(expected_note_off_with_swing, output_messages[15]);
     }
+
+    /// Times at which each note-on is sent over the first 48 ticks, for a track whose 2nd step is
+    /// pulled early by 10% of a step.
+    fn early_step_note_on_times(look_ahead: bool) -> std::vec::Vec<u64> {
+        let mut track = generated_track(&SequenceGenerator::default());
+        track.sequence.steps[1].as_mut().unwrap().delay = -10;
+        timed_messages(track, 48, |sequencer| sequencer.set_look_ahead(look_ahead))
+            .into_iter()
+            .filter_map(|(now_us, message)| match message {
+                ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(..))
+                | ScheduledMidiMessage::Delayed(MidiMessage::NoteOn(..), _) => {
+                    Some(now_us + message_delay_us(&message))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn vibrato_pitch_bends(sequencer: &mut Sequencer, ticks: u32) -> std::vec::Vec<(u32, i16)> {
+        let mut bends = vec![];
+        for tick in 0..ticks {
+            for message in sequencer.advance(tick as u64 * DEFAULT_TICK_DURATION_US) {
+                if let ScheduledMidiMessage::Immediate(MidiMessage::PitchBendChange(_, value)) =
+                    message
+                {
+                    bends.push((tick, value.into()));
+                }
+            }
+        }
+        bends
+    }
+
+    #[test]
+    fn sequencer_should_send_vibrato_pitch_bend_while_note_sounds() {
+        let mut sequencer = Sequencer::default();
+        let mut step = Step::new(60).unwrap();
+        step.length_step_cents = 50;
+        sequencer.enable_track(
+            0,
+            Track {
+                time_division: TimeDivision::Whole,
+                length: 1,
+                sequence: [Some(step)].into_iter().collect(),
+                vibrato: crate::Vibrato {
+                    depth: crate::VIBRATO_MAX_DEPTH,
+                    rate: TimeDivision::Quarter,
+                },
+                ..Default::default()
+            },
+        );
+        sequencer.start_playing();
+        let bends = vibrato_pitch_bends(&mut sequencer, 60);
+        assert_eq!(
+            vec![
+                (2, 2730),
+                (4, 5460),
+                (6, 8191),
+                (8, 5460),
+                (10, 2730),
+                (12, 0),
+                (14, -2730),
+                (16, -5460),
+                (18, -8191),
+                (20, -5460),
+                (22, -2730),
+                (24, 0),
+            ],
+            bends[..12]
+        );
+        // note is released half way through the bar, and the pitch bend returns to centre
+        assert_eq!(24, bends.len());
+        assert_eq!(Some(&(48, 0)), bends.last());
+    }
+
+    #[test]
+    fn sequencer_should_not_send_pitch_bend_without_vibrato() {
+        let mut sequencer = Sequencer::default();
+        let mut track = Track::default();
+        track.sequence = SequenceGenerator::default().apply(track.length);
+        sequencer.enable_track(0, track);
+        sequencer.start_playing();
+        assert!(vibrato_pitch_bends(&mut sequencer, 96).is_empty());
+    }
+
+    #[test]
+    fn sequencer_with_look_ahead_should_play_steps_with_negative_delay_early() {
+        let note_on_times = early_step_note_on_times(true);
+        assert_eq!(8, note_on_times.len());
+        let straight_time_us = 6 * DEFAULT_TICK_DURATION_US;
+        let early_us = 6 * DEFAULT_TICK_DURATION_US / 10;
+        assert_eq!(straight_time_us - early_us, note_on_times[1]);
+        assert_eq!(12 * DEFAULT_TICK_DURATION_US, note_on_times[2]);
+    }
+
+    #[test]
+    fn sequencer_without_look_ahead_should_play_steps_with_negative_delay_on_time() {
+        let note_on_times = early_step_note_on_times(false);
+        assert_eq!(8, note_on_times.len());
+        assert_eq!(6 * DEFAULT_TICK_DURATION_US, note_on_times[1]);
+    }
+
+    #[test]
+    fn sequencer_look_ahead_should_play_steps_at_most_one_tick_early() {
+        let mut sequencer = Sequencer::default();
+        let mut track = Track::default();
+        track.sequence = SequenceGenerator::default().apply(track.length);
+        track.sequence.steps[1].as_mut().unwrap().delay = -100;
+        sequencer.enable_track(0, track);
+        sequencer.set_look_ahead(true);
+        sequencer.start_playing();
+        let mut now_us = 0;
+        for _ in 0..5 {
+            sequencer.advance(now_us);
+            now_us += DEFAULT_TICK_DURATION_US;
+        }
+        let messages = sequencer.advance(now_us);
+        assert_eq!(
+            ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(0.into(), 60.into(), 127.into())),
+            messages[0]
+        );
+        now_us += DEFAULT_TICK_DURATION_US;
+        assert!(sequencer.advance(now_us).is_empty());
+    }
+
+    fn drone_sequencer() -> Sequencer {
+        let mut sequencer = Sequencer::default();
+        let mut drone = Track::default();
+        drone.set_time_division(TimeDivision::Free);
+        sequencer.enable_track(0, drone);
+        sequencer.start_playing();
+        sequencer
+    }
+
+    #[test]
+    fn sequencer_advance_with_drone_track_should_hold_note_without_retriggering() {
+        let mut now_us = 0;
+        let mut sequencer = drone_sequencer();
+        let mut output_messages = std::vec::Vec::new();
+        for _ in 0..192 {
+            output_messages.extend(sequencer.advance(now_us));
+            now_us += DEFAULT_TICK_DURATION_US;
+        }
+        assert_eq!(
+            vec![ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(
+                0.into(),
+                60.into(),
+                127.into()
+            ))],
+            output_messages
+        );
+    }
+
+    #[test]
+    fn sequencer_advance_with_drone_track_should_retrigger_when_note_changes_and_release_on_stop() {
+        let mut sequencer = drone_sequencer();
+        sequencer.advance(0);
+        sequencer.tracks[0].as_mut().unwrap().sequence =
+            SequenceGenerator::initial_sequence(8).map_notes(|_| 64.try_into().unwrap());
+        let note_off = |note: u8| {
+            ScheduledMidiMessage::Immediate(MidiMessage::NoteOff(0.into(), note.into(), 0.into()))
+        };
+        assert_eq!(
+            [
+                note_off(60),
+                ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(
+                    0.into(),
+                    64.into(),
+                    127.into()
+                ))
+            ],
+            sequencer.advance(DEFAULT_TICK_DURATION_US).as_slice()
+        );
+        sequencer.stop_playing();
+        assert_eq!(
+            [note_off(64)],
+            sequencer.advance(2 * DEFAULT_TICK_DURATION_US).as_slice()
+        );
+    }
+
+    fn hi_hat_messages(
+        open_hat_group: Option<u8>,
+        closed_hat_group: Option<u8>,
+    ) -> std::vec::Vec<std::vec::Vec<ScheduledMidiMessage>> {
+        let mut now_us = 0;
+        let mut sequencer = Sequencer::default();
+        let mut open_hat = Track::default();
+        let mut open_hat_step = Step::new(46).unwrap();
+        open_hat_step.length_step_cents = 250;
+        open_hat.sequence = Sequence::new(Vec::new()).resize(open_hat.length as usize);
+        open_hat.sequence.steps[0] = Some(open_hat_step);
+        open_hat.mute_group = open_hat_group;
+        let mut closed_hat = Track::default();
+        closed_hat.sequence = Sequence::new(Vec::new()).resize(closed_hat.length as usize);
+        closed_hat.sequence.steps[1] = Step::new(42).ok();
+        closed_hat.mute_group = closed_hat_group;
+        sequencer.enable_track(0, open_hat);
+        sequencer.enable_track(1, closed_hat);
+        sequencer.start_playing();
+        (0..12)
+            .map(|_| {
+                let messages = sequencer.advance(now_us).into_iter().collect();
+                now_us += DEFAULT_TICK_DURATION_US;
+                messages
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sequencer_advance_should_choke_sounding_notes_in_same_mute_group() {
+        let messages = hi_hat_messages(Some(1), Some(1));
+        assert_eq!(
+            vec![
+                ScheduledMidiMessage::Immediate(MidiMessage::NoteOff(
+                    0.into(),
+                    46.into(),
+                    0.into()
+                )),
+                ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(
+                    0.into(),
+                    42.into(),
+                    127.into()
+                )),
+                ScheduledMidiMessage::Delayed(
+                    MidiMessage::NoteOff(0.into(), 42.into(), 0.into()),
+                    (DEFAULT_TICK_DURATION_US * 6 * 80 / 100).micros()
+                ),
+            ],
+            messages[6]
+        );
+    }
+
+    #[test]
+    fn sequencer_advance_should_not_choke_notes_in_other_mute_groups() {
+        for (open_hat_group, closed_hat_group) in
+            [(Some(1), Some(2)), (None, None), (Some(1), None)]
+        {
+            let messages = hi_hat_messages(open_hat_group, closed_hat_group);
+            assert_eq!(2, messages[6].len());
+            assert!(matches!(
+                messages[6][0],
+                ScheduledMidiMessage::Immediate(MidiMessage::NoteOn(..))
+            ));
+        }
+    }
+
+    fn swung_note_on_ticks(
+        time_division: TimeDivision,
+        swing_resolution_override: Option<TimeDivision>,
+    ) -> std::vec::Vec<u32> {
+        let mut now_us = 0;
+        let mut sequencer = Sequencer::default();
+        let mut new_track = Track::default();
+        new_track.sequence = SequenceGenerator::default().apply(new_track.length);
+        new_track.set_time_division(time_division);
+        sequencer.enable_track(0, new_track);
+        sequencer.set_swing(Swing::Mpc54);
+        sequencer.set_swing_resolution_override(swing_resolution_override);
+        sequencer.start_playing();
+        let mut swung_ticks = std::vec::Vec::new();
+        for tick in 0..48 {
+            let messages = sequencer.advance(now_us);
+            if let Some(ScheduledMidiMessage::Delayed(MidiMessage::NoteOn(..), _)) =
+                messages.first()
+            {
+                swung_ticks.push(tick);
+            }
+            now_us += DEFAULT_TICK_DURATION_US;
+        }
+        swung_ticks
+    }
+
+    #[test]
+    fn sequencer_swing_resolution_should_follow_track_time_division_by_default() {
+        assert_eq!(
+            vec![6, 18, 30, 42],
+            swung_note_on_ticks(TimeDivision::Sixteenth, None)
+        );
+        assert_eq!(vec![12, 36], swung_note_on_ticks(TimeDivision::Eigth, None));
+        assert_eq!(
+            vec![3, 9, 15, 21, 27, 33, 39, 45],
+            swung_note_on_ticks(TimeDivision::ThirtySecond, None)
+        );
+    }
+
+    #[test]
+    fn sequencer_swing_resolution_override_should_swing_all_tracks_at_same_division() {
+        let sixteenth = Some(TimeDivision::Sixteenth);
+        assert_eq!(
+            vec![6, 18, 30, 42],
+            swung_note_on_ticks(TimeDivision::Sixteenth, sixteenth)
+        );
+        assert!(swung_note_on_ticks(TimeDivision::Eigth, sixteenth).is_empty());
+        assert_eq!(
+            vec![6, 18, 30, 42],
+            swung_note_on_ticks(TimeDivision::ThirtySecond, sixteenth)
+        );
+    }
+
+    #[test]
+    fn sequencer_swing_delay_should_scale_with_swing_resolution() {
+        let tick_duration = DEFAULT_TICK_DURATION_US.micros();
+        let sixteenth_delay = Sequencer::swing_delay(Swing::Mpc54, 6, tick_duration);
+        let eighth_delay = Sequencer::swing_delay(Swing::Mpc54, 12, tick_duration);
+        assert_eq!(9615, sixteenth_delay.to_micros());
+        assert_eq!(sixteenth_delay * 2, eighth_delay);
+    }
+
+    fn swing_gate_lengths(swing: Swing) -> std::vec::Vec<(u64, u64)> {
+        let track = generated_track(&SequenceGenerator::default());
+        let messages = timed_messages(track, 12, |sequencer| sequencer.set_swing(swing));
+        // each step sends a note on followed by its note off
+        messages
+            .chunks(2)
+            .map(|pair| match pair {
+                [(note_on_tick_us, note_on), (note_off_tick_us, note_off)] => (
+                    note_on_tick_us + message_delay_us(note_on),
+                    note_off_tick_us + message_delay_us(note_off),
+                ),
+                _ => panic!("expected note on and note off"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sequencer_advance_with_swing_should_preserve_gate_percentage_of_swung_steps() {
+        for swing in [Swing::Mpc54, Swing::Mpc75] {
+            let gates = swing_gate_lengths(swing);
+            assert_eq!(2, gates.len());
+            let (straight_start_us, straight_end_us) = gates[0];
+            let (swung_start_us, swung_end_us) = gates[1];
+            let pair_end_us = 12 * DEFAULT_TICK_DURATION_US;
+
+            // default gate is 80% of the step, allow 1us either way for rounding
+            let straight_gate = (swung_start_us - straight_start_us) * 80 / 100;
+            let swung_gate = (pair_end_us - swung_start_us) * 80 / 100;
+            assert!((straight_end_us - straight_start_us).abs_diff(straight_gate) <= 1);
+            assert!((swung_end_us - swung_start_us).abs_diff(swung_gate) <= 1);
+
+            // the straight step's note ends before the swung step starts
+            assert!(straight_end_us < swung_start_us);
+        }
+    }
 }
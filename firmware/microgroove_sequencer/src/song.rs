@@ -0,0 +1,127 @@
+/// Arrangement of patterns into a song. Each part of the song switches every track to one of its
+/// stored patterns and plays it for a number of loops before moving on to the next part. After
+/// the last part the song starts again from the first.
+use heapless::Vec;
+
+pub const SONG_MAX_PARTS: usize = 16;
+
+/// Pattern slot which a part of a song switches every track to, see `Track::recall_pattern`.
+/// Tracks with nothing stored in the slot keep playing their current sequence.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PatternRef(pub usize);
+
+#[derive(Debug)]
+pub enum SongError {
+    TooManyPartsError(),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Song {
+    /// Pattern played by each part, and the number of loops it plays for.
+    parts: Vec<(PatternRef, u8), SONG_MAX_PARTS>,
+
+    /// Index of the part playing, and the number of loops it has finished.
+    part_num: usize,
+    loops_played: u8,
+}
+
+impl Song {
+    pub fn new() -> Song {
+        Default::default()
+    }
+
+    /// Song playing each pattern for its number of loops, in order.
+    pub fn from_parts(parts: &[(PatternRef, u8)]) -> Result<Song, SongError> {
+        let mut song = Song::new();
+        for &(pattern, repeats) in parts {
+            song.push_part(pattern, repeats)?;
+        }
+        Ok(song)
+    }
+
+    /// Add a part to the end of the song. A part plays at least once, even with 0 repeats.
+    pub fn push_part(&mut self, pattern: PatternRef, repeats: u8) -> Result<(), SongError> {
+        self.parts
+            .push((pattern, repeats.max(1)))
+            .map_err(|_| SongError::TooManyPartsError())
+    }
+
+    pub fn parts(&self) -> &[(PatternRef, u8)] {
+        &self.parts
+    }
+
+    /// Index of the part playing, or `None` if the song is empty.
+    pub fn part_num(&self) -> Option<usize> {
+        if self.parts.is_empty() {
+            return None;
+        }
+        Some(self.part_num)
+    }
+
+    /// Pattern of the part playing, or `None` if the song is empty.
+    pub fn pattern(&self) -> Option<PatternRef> {
+        self.parts.get(self.part_num).map(|&(pattern, _)| pattern)
+    }
+
+    /// Go back to the start of the first part.
+    pub fn rewind(&mut self) {
+        self.part_num = 0;
+        self.loops_played = 0;
+    }
+
+    /// Count a finished loop of the part playing. Once the part has played all its loops, move on
+    /// to the next part and return its pattern, otherwise return `None`.
+    pub fn next_loop(&mut self) -> Option<PatternRef> {
+        let &(_, repeats) = self.parts.get(self.part_num)?;
+        self.loops_played += 1;
+        if self.loops_played < repeats {
+            return None;
+        }
+        self.loops_played = 0;
+        self.part_num = (self.part_num + 1) % self.parts.len();
+        self.pattern()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn song_should_play_each_part_for_its_repeats_then_start_again() {
+        let mut song = Song::from_parts(&[(PatternRef(0), 2), (PatternRef(1), 1)]).unwrap();
+        assert_eq!(Some(PatternRef(0)), song.pattern());
+        assert_eq!(None, song.next_loop());
+        assert_eq!(Some(PatternRef(1)), song.next_loop());
+        assert_eq!(Some(1), song.part_num());
+        assert_eq!(Some(PatternRef(0)), song.next_loop());
+        assert_eq!(Some(0), song.part_num());
+    }
+
+    #[test]
+    fn song_should_play_parts_with_zero_repeats_once() {
+        let mut song = Song::from_parts(&[(PatternRef(1), 0), (PatternRef(0), 3)]).unwrap();
+        assert_eq!(&[(PatternRef(1), 1), (PatternRef(0), 3)], song.parts());
+        assert_eq!(Some(PatternRef(0)), song.next_loop());
+        song.rewind();
+        assert_eq!(Some(PatternRef(1)), song.pattern());
+    }
+
+    #[test]
+    fn empty_song_should_have_no_pattern() {
+        let mut song = Song::new();
+        assert_eq!(None, song.part_num());
+        assert_eq!(None, song.pattern());
+        assert_eq!(None, song.next_loop());
+    }
+
+    #[test]
+    fn song_should_limit_number_of_parts() {
+        let parts = [(PatternRef(0), 1); SONG_MAX_PARTS];
+        let mut song = Song::from_parts(&parts).unwrap();
+        assert!(matches!(
+            song.push_part(PatternRef(1), 1),
+            Err(SongError::TooManyPartsError())
+        ));
+    }
+}
@@ -0,0 +1,984 @@
+/// Save the sequencer to flash, so patterns survive a power cycle. A snapshot holds the
+/// sequencer's params and tempo, and for each track its params, sequence and settings, along with
+/// the params and seed of its `SequenceGenerator`. Rhythm and melody machine ids are taken from the
+/// track's params, which follow the generator's machines, and the groove machine id from the
+/// generator's groove params. The links and accent tracks between tracks are saved last.
+///
+/// Left out on purpose: a track's morph patterns, which would need more than a slot to hold, and
+/// the random levels of a sample and hold LFO, which are rolled again each time the track loops.
+/// Playback state, e.g. the current step or a note repeat, isn't part of a pattern.
+///
+/// Format: `MGRV <version> <data length, 2 bytes> <data>`. Values which need more than a byte are
+/// stored least significant byte first. The version changes whenever the format does, so
+/// snapshots saved by another version of Microgroove are refused rather than misread.
+use alloc::boxed::Box;
+use heapless::Vec;
+use midi_types::Value14;
+
+use crate::{
+    machine::{GrooveMachine, GrooveMachineId, Machine, MelodyMachineId, RhythmMachineId},
+    param::ParamList,
+    sequence_generator::SequenceGenerator,
+    sequencer::Sequencer,
+    ClockMod, Direction, Lfo, LfoShape, Sequence, Step, TimeDivision, Track, Vibrato,
+    CLOCK_MOD_MAX, LFO_MAX_DEPTH, SEQUENCE_MAX_STEPS, STEP_PROBABILITY_MAX, STEP_RATCHET_MAX,
+    TRACK_COUNT, VIBRATO_MAX_DEPTH,
+};
+
+/// Number of snapshots which can be saved.
+pub const SLOT_COUNT: usize = 4;
+
+/// Space set aside for each slot, two RP2040 flash sectors.
+pub const SLOT_SIZE: usize = 8192;
+
+const SNAPSHOT_MAGIC: [u8; 4] = *b"MGRV";
+const SNAPSHOT_VERSION: u8 = 0x05;
+
+// magic, version and data length
+const SNAPSHOT_HEADER_LEN: usize = 7;
+
+// byte read from flash which has been erased but not written
+const ERASED_BYTE: u8 = 0xff;
+
+// index of the params which select the track's machines
+const RHYTHM_MACHINE_PARAM_INDEX: usize = 0;
+const MELODY_MACHINE_PARAM_INDEX: usize = 3;
+
+// index of the groove param which selects the generator's groove machine
+const GROOVE_MACHINE_PARAM_INDEX: usize = 3;
+
+const NO_PROGRAM: u8 = 0xff;
+const NO_TRACK: u8 = 0xff;
+
+const CLOCK_MOD_DIV: u8 = 0;
+const CLOCK_MOD_MULT: u8 = 1;
+
+const STEP_FLAG_ACTIVE: u8 = 0b000001;
+const STEP_FLAG_MUTED: u8 = 0b000010;
+const STEP_FLAG_VELOCITY_LOCK: u8 = 0b000100;
+const STEP_FLAG_LENGTH_LOCK: u8 = 0b001000;
+const STEP_FLAG_PITCH_BEND_LOCK: u8 = 0b010000;
+const STEP_FLAG_DELAY_LOCK: u8 = 0b100000;
+
+// param count and a byte for each param
+const PARAM_LIST_MAX_LEN: usize = 1 + 6;
+
+// flags, note, velocity, pitch bend (2), gate length, delay, probability, ratchet, and locked
+// velocity, gate length, pitch bend (2) and delay
+const STEP_MAX_LEN: usize = 14;
+
+// seeded flag and seed
+const SEED_MAX_LEN: usize = 9;
+
+// enabled flag, params, midi channel, program, muted flag, direction, clock mod (2), mute group
+// (2), keep steps flag, vibrato (2), LFO (5), step count and steps, and
+// params for the rhythm, melody, groove, groove machine and harmony of the generator, and its seed
+const TRACK_MAX_LEN: usize = 1
+    + PARAM_LIST_MAX_LEN
+    + 16
+    + 1
+    + SEQUENCE_MAX_STEPS * STEP_MAX_LEN
+    + 5 * PARAM_LIST_MAX_LEN
+    + SEED_MAX_LEN;
+
+/// Longest snapshot: header, sequencer params, tempo, every track, and the linked and accent track
+/// of every track.
+pub const SNAPSHOT_MAX_LEN: usize =
+    SNAPSHOT_HEADER_LEN + PARAM_LIST_MAX_LEN + 2 + TRACK_COUNT * (TRACK_MAX_LEN + 2);
+
+const _: () = assert!(SNAPSHOT_MAX_LEN <= SLOT_SIZE);
+
+#[derive(Debug, PartialEq)]
+pub enum StorageError {
+    /// There's no slot with this number.
+    InvalidSlot,
+
+    /// Nothing has been saved to the slot.
+    EmptySlot,
+
+    /// Not a snapshot, or a snapshot saved in a format this version doesn't understand.
+    UnsupportedVersion,
+
+    /// The snapshot is malformed or holds values out of range.
+    InvalidData,
+}
+
+/// Flash memory set aside for the slots. Offsets are from the start of the region, which must be
+/// `SLOT_COUNT * SLOT_SIZE` bytes long.
+pub trait Flash {
+    /// Erase the `SLOT_SIZE` bytes starting at `offset`, then write `bytes` to the start of them.
+    fn erase_and_write(&mut self, offset: usize, bytes: &[u8]);
+
+    /// Fill `bytes` with the contents of flash starting at `offset`.
+    fn read(&mut self, offset: usize, bytes: &mut [u8]);
+}
+
+pub type SnapshotBytes = Vec<u8, SNAPSHOT_MAX_LEN>;
+
+/// Serialized state of a sequencer and its sequence generators, ready to save to a slot.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SequencerSnapshot {
+    bytes: SnapshotBytes,
+}
+
+impl SequencerSnapshot {
+    /// Serialize `sequencer` and the generator of each of its enabled tracks, indexed by track
+    /// number.
+    pub fn capture(sequencer: &Sequencer, generators: &[SequenceGenerator]) -> SequencerSnapshot {
+        let mut bytes = SnapshotBytes::new();
+        bytes
+            .extend_from_slice(&SNAPSHOT_MAGIC)
+            .expect("magic should fit in snapshot");
+        push(&mut bytes, SNAPSHOT_VERSION);
+        push_u16(&mut bytes, 0);
+
+        push_params(&mut bytes, sequencer.params());
+        push_u16(&mut bytes, sequencer.internal_clock_bpm());
+        for (track_num, track) in sequencer.tracks.iter().enumerate() {
+            match track {
+                Some(track) => {
+                    let generator = generators
+                        .get(track_num)
+                        .expect("should get sequence generator for track");
+                    push(&mut bytes, 1);
+                    push_track(&mut bytes, track);
+                    push_params(&mut bytes, generator.rhythm_machine.params());
+                    push_params(&mut bytes, generator.melody_machine.params());
+                    push_params(&mut bytes, generator.groove_params());
+                    push_params(&mut bytes, generator.groove_machine.params());
+                    push_params(&mut bytes, generator.harmony_params());
+                    push_seed(&mut bytes, generator.seed());
+                }
+                None => push(&mut bytes, 0),
+            }
+        }
+        for track_num in 0..TRACK_COUNT as u8 {
+            push(
+                &mut bytes,
+                sequencer.linked_track(track_num).unwrap_or(NO_TRACK),
+            );
+            push(
+                &mut bytes,
+                sequencer.accent_track(track_num).unwrap_or(NO_TRACK),
+            );
+        }
+
+        let data_len = (bytes.len() - SNAPSHOT_HEADER_LEN) as u16;
+        bytes[SNAPSHOT_HEADER_LEN - 2..SNAPSHOT_HEADER_LEN]
+            .copy_from_slice(&data_len.to_le_bytes());
+        SequencerSnapshot { bytes }
+    }
+
+    /// Snapshot from bytes saved with `as_bytes`. Only the header is checked, the data is checked
+    /// when the snapshot is restored.
+    pub fn from_bytes(bytes: &[u8]) -> Result<SequencerSnapshot, StorageError> {
+        let data_len = read_header(bytes)?;
+        if bytes.len() != SNAPSHOT_HEADER_LEN + data_len {
+            return Err(StorageError::InvalidData);
+        }
+        Ok(SequencerSnapshot {
+            bytes: Vec::from_slice(bytes).map_err(|_| StorageError::InvalidData)?,
+        })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Replace the tracks, sequencer params, tempo and track links of `sequencer` with those in the
+    /// snapshot, and the machines and params of each enabled track's generator. While playing, tracks are
+    /// launched like any other new track, see `Sequencer::enable_track`. If the snapshot can't be
+    /// restored, the sequencer and generators are unchanged.
+    pub fn restore(
+        &self,
+        sequencer: &mut Sequencer,
+        generators: &mut [SequenceGenerator],
+    ) -> Result<(), StorageError> {
+        let mut data = SnapshotReader {
+            bytes: &self.bytes[SNAPSHOT_HEADER_LEN..],
+        };
+        let params = data.read_params(sequencer.params())?;
+        let bpm = data.read_u16()?;
+        let mut tracks: Vec<Option<(Track, RestoredGenerator)>, TRACK_COUNT> = Vec::new();
+        for track_num in 0..TRACK_COUNT {
+            let track = match data.read()? {
+                0 => None,
+                1 => {
+                    let track = data.read_track()?;
+                    let generator = generators
+                        .get(track_num)
+                        .expect("should get sequence generator for track");
+                    let restored = data.read_generator(&track, generator)?;
+                    Some((track, restored))
+                }
+                _ => return Err(StorageError::InvalidData),
+            };
+            let _ = tracks.push(track);
+        }
+        let mut links = [None; TRACK_COUNT];
+        let mut accent_tracks = [None; TRACK_COUNT];
+        for track_num in 0..TRACK_COUNT {
+            links[track_num] = data.read_other_track(track_num)?;
+            accent_tracks[track_num] = data.read_other_track(track_num)?;
+        }
+        // links go both ways
+        for (track_num, link) in links.iter().enumerate() {
+            if matches!(link, Some(other) if links[*other as usize] != Some(track_num as u8)) {
+                return Err(StorageError::InvalidData);
+            }
+        }
+        if !data.bytes.is_empty() {
+            return Err(StorageError::InvalidData);
+        }
+
+        *sequencer.params_mut() = params;
+        sequencer.set_internal_clock_bpm(bpm);
+        for (track_num, track) in tracks.into_iter().enumerate() {
+            match track {
+                Some((track, restored)) => {
+                    let generator = generators
+                        .get_mut(track_num)
+                        .expect("should get mut ref to sequence generator for track");
+                    generator.rhythm_machine = restored.rhythm_machine;
+                    generator.melody_machine = restored.melody_machine;
+                    generator.groove_machine = restored.groove_machine;
+                    *generator.groove_params_mut() = restored.groove_params;
+                    *generator.harmony_params_mut() = restored.harmony_params;
+                    generator.set_seed(restored.seed);
+                    generator.set_time_division(track.time_division);
+                    let _ = sequencer.enable_track(track_num as u8, track);
+                }
+                None => sequencer.tracks[track_num] = None,
+            }
+        }
+        for track_num in 0..TRACK_COUNT as u8 {
+            sequencer.unlink_track(track_num);
+        }
+        for (track_num, (link, accent_track)) in links.into_iter().zip(accent_tracks).enumerate() {
+            if let Some(other_track_num) = link {
+                sequencer.link_tracks(track_num as u8, other_track_num);
+            }
+            sequencer.set_accent_track(track_num as u8, accent_track);
+        }
+        Ok(())
+    }
+}
+
+/// Save `snapshot` to slot `slot_num`, replacing anything saved there before.
+pub fn save_slot<F: Flash>(
+    flash: &mut F,
+    slot_num: usize,
+    snapshot: &SequencerSnapshot,
+) -> Result<(), StorageError> {
+    if slot_num >= SLOT_COUNT {
+        return Err(StorageError::InvalidSlot);
+    }
+    flash.erase_and_write(slot_num * SLOT_SIZE, snapshot.as_bytes());
+    Ok(())
+}
+
+/// Load the snapshot saved to slot `slot_num`.
+pub fn load_slot<F: Flash>(
+    flash: &mut F,
+    slot_num: usize,
+) -> Result<SequencerSnapshot, StorageError> {
+    if slot_num >= SLOT_COUNT {
+        return Err(StorageError::InvalidSlot);
+    }
+    let offset = slot_num * SLOT_SIZE;
+    let mut header = [0; SNAPSHOT_HEADER_LEN];
+    flash.read(offset, &mut header);
+    if header.iter().all(|&byte| byte == ERASED_BYTE) {
+        return Err(StorageError::EmptySlot);
+    }
+    let data_len = read_header(&header)?;
+    let mut bytes = SnapshotBytes::new();
+    bytes
+        .resize_default(SNAPSHOT_HEADER_LEN + data_len)
+        .map_err(|_| StorageError::InvalidData)?;
+    flash.read(offset, &mut bytes);
+    SequencerSnapshot::from_bytes(&bytes)
+}
+
+/// Check the magic and version of a snapshot, and return the length of its data.
+fn read_header(bytes: &[u8]) -> Result<usize, StorageError> {
+    if bytes.len() < SNAPSHOT_HEADER_LEN
+        || bytes[..SNAPSHOT_MAGIC.len()] != SNAPSHOT_MAGIC
+        || bytes[SNAPSHOT_MAGIC.len()] != SNAPSHOT_VERSION
+    {
+        return Err(StorageError::UnsupportedVersion);
+    }
+    let data_len = u16::from_le_bytes([bytes[5], bytes[6]]) as usize;
+    if data_len > SNAPSHOT_MAX_LEN - SNAPSHOT_HEADER_LEN {
+        return Err(StorageError::InvalidData);
+    }
+    Ok(data_len)
+}
+
+fn push(bytes: &mut SnapshotBytes, byte: u8) {
+    bytes.push(byte).expect("sequencer should fit in snapshot");
+}
+
+fn push_u16(bytes: &mut SnapshotBytes, value: u16) {
+    let [lsb, msb] = value.to_le_bytes();
+    push(bytes, lsb);
+    push(bytes, msb);
+}
+
+fn push_params(bytes: &mut SnapshotBytes, params: &ParamList) {
+    push(bytes, params.len() as u8);
+    for param in params.iter() {
+        push(bytes, i32::from(param.value()) as u8);
+    }
+}
+
+fn push_seed(bytes: &mut SnapshotBytes, seed: Option<u64>) {
+    match seed {
+        Some(seed) => {
+            push(bytes, 1);
+            bytes
+                .extend_from_slice(&seed.to_le_bytes())
+                .expect("seed should fit in snapshot");
+        }
+        None => push(bytes, 0),
+    }
+}
+
+fn push_track(bytes: &mut SnapshotBytes, track: &Track) {
+    push_params(bytes, track.params());
+    push(bytes, track.midi_channel.into());
+    push(bytes, track.program.unwrap_or(NO_PROGRAM));
+    push(bytes, track.muted as u8);
+    push(bytes, track.direction as u8);
+    match track.clock_mod {
+        ClockMod::Div(n) => {
+            push(bytes, CLOCK_MOD_DIV);
+            push(bytes, n);
+        }
+        ClockMod::Mult(n) => {
+            push(bytes, CLOCK_MOD_MULT);
+            push(bytes, n);
+        }
+    }
+    match track.mute_group {
+        Some(mute_group) => {
+            push(bytes, 1);
+            push(bytes, mute_group);
+        }
+        None => push(bytes, 0),
+    }
+    push(bytes, track.keep_steps_on_resize as u8);
+    push(bytes, track.vibrato.depth);
+    push(bytes, track.vibrato.rate as u8);
+    push(bytes, track.lfo.shape as u8);
+    push(bytes, track.lfo.rate as u8);
+    push(bytes, track.lfo.depth);
+    push(bytes, track.lfo.control.into());
+    push(bytes, track.lfo.midi_channel.into());
+    push(bytes, track.sequence.len() as u8);
+    for step in track.sequence.iter() {
+        match step {
+            Some(step) => push_step(bytes, step),
+            None => push(bytes, 0),
+        }
+    }
+}
+
+fn push_step(bytes: &mut SnapshotBytes, step: &Step) {
+    let mut flags = STEP_FLAG_ACTIVE;
+    if step.muted {
+        flags |= STEP_FLAG_MUTED;
+    }
+    if step.lock.velocity.is_some() {
+        flags |= STEP_FLAG_VELOCITY_LOCK;
+    }
+    if step.lock.length_step_cents.is_some() {
+        flags |= STEP_FLAG_LENGTH_LOCK;
+    }
+    if step.lock.pitch_bend.is_some() {
+        flags |= STEP_FLAG_PITCH_BEND_LOCK;
+    }
+    if step.lock.delay.is_some() {
+        flags |= STEP_FLAG_DELAY_LOCK;
+    }
+    push(bytes, flags);
+    push(bytes, step.note.into());
+    push(bytes, step.velocity.into());
+    push_u16(bytes, step.pitch_bend.into());
+    push(bytes, step.length_step_cents);
+    push(bytes, step.delay as u8);
+    push(bytes, step.probability);
+    push(bytes, step.ratchet);
+    if let Some(velocity) = step.lock.velocity {
+        push(bytes, velocity.into());
+    }
+    if let Some(length_step_cents) = step.lock.length_step_cents {
+        push(bytes, length_step_cents);
+    }
+    if let Some(pitch_bend) = step.lock.pitch_bend {
+        push_u16(bytes, pitch_bend.into());
+    }
+    if let Some(delay) = step.lock.delay {
+        push(bytes, delay as u8);
+    }
+}
+
+/// Machines and params read for a track's generator, set on the generator once the whole
+/// snapshot has been read.
+struct RestoredGenerator {
+    rhythm_machine: Box<dyn Machine>,
+    melody_machine: Box<dyn Machine>,
+    groove_machine: Box<dyn GrooveMachine>,
+    groove_params: ParamList,
+    harmony_params: ParamList,
+    seed: Option<u64>,
+}
+
+struct SnapshotReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl SnapshotReader<'_> {
+    fn read(&mut self) -> Result<u8, StorageError> {
+        let (&byte, rest) = self.bytes.split_first().ok_or(StorageError::InvalidData)?;
+        self.bytes = rest;
+        Ok(byte)
+    }
+
+    fn read_u16(&mut self) -> Result<u16, StorageError> {
+        Ok(u16::from_le_bytes([self.read()?, self.read()?]))
+    }
+
+    fn read_seed(&mut self) -> Result<Option<u64>, StorageError> {
+        match self.read()? {
+            0 => Ok(None),
+            1 => {
+                let mut seed = [0; 8];
+                for byte in seed.iter_mut() {
+                    *byte = self.read()?;
+                }
+                Ok(Some(u64::from_le_bytes(seed)))
+            }
+            _ => Err(StorageError::InvalidData),
+        }
+    }
+
+    fn read_bool(&mut self) -> Result<bool, StorageError> {
+        match self.read()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StorageError::InvalidData),
+        }
+    }
+
+    fn read_time_division(&mut self) -> Result<TimeDivision, StorageError> {
+        self.read()?
+            .try_into()
+            .map_err(|_| StorageError::InvalidData)
+    }
+
+    /// Read the number of a track other than `track_num`, or none.
+    fn read_other_track(&mut self, track_num: usize) -> Result<Option<u8>, StorageError> {
+        match self.read()? {
+            NO_TRACK => Ok(None),
+            other if (other as usize) < TRACK_COUNT && other as usize != track_num => {
+                Ok(Some(other))
+            }
+            _ => Err(StorageError::InvalidData),
+        }
+    }
+
+    fn read_value7(&mut self) -> Result<u8, StorageError> {
+        match self.read()? {
+            value @ 0..=127 => Ok(value),
+            _ => Err(StorageError::InvalidData),
+        }
+    }
+
+    fn read_percent(&mut self, max: u8) -> Result<u8, StorageError> {
+        match self.read()? {
+            value if value <= max => Ok(value),
+            _ => Err(StorageError::InvalidData),
+        }
+    }
+
+    fn read_value14(&mut self) -> Result<Value14, StorageError> {
+        match self.read_u16()? {
+            value @ 0..=0x3fff => Ok(value.into()),
+            _ => Err(StorageError::InvalidData),
+        }
+    }
+
+    /// Read the values of a param list saved with `push_params`, checking each is in the range
+    /// of the matching param in `template`.
+    fn read_params(&mut self, template: &ParamList) -> Result<ParamList, StorageError> {
+        if self.read()? as usize != template.len() {
+            return Err(StorageError::InvalidData);
+        }
+        let mut params = ParamList::new();
+        for param in template.iter() {
+            let mut received = param.clone();
+            received
+                .set_from_u8(self.read()?)
+                .map_err(|_| StorageError::InvalidData)?;
+            let mut new_param = param.clone();
+            if !new_param.copy_value_from(&received) {
+                return Err(StorageError::InvalidData);
+            }
+            params
+                .push(new_param)
+                .map_err(|_| StorageError::InvalidData)?;
+        }
+        Ok(params)
+    }
+
+    fn read_track(&mut self) -> Result<Track, StorageError> {
+        let mut track = Track::default();
+        let params = self.read_params(track.params())?;
+        *track.params_mut() = params;
+        track
+            .apply_params()
+            .map_err(|_| StorageError::InvalidData)?;
+        track.midi_channel = match self.read()? {
+            channel @ 0..=15 => channel.into(),
+            _ => return Err(StorageError::InvalidData),
+        };
+        track.program = match self.read()? {
+            NO_PROGRAM => None,
+            program @ 0..=127 => Some(program),
+            _ => return Err(StorageError::InvalidData),
+        };
+        track.muted = self.read_bool()?;
+        track.direction = match self.read()? {
+            0 => Direction::Forward,
+            1 => Direction::Reverse,
+            2 => Direction::PingPong,
+            3 => Direction::Random,
+            _ => return Err(StorageError::InvalidData),
+        };
+        track.clock_mod = match (self.read()?, self.read()?) {
+            (CLOCK_MOD_DIV, n @ 1..=CLOCK_MOD_MAX) => ClockMod::Div(n),
+            (CLOCK_MOD_MULT, n @ 1..=CLOCK_MOD_MAX) => ClockMod::Mult(n),
+            _ => return Err(StorageError::InvalidData),
+        };
+        track.mute_group = match self.read()? {
+            0 => None,
+            1 => Some(self.read()?),
+            _ => return Err(StorageError::InvalidData),
+        };
+        track.keep_steps_on_resize = self.read_bool()?;
+        track.vibrato = Vibrato {
+            depth: self.read_percent(VIBRATO_MAX_DEPTH)?,
+            rate: self.read_time_division()?,
+        };
+        track.lfo = Lfo {
+            shape: match self.read()? {
+                0 => LfoShape::Triangle,
+                1 => LfoShape::Ramp,
+                2 => LfoShape::Square,
+                3 => LfoShape::SampleAndHold,
+                _ => return Err(StorageError::InvalidData),
+            },
+            rate: self.read_time_division()?,
+            depth: self.read_percent(LFO_MAX_DEPTH)?,
+            control: self.read_value7()?.into(),
+            midi_channel: match self.read()? {
+                channel @ 0..=15 => channel.into(),
+                _ => return Err(StorageError::InvalidData),
+            },
+            ..Default::default()
+        };
+
+        let step_count = self.read()? as usize;
+        if step_count > SEQUENCE_MAX_STEPS || step_count < track.length as usize {
+            return Err(StorageError::InvalidData);
+        }
+        let mut steps = Vec::new();
+        for _ in 0..step_count {
+            let flags = self.read()?;
+            let step = if flags & STEP_FLAG_ACTIVE != 0 {
+                Some(self.read_step(flags)?)
+            } else {
+                None
+            };
+            let _ = steps.push(step);
+        }
+        track.sequence = Sequence::new(steps);
+        Ok(track)
+    }
+
+    fn read_step(&mut self, flags: u8) -> Result<Step, StorageError> {
+        let mut step = Step::new(self.read()?).map_err(|_| StorageError::InvalidData)?;
+        step.muted = flags & STEP_FLAG_MUTED != 0;
+        step.velocity = self.read_value7()?.into();
+        step.pitch_bend = self.read_value14()?;
+        step.length_step_cents = self.read()?;
+        step.delay = self.read()? as i8;
+        step.probability = self.read()?;
+        step.ratchet = self.read()?;
+        if step.probability > STEP_PROBABILITY_MAX
+            || !(1..=STEP_RATCHET_MAX).contains(&step.ratchet)
+        {
+            return Err(StorageError::InvalidData);
+        }
+        if flags & STEP_FLAG_VELOCITY_LOCK != 0 {
+            step.lock.velocity = Some(self.read_value7()?.into());
+        }
+        if flags & STEP_FLAG_LENGTH_LOCK != 0 {
+            step.lock.length_step_cents = Some(self.read()?);
+        }
+        if flags & STEP_FLAG_PITCH_BEND_LOCK != 0 {
+            step.lock.pitch_bend = Some(self.read_value14()?);
+        }
+        if flags & STEP_FLAG_DELAY_LOCK != 0 {
+            step.lock.delay = Some(self.read()? as i8);
+        }
+        Ok(step)
+    }
+
+    /// Read the params and seed of a track's generator, creating its rhythm and melody machines
+    /// from the ids in the track's params, and its groove machine from the id in its groove params.
+    /// Groove and harmony params are checked against those of `generator`.
+    fn read_generator(
+        &mut self,
+        track: &Track,
+        generator: &SequenceGenerator,
+    ) -> Result<RestoredGenerator, StorageError> {
+        let rhythm_machine_id: RhythmMachineId = track.params()[RHYTHM_MACHINE_PARAM_INDEX]
+            .value()
+            .try_into()
+            .map_err(|_| StorageError::InvalidData)?;
+        let melody_machine_id: MelodyMachineId = track.params()[MELODY_MACHINE_PARAM_INDEX]
+            .value()
+            .try_into()
+            .map_err(|_| StorageError::InvalidData)?;
+        let mut rhythm_machine: Box<dyn Machine> = rhythm_machine_id.into();
+        let params = self.read_params(rhythm_machine.params())?;
+        *rhythm_machine.params_mut() = params;
+        let mut melody_machine: Box<dyn Machine> = melody_machine_id.into();
+        let params = self.read_params(melody_machine.params())?;
+        *melody_machine.params_mut() = params;
+        let groove_params = self.read_params(generator.groove_params())?;
+        let groove_machine_id: GrooveMachineId = groove_params[GROOVE_MACHINE_PARAM_INDEX]
+            .value()
+            .try_into()
+            .map_err(|_| StorageError::InvalidData)?;
+        let mut groove_machine: Box<dyn GrooveMachine> = groove_machine_id.into();
+        let params = self.read_params(groove_machine.params())?;
+        *groove_machine.params_mut() = params;
+        let harmony_params = self.read_params(generator.harmony_params())?;
+        let seed = self.read_seed()?;
+        Ok(RestoredGenerator {
+            rhythm_machine,
+            melody_machine,
+            groove_machine,
+            groove_params,
+            harmony_params,
+            seed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{param::ParamValue, quantizer::Key, sequencer::Swing, TimeDivision};
+
+    struct TestFlash {
+        bytes: std::vec::Vec<u8>,
+    }
+
+    impl TestFlash {
+        fn new() -> TestFlash {
+            TestFlash {
+                bytes: std::vec![ERASED_BYTE; SLOT_COUNT * SLOT_SIZE],
+            }
+        }
+    }
+
+    impl Flash for TestFlash {
+        fn erase_and_write(&mut self, offset: usize, bytes: &[u8]) {
+            self.bytes[offset..offset + SLOT_SIZE].fill(ERASED_BYTE);
+            self.bytes[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+
+        fn read(&mut self, offset: usize, bytes: &mut [u8]) {
+            bytes.copy_from_slice(&self.bytes[offset..offset + bytes.len()]);
+        }
+    }
+
+    fn default_generators() -> std::vec::Vec<SequenceGenerator> {
+        (0..TRACK_COUNT)
+            .map(|_| SequenceGenerator::default())
+            .collect()
+    }
+
+    fn saved_sequencer() -> (Sequencer, std::vec::Vec<SequenceGenerator>) {
+        let mut sequencer = Sequencer::default();
+        sequencer.set_swing(Swing::Mpc62);
+        sequencer
+            .set_note_repeat_rate(TimeDivision::ThirtySecond)
+            .unwrap();
+        sequencer.set_internal_clock_bpm(132);
+        let mut generators = default_generators();
+
+        let mut track = Track::default();
+        track.params_mut()[0]
+            .set(ParamValue::RhythmMachineId(RhythmMachineId::Grids))
+            .unwrap();
+        track.params_mut()[1].set(ParamValue::Number(6)).unwrap();
+        track.params_mut()[2].set(ParamValue::Number(3)).unwrap();
+        track.params_mut()[3]
+            .set(ParamValue::MelodyMachineId(MelodyMachineId::Arp))
+            .unwrap();
+        track.params_mut()[4]
+            .set(ParamValue::TimeDivision(TimeDivision::Eigth))
+            .unwrap();
+        track.apply_params().unwrap();
+        track.midi_channel = 9.into();
+        track.program = Some(42);
+        track.muted = true;
+        track.direction = Direction::PingPong;
+        track.clock_mod = ClockMod::Div(3);
+        track.mute_group = Some(2);
+        track.keep_steps_on_resize = true;
+        track.vibrato = Vibrato {
+            depth: 30,
+            rate: TimeDivision::Quarter,
+        };
+        track.lfo.shape = LfoShape::Square;
+        track.lfo.rate = TimeDivision::Eigth;
+        track.lfo.depth = 75;
+        track.lfo.control = 74.into();
+        track.lfo.midi_channel = 9.into();
+        track.sequence = (0..6)
+            .map(|i| {
+                if i == 1 {
+                    return None;
+                }
+                let mut step = Step::new(48 + i * 7).unwrap();
+                step.velocity = (100 - i).into();
+                step.pitch_bend = (8192 + i as u16 * 1000).into();
+                step.length_step_cents = 80 + i * 30;
+                step.delay = i as i8 * 10 - 20;
+                step.muted = i == 3;
+                step.probability = 100 - i * 15;
+                step.ratchet = i % 4 + 1;
+                if i == 2 {
+                    step.lock.velocity = Some(12.into());
+                    step.lock.pitch_bend = Some(0u16.into());
+                }
+                if i == 4 {
+                    step.lock.length_step_cents = Some(200);
+                    step.lock.delay = Some(-7);
+                }
+                Some(step)
+            })
+            .collect();
+        let _ = sequencer.enable_track(2, track);
+        // a plain track, with machines matching its generator's
+        let mut linked_track = Track::default();
+        linked_track.params_mut()[0]
+            .set(ParamValue::RhythmMachineId(RhythmMachineId::Unit))
+            .unwrap();
+        linked_track.params_mut()[3]
+            .set(ParamValue::MelodyMachineId(MelodyMachineId::Unit))
+            .unwrap();
+        let _ = sequencer.enable_track(5, linked_track);
+        sequencer.link_tracks(2, 5);
+        sequencer.set_accent_track(5, Some(2));
+
+        let generator = &mut generators[2];
+        generator.set_rhythm_machine(RhythmMachineId::Grids);
+        generator.set_melody_machine(MelodyMachineId::Arp);
+        generator.rhythm_machine.params_mut()[1]
+            .set(ParamValue::Number(5))
+            .unwrap();
+        generator.melody_machine.params_mut()[2]
+            .set(ParamValue::Number(3))
+            .unwrap();
+        generator.set_groove_machine(GrooveMachineId::Ghost);
+        generator.groove_machine.params_mut()[0]
+            .set(ParamValue::Number(3))
+            .unwrap();
+        generator.set_base_velocity(90);
+        generator.set_key(Key::FSharp);
+        generator.set_seed(Some(0x5eed_1234_5678));
+        (sequencer, generators)
+    }
+
+    fn param_values(params: &ParamList) -> std::vec::Vec<ParamValue> {
+        params.iter().map(|param| param.value()).collect()
+    }
+
+    #[test]
+    fn snapshot_should_round_trip_tracks_params_and_generators() {
+        let (sequencer, generators) = saved_sequencer();
+        let snapshot = SequencerSnapshot::capture(&sequencer, &generators);
+        assert!(snapshot.as_bytes().starts_with(b"MGRV"));
+        assert!(snapshot.as_bytes().len() <= SNAPSHOT_MAX_LEN);
+
+        let mut loaded = Sequencer::default();
+        let mut loaded_generators = default_generators();
+        snapshot
+            .restore(&mut loaded, &mut loaded_generators)
+            .unwrap();
+
+        assert_eq!(Swing::Mpc62, loaded.swing());
+        assert_eq!(TimeDivision::ThirtySecond, loaded.note_repeat_rate());
+        assert_eq!(132, loaded.internal_clock_bpm());
+        assert_eq!(2, loaded.tracks.iter().flatten().count());
+        let saved_track = sequencer.tracks[2].as_ref().unwrap();
+        let track = loaded.tracks[2].as_ref().unwrap();
+        assert!(saved_track.sequence.steps_eq(&track.sequence.steps));
+        assert_eq!(saved_track.sequence.checksum(), track.sequence.checksum());
+        assert_eq!(
+            param_values(saved_track.params()),
+            param_values(track.params())
+        );
+        assert_eq!(6, track.length);
+        assert_eq!(TimeDivision::Eigth, track.time_division);
+        assert_eq!(saved_track.midi_channel, track.midi_channel);
+        assert_eq!(Some(42), track.program);
+        assert!(track.muted);
+        assert_eq!(Direction::PingPong, track.direction);
+        assert_eq!(ClockMod::Div(3), track.clock_mod);
+        assert_eq!(Some(2), track.mute_group);
+        assert!(track.keep_steps_on_resize);
+        assert_eq!(saved_track.vibrato, track.vibrato);
+        assert_eq!(saved_track.lfo, track.lfo);
+        assert_eq!(Some(5), loaded.linked_track(2));
+        assert_eq!(Some(2), loaded.linked_track(5));
+        assert_eq!(Some(2), loaded.accent_track(5));
+        assert_eq!(None, loaded.accent_track(2));
+
+        let generator = &loaded_generators[2];
+        assert_eq!("GRIDS", generator.rhythm_machine.name());
+        assert_eq!("ARP", generator.melody_machine.name());
+        assert_eq!("GHOST", generator.groove_machine.name());
+        for (saved, loaded) in [
+            (
+                generators[2].rhythm_machine.params(),
+                generator.rhythm_machine.params(),
+            ),
+            (
+                generators[2].melody_machine.params(),
+                generator.melody_machine.params(),
+            ),
+            (generators[2].groove_params(), generator.groove_params()),
+            (
+                generators[2].groove_machine.params(),
+                generator.groove_machine.params(),
+            ),
+            (generators[2].harmony_params(), generator.harmony_params()),
+        ] {
+            assert_eq!(param_values(saved), param_values(loaded));
+        }
+        assert_eq!(90, generator.base_velocity());
+        assert_eq!(Key::FSharp, generator.key());
+        assert_eq!(TimeDivision::Eigth, generator.time_division());
+        assert_eq!(Some(0x5eed_1234_5678), generator.seed());
+        assert_eq!(None, loaded_generators[0].seed());
+    }
+
+    #[test]
+    fn restore_should_disable_tracks_which_were_disabled_when_saved() {
+        let (sequencer, generators) = saved_sequencer();
+        let snapshot = SequencerSnapshot::capture(&sequencer, &generators);
+        let mut loaded = Sequencer::default();
+        let _ = loaded.enable_track(0, Track::default());
+        snapshot
+            .restore(&mut loaded, &mut default_generators())
+            .unwrap();
+        assert!(loaded.tracks[0].is_none());
+        assert!(loaded.tracks[2].is_some());
+    }
+
+    #[test]
+    fn restore_should_replace_links_between_tracks() {
+        let snapshot = SequencerSnapshot::capture(&Sequencer::default(), &default_generators());
+        let mut loaded = Sequencer::default();
+        loaded.link_tracks(0, 1);
+        snapshot
+            .restore(&mut loaded, &mut default_generators())
+            .unwrap();
+        assert_eq!(None, loaded.linked_track(0));
+        assert_eq!(None, loaded.linked_track(1));
+    }
+
+    #[test]
+    fn slots_should_save_and_load_snapshots() {
+        let (sequencer, generators) = saved_sequencer();
+        let snapshot = SequencerSnapshot::capture(&sequencer, &generators);
+        let empty_snapshot = SequencerSnapshot::capture(&Sequencer::default(), &generators);
+        let mut flash = TestFlash::new();
+
+        save_slot(&mut flash, 3, &snapshot).unwrap();
+        save_slot(&mut flash, 2, &empty_snapshot).unwrap();
+        assert_eq!(Ok(snapshot), load_slot(&mut flash, 3));
+        assert_eq!(Ok(empty_snapshot.clone()), load_slot(&mut flash, 2));
+        assert_eq!(Err(StorageError::EmptySlot), load_slot(&mut flash, 0));
+
+        save_slot(&mut flash, 3, &empty_snapshot).unwrap();
+        assert_eq!(Ok(empty_snapshot.clone()), load_slot(&mut flash, 3));
+        assert_eq!(
+            Err(StorageError::InvalidSlot),
+            save_slot(&mut flash, SLOT_COUNT, &empty_snapshot)
+        );
+        assert_eq!(
+            Err(StorageError::InvalidSlot),
+            load_slot(&mut flash, SLOT_COUNT)
+        );
+    }
+
+    #[test]
+    fn snapshot_should_refuse_other_versions_and_bad_data_and_leave_sequencer_unchanged() {
+        let (sequencer, generators) = saved_sequencer();
+        let bytes = SequencerSnapshot::capture(&sequencer, &generators)
+            .as_bytes()
+            .to_vec();
+
+        let mut other_version = bytes.clone();
+        other_version[4] = SNAPSHOT_VERSION + 1;
+        assert_eq!(
+            Err(StorageError::UnsupportedVersion),
+            SequencerSnapshot::from_bytes(&other_version)
+        );
+        let mut not_a_snapshot = bytes.clone();
+        not_a_snapshot[0] = b'X';
+        assert_eq!(
+            Err(StorageError::UnsupportedVersion),
+            SequencerSnapshot::from_bytes(&not_a_snapshot)
+        );
+        assert_eq!(
+            Err(StorageError::InvalidData),
+            SequencerSnapshot::from_bytes(&bytes[..bytes.len() - 1])
+        );
+
+        // a swing amount which doesn't exist
+        let mut bad_swing = bytes.clone();
+        bad_swing[SNAPSHOT_HEADER_LEN + 1] = 0x7f;
+        let bad_swing = SequencerSnapshot::from_bytes(&bad_swing).unwrap();
+        let mut loaded = Sequencer::default();
+        let mut loaded_generators = default_generators();
+        assert_eq!(
+            Err(StorageError::InvalidData),
+            bad_swing.restore(&mut loaded, &mut loaded_generators)
+        );
+
+        // the last track is missing
+        let mut truncated = bytes.clone();
+        truncated.pop();
+        let data_len = (truncated.len() - SNAPSHOT_HEADER_LEN) as u16;
+        truncated[5..7].copy_from_slice(&data_len.to_le_bytes());
+        let truncated = SequencerSnapshot::from_bytes(&truncated).unwrap();
+        assert_eq!(
+            Err(StorageError::InvalidData),
+            truncated.restore(&mut loaded, &mut loaded_generators)
+        );
+
+        assert_eq!(Swing::None, loaded.swing());
+        assert!(loaded.tracks[2].is_none());
+        assert_eq!("UNIT", loaded_generators[2].rhythm_machine.name());
+    }
+}
@@ -0,0 +1,421 @@
+/// Share a track between Microgroove units over MIDI, as a System Exclusive message. The message
+/// carries the track's params, including its machine ids, and its sequence. Machine params live
+/// in the track's `SequenceGenerator`, so aren't included.
+///
+/// Framing: `F0 7D 4D <message type> <version> <data> F7`. 7D is the manufacturer id set aside
+/// for non-commercial use, 4D is "M". Every data byte is 7-bit, values which need more bits are
+/// split across two bytes, most significant first.
+use heapless::Vec;
+use midi_types::Value14;
+
+use crate::{
+    param::{ParamList, ParamValue},
+    Sequence, Step, Track, SEQUENCE_MAX_STEPS, STEP_PROBABILITY_MAX, STEP_RATCHET_MAX,
+};
+
+const SYSEX_START: u8 = 0xf0;
+const SYSEX_END: u8 = 0xf7;
+const SYSEX_NON_COMMERCIAL_ID: u8 = 0x7d;
+const SYSEX_MICROGROOVE_ID: u8 = 0x4d;
+const SYSEX_TRACK_MESSAGE: u8 = 0x01;
+const SYSEX_TRACK_VERSION: u8 = 0x01;
+
+const SYSEX_HEADER_LEN: usize = 5;
+
+// status bytes of real-time messages, e.g. clock, which can arrive in the middle of a SysEx message
+const REALTIME_STATUS_MIN: u8 = 0xf8;
+
+// index of the TRACK param, which says which track the params belong to rather than describing
+// the track, so it isn't loaded
+const TRACK_NUM_PARAM_INDEX: usize = 2;
+
+const STEP_FLAG_ACTIVE: u8 = 0b01;
+const STEP_FLAG_MUTED: u8 = 0b10;
+
+// flags, note, velocity, pitch bend (2), gate length (2), delay (2), probability, ratchet
+const STEP_SYSEX_LEN: usize = 11;
+
+const PARAM_COUNT: usize = 6;
+
+/// Longest track message: header, param count and params, step count and steps, and end byte.
+pub const TRACK_SYSEX_MAX_LEN: usize =
+    SYSEX_HEADER_LEN + 1 + PARAM_COUNT * 2 + 1 + SEQUENCE_MAX_STEPS * STEP_SYSEX_LEN + 1;
+
+#[derive(Debug, PartialEq)]
+pub enum SysExError {
+    /// Not a complete SysEx message.
+    InvalidFraming,
+
+    /// A SysEx message meant for another device, or a kind of Microgroove message this version
+    /// doesn't understand.
+    UnsupportedMessage,
+
+    /// The message is malformed or holds values out of range.
+    InvalidData,
+}
+
+pub type TrackSysEx = Vec<u8, TRACK_SYSEX_MAX_LEN>;
+
+/// Encode `track` as a SysEx message, ready to send to MIDI out.
+pub fn track_to_sysex(track: &Track) -> TrackSysEx {
+    let mut bytes = TrackSysEx::new();
+    bytes
+        .extend_from_slice(&[
+            SYSEX_START,
+            SYSEX_NON_COMMERCIAL_ID,
+            SYSEX_MICROGROOVE_ID,
+            SYSEX_TRACK_MESSAGE,
+            SYSEX_TRACK_VERSION,
+        ])
+        .expect("header should fit in track sysex");
+    push(&mut bytes, track.params().len() as u8);
+    for param in track.params().iter() {
+        push_u8(&mut bytes, i32::from(param.value()) as u8);
+    }
+    push(&mut bytes, track.sequence.len() as u8);
+    for step in track.sequence.iter() {
+        match step {
+            Some(step) => {
+                let flags = if step.muted {
+                    STEP_FLAG_ACTIVE | STEP_FLAG_MUTED
+                } else {
+                    STEP_FLAG_ACTIVE
+                };
+                push(&mut bytes, flags);
+                push(&mut bytes, step.note.into());
+                push(&mut bytes, step.velocity.into());
+                let (pitch_bend_msb, pitch_bend_lsb): (u8, u8) = step.pitch_bend.into();
+                push(&mut bytes, pitch_bend_msb);
+                push(&mut bytes, pitch_bend_lsb);
+                push_u8(&mut bytes, step.length_step_cents);
+                push_u8(&mut bytes, step.delay as u8);
+                push(&mut bytes, step.probability);
+                push(&mut bytes, step.ratchet);
+            }
+            None => push(&mut bytes, 0),
+        }
+    }
+    bytes
+        .push(SYSEX_END)
+        .expect("end should fit in track sysex");
+    bytes
+}
+
+/// Load a track sent with `track_to_sysex` into `track`, replacing its sequence and params. The
+/// track keeps its own track number. If the message can't be loaded, the track is unchanged.
+pub fn load_track_sysex(track: &mut Track, bytes: &[u8]) -> Result<(), SysExError> {
+    if bytes.len() < SYSEX_HEADER_LEN + 1
+        || bytes[0] != SYSEX_START
+        || bytes[bytes.len() - 1] != SYSEX_END
+    {
+        return Err(SysExError::InvalidFraming);
+    }
+    if bytes[1..SYSEX_HEADER_LEN]
+        != [
+            SYSEX_NON_COMMERCIAL_ID,
+            SYSEX_MICROGROOVE_ID,
+            SYSEX_TRACK_MESSAGE,
+            SYSEX_TRACK_VERSION,
+        ]
+    {
+        return Err(SysExError::UnsupportedMessage);
+    }
+    let mut data = SysExReader {
+        bytes: &bytes[SYSEX_HEADER_LEN..bytes.len() - 1],
+    };
+
+    let param_count = data.read()? as usize;
+    if param_count != track.params().len() {
+        return Err(SysExError::InvalidData);
+    }
+    let mut params = ParamList::new();
+    for (param_num, param) in track.params().iter().enumerate() {
+        let value = data.read_u8()?;
+        let mut new_param = param.clone();
+        if param_num != TRACK_NUM_PARAM_INDEX {
+            let mut received = param.clone();
+            received
+                .set_from_u8(value)
+                .map_err(|_| SysExError::InvalidData)?;
+            if !new_param.copy_value_from(&received) {
+                return Err(SysExError::InvalidData);
+            }
+        }
+        params
+            .push(new_param)
+            .map_err(|_| SysExError::InvalidData)?;
+    }
+
+    let step_count = data.read()? as usize;
+    if step_count > SEQUENCE_MAX_STEPS {
+        return Err(SysExError::InvalidData);
+    }
+    let mut steps = Vec::new();
+    for _ in 0..step_count {
+        let flags = data.read()?;
+        let step = if flags & STEP_FLAG_ACTIVE != 0 {
+            let mut step = Step::new(data.read()?).map_err(|_| SysExError::InvalidData)?;
+            step.velocity = data.read()?.into();
+            step.pitch_bend = Value14::from((data.read()?, data.read()?));
+            step.length_step_cents = data.read_u8()?;
+            step.delay = data.read_u8()? as i8;
+            step.probability = data.read()?;
+            step.ratchet = data.read()?;
+            if step.probability > STEP_PROBABILITY_MAX
+                || !(1..=STEP_RATCHET_MAX).contains(&step.ratchet)
+            {
+                return Err(SysExError::InvalidData);
+            }
+            step.muted = flags & STEP_FLAG_MUTED != 0;
+            Some(step)
+        } else {
+            None
+        };
+        let _ = steps.push(step);
+    }
+    if !data.bytes.is_empty() {
+        return Err(SysExError::InvalidData);
+    }
+
+    let length: u8 = match params[1].value() {
+        ParamValue::Number(length) => length,
+        _ => return Err(SysExError::InvalidData),
+    };
+    if step_count < length as usize {
+        return Err(SysExError::InvalidData);
+    }
+    *track.params_mut() = params;
+    track.apply_params().map_err(|_| SysExError::InvalidData)?;
+    track.sequence = Sequence::new(steps);
+    Ok(())
+}
+
+/// Collect a SysEx message from MIDI in as it arrives, a byte at a time. Real-time messages, e.g.
+/// clock, can arrive in the middle of a SysEx message and are skipped. Any other status byte
+/// abandons the message, as do messages too long to be a track.
+#[derive(Debug, Default)]
+pub struct SysExReceiver {
+    bytes: TrackSysEx,
+    receiving: bool,
+}
+
+impl SysExReceiver {
+    pub fn new() -> SysExReceiver {
+        Default::default()
+    }
+
+    /// Take the next byte from MIDI in. Returns the whole message, from start byte to end byte,
+    /// when its end byte arrives.
+    pub fn receive(&mut self, byte: u8) -> Option<TrackSysEx> {
+        match byte {
+            SYSEX_START => {
+                self.bytes.clear();
+                self.receiving = self.bytes.push(byte).is_ok();
+                None
+            }
+            REALTIME_STATUS_MIN..=0xff => None,
+            SYSEX_END if self.receiving => {
+                self.receiving = false;
+                self.bytes.push(byte).ok()?;
+                Some(core::mem::take(&mut self.bytes))
+            }
+            0x80.. => {
+                self.receiving = false;
+                None
+            }
+            _ => {
+                if self.receiving && self.bytes.push(byte).is_err() {
+                    self.receiving = false;
+                }
+                None
+            }
+        }
+    }
+}
+
+fn push(bytes: &mut TrackSysEx, byte: u8) {
+    bytes
+        .push(byte & 0x7f)
+        .expect("track should fit in track sysex");
+}
+
+fn push_u8(bytes: &mut TrackSysEx, value: u8) {
+    push(bytes, value >> 7);
+    push(bytes, value);
+}
+
+struct SysExReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl SysExReader<'_> {
+    fn read(&mut self) -> Result<u8, SysExError> {
+        let (&byte, rest) = self.bytes.split_first().ok_or(SysExError::InvalidData)?;
+        if byte > 0x7f {
+            return Err(SysExError::InvalidData);
+        }
+        self.bytes = rest;
+        Ok(byte)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, SysExError> {
+        let msb = self.read()?;
+        let lsb = self.read()?;
+        if msb > 1 {
+            return Err(SysExError::InvalidData);
+        }
+        Ok(msb << 7 | lsb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        machine::{MelodyMachineId, RhythmMachineId},
+        TimeDivision,
+    };
+
+    fn shared_track() -> Track {
+        let mut track = Track::default();
+        track.params_mut()[0]
+            .set(ParamValue::RhythmMachineId(RhythmMachineId::Grids))
+            .unwrap();
+        track.params_mut()[1].set(ParamValue::Number(6)).unwrap();
+        track.params_mut()[3]
+            .set(ParamValue::MelodyMachineId(MelodyMachineId::Arp))
+            .unwrap();
+        track.params_mut()[4]
+            .set(ParamValue::TimeDivision(TimeDivision::Eigth))
+            .unwrap();
+        track.params_mut()[5].set(ParamValue::Number(10)).unwrap();
+        track.apply_params().unwrap();
+        track.sequence = (0..6)
+            .map(|i| {
+                if i == 1 {
+                    return None;
+                }
+                let mut step = Step::new(48 + i * 7).unwrap();
+                step.velocity = (100 - i).into();
+                step.pitch_bend = (8192 + i as u16 * 1000).into();
+                step.length_step_cents = 80 + i * 30;
+                step.delay = i as i8 * 10 - 20;
+                step.muted = i == 3;
+                step.probability = 100 - i * 15;
+                step.ratchet = i % 4 + 1;
+                Some(step)
+            })
+            .collect();
+        track
+    }
+
+    #[test]
+    fn track_sysex_should_round_trip_sequence_params_and_machine_ids() {
+        let track = shared_track();
+        let bytes = track_to_sysex(&track);
+        assert_eq!(SYSEX_START, bytes[0]);
+        assert_eq!(SYSEX_END, *bytes.last().unwrap());
+        assert!(bytes[1..bytes.len() - 1].iter().all(|&byte| byte < 0x80));
+
+        let mut loaded = Track::default();
+        loaded.params_mut()[2].set(ParamValue::Number(4)).unwrap();
+        load_track_sysex(&mut loaded, &bytes).unwrap();
+        assert!(track.sequence.steps_eq(&loaded.sequence.steps));
+        assert_eq!(track.sequence.checksum(), loaded.sequence.checksum());
+        assert_eq!(6, loaded.length);
+        assert_eq!(TimeDivision::Eigth, loaded.time_division);
+        assert_eq!(track.midi_channel, loaded.midi_channel);
+        assert_eq!(
+            ParamValue::RhythmMachineId(RhythmMachineId::Grids),
+            loaded.params()[0].value()
+        );
+        assert_eq!(
+            ParamValue::MelodyMachineId(MelodyMachineId::Arp),
+            loaded.params()[3].value()
+        );
+        assert_eq!(ParamValue::Number(4), loaded.params()[2].value());
+    }
+
+    fn receive_all(receiver: &mut SysExReceiver, bytes: &[u8]) -> std::vec::Vec<TrackSysEx> {
+        bytes
+            .iter()
+            .filter_map(|&byte| receiver.receive(byte))
+            .collect()
+    }
+
+    #[test]
+    fn sysex_receiver_should_collect_track_between_clock_ticks() {
+        let bytes = track_to_sysex(&shared_track());
+        let mut with_clock = std::vec::Vec::new();
+        for (i, &byte) in bytes.iter().enumerate() {
+            if i % 10 == 5 {
+                with_clock.push(0xf8);
+            }
+            with_clock.push(byte);
+        }
+        let mut receiver = SysExReceiver::new();
+
+        // a note before the message is ignored
+        assert!(receive_all(&mut receiver, &[0x90, 60, 100]).is_empty());
+        let received = receive_all(&mut receiver, &with_clock);
+        assert_eq!(1, received.len());
+        assert_eq!(bytes, received[0]);
+
+        let mut loaded = Track::default();
+        load_track_sysex(&mut loaded, &received[0]).unwrap();
+        assert!(shared_track().sequence.steps_eq(&loaded.sequence.steps));
+    }
+
+    #[test]
+    fn sysex_receiver_should_abandon_interrupted_and_overlong_messages() {
+        let bytes = track_to_sysex(&shared_track());
+        let mut receiver = SysExReceiver::new();
+
+        let mut interrupted = std::vec::Vec::from(&bytes[..20]);
+        interrupted.extend_from_slice(&[0x90, 60, 100]);
+        interrupted.extend_from_slice(&bytes[20..]);
+        assert!(receive_all(&mut receiver, &interrupted).is_empty());
+
+        let mut overlong = std::vec::Vec::from(&bytes[..bytes.len() - 1]);
+        overlong.resize(TRACK_SYSEX_MAX_LEN + 1, 0);
+        overlong.push(SYSEX_END);
+        assert!(receive_all(&mut receiver, &overlong).is_empty());
+
+        // and carry on receiving the next message
+        assert_eq!(1, receive_all(&mut receiver, &bytes).len());
+    }
+
+    #[test]
+    fn track_sysex_should_reject_bad_messages_and_leave_track_unchanged() {
+        let bytes = track_to_sysex(&shared_track());
+        let mut track = Track::default();
+        let checksum = track.sequence.checksum();
+
+        assert_eq!(
+            Err(SysExError::InvalidFraming),
+            load_track_sysex(&mut track, &bytes[..bytes.len() - 1])
+        );
+        let mut other_device = bytes.clone();
+        other_device[1] = 0x41;
+        assert_eq!(
+            Err(SysExError::UnsupportedMessage),
+            load_track_sysex(&mut track, &other_device)
+        );
+        let mut truncated = bytes.clone();
+        truncated.truncate(bytes.len() - 4);
+        truncated.push(SYSEX_END).unwrap();
+        assert_eq!(
+            Err(SysExError::InvalidData),
+            load_track_sysex(&mut track, &truncated)
+        );
+        let mut bad_time_division = bytes.clone();
+        bad_time_division[SYSEX_HEADER_LEN + 1 + 4 * 2 + 1] = 0x7f;
+        assert_eq!(
+            Err(SysExError::InvalidData),
+            load_track_sysex(&mut track, &bad_time_division)
+        );
+
+        assert_eq!(checksum, track.sequence.checksum());
+        assert_eq!(TimeDivision::Sixteenth, track.time_division);
+    }
+}